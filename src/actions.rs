@@ -24,6 +24,26 @@ use crate::ui::search_dialog::{RESPONSE_TYPE_FIND_PREVIOUS, RESPONSE_TYPE_REPLAC
 
 use crate::tab_manager;
 use crate::indentation;
+use crate::line_operations::{self, SortMode};
+use crate::block_selection;
+use crate::comments;
+use crate::snippets;
+use crate::completion;
+use crate::folding;
+use crate::syntax_highlighting;
+use crate::editorconfig;
+use crate::multi_cursor;
+use crate::undo_history;
+use crate::whitespace;
+use crate::formatter;
+use crate::problems;
+use crate::lsp;
+use crate::diagnostics;
+use crate::go_to_line;
+use crate::navigation;
+use crate::outline;
+use crate::tasks;
+use crate::rename;
 
 use crate::search;
 
@@ -50,6 +70,91 @@ pub fn open_directory_in_tree(path: &PathBuf, app_context: Rc<RefCell<AppContext
 }
 
 
+/// Enables or disables the undo and redo actions for the current tab
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn update_undo_redo_actions(app_context: &Rc<RefCell<AppContext>>) {
+    let context = app_context.borrow();
+    let (can_undo, can_redo) = crate::ui::helpers::get_current_text_view(&context.notebook)
+        .map_or((false, false), |text_view| {
+            undo_history::undo_redo_availability(app_context, &text_view.buffer())
+        });
+    for (name, enabled) in [("undo", can_undo), ("redo", can_redo)] {
+        if let Some(action) = context
+            .app
+            .lookup_action(name)
+            .and_then(|action| action.downcast::<SimpleAction>().ok())
+        {
+            action.set_enabled(enabled);
+        }
+    }
+}
+
+/// Reloads the current file from disk, discarding unsaved changes
+///
+/// Unsaved changes are only discarded once the user confirms it. The undo
+/// history of the buffer is cleared, and the cleared history is persisted
+/// since the buffer matches the file again.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view showing the file to reload
+pub fn reload_file_from_disk(app_context: &Rc<RefCell<AppContext>>, text_view: &gtk4::TextView) {
+    let buffer = text_view.buffer();
+    if app_context.borrow().buffer_paths.borrow().get(&buffer).is_none() {
+        return;
+    }
+    if !buffer.is_modified() {
+        reload_buffer(app_context, text_view);
+        return;
+    }
+
+    let window = app_context.borrow().window.clone();
+    let app_context = app_context.clone();
+    let text_view = text_view.clone();
+    crate::dialogs::show_confirm_dialog(
+        &window,
+        "Discard changes?",
+        "The document has been modified. Reloading it from disk discards your changes.",
+        "Reload",
+        move || reload_buffer(&app_context, &text_view),
+    );
+}
+
+/// Replaces the text of a buffer with its file on disk
+fn reload_buffer(app_context: &Rc<RefCell<AppContext>>, text_view: &gtk4::TextView) {
+    let buffer = text_view.buffer();
+    let context = app_context.borrow();
+    let Some(path) = context.buffer_paths.borrow().get(&buffer).cloned() else {
+        return;
+    };
+
+    let editor_config = crate::editorconfig::config_for_path(&path);
+    match crate::editorconfig::read_file(&path, &editor_config) {
+        Ok(content) => {
+            multi_cursor::clear_carets(app_context, text_view);
+            block_selection::clear_block_selection(app_context, text_view);
+
+            let cursor_line = buffer.iter_at_mark(&buffer.get_insert()).line();
+            buffer.set_text(&content);
+            undo_history::clear_history(app_context, &buffer);
+            buffer.set_modified(false);
+            let cursor = buffer.iter_at_line(cursor_line).unwrap_or_else(|| buffer.end_iter());
+            buffer.place_cursor(&cursor);
+        }
+        Err(e) => {
+            crate::dialogs::show_error_dialog(
+                &context.window,
+                "Error reloading file",
+                &format!("Could not read file: {}", e),
+            );
+        }
+    }
+}
+
 /// Sets up all application actions and connects them to their respective handlers
 ///
 /// This function creates all the menu actions for the application and connects them
@@ -76,11 +181,9 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
                 drop(buffer_paths_borrowed);
                 let app_context_clone_for_prompt = app_context_clone.clone();
                 tab_manager::prompt_save_changes_async(
-                    &context.window,
+                    &app_context_clone,
                     buffer,
                     file_path,
-                    context.buffer_paths.clone(),
-                    context.notebook.clone(),
                     context.notebook.current_page().unwrap_or(0),
                     move |proceed| {
                         if proceed {
@@ -108,11 +211,9 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
                 drop(buffer_paths_borrowed);
                 let app_context_clone_for_prompt = app_context_clone.clone();
                 tab_manager::prompt_save_changes_async(
-                    &context.window,
+                    &app_context_clone,
                     buffer,
                     file_path,
-                    context.buffer_paths.clone(),
-                    context.notebook.clone(),
                     context.notebook.current_page().unwrap_or(0),
                     move |proceed| {
                         if proceed {
@@ -142,24 +243,14 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
     let close_current_file_action = SimpleAction::new("close_current_file", None);
     let app_context_clone = app_context_for_closures.clone();
     close_current_file_action.connect_activate(move |_, _| {
-        let context = app_context_clone.borrow();
-        tab_manager::close_current_tab(
-            &context.window,
-            &context.notebook,
-            &context.buffer_paths,
-        );
+        tab_manager::close_current_tab(&app_context_clone);
     });
     app.add_action(&close_current_file_action);
 
     let close_all_files_action = SimpleAction::new("close_all_files", None);
     let app_context_clone = app_context_for_closures.clone();
     close_all_files_action.connect_activate(move |_, _| {
-        let context = app_context_clone.borrow();
-        tab_manager::close_all_tabs_with_prompts(
-            context.window.clone(),
-            context.notebook.clone(),
-            context.buffer_paths.clone(),
-        );
+        tab_manager::close_all_tabs_with_prompts(&app_context_clone);
     });
     app.add_action(&close_all_files_action);
 
@@ -173,7 +264,7 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
             let file_path = buffer_paths_borrowed.get(&buffer);
 
             if let Some(path) = file_path {
-                if let Err(e) = tab_manager::save_buffer_to_file(&context.window, &buffer, path) {
+                if let Err(e) = tab_manager::save_buffer_to_file(&app_context_clone, &buffer, path) {
                     
                     crate::dialogs::show_error_dialog(
                         &context.window,
@@ -183,12 +274,7 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
                 }
             } else {
                 drop(buffer_paths_borrowed);
-                crate::file_operations::save_file_dialog(
-                    context.window.clone().into(),
-                    buffer,
-                    context.buffer_paths.clone(),
-                    Some(context.notebook.clone()),
-                );
+                crate::file_operations::save_file_dialog(&app_context_clone, buffer);
             }
         }
     });
@@ -200,12 +286,7 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
         let context = app_context_clone.borrow();
         if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
             let buffer = text_view.buffer();
-            crate::file_operations::save_file_dialog(
-                context.window.clone().into(),
-                buffer,
-                context.buffer_paths.clone(),
-                Some(context.notebook.clone()),
-            );
+            crate::file_operations::save_file_dialog(&app_context_clone, buffer);
         }
     });
     app.add_action(&save_as_action);
@@ -217,19 +298,27 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
         if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
             let buffer = text_view.buffer();
             let initial_text = search::get_selected_text_or_word(&buffer);
-            let (dialog, search_entry, replace_entry, match_case_cb, whole_word_cb, regex_cb, status_label) = search_dialog::create_search_replace_dialog(&context.window, &initial_text, &buffer);
+            let (dialog, search_entry, replace_entry, match_case_cb, whole_word_cb, regex_cb, escapes_cb, status_label) = search_dialog::create_search_replace_dialog(&context.window, &initial_text, &buffer);
 
             let buffer_clone = buffer.clone();
             let text_view_clone = text_view.clone();
             let status_label_clone = status_label.clone();
 
             dialog.connect_response(move |d, response| {
-                let search_text = search_entry.text().to_string();
-                let replace_text = replace_entry.text().to_string();
+                let mut search_text = search_entry.text().to_string();
+                let mut replace_text = replace_entry.text().to_string();
                 let match_case = match_case_cb.is_active();
                 let whole_word = whole_word_cb.is_active();
                 let use_regex = regex_cb.is_active();
 
+                if escapes_cb.is_active() {
+                    // Regex patterns already understand \n and \t, only expand them in plain searches
+                    if !use_regex {
+                        search_text = search::unescape_search_text(&search_text);
+                    }
+                    replace_text = search::unescape_search_text(&replace_text);
+                }
+
                 if use_regex && !search_text.is_empty() {
                     match search::compile_regex(&search_text, match_case) {
                         Ok(_) => {}
@@ -299,12 +388,80 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
     });
     app.add_action(&search_and_replace_action);
 
+    let undo_action = SimpleAction::new("undo", None);
+    let app_context_clone = app_context_for_closures.clone();
+    undo_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            undo_history::undo(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&undo_action);
+
+    let redo_action = SimpleAction::new("redo", None);
+    let app_context_clone = app_context_for_closures.clone();
+    redo_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            undo_history::redo(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&redo_action);
+
+    // Refresh the undo and redo actions and the EditorConfig status whenever the current tab changes
+    let app_context_clone = app_context_for_closures.clone();
+    app_context.borrow().notebook.connect_switch_page(move |_, _, _| {
+        let app_context_clone = app_context_clone.clone();
+        // The current page is only updated after the signal has been emitted
+        glib::idle_add_local_once(move || {
+            update_undo_redo_actions(&app_context_clone);
+            editorconfig::update_status(&app_context_clone);
+        });
+    });
+    let app_context_clone = app_context_for_closures.clone();
+    app_context.borrow().notebook.connect_page_removed(move |_, page, _| {
+        if let Some(text_view) = crate::ui::helpers::get_text_view_from_page(page) {
+            undo_history::forget_history(&app_context_clone, &text_view.buffer());
+            folding::forget_folds(&app_context_clone, &text_view.buffer());
+            syntax_highlighting::forget_scopes(&app_context_clone, &text_view.buffer());
+            completion::forget_buffer(&app_context_clone, &text_view.buffer());
+            snippets::forget_session(&app_context_clone, &text_view.buffer());
+            editorconfig::forget_config(&app_context_clone, &text_view.buffer());
+            lsp::close_document(&app_context_clone, &text_view.buffer());
+            diagnostics::forget_buffer(&app_context_clone, &text_view.buffer());
+            outline::forget_buffer(&app_context_clone, &text_view.buffer());
+            crate::ui::file_history::forget_buffer(&app_context_clone, &text_view.buffer());
+        }
+        update_undo_redo_actions(&app_context_clone);
+    });
+    update_undo_redo_actions(&app_context_for_closures);
+
+    let reload_action = SimpleAction::new("reload", None);
+    let app_context_clone = app_context_for_closures.clone();
+    reload_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            reload_file_from_disk(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&reload_action);
+
+    let file_history_action = SimpleAction::new("file_history", None);
+    let app_context_clone = app_context_for_closures.clone();
+    file_history_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            crate::ui::file_history::show_file_history(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&file_history_action);
+
     let cut_action = SimpleAction::new("cut", None);
     let app_context_clone = app_context_for_closures.clone();
     cut_action.connect_activate(move |_, _| {
         let context = app_context_clone.borrow();
         if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
-            crate::clipboard::cut_selected_text(&text_view.buffer());
+            crate::clipboard::cut_selected_text(&app_context_clone, &text_view);
         }
     });
     app.add_action(&cut_action);
@@ -314,7 +471,7 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
     copy_action.connect_activate(move |_, _| {
         let context = app_context_clone.borrow();
         if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
-            crate::clipboard::copy_selected_text(&text_view.buffer());
+            crate::clipboard::copy_selected_text(&app_context_clone, &text_view.buffer());
         }
     });
     app.add_action(&copy_action);
@@ -324,7 +481,7 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
     paste_action.connect_activate(move |_, _| {
         let context = app_context_clone.borrow();
         if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
-            crate::clipboard::paste_text_async(&text_view);
+            crate::clipboard::paste_text_async(&app_context_clone, &text_view);
         }
     });
     app.add_action(&paste_action);
@@ -349,6 +506,414 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
     });
     app.add_action(&outdent_action);
 
+    for (name, to_tabs) in [("convert_indentation_to_tabs", true), ("convert_indentation_to_spaces", false)] {
+        let convert_action = SimpleAction::new(name, None);
+        let app_context_clone = app_context_for_closures.clone();
+        convert_action.connect_activate(move |_, _| {
+            let context = app_context_clone.borrow();
+            if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+                whitespace::convert_indentation(&app_context_clone, &text_view.buffer(), to_tabs);
+            }
+        });
+        app.add_action(&convert_action);
+    }
+
+    let format_document_action = SimpleAction::new("format_document", None);
+    let app_context_clone = app_context_for_closures.clone();
+    format_document_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            formatter::format_document(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&format_document_action);
+
+    let toggle_problems_action = SimpleAction::new("toggle_problems", None);
+    let app_context_clone = app_context_for_closures.clone();
+    toggle_problems_action.connect_activate(move |_, _| {
+        problems::toggle_problems_panel(&app_context_clone);
+    });
+    app.add_action(&toggle_problems_action);
+
+    let toggle_outline_action = SimpleAction::new("toggle_outline", None);
+    let app_context_clone = app_context_for_closures.clone();
+    toggle_outline_action.connect_activate(move |_, _| {
+        outline::toggle_outline_panel(&app_context_clone);
+    });
+    app.add_action(&toggle_outline_action);
+
+    let outline_popup_action = SimpleAction::new("outline_popup", None);
+    let app_context_clone = app_context_for_closures.clone();
+    outline_popup_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            outline::show_outline_popup(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&outline_popup_action);
+
+    let go_to_definition_action = SimpleAction::new("go_to_definition", None);
+    let app_context_clone = app_context_for_closures.clone();
+    go_to_definition_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            lsp::go_to_definition(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&go_to_definition_action);
+
+    let find_references_action = SimpleAction::new("find_references", None);
+    let app_context_clone = app_context_for_closures.clone();
+    find_references_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            lsp::find_references(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&find_references_action);
+
+    let rename_symbol_action = SimpleAction::new("rename_symbol", None);
+    let app_context_clone = app_context_for_closures.clone();
+    rename_symbol_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            rename::rename_symbol(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&rename_symbol_action);
+
+    let go_to_line_action = SimpleAction::new("go_to_line", None);
+    let app_context_clone = app_context_for_closures.clone();
+    go_to_line_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            go_to_line::show_go_to_line_dialog(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&go_to_line_action);
+
+    let run_task_action = SimpleAction::new("run_task", None);
+    let app_context_clone = app_context_for_closures.clone();
+    run_task_action.connect_activate(move |_, _| {
+        tasks::show_task_list(&app_context_clone);
+    });
+    app.add_action(&run_task_action);
+
+    let run_last_task_action = SimpleAction::new("run_last_task", None);
+    let app_context_clone = app_context_for_closures.clone();
+    run_last_task_action.connect_activate(move |_, _| {
+        tasks::run_last_task(&app_context_clone);
+    });
+    app.add_action(&run_last_task_action);
+
+    let stop_task_action = SimpleAction::new("stop_task", None);
+    let app_context_clone = app_context_for_closures.clone();
+    stop_task_action.connect_activate(move |_, _| {
+        tasks::stop_task(&app_context_clone);
+    });
+    app.add_action(&stop_task_action);
+
+    let toggle_output_action = SimpleAction::new("toggle_output", None);
+    let app_context_clone = app_context_for_closures.clone();
+    toggle_output_action.connect_activate(move |_, _| {
+        tasks::toggle_output_panel(&app_context_clone);
+    });
+    app.add_action(&toggle_output_action);
+
+    let go_back_action = SimpleAction::new("go_back", None);
+    let app_context_clone = app_context_for_closures.clone();
+    go_back_action.connect_activate(move |_, _| {
+        navigation::go_back(&app_context_clone);
+    });
+    app.add_action(&go_back_action);
+
+    let go_forward_action = SimpleAction::new("go_forward", None);
+    let app_context_clone = app_context_for_closures.clone();
+    go_forward_action.connect_activate(move |_, _| {
+        navigation::go_forward(&app_context_clone);
+    });
+    app.add_action(&go_forward_action);
+
+    let move_line_up_action = SimpleAction::new("move_line_up", None);
+    let app_context_clone = app_context_for_closures.clone();
+    move_line_up_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::move_lines(&text_view.buffer(), true);
+        }
+    });
+    app.add_action(&move_line_up_action);
+
+    let move_line_down_action = SimpleAction::new("move_line_down", None);
+    let app_context_clone = app_context_for_closures.clone();
+    move_line_down_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::move_lines(&text_view.buffer(), false);
+        }
+    });
+    app.add_action(&move_line_down_action);
+
+    let duplicate_lines_action = SimpleAction::new("duplicate_lines", None);
+    let app_context_clone = app_context_for_closures.clone();
+    duplicate_lines_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::duplicate_lines(&text_view.buffer());
+        }
+    });
+    app.add_action(&duplicate_lines_action);
+
+    let delete_lines_action = SimpleAction::new("delete_lines", None);
+    let app_context_clone = app_context_for_closures.clone();
+    delete_lines_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::delete_lines(&text_view.buffer());
+        }
+    });
+    app.add_action(&delete_lines_action);
+
+    let join_lines_action = SimpleAction::new("join_lines", None);
+    let app_context_clone = app_context_for_closures.clone();
+    join_lines_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::join_lines(&text_view.buffer());
+        }
+    });
+    app.add_action(&join_lines_action);
+
+    let sort_lines_action = SimpleAction::new("sort_lines", None);
+    let app_context_clone = app_context_for_closures.clone();
+    sort_lines_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::sort_lines(&text_view.buffer(), SortMode::CaseSensitive);
+        }
+    });
+    app.add_action(&sort_lines_action);
+
+    let sort_lines_case_insensitive_action = SimpleAction::new("sort_lines_case_insensitive", None);
+    let app_context_clone = app_context_for_closures.clone();
+    sort_lines_case_insensitive_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::sort_lines(&text_view.buffer(), SortMode::CaseInsensitive);
+        }
+    });
+    app.add_action(&sort_lines_case_insensitive_action);
+
+    let sort_lines_numeric_action = SimpleAction::new("sort_lines_numeric", None);
+    let app_context_clone = app_context_for_closures.clone();
+    sort_lines_numeric_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::sort_lines(&text_view.buffer(), SortMode::Numeric);
+        }
+    });
+    app.add_action(&sort_lines_numeric_action);
+
+    let reverse_lines_action = SimpleAction::new("reverse_lines", None);
+    let app_context_clone = app_context_for_closures.clone();
+    reverse_lines_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::reverse_lines(&text_view.buffer());
+        }
+    });
+    app.add_action(&reverse_lines_action);
+
+    let remove_duplicate_lines_action = SimpleAction::new("remove_duplicate_lines", None);
+    let app_context_clone = app_context_for_closures.clone();
+    remove_duplicate_lines_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::remove_duplicate_lines(&text_view.buffer());
+        }
+    });
+    app.add_action(&remove_duplicate_lines_action);
+
+    let insert_line_above_action = SimpleAction::new("insert_line_above", None);
+    let app_context_clone = app_context_for_closures.clone();
+    insert_line_above_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::insert_line(&text_view, true);
+        }
+    });
+    app.add_action(&insert_line_above_action);
+
+    let insert_line_below_action = SimpleAction::new("insert_line_below", None);
+    let app_context_clone = app_context_for_closures.clone();
+    insert_line_below_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            line_operations::insert_line(&text_view, false);
+        }
+    });
+    app.add_action(&insert_line_below_action);
+
+    let toggle_line_comment_action = SimpleAction::new("toggle_line_comment", None);
+    let app_context_clone = app_context_for_closures.clone();
+    toggle_line_comment_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            comments::toggle_line_comment(&app_context_clone, &text_view.buffer());
+        }
+    });
+    app.add_action(&toggle_line_comment_action);
+
+    let toggle_block_comment_action = SimpleAction::new("toggle_block_comment", None);
+    let app_context_clone = app_context_for_closures.clone();
+    toggle_block_comment_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            comments::toggle_block_comment(&app_context_clone, &text_view.buffer());
+        }
+    });
+    app.add_action(&toggle_block_comment_action);
+
+    let complete_action = SimpleAction::new("complete", None);
+    let app_context_clone = app_context_for_closures.clone();
+    complete_action.connect_activate(move |_, _| {
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let Some(text_view) = text_view {
+            completion::show_completion(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&complete_action);
+
+    let edit_snippets_action = SimpleAction::new("edit_snippets", None);
+    let app_context_clone = app_context_for_closures.clone();
+    edit_snippets_action.connect_activate(move |_, _| {
+        if let Some(path) = snippets::snippets_path() {
+            // Start from an empty definition so that the file can be opened
+            if !path.exists() {
+                let created = path.parent().is_some_and(|dir| std::fs::create_dir_all(dir).is_ok());
+                if !created || std::fs::write(&path, "{\n}\n").is_err() {
+                    return;
+                }
+            }
+            tab_manager::open_file_in_new_tab(&path, &app_context_clone);
+        }
+    });
+    app.add_action(&edit_snippets_action);
+
+    let fold_action = SimpleAction::new("fold", None);
+    let app_context_clone = app_context_for_closures.clone();
+    fold_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            folding::fold_at_cursor(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&fold_action);
+
+    let unfold_action = SimpleAction::new("unfold", None);
+    let app_context_clone = app_context_for_closures.clone();
+    unfold_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            folding::unfold_at_cursor(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&unfold_action);
+
+    let fold_all_action = SimpleAction::new("fold_all", None);
+    let app_context_clone = app_context_for_closures.clone();
+    fold_all_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            folding::fold_all(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&fold_all_action);
+
+    let unfold_all_action = SimpleAction::new("unfold_all", None);
+    let app_context_clone = app_context_for_closures.clone();
+    unfold_all_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            folding::unfold_all(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&unfold_all_action);
+
+    // The parameter is the shallowest nesting level to fold, starting at 1
+    let fold_to_level_action = SimpleAction::new("fold_to_level", Some(glib::VariantTy::INT32));
+    let app_context_clone = app_context_for_closures.clone();
+    fold_to_level_action.connect_activate(move |_, parameter| {
+        let Some(level) = parameter.and_then(|parameter| parameter.get::<i32>()) else {
+            return;
+        };
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            folding::fold_to_level(&app_context_clone, &text_view, level.max(1) as usize);
+        }
+    });
+    app.add_action(&fold_to_level_action);
+
+    let add_next_occurrence_action = SimpleAction::new("add_next_occurrence", None);
+    let app_context_clone = app_context_for_closures.clone();
+    add_next_occurrence_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            multi_cursor::add_next_occurrence(&app_context_clone, &text_view);
+        }
+    });
+    app.add_action(&add_next_occurrence_action);
+
+    let add_caret_above_action = SimpleAction::new("add_caret_above", None);
+    let app_context_clone = app_context_for_closures.clone();
+    add_caret_above_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            // With a block selection the shortcut extends the block instead
+            if block_selection::has_block_selection(&app_context_clone, &text_view.buffer()) {
+                block_selection::extend_block_selection(&app_context_clone, &text_view, 0, -1);
+            } else {
+                multi_cursor::add_caret_on_adjacent_line(&app_context_clone, &text_view, true);
+            }
+        }
+    });
+    app.add_action(&add_caret_above_action);
+
+    let add_caret_below_action = SimpleAction::new("add_caret_below", None);
+    let app_context_clone = app_context_for_closures.clone();
+    add_caret_below_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            // With a block selection the shortcut extends the block instead
+            if block_selection::has_block_selection(&app_context_clone, &text_view.buffer()) {
+                block_selection::extend_block_selection(&app_context_clone, &text_view, 0, 1);
+            } else {
+                multi_cursor::add_caret_on_adjacent_line(&app_context_clone, &text_view, false);
+            }
+        }
+    });
+    app.add_action(&add_caret_below_action);
+
+    let block_select_left_action = SimpleAction::new("block_select_left", None);
+    let app_context_clone = app_context_for_closures.clone();
+    block_select_left_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            block_selection::extend_block_selection(&app_context_clone, &text_view, -1, 0);
+        }
+    });
+    app.add_action(&block_select_left_action);
+
+    let block_select_right_action = SimpleAction::new("block_select_right", None);
+    let app_context_clone = app_context_for_closures.clone();
+    block_select_right_action.connect_activate(move |_, _| {
+        let context = app_context_clone.borrow();
+        if let Some(text_view) = crate::ui::helpers::get_current_text_view(&context.notebook) {
+            block_selection::extend_block_selection(&app_context_clone, &text_view, 1, 0);
+        }
+    });
+    app.add_action(&block_select_right_action);
+
     let about_action = SimpleAction::new("about", None);
     let app_context_clone = app_context_for_closures.clone();
     about_action.connect_activate(move |_, _| {
@@ -364,9 +929,17 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
     settings_action.connect_activate(move |_, _| {
         let context = app_context_clone.borrow();
         let current_theme = context.app_settings.borrow().theme.clone();
+        let current_tab_width = context.app_settings.borrow().tab_width;
+        let current_insert_spaces = context.app_settings.borrow().insert_spaces;
         let current_font = context.app_settings.borrow().font.clone();
 
-        let dialog = crate::ui::windows::create_settings_dialog(&context.window, &current_theme, &current_font);
+        let dialog = crate::ui::windows::create_settings_dialog(
+            &context.window,
+            &current_theme,
+            current_tab_width,
+            current_insert_spaces,
+            &current_font,
+        );
 
         let app_context_clone_response = app_context_clone.clone();
         let settings_clone_response = settings_gtk.clone();
@@ -424,6 +997,19 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
                             }
                         }
 
+                        let tab_width_hbox = vbox.first_child().and_then(|widget| widget.next_sibling());
+                        if let Some(widget) = tab_width_hbox.as_ref().and_then(|hbox| hbox.last_child())
+                            && let Ok(spin) = widget.downcast::<gtk4::SpinButton>()
+                        {
+                            context_response.app_settings.borrow_mut().tab_width = spin.value_as_int().max(1) as usize;
+                        }
+                        if let Some(widget) = tab_width_hbox.and_then(|hbox| hbox.next_sibling())
+                            && let Ok(check) = widget.downcast::<gtk4::CheckButton>()
+                        {
+                            context_response.app_settings.borrow_mut().insert_spaces = check.is_active();
+                        }
+                        indentation::apply_indent_settings(&app_context_clone_response);
+
                         save_settings(&context_response.app_settings.borrow());
                     }
                 }
@@ -469,11 +1055,9 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
             if let Some(buffer) = first_unsaved_buffer {
                 let app_context_clone_for_prompt = app_context_clone.clone();
                 tab_manager::prompt_save_changes_async(
-                    &context.window,
+                    &app_context_clone,
                     buffer,
                     first_unsaved_file_path,
-                    context.buffer_paths.clone(),
-                    context.notebook.clone(),
                     first_unsaved_page_index as u32,
                     move |proceed| {
                         if proceed {
@@ -526,9 +1110,47 @@ pub fn setup_actions(app_context: Rc<RefCell<AppContext>>) {
     app.set_accels_for_action("app.save_as", &["<Control><Shift>s"]);
     app.set_accels_for_action("app.quit", &["<Control>q"]);
     app.set_accels_for_action("app.search_and_replace", &["<Control>f"]);
+    app.set_accels_for_action("app.undo", &["<Control>z"]);
+    app.set_accels_for_action("app.redo", &["<Control><Shift>z", "<Control>y"]);
     app.set_accels_for_action("app.cut", &["<Control>x"]);
     app.set_accels_for_action("app.copy", &["<Control>c"]);
     app.set_accels_for_action("app.paste", &["<Control>v"]);
-    app.set_accels_for_action("app.indent", &["Tab"]);
-    app.set_accels_for_action("app.outdent", &["<Control><Shift>Tab"]);
+    // Tab and Shift+Tab are handled by the text views themselves
+    app.set_accels_for_action("app.indent", &["<Control>bracketright"]);
+    app.set_accels_for_action("app.outdent", &["<Control>bracketleft"]);
+    app.set_accels_for_action("app.move_line_up", &["<Alt>Up"]);
+    app.set_accels_for_action("app.move_line_down", &["<Alt>Down"]);
+    app.set_accels_for_action("app.duplicate_lines", &["<Control><Shift>d"]);
+    app.set_accels_for_action("app.delete_lines", &["<Control><Shift>k"]);
+    app.set_accels_for_action("app.join_lines", &["<Control>j"]);
+    app.set_accels_for_action("app.insert_line_above", &["<Control><Shift>Return"]);
+    app.set_accels_for_action("app.insert_line_below", &["<Control>Return"]);
+    app.set_accels_for_action("app.toggle_line_comment", &["<Control>slash"]);
+    app.set_accels_for_action("app.toggle_block_comment", &["<Control><Shift>slash", "<Control>question"]);
+    app.set_accels_for_action("app.complete", &["<Control>space"]);
+    app.set_accels_for_action("app.fold", &["<Control><Shift>bracketleft", "<Control>braceleft"]);
+    app.set_accels_for_action("app.unfold", &["<Control><Shift>bracketright", "<Control>braceright"]);
+    app.set_accels_for_action("app.fold_all", &["<Control><Alt>bracketleft"]);
+    app.set_accels_for_action("app.unfold_all", &["<Control><Alt>bracketright"]);
+    app.set_accels_for_action("app.fold_to_level(1)", &["<Control><Alt>1"]);
+    app.set_accels_for_action("app.fold_to_level(2)", &["<Control><Alt>2"]);
+    app.set_accels_for_action("app.fold_to_level(3)", &["<Control><Alt>3"]);
+    app.set_accels_for_action("app.add_next_occurrence", &["<Control>d"]);
+    app.set_accels_for_action("app.add_caret_above", &["<Alt><Shift>Up"]);
+    app.set_accels_for_action("app.add_caret_below", &["<Alt><Shift>Down"]);
+    app.set_accels_for_action("app.block_select_left", &["<Alt><Shift>Left"]);
+    app.set_accels_for_action("app.block_select_right", &["<Alt><Shift>Right"]);
+    app.set_accels_for_action("app.format_document", &["<Control><Shift>i"]);
+    app.set_accels_for_action("app.toggle_problems", &["<Control><Shift>m"]);
+    app.set_accels_for_action("app.go_to_definition", &["F12"]);
+    app.set_accels_for_action("app.find_references", &["<Shift>F12"]);
+    app.set_accels_for_action("app.rename_symbol", &["F2"]);
+    app.set_accels_for_action("app.go_back", &["<Alt>Left"]);
+    app.set_accels_for_action("app.go_forward", &["<Alt>Right"]);
+    app.set_accels_for_action("app.outline_popup", &["<Control><Shift>o"]);
+    app.set_accels_for_action("app.go_to_line", &["<Control>g"]);
+    app.set_accels_for_action("app.run_task", &["<Control><Shift>b"]);
+    app.set_accels_for_action("app.run_last_task", &["F5"]);
+    app.set_accels_for_action("app.stop_task", &["<Shift>F5"]);
+    app.set_accels_for_action("app.toggle_output", &["<Control><Shift>u"]);
 }
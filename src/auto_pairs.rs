@@ -0,0 +1,169 @@
+//! Module for automatic bracket and quote pairs
+//!
+//! Typing an opener inserts its closer, typing a closer right before the same
+//! closer moves over it, Backspace between an empty pair deletes both characters
+//! and typing an opener with a selection wraps the selection. Nothing is paired
+//! inside strings and comments. The pairs and each behaviour are configured per
+//! language in `LanguageSettings`.
+
+use gtk4::gdk;
+use gtk4::prelude::*;
+use gtk4::{EventControllerKey, TextView};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::AppContext;
+use crate::settings::LanguageSettings;
+
+/// Returns the configured pairs as (opener, closer) characters
+fn pairs(language: &LanguageSettings) -> Vec<(char, char)> {
+    language
+        .pairs
+        .iter()
+        .filter_map(|pair| {
+            let mut chars = pair.chars();
+            match (chars.next(), chars.next(), chars.next()) {
+                (Some(opener), Some(closer), None) => Some((opener, closer)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Handles a typed character, returning whether it was consumed
+fn handle_typed_char(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, c: char) -> bool {
+    let buffer = text_view.buffer();
+    let language = crate::syntax_highlighting::language_settings_for_buffer(app_context, &buffer);
+    let pairs = pairs(&language);
+
+    // Wrap the selection in the pair
+    if let Some((start, end)) = buffer.selection_bounds() {
+        let Some(&(opener, closer)) = pairs.iter().find(|(opener, _)| *opener == c) else {
+            return false;
+        };
+        if !language.wrap_selection {
+            return false;
+        }
+        let start_offset = start.offset();
+        let end_offset = end.offset();
+        buffer.begin_user_action();
+        buffer.insert(&mut buffer.iter_at_offset(end_offset), &closer.to_string());
+        buffer.insert(&mut buffer.iter_at_offset(start_offset), &opener.to_string());
+        buffer.end_user_action();
+        buffer.select_range(
+            &buffer.iter_at_offset(start_offset + 1),
+            &buffer.iter_at_offset(end_offset + 1),
+        );
+        return true;
+    }
+
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let next_char = cursor.char();
+
+    // Move over the closer that is already there
+    if language.overtype && next_char == c && pairs.iter().any(|(_, closer)| *closer == c) {
+        let mut next = cursor;
+        next.forward_char();
+        buffer.place_cursor(&next);
+        return true;
+    }
+
+    let Some(&(opener, closer)) = pairs.iter().find(|(opener, _)| *opener == c) else {
+        return false;
+    };
+    if !language.auto_close {
+        return false;
+    }
+
+    // Only pair before whitespace, the end of the line or another closer
+    let next_allows_pair = cursor.is_end()
+        || next_char.is_whitespace()
+        || pairs.iter().any(|(_, closer)| *closer == next_char);
+    if !next_allows_pair {
+        return false;
+    }
+    // A quote right after a word character is most likely an apostrophe or a closing quote
+    if opener == closer {
+        let mut previous = cursor;
+        if previous.backward_char() && (previous.char().is_alphanumeric() || previous.char() == '_') {
+            return false;
+        }
+    }
+    if crate::syntax_highlighting::is_in_string_or_comment(app_context, &cursor) {
+        return false;
+    }
+
+    let offset = cursor.offset();
+    buffer.begin_user_action();
+    buffer.insert(&mut buffer.iter_at_offset(offset), &format!("{}{}", opener, closer));
+    buffer.end_user_action();
+    buffer.place_cursor(&buffer.iter_at_offset(offset + 1));
+    true
+}
+
+/// Deletes an empty pair around the cursor, returning whether it did
+fn delete_empty_pair(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) -> bool {
+    let buffer = text_view.buffer();
+    if buffer.has_selection() {
+        return false;
+    }
+    let language = crate::syntax_highlighting::language_settings_for_buffer(app_context, &buffer);
+    if !language.delete_pairs {
+        return false;
+    }
+
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut previous = cursor;
+    if !previous.backward_char() {
+        return false;
+    }
+    let is_empty_pair = pairs(&language)
+        .iter()
+        .any(|(opener, closer)| previous.char() == *opener && cursor.char() == *closer);
+    if !is_empty_pair {
+        return false;
+    }
+
+    let mut next = cursor;
+    next.forward_char();
+    buffer.begin_user_action();
+    buffer.delete(&mut previous, &mut next);
+    buffer.end_user_action();
+    true
+}
+
+/// Adds the auto-pairs controller to a text view
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to add the controller to
+pub fn add_auto_pairs_controller(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let key_controller = EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    key_controller.connect_key_pressed(move |_, keyval, _, state| {
+        let shortcut_modifiers = gdk::ModifierType::CONTROL_MASK
+            | gdk::ModifierType::ALT_MASK
+            | gdk::ModifierType::SUPER_MASK;
+        if state.intersects(shortcut_modifiers) || !text_view_clone.is_editable() {
+            return glib::Propagation::Proceed;
+        }
+
+        let handled = if keyval == gdk::Key::BackSpace {
+            delete_empty_pair(&app_context_clone, &text_view_clone)
+        } else {
+            keyval
+                .to_unicode()
+                .is_some_and(|c| handle_typed_char(&app_context_clone, &text_view_clone, c))
+        };
+        if handled {
+            text_view_clone.scroll_mark_onscreen(&text_view_clone.buffer().get_insert());
+            glib::Propagation::Stop
+        } else {
+            glib::Propagation::Proceed
+        }
+    });
+    text_view.add_controller(key_controller);
+}
@@ -0,0 +1,443 @@
+//! Module for rectangular (column) selection
+//!
+//! GTK's `TextView` only supports linear selections, so this module keeps a
+//! block selection per buffer, described by an anchor and a head position in
+//! (line, column) coordinates, and draws it with an overlay. Columns are visual,
+//! a tab reaching the next tab stop, and may lie past the end of a line; lines
+//! shorter than the block are padded with spaces when text is typed or pasted
+//! into the block.
+
+use gtk4::gdk;
+use gtk4::prelude::*;
+use gtk4::{DrawingArea, EventControllerKey, GestureDrag, TextBuffer, TextIter, TextView};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::AppContext;
+
+/// A rectangular selection, in (line, column) coordinates
+pub struct BlockSelection {
+    /// Position where the selection started
+    pub anchor: (i32, i32),
+    /// Position that moves when the selection is extended
+    pub head: (i32, i32),
+    /// Overlay widget drawing the block in the text view
+    overlay: Option<DrawingArea>,
+}
+
+impl BlockSelection {
+    /// Returns the first and last line covered by the block
+    pub fn lines(&self) -> (i32, i32) {
+        (self.anchor.0.min(self.head.0), self.anchor.0.max(self.head.0))
+    }
+
+    /// Returns the left (inclusive) and right (exclusive) columns of the block
+    pub fn columns(&self) -> (i32, i32) {
+        (self.anchor.1.min(self.head.1), self.anchor.1.max(self.head.1))
+    }
+}
+
+/// Map of buffers to their block selection
+pub type BlockSelections = Rc<RefCell<HashMap<TextBuffer, BlockSelection>>>;
+
+/// Returns the block selection map of the application context
+fn block_selections(app_context: &Rc<RefCell<AppContext>>) -> BlockSelections {
+    app_context.borrow().block_selections.clone()
+}
+
+/// Checks whether a buffer currently has a block selection
+pub fn has_block_selection(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> bool {
+    block_selections(app_context).borrow().contains_key(buffer)
+}
+
+/// Returns the visual column after a character
+///
+/// # Arguments
+///
+/// * `c` - The character
+/// * `column` - Visual column of the character
+/// * `tab_width` - Number of columns between tab stops
+fn next_column(c: char, column: i32, tab_width: i32) -> i32 {
+    if c == '\t' {
+        (column / tab_width.max(1) + 1) * tab_width.max(1)
+    } else {
+        column + 1
+    }
+}
+
+/// Returns the visual column of an iterator within its line
+fn visual_column(iter: &TextIter, tab_width: i32) -> i32 {
+    let mut line_start = *iter;
+    line_start.set_line_offset(0);
+    line_start
+        .text(iter)
+        .chars()
+        .fold(0, |column, c| next_column(c, column, tab_width))
+}
+
+/// Returns the visual length of a line, excluding the line terminator
+fn line_length(buffer: &TextBuffer, line: i32, tab_width: i32) -> i32 {
+    match buffer.iter_at_line(line) {
+        Some(mut iter) => {
+            if !iter.ends_line() {
+                iter.forward_to_line_end();
+            }
+            visual_column(&iter, tab_width)
+        }
+        None => 0,
+    }
+}
+
+/// Returns an iterator on `line` at a visual `column`
+///
+/// A column inside a tab gives the position before the tab, and a column past
+/// the end of the line gives the end of the line.
+fn iter_at_column(buffer: &TextBuffer, line: i32, column: i32, tab_width: i32) -> TextIter {
+    let mut iter = buffer.iter_at_line(line).unwrap_or_else(|| buffer.end_iter());
+    let mut visual = 0;
+    while !iter.ends_line() {
+        let next = next_column(iter.char(), visual, tab_width);
+        if next > column {
+            break;
+        }
+        visual = next;
+        iter.forward_char();
+    }
+    iter
+}
+
+/// Converts widget coordinates to a (line, column) position, allowing virtual columns
+fn position_at_coords(text_view: &TextView, x: f64, y: f64) -> (i32, i32) {
+    let (buffer_x, buffer_y) =
+        text_view.window_to_buffer_coords(gtk4::TextWindowType::Widget, x as i32, y as i32);
+    let (line_start, _) = text_view.line_at_y(buffer_y);
+    let line_x = text_view.iter_location(&line_start).x();
+    let column = ((buffer_x - line_x) as f64 / crate::ui::helpers::char_width(text_view)).round() as i32;
+    (line_start.line(), column.max(0))
+}
+
+/// Removes the block selection of the text view's buffer
+pub fn clear_block_selection(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let removed = block_selections(app_context)
+        .borrow_mut()
+        .remove(&text_view.buffer());
+    if let Some(overlay) = removed.and_then(|block| block.overlay) {
+        text_view.remove(&overlay);
+    }
+}
+
+/// Sets the block selection of the text view's buffer and redraws it
+fn set_block_selection(
+    app_context: &Rc<RefCell<AppContext>>,
+    text_view: &TextView,
+    anchor: (i32, i32),
+    head: (i32, i32),
+) {
+    let buffer = text_view.buffer();
+    // A block selection replaces any secondary carets
+    crate::multi_cursor::clear_carets(app_context, text_view);
+
+    let block_selections = block_selections(app_context);
+    let mut block_selections_borrowed = block_selections.borrow_mut();
+    let block = block_selections_borrowed
+        .entry(buffer.clone())
+        .or_insert_with(|| BlockSelection { anchor, head, overlay: None });
+    block.anchor = anchor;
+    block.head = head;
+
+    if let Some(overlay) = block.overlay.take() {
+        text_view.remove(&overlay);
+    }
+
+    // Draw the block as one translucent rectangle in buffer coordinates
+    let (top, bottom) = block.lines();
+    let (left, right) = block.columns();
+    let width = crate::ui::helpers::char_width(text_view);
+    let top_iter = buffer.iter_at_line(top).unwrap_or_else(|| buffer.end_iter());
+    let bottom_iter = buffer.iter_at_line(bottom).unwrap_or_else(|| buffer.end_iter());
+    let (top_y, _) = text_view.line_yrange(&top_iter);
+    let (bottom_y, bottom_height) = text_view.line_yrange(&bottom_iter);
+    let x = text_view.iter_location(&top_iter).x() + (left as f64 * width) as i32;
+
+    let overlay = DrawingArea::new();
+    overlay.set_content_width((((right - left) as f64 * width) as i32).max(2));
+    overlay.set_content_height((bottom_y + bottom_height - top_y).max(1));
+    overlay.set_can_target(false);
+    overlay.set_draw_func(|_, cr, width, height| {
+        cr.set_source_rgba(0.4, 0.6, 1.0, 0.35);
+        cr.rectangle(0.0, 0.0, width as f64, height as f64);
+        cr.fill().ok();
+    });
+    text_view.add_overlay(&overlay, x, top_y);
+    block.overlay = Some(overlay);
+    drop(block_selections_borrowed);
+
+    // Keep the real cursor on the head so that the view follows the selection
+    let tab_width = crate::indentation::tab_width(app_context, &buffer) as i32;
+    let head_iter = iter_at_column(&buffer, head.0, head.1, tab_width);
+    buffer.place_cursor(&head_iter);
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+}
+
+/// Extends the block selection by `columns` and `lines`
+///
+/// If the buffer has no block selection yet, one is started at the cursor.
+pub fn extend_block_selection(
+    app_context: &Rc<RefCell<AppContext>>,
+    text_view: &TextView,
+    columns: i32,
+    lines: i32,
+) {
+    let buffer = text_view.buffer();
+    let current = block_selections(app_context)
+        .borrow()
+        .get(&buffer)
+        .map(|block| (block.anchor, block.head));
+    let (anchor, head) = current.unwrap_or_else(|| {
+        let cursor = buffer.iter_at_mark(&buffer.get_insert());
+        let tab_width = crate::indentation::tab_width(app_context, &buffer) as i32;
+        let position = (cursor.line(), visual_column(&cursor, tab_width));
+        (position, position)
+    });
+
+    let head = (
+        (head.0 + lines).clamp(0, buffer.line_count() - 1),
+        (head.1 + columns).max(0),
+    );
+    set_block_selection(app_context, text_view, anchor, head);
+}
+
+/// Returns the text of the block selection as newline-separated rows
+///
+/// Rows of lines shorter than the block contain only the characters that exist.
+pub fn block_selection_text(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> Option<String> {
+    let tab_width = crate::indentation::tab_width(app_context, buffer) as i32;
+    let block_selections = block_selections(app_context);
+    let block_selections_borrowed = block_selections.borrow();
+    let block = block_selections_borrowed.get(buffer)?;
+    let (top, bottom) = block.lines();
+    let (left, right) = block.columns();
+
+    let rows: Vec<String> = (top..=bottom)
+        .map(|line| {
+            let start = iter_at_column(buffer, line, left, tab_width);
+            let end = iter_at_column(buffer, line, right, tab_width);
+            buffer.text(&start, &end, true).to_string()
+        })
+        .collect();
+    Some(rows.join("\n"))
+}
+
+/// Replaces the contents of the block selection, row by row
+///
+/// Each line of the block first has its selected columns deleted; it is then
+/// padded with spaces up to the block's left column and receives its row of
+/// `rows`. A single row is repeated on every line of the block, and rows past
+/// the end of the buffer are appended as new lines. Afterwards the block
+/// collapses to a zero-width column after the inserted text. The whole edit is
+/// a single user action.
+pub fn replace_block_selection(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, rows: &[String]) {
+    let buffer = text_view.buffer();
+    let Some((top, bottom, left, right)) = block_selections(app_context)
+        .borrow()
+        .get(&buffer)
+        .map(|block| {
+            let (top, bottom) = block.lines();
+            let (left, right) = block.columns();
+            (top, bottom, left, right)
+        })
+    else {
+        return;
+    };
+
+    let tab_width = crate::indentation::tab_width(app_context, &buffer) as i32;
+    let last_line = if rows.len() > 1 { top + rows.len() as i32 - 1 } else { bottom };
+    let mut new_column = left;
+
+    buffer.begin_user_action();
+    for (i, line) in (top..=last_line).enumerate() {
+        if line >= buffer.line_count() {
+            buffer.insert(&mut buffer.end_iter(), "\n");
+        }
+        let mut start = iter_at_column(&buffer, line, left, tab_width);
+        let mut end = iter_at_column(&buffer, line, right, tab_width);
+        buffer.delete(&mut start, &mut end);
+
+        let row = if rows.len() > 1 { &rows[i] } else { rows.first().map_or("", |row| row.as_str()) };
+        if row.is_empty() {
+            continue;
+        }
+        let padding = (left - line_length(&buffer, line, tab_width)).max(0) as usize;
+        let mut insert_iter = iter_at_column(&buffer, line, left, tab_width);
+        buffer.insert(&mut insert_iter, &format!("{}{}", " ".repeat(padding), row));
+        new_column = visual_column(&insert_iter, tab_width);
+    }
+    buffer.end_user_action();
+
+    set_block_selection(app_context, text_view, (top, new_column), (last_line, new_column));
+}
+
+/// Deletes one column before (`forward == false`) or after the block on every
+/// line, used by Backspace and Delete on a zero-width block
+fn delete_block_column(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, forward: bool) {
+    let buffer = text_view.buffer();
+    let Some((top, bottom, column)) = block_selections(app_context)
+        .borrow()
+        .get(&buffer)
+        .map(|block| {
+            let (top, bottom) = block.lines();
+            (top, bottom, block.columns().0)
+        })
+    else {
+        return;
+    };
+
+    let delete_column = if forward { column } else { column - 1 };
+    if delete_column < 0 {
+        return;
+    }
+
+    let tab_width = crate::indentation::tab_width(app_context, &buffer) as i32;
+    buffer.begin_user_action();
+    for line in top..=bottom {
+        if line_length(&buffer, line, tab_width) > delete_column {
+            // A column inside a tab deletes the whole tab
+            let mut start = iter_at_column(&buffer, line, delete_column, tab_width);
+            let mut end = start;
+            end.forward_char();
+            buffer.delete(&mut start, &mut end);
+        }
+    }
+    buffer.end_user_action();
+
+    set_block_selection(app_context, text_view, (top, delete_column), (bottom, delete_column));
+}
+
+/// Adds the block selection controllers to a text view
+///
+/// Alt+drag selects a block; while a block is selected, typing, Backspace and
+/// Delete apply to every line of the block. Escape, a plain click or cursor
+/// movement removes the block selection.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to add the controllers to
+pub fn add_block_selection_controllers(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let drag_gesture = GestureDrag::new();
+    drag_gesture.set_button(gdk::BUTTON_PRIMARY);
+    drag_gesture.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_drag = app_context.clone();
+    let text_view_drag = text_view.clone();
+    drag_gesture.connect_drag_begin(move |gesture, x, y| {
+        if gesture.current_event_state().contains(gdk::ModifierType::ALT_MASK) {
+            let position = position_at_coords(&text_view_drag, x, y);
+            set_block_selection(&app_context_drag, &text_view_drag, position, position);
+            gesture.set_state(gtk4::EventSequenceState::Claimed);
+        } else {
+            clear_block_selection(&app_context_drag, &text_view_drag);
+            gesture.set_state(gtk4::EventSequenceState::Denied);
+        }
+    });
+    let app_context_drag = app_context.clone();
+    let text_view_drag = text_view.clone();
+    drag_gesture.connect_drag_update(move |gesture, offset_x, offset_y| {
+        let buffer = text_view_drag.buffer();
+        let anchor = block_selections(&app_context_drag)
+            .borrow()
+            .get(&buffer)
+            .map(|block| block.anchor);
+        if let (Some(anchor), Some((start_x, start_y))) = (anchor, gesture.start_point()) {
+            let head = position_at_coords(&text_view_drag, start_x + offset_x, start_y + offset_y);
+            set_block_selection(&app_context_drag, &text_view_drag, anchor, head);
+        }
+    });
+    text_view.add_controller(drag_gesture);
+
+    let key_controller = EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_key = app_context.clone();
+    let text_view_key = text_view.clone();
+    key_controller.connect_key_pressed(move |_, keyval, _, state| {
+        let buffer = text_view_key.buffer();
+        let Some(is_empty) = block_selections(&app_context_key)
+            .borrow()
+            .get(&buffer)
+            .map(|block| block.columns().0 == block.columns().1)
+        else {
+            return glib::Propagation::Proceed;
+        };
+
+        let shortcut_modifiers = gdk::ModifierType::CONTROL_MASK
+            | gdk::ModifierType::ALT_MASK
+            | gdk::ModifierType::SUPER_MASK;
+        if state.intersects(shortcut_modifiers) {
+            return glib::Propagation::Proceed;
+        }
+
+        match keyval {
+            // Modifiers pressed on their own come before the character they change
+            gdk::Key::Shift_L
+            | gdk::Key::Shift_R
+            | gdk::Key::Control_L
+            | gdk::Key::Control_R
+            | gdk::Key::Alt_L
+            | gdk::Key::Alt_R
+            | gdk::Key::Meta_L
+            | gdk::Key::Meta_R
+            | gdk::Key::Super_L
+            | gdk::Key::Super_R
+            | gdk::Key::ISO_Level3_Shift
+            | gdk::Key::Caps_Lock => return glib::Propagation::Proceed,
+            gdk::Key::Escape => clear_block_selection(&app_context_key, &text_view_key),
+            gdk::Key::BackSpace | gdk::Key::Delete | gdk::Key::KP_Delete => {
+                let forward = keyval != gdk::Key::BackSpace;
+                if is_empty {
+                    delete_block_column(&app_context_key, &text_view_key, forward);
+                } else {
+                    replace_block_selection(&app_context_key, &text_view_key, &[String::new()]);
+                }
+            }
+            _ => match keyval.to_unicode().filter(|c| !c.is_control()) {
+                Some(c) => replace_block_selection(&app_context_key, &text_view_key, &[c.to_string()]),
+                None => {
+                    // Any other key, such as cursor movement, ends the block selection
+                    clear_block_selection(&app_context_key, &text_view_key);
+                    return glib::Propagation::Proceed;
+                }
+            },
+        }
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the visual width of a line
+    fn width(text: &str, tab_width: i32) -> i32 {
+        text.chars().fold(0, |column, c| next_column(c, column, tab_width))
+    }
+
+    #[test]
+    fn tabs_reach_the_next_tab_stop() {
+        assert_eq!(width("\tx", 4), 5);
+        assert_eq!(width("ab\tx", 4), 5);
+        assert_eq!(width("abcd\tx", 4), 9);
+        assert_eq!(width("\t\t", 8), 16);
+    }
+
+    #[test]
+    fn other_characters_take_one_column() {
+        assert_eq!(width("é日本🦀", 4), 4);
+        assert_eq!(width("", 4), 0);
+    }
+
+    #[test]
+    fn zero_tab_width_is_treated_as_one() {
+        assert_eq!(width("\t\t", 0), 2);
+    }
+}
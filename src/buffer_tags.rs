@@ -6,8 +6,10 @@
 use gtk4::prelude::*;
 use gtk4::{TextBuffer, TextTag};
 
+use crate::problems::Severity;
+
 /// Sets up the standard tags for a text buffer
-/// This includes document highlight and bracket match tags
+/// This includes document highlight, bracket match, multi-cursor selection, folding, diagnostic and line flash tags
 pub fn setup_buffer_tags(buffer: &TextBuffer) {
     let tag_table = buffer.tag_table();
     
@@ -21,4 +23,31 @@ pub fn setup_buffer_tags(buffer: &TextBuffer) {
     bracket_match_tag.set_weight(700);
     bracket_match_tag.set_scale(1.3);
     tag_table.add(&bracket_match_tag);
+
+    // Add multi_cursor_selection tag for the selections of secondary carets
+    let multi_cursor_selection_tag = TextTag::new(Some("multi_cursor_selection"));
+    multi_cursor_selection_tag.set_background_rgba(Some(&gtk4::gdk::RGBA::new(0.4, 0.6, 1.0, 0.35)));
+    tag_table.add(&multi_cursor_selection_tag);
+
+    // Add folded tag, hiding the lines of folded regions
+    let folded_tag = TextTag::new(Some("folded"));
+    folded_tag.set_invisible(true);
+    tag_table.add(&folded_tag);
+
+    // Add diagnostic tags, underlining diagnostics with a wavy line coloured by severity
+    for (name, severity) in [
+        ("diagnostic_error", Severity::Error),
+        ("diagnostic_warning", Severity::Warning),
+        ("diagnostic_info", Severity::Info),
+    ] {
+        let diagnostic_tag = TextTag::new(Some(name));
+        diagnostic_tag.set_underline(gtk4::pango::Underline::Error);
+        diagnostic_tag.set_underline_rgba(Some(&crate::diagnostics::severity_color(severity)));
+        tag_table.add(&diagnostic_tag);
+    }
+
+    // Add line_flash tag, briefly highlighting the line reached by Go to line
+    let line_flash_tag = TextTag::new(Some("line_flash"));
+    line_flash_tag.set_paragraph_background_rgba(Some(&gtk4::gdk::RGBA::new(1.0, 0.85, 0.3, 0.45)));
+    tag_table.add(&line_flash_tag);
 }
\ No newline at end of file
@@ -1,10 +1,53 @@
 //! Module for tracking buffer changes for incremental highlighting
 //!
 //! This module provides functionality to track which lines have changed in a text buffer
-//! to enable efficient incremental syntax highlighting.
+//! to enable efficient incremental syntax highlighting and completion indexing.
+//! It also records the edits of buffers synchronized with a language server.
 
 use gtk4::{TextIter};
 
+/// Number of line splices kept for the completion index before it is rebuilt instead
+const MAX_LINE_SPLICES: usize = 10_000;
+
+/// Lines of a buffer replaced by an edit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LineSplice {
+    /// First replaced line
+    pub start: i32,
+    /// Number of lines replaced
+    pub removed: i32,
+    /// Number of lines replacing them
+    pub inserted: i32,
+}
+
+/// An edit of a buffer, in the coordinates used by the Language Server Protocol
+pub struct TextChange {
+    /// Start of the replaced range, as a line and a UTF-16 column
+    pub start: (u32, u32),
+    /// End of the replaced range, as a line and a UTF-16 column
+    pub end: (u32, u32),
+    /// Text replacing the range
+    pub text: String,
+}
+
+/// Counts the line terminators of a text as GTK does: `\n`, `\r\n`, `\r` and
+/// the paragraph separator
+fn count_line_breaks(text: &str) -> i32 {
+    let mut count = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                chars.next_if_eq(&'\n');
+                count += 1;
+            }
+            '\n' | '\u{2029}' => count += 1,
+            _ => {}
+        }
+    }
+    count
+}
+
 /// Tracks changes in a text buffer for incremental highlighting
 pub struct ChangeTracker {
     /// Set of lines that have been modified
@@ -13,6 +56,14 @@ pub struct ChangeTracker {
     pub last_inserted_text: String,
     /// The position where the last insertion occurred
     pub last_insert_position: Option<(i32, i32)>, // (line, offset)
+    /// Lines replaced since the completion index was last updated, in order
+    completion_splices: Vec<LineSplice>,
+    /// Whether more edits happened than `MAX_LINE_SPLICES` since then
+    completion_splices_overflowed: bool,
+    /// Whether edits are recorded for a language server
+    pub record_text_changes: bool,
+    /// Edits not yet sent to the language server, in order
+    pub text_changes: Vec<TextChange>,
 }
 
 impl ChangeTracker {
@@ -22,6 +73,10 @@ impl ChangeTracker {
             changed_lines: std::collections::HashSet::new(),
             last_inserted_text: String::new(),
             last_insert_position: None,
+            completion_splices: Vec::new(),
+            completion_splices_overflowed: false,
+            record_text_changes: false,
+            text_changes: Vec::new(),
         }
     }
 
@@ -34,9 +89,19 @@ impl ChangeTracker {
         for line in start_line..=end_line {
             self.changed_lines.insert(line);
         }
+        self.push_completion_splice(LineSplice {
+            start: start_line,
+            removed: 1,
+            inserted: 1 + count_line_breaks(text),
+        });
         
         self.last_inserted_text = text.to_string();
         self.last_insert_position = Some((start_line, start_iter.line_offset()));
+
+        if self.record_text_changes {
+            let position = crate::lsp::position_at_iter(start_iter);
+            self.text_changes.push(TextChange { start: position, end: position, text: text.to_string() });
+        }
     }
 
     /// Records a deletion in the buffer
@@ -48,6 +113,19 @@ impl ChangeTracker {
         for line in start_line..=end_line {
             self.changed_lines.insert(line);
         }
+        self.push_completion_splice(LineSplice {
+            start: start_line,
+            removed: end_line - start_line + 1,
+            inserted: 1,
+        });
+
+        if self.record_text_changes {
+            self.text_changes.push(TextChange {
+                start: crate::lsp::position_at_iter(start_iter),
+                end: crate::lsp::position_at_iter(end_iter),
+                text: String::new(),
+            });
+        }
     }
 
     /// Gets the set of changed lines and clears the tracker
@@ -55,8 +133,64 @@ impl ChangeTracker {
         std::mem::take(&mut self.changed_lines)
     }
 
+    /// Records the lines an edit replaces for the completion index
+    fn push_completion_splice(&mut self, splice: LineSplice) {
+        if self.completion_splices_overflowed {
+            return;
+        }
+        if self.completion_splices.len() == MAX_LINE_SPLICES {
+            self.completion_splices = Vec::new();
+            self.completion_splices_overflowed = true;
+            return;
+        }
+        self.completion_splices.push(splice);
+    }
+
+    /// Gets the lines replaced since the last completion index update and clears them
+    ///
+    /// # Returns
+    ///
+    /// The replaced lines in order, or `None` if there were too many edits to
+    /// keep track of, in which case the index must be rebuilt
+    pub fn take_completion_splices(&mut self) -> Option<Vec<LineSplice>> {
+        let splices = std::mem::take(&mut self.completion_splices);
+        (!std::mem::replace(&mut self.completion_splices_overflowed, false)).then_some(splices)
+    }
+
+    /// Gets the edits not yet sent to the language server and clears them
+    pub fn take_text_changes(&mut self) -> Vec<TextChange> {
+        std::mem::take(&mut self.text_changes)
+    }
+
     /// Checks if there are any pending changes
     pub fn has_changes(&self) -> bool {
         !self.changed_lines.is_empty()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_breaks_are_counted_like_gtk() {
+        assert_eq!(count_line_breaks("no break"), 0);
+        assert_eq!(count_line_breaks("a\nb\r\nc\rd\u{2029}"), 4);
+        assert_eq!(count_line_breaks("\r\n\n"), 2);
+        assert_eq!(count_line_breaks("日本😀\n"), 1);
+    }
+
+    #[test]
+    fn too_many_splices_ask_for_a_rebuild() {
+        let mut tracker = ChangeTracker::new();
+        for line in 0..=MAX_LINE_SPLICES as i32 {
+            tracker.push_completion_splice(LineSplice { start: line, removed: 1, inserted: 1 });
+        }
+        assert_eq!(tracker.take_completion_splices(), None);
+        // Edits after the rebuild are followed again
+        tracker.push_completion_splice(LineSplice { start: 0, removed: 1, inserted: 2 });
+        assert_eq!(
+            tracker.take_completion_splices(),
+            Some(vec![LineSplice { start: 0, removed: 1, inserted: 2 }])
+        );
+    }
+}
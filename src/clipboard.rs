@@ -5,22 +5,41 @@
 
 use gtk4::prelude::*;
 use gtk4::{TextBuffer, TextView};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::AppContext;
 
 /// Copies the selected text from a buffer to the clipboard
-pub fn copy_selected_text(buffer: &TextBuffer) {
-    if let Some((start, end)) = buffer.selection_bounds() {
-        let selected_text = buffer.text(&start, &end, false).to_string();
-        if let Some(display) = gtk4::gdk::Display::default() {
-            let clipboard = display.clipboard();
-            clipboard.set_text(&selected_text);
-        }
+///
+/// A block selection is copied as newline-separated rows.
+pub fn copy_selected_text(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let selected_text = match crate::block_selection::block_selection_text(app_context, buffer) {
+        Some(text) => text,
+        None => match buffer.selection_bounds() {
+            Some((start, end)) => buffer.text(&start, &end, true).to_string(),
+            None => return,
+        },
+    };
+    if let Some(display) = gtk4::gdk::Display::default() {
+        let clipboard = display.clipboard();
+        clipboard.set_text(&selected_text);
     }
 }
 
 /// Cuts the selected text from a buffer and copies it to the clipboard
-pub fn cut_selected_text(buffer: &TextBuffer) {
+///
+/// A block selection is copied as newline-separated rows and then removed
+/// from every line it spans.
+pub fn cut_selected_text(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    if crate::block_selection::has_block_selection(app_context, &buffer) {
+        copy_selected_text(app_context, &buffer);
+        crate::block_selection::replace_block_selection(app_context, text_view, &[String::new()]);
+        return;
+    }
     if let Some((start, end)) = buffer.selection_bounds() {
-        let selected_text = buffer.text(&start, &end, false).to_string();
+        let selected_text = buffer.text(&start, &end, true).to_string();
         if let Some(display) = gtk4::gdk::Display::default() {
             let clipboard = display.clipboard();
             clipboard.set_text(&selected_text);
@@ -28,19 +47,39 @@ pub fn cut_selected_text(buffer: &TextBuffer) {
         // Delete the selected text
         let mut start_clone = start.clone();
         let mut end_clone = end.clone();
+        buffer.begin_user_action();
         buffer.delete(&mut start_clone, &mut end_clone);
+        buffer.end_user_action();
     }
 }
 
 /// Pastes text from the clipboard into a text view at the cursor position
-pub fn paste_text_async(text_view: &TextView) {
+///
+/// When the buffer has secondary carets the text is pasted at every caret;
+/// if the clipboard holds one line per caret, each caret receives its own line.
+/// With a block selection, the clipboard lines are pasted as a block starting
+/// at the block's top-left corner.
+pub fn paste_text_async(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
     let buffer = text_view.buffer();
+    let app_context = app_context.clone();
+    let text_view = text_view.clone();
     if let Some(display) = gtk4::gdk::Display::default() {
         let clipboard = display.clipboard();
         clipboard.read_text_async(None::<&gio::Cancellable>, move |res| {
             if let Ok(Some(text)) = res {
-                let mut iter = buffer.iter_at_mark(&buffer.get_insert());
-                buffer.insert(&mut iter, &text);
+                if crate::block_selection::has_block_selection(&app_context, &buffer) {
+                    let rows: Vec<String> = text.lines().map(|line| line.to_string()).collect();
+                    crate::block_selection::replace_block_selection(&app_context, &text_view, &rows);
+                } else if crate::multi_cursor::has_secondary_carets(&app_context, &buffer) {
+                    crate::multi_cursor::paste_at_carets(&app_context, &text_view, &text);
+                } else {
+                    // Replace the selection, if any, as a single undoable step
+                    buffer.begin_user_action();
+                    buffer.delete_selection(true, text_view.is_editable());
+                    let mut iter = buffer.iter_at_mark(&buffer.get_insert());
+                    buffer.insert(&mut iter, &text);
+                    buffer.end_user_action();
+                }
             }
         });
     }
@@ -0,0 +1,307 @@
+//! Module for the command line
+//!
+//! e4code runs as a single instance: launching it again forwards the command
+//! line to the running instance, which opens the files in its window or
+//! selects their tabs. A file may be followed by a position, as in
+//! `src/main.rs:120:5`, or preceded by `+line`, as editors are commonly called.
+//! `--new-window` starts a separate instance with its own window, and `--wait`
+//! returns only once the files are closed, so that e4code can be used as
+//! `$EDITOR` and `GIT_EDITOR`.
+
+use gtk4::prelude::*;
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::AppContext;
+
+/// Usage printed by `--help`
+pub const USAGE: &str = "\
+Usage: e4code [OPTION]... [+LINE] [FILE[:LINE[:COLUMN]] | DIRECTORY]...
+
+Options:
+  --new-window  open the files in a new window instead of the running one
+  -w, --wait    return only once the files are closed
+  -h, --help    print this help
+";
+
+/// A file or directory to open
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileArgument {
+    /// The file or directory, made absolute
+    pub path: PathBuf,
+    /// Line to move the cursor to, counted from 0
+    pub line: Option<i32>,
+    /// Column to move the cursor to in characters, counted from 0
+    pub column: Option<i32>,
+}
+
+/// A parsed command line
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Arguments {
+    /// Files and directories to open, in order
+    pub files: Vec<FileArgument>,
+    /// Whether the files open in a new instance with its own window
+    pub new_window: bool,
+    /// Whether the command returns only once the files are closed
+    pub wait: bool,
+    /// Whether the usage was asked for
+    pub help: bool,
+}
+
+/// Reads a file argument, with the position that may follow its path
+fn parse_file(arg: &OsString, cwd: &Path, line: Option<i32>) -> FileArgument {
+    let path = cwd.join(arg);
+    // A file whose name ends like a position is opened as it is
+    if !path.exists()
+        && let Some(target) = arg.to_str().and_then(|arg| crate::go_to_line::parse_target(arg, 0, 0))
+        && let Some(target_path) = target.path
+    {
+        return FileArgument {
+            path: cwd.join(target_path),
+            line: Some(target.line),
+            column: target.column,
+        };
+    }
+    FileArgument {
+        path,
+        line,
+        column: None,
+    }
+}
+
+/// Parses a command line
+///
+/// # Arguments
+///
+/// * `args` - The arguments, without the program name
+/// * `cwd` - Directory relative paths are resolved against
+///
+/// # Returns
+///
+/// The arguments, or an error message for an unknown option or a `+LINE`
+/// without a file after it
+pub fn parse_arguments(args: &[OsString], cwd: &Path) -> Result<Arguments, String> {
+    let mut arguments = Arguments::default();
+    let mut options_ended = false;
+    // The line and the argument giving it
+    let mut next_line: Option<(i32, &str)> = None;
+    for arg in args {
+        if !options_ended && let Some(option) = arg.to_str().filter(|arg| arg.starts_with('-')) {
+            match option {
+                "--" => options_ended = true,
+                "--new-window" => arguments.new_window = true,
+                "--wait" | "-w" => arguments.wait = true,
+                "--help" | "-h" => arguments.help = true,
+                _ => return Err(format!("Unknown option {}\n\n{}", option, USAGE)),
+            }
+            continue;
+        }
+        // `+LINE` applies to the file that follows it
+        if let Some((arg, line)) = arg
+            .to_str()
+            .and_then(|arg| Some((arg, arg.strip_prefix('+')?.parse::<i32>().ok()?)))
+        {
+            next_line = Some(((line - 1).max(0), arg));
+            continue;
+        }
+        arguments.files.push(parse_file(arg, cwd, next_line.take().map(|(line, _)| line)));
+    }
+    if let Some((_, arg)) = next_line {
+        return Err(format!("No file after {}\n\n{}", arg, USAGE));
+    }
+    Ok(arguments)
+}
+
+/// Checks the command line of this process before the application starts
+///
+/// Errors and the usage are printed here, since the command line may be
+/// handled by the running instance, which can't print to this terminal.
+///
+/// # Arguments
+///
+/// * `args` - The arguments, without the program name
+///
+/// # Returns
+///
+/// The arguments, or the exit code if the process has nothing more to do
+pub fn check_arguments(args: &[OsString]) -> Result<Arguments, glib::ExitCode> {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let arguments = match parse_arguments(args, &cwd) {
+        Ok(arguments) => arguments,
+        Err(message) => {
+            eprint!("{}", message);
+            return Err(glib::ExitCode::FAILURE);
+        }
+    };
+    if arguments.help {
+        print!("{}", USAGE);
+        return Err(glib::ExitCode::SUCCESS);
+    }
+    for file in arguments.files.iter().filter(|file| !file.path.exists()) {
+        eprintln!("{}: No such file or directory", file.path.display());
+    }
+    Ok(arguments)
+}
+
+/// Opens the files and directories of a command line
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `files` - The files and directories
+///
+/// # Returns
+///
+/// The files opened; arguments that are neither files nor directories are skipped
+pub fn open_files(app_context: &Rc<RefCell<AppContext>>, files: &[FileArgument]) -> Vec<PathBuf> {
+    let mut opened = Vec::new();
+    for file in files {
+        if file.path.is_dir() {
+            crate::actions::open_directory_in_tree(&file.path, app_context.clone());
+        } else if !file.path.is_file() {
+            continue;
+        } else if let Some(line) = file.line {
+            crate::tab_manager::open_file_at_position(&file.path, line, file.column.unwrap_or(0), app_context);
+            let text_view = crate::ui::helpers::get_current_text_view(&app_context.borrow().notebook);
+            if let Some(text_view) = text_view {
+                crate::go_to_line::flash_line(&text_view, line);
+            }
+            opened.push(file.path.clone());
+        } else {
+            crate::tab_manager::open_file_in_new_tab(&file.path, app_context);
+            opened.push(file.path.clone());
+        }
+    }
+    opened
+}
+
+/// Returns whether a file is open in a tab
+fn is_open(app_context: &Rc<RefCell<AppContext>>, path: &Path) -> bool {
+    let context = app_context.borrow();
+    let buffer_paths = context.buffer_paths.borrow();
+    (0..context.notebook.n_pages())
+        .filter_map(|index| context.notebook.nth_page(Some(index)))
+        .filter_map(|page| crate::ui::helpers::get_text_view_from_page(&page))
+        .any(|text_view| buffer_paths.get(&text_view.buffer()).is_some_and(|open| open == path))
+}
+
+/// Keeps a command line waiting until files are closed
+///
+/// The process that forwarded the command line returns once the command line
+/// object is released, which happens when the last of the files is closed.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `files` - The files to wait for
+/// * `command_line` - The command line to release
+pub fn wait_for_close(app_context: &Rc<RefCell<AppContext>>, files: Vec<PathBuf>, command_line: gio::ApplicationCommandLine) {
+    if !files.iter().any(|file| is_open(app_context, file)) {
+        return;
+    }
+    let notebook = app_context.borrow().notebook.clone();
+    let handler: Rc<RefCell<Option<glib::SignalHandlerId>>> = Rc::new(RefCell::new(None));
+    let command_line = RefCell::new(Some(command_line));
+    let app_context_clone = app_context.clone();
+    let handler_clone = handler.clone();
+    let id = notebook.connect_page_removed(move |notebook, _, _| {
+        if files.iter().any(|file| is_open(&app_context_clone, file)) {
+            return;
+        }
+        command_line.borrow_mut().take();
+        if let Some(id) = handler_clone.borrow_mut().take() {
+            notebook.disconnect(id);
+        }
+    });
+    *handler.borrow_mut() = Some(id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn parse(args: &[&str], cwd: &Path) -> Result<Arguments, String> {
+        let args: Vec<OsString> = args.iter().map(OsString::from).collect();
+        parse_arguments(&args, cwd)
+    }
+
+    fn file(path: PathBuf, line: Option<i32>, column: Option<i32>) -> FileArgument {
+        FileArgument { path, line, column }
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let cwd = Path::new("/work");
+        let arguments = parse(&["--new-window", "-w", "a.rs"], cwd).expect("valid arguments");
+        assert!(arguments.new_window && arguments.wait && !arguments.help);
+        assert_eq!(arguments.files, vec![file(cwd.join("a.rs"), None, None)]);
+        assert!(parse(&["--wait", "--help"], cwd).expect("valid arguments").help);
+        assert!(parse(&["-h"], cwd).expect("valid arguments").help);
+        assert_eq!(parse(&[], cwd), Ok(Arguments::default()));
+    }
+
+    #[test]
+    fn unknown_options_are_errors() {
+        let cwd = Path::new("/work");
+        for option in ["--bogus", "-x", "-", "--wait=yes"] {
+            let message = parse(&["a.rs", option], cwd).expect_err("an unknown option");
+            assert!(message.starts_with(&format!("Unknown option {}\n", option)), "{}", message);
+            assert!(message.ends_with(USAGE));
+        }
+    }
+
+    #[test]
+    fn arguments_after_double_dash_are_files() {
+        let cwd = Path::new("/work");
+        let arguments = parse(&["--", "--wait", "-h"], cwd).expect("valid arguments");
+        assert!(!arguments.wait && !arguments.help);
+        assert_eq!(
+            arguments.files,
+            vec![file(cwd.join("--wait"), None, None), file(cwd.join("-h"), None, None)]
+        );
+    }
+
+    #[test]
+    fn positions_are_parsed() {
+        let cwd = Path::new("/work");
+        let arguments = parse(&["+12", "a.rs", "b.rs", "src/c.rs:120:5", "/abs/d.rs:7", "+0", "e.rs"], cwd)
+            .expect("valid arguments");
+        assert_eq!(
+            arguments.files,
+            vec![
+                // `+LINE` applies to the next file only
+                file(cwd.join("a.rs"), Some(11), None),
+                file(cwd.join("b.rs"), None, None),
+                file(cwd.join("src/c.rs"), Some(119), Some(4)),
+                file(PathBuf::from("/abs/d.rs"), Some(6), None),
+                file(cwd.join("e.rs"), Some(0), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn existing_files_named_like_positions_are_opened_as_they_are() {
+        let cwd = temp_dir("command-line-positions");
+        std::fs::write(cwd.join("notes:3"), "").expect("the file can be written");
+        let arguments = parse(&["notes:3", "notes:4"], &cwd).expect("valid arguments");
+        assert_eq!(
+            arguments.files,
+            vec![file(cwd.join("notes:3"), None, None), file(cwd.join("notes"), Some(3), None)]
+        );
+        std::fs::remove_dir_all(&cwd).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn a_line_without_a_file_is_an_error() {
+        let cwd = Path::new("/work");
+        let message = parse(&["a.rs", "+12"], cwd).expect_err("a line without a file");
+        assert!(message.starts_with("No file after +12\n"), "{}", message);
+        assert!(parse(&["+12", "--wait"], cwd).is_err());
+        // A `+` that isn't followed by a number is a file
+        let arguments = parse(&["+notes"], cwd).expect("valid arguments");
+        assert_eq!(arguments.files, vec![file(cwd.join("+notes"), None, None)]);
+    }
+}
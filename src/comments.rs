@@ -0,0 +1,311 @@
+//! Module for toggling comments
+//!
+//! This module comments and uncomments the current line or the selected lines,
+//! using line comments or block comments. The comment tokens come from the
+//! language settings if set there, otherwise from the metadata of the buffer's
+//! syntect syntax, falling back to C-style tokens.
+
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextIter};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::AppContext;
+use crate::line_operations::{line_text, selected_lines};
+
+/// Line comment token used when neither the settings nor the syntax provide one
+const FALLBACK_LINE_COMMENT: &str = "//";
+/// Block comment tokens used when neither the settings nor the syntax provide them
+const FALLBACK_BLOCK_COMMENT: (&str, &str) = ("/*", "*/");
+
+/// Comment tokens of a language
+struct CommentTokens {
+    line: Option<String>,
+    block: Option<(String, String)>,
+}
+
+/// Returns the comment tokens of the language of a buffer
+fn comment_tokens(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> CommentTokens {
+    let language = crate::syntax_highlighting::language_settings_for_buffer(app_context, buffer);
+    let syntax_name = crate::syntax_highlighting::syntax_name_for_buffer(app_context, buffer);
+
+    let context = app_context.borrow();
+    let syntax_context = context.syntax_context.borrow();
+    let ps = &syntax_context.ps;
+    let (metadata_line, metadata_block) = match ps.find_syntax_by_name(&syntax_name) {
+        Some(syntax) => {
+            let metadata = ps.metadata().metadata_for_scope(&[syntax.scope]);
+            (
+                metadata.line_comment().map(|token| token.trim().to_string()),
+                metadata
+                    .block_comment()
+                    .map(|(start, end)| (start.trim().to_string(), end.trim().to_string())),
+            )
+        }
+        None => (None, None),
+    };
+
+    let line = language.line_comment.or(metadata_line);
+    let block = language.block_comment.or(metadata_block);
+    if line.is_none() && block.is_none() {
+        CommentTokens {
+            line: Some(FALLBACK_LINE_COMMENT.to_string()),
+            block: Some((FALLBACK_BLOCK_COMMENT.0.to_string(), FALLBACK_BLOCK_COMMENT.1.to_string())),
+        }
+    } else {
+        CommentTokens { line, block }
+    }
+}
+
+/// Returns the number of leading whitespace characters of a line
+fn indentation_width(text: &str) -> i32 {
+    text.chars().take_while(|c| *c == ' ' || *c == '\t').count() as i32
+}
+
+/// Returns an iterator at a character offset within a line
+fn iter_at_line_offset(buffer: &TextBuffer, line: i32, offset: i32) -> TextIter {
+    let mut iter = buffer.iter_at_line(line).unwrap_or_else(|| buffer.end_iter());
+    iter.set_line_offset(offset);
+    iter
+}
+
+/// Toggles line comments on the current line or the selected lines
+///
+/// If every non-blank line is commented, the comments are removed; otherwise
+/// every non-blank line is commented, with the tokens aligned to the minimum
+/// indentation of the lines. Languages without line comments use block
+/// comments instead. The toggle is a single undo step.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to edit
+pub fn toggle_line_comment(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    toggle_line_comment_with(buffer, &comment_tokens(app_context, buffer));
+}
+
+/// Toggles line comments with the given tokens, see `toggle_line_comment`
+fn toggle_line_comment_with(buffer: &TextBuffer, tokens: &CommentTokens) {
+    let Some(token) = tokens.line.as_ref() else {
+        if tokens.block.is_some() {
+            toggle_block_comment_with(buffer, tokens);
+        }
+        return;
+    };
+    let (start_line, end_line) = selected_lines(buffer);
+    let lines: Vec<(i32, String)> = (start_line..=end_line)
+        .map(|line| (line, line_text(buffer, line)))
+        .filter(|(_, text)| !text.trim().is_empty())
+        .collect();
+    if lines.is_empty() {
+        return;
+    }
+
+    let all_commented = lines
+        .iter()
+        .all(|(_, text)| text.trim_start().starts_with(token.as_str()));
+    let min_indent = lines
+        .iter()
+        .map(|(_, text)| indentation_width(text))
+        .min()
+        .unwrap_or(0);
+    let token_length = token.chars().count() as i32;
+
+    buffer.begin_user_action();
+    for (line, text) in &lines {
+        if all_commented {
+            let indent = indentation_width(text);
+            let after_token: String = text.chars().skip((indent + token_length) as usize).collect();
+            let length = token_length + i32::from(after_token.starts_with(' '));
+            let mut start = iter_at_line_offset(buffer, *line, indent);
+            let mut end = iter_at_line_offset(buffer, *line, indent + length);
+            buffer.delete(&mut start, &mut end);
+        } else {
+            let mut iter = iter_at_line_offset(buffer, *line, min_indent);
+            buffer.insert(&mut iter, &format!("{} ", token));
+        }
+    }
+    buffer.end_user_action();
+}
+
+/// Toggles a block comment around the selection or the current line
+///
+/// A selection within a single line is wrapped as it is. Otherwise the block
+/// spans the whole lines, starting at their minimum indentation. If the range
+/// is already enclosed in block comment tokens, they are removed instead.
+/// Languages without block comments use line comments instead. The toggle is a
+/// single undo step.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to edit
+pub fn toggle_block_comment(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    toggle_block_comment_with(buffer, &comment_tokens(app_context, buffer));
+}
+
+/// Toggles a block comment with the given tokens, see `toggle_block_comment`
+fn toggle_block_comment_with(buffer: &TextBuffer, tokens: &CommentTokens) {
+    let Some((open, close)) = tokens.block.as_ref() else {
+        if tokens.line.is_some() {
+            toggle_line_comment_with(buffer, tokens);
+        }
+        return;
+    };
+
+    let (start_offset, end_offset) = match buffer.selection_bounds() {
+        Some((start, end)) if start.line() == end.line() => (start.offset(), end.offset()),
+        _ => {
+            let (start_line, end_line) = selected_lines(buffer);
+            let min_indent = (start_line..=end_line)
+                .map(|line| line_text(buffer, line))
+                .filter(|text| !text.trim().is_empty())
+                .map(|text| indentation_width(&text))
+                .min()
+                .unwrap_or(0);
+            let start = iter_at_line_offset(buffer, start_line, min_indent);
+            let mut end = iter_at_line_offset(buffer, end_line, 0);
+            if !end.ends_line() {
+                end.forward_to_line_end();
+            }
+            (start.offset(), end.offset())
+        }
+    };
+
+    let text = buffer
+        .text(&buffer.iter_at_offset(start_offset), &buffer.iter_at_offset(end_offset), true)
+        .to_string();
+    let trimmed = text.trim_end();
+    let open_length = open.chars().count() as i32;
+    let close_length = close.chars().count() as i32;
+
+    buffer.begin_user_action();
+    if trimmed.starts_with(open.as_str()) && trimmed.ends_with(close.as_str()) && trimmed.len() >= open.len() + close.len() {
+        // Remove the closing token first, so that the start offset stays valid
+        let inner = &trimmed[open.len()..trimmed.len() - close.len()];
+        let space_before_close = i32::from(inner.ends_with(' ') && !inner.trim().is_empty());
+        let space_after_open = i32::from(inner.starts_with(' ') && !inner.trim().is_empty());
+        let close_end = start_offset + trimmed.chars().count() as i32;
+        let mut start = buffer.iter_at_offset(close_end - close_length - space_before_close);
+        let mut end = buffer.iter_at_offset(close_end);
+        buffer.delete(&mut start, &mut end);
+        let mut start = buffer.iter_at_offset(start_offset);
+        let mut end = buffer.iter_at_offset(start_offset + open_length + space_after_open);
+        buffer.delete(&mut start, &mut end);
+    } else {
+        buffer.insert(&mut buffer.iter_at_offset(end_offset), &format!(" {}", close));
+        buffer.insert(&mut buffer.iter_at_offset(start_offset), &format!("{} ", open));
+    }
+    buffer.end_user_action();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::run_with_gtk;
+
+    /// Returns C-style comment tokens, without line or block comments if asked
+    fn tokens(line: bool, block: bool) -> CommentTokens {
+        CommentTokens {
+            line: line.then(|| "//".to_string()),
+            block: block.then(|| ("/*".to_string(), "*/".to_string())),
+        }
+    }
+
+    /// Returns a buffer with a text and a selection between two (line, column) positions
+    fn buffer_with_selection(text: &str, start: (i32, i32), end: (i32, i32)) -> TextBuffer {
+        let buffer = TextBuffer::new(None);
+        buffer.set_text(text);
+        let start = buffer.iter_at_line_offset(start.0, start.1).unwrap();
+        let end = buffer.iter_at_line_offset(end.0, end.1).unwrap();
+        buffer.select_range(&end, &start);
+        buffer
+    }
+
+    /// Returns the whole text of a buffer
+    fn text(buffer: &TextBuffer) -> String {
+        buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string()
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn line_comments_align_to_the_least_indented_line_and_skip_blank_lines() {
+        run_with_gtk(|| {
+            let original = "fn a() {\n    x();\n\n        y();\n}\n";
+            let buffer = buffer_with_selection(original, (1, 0), (4, 0));
+            toggle_line_comment_with(&buffer, &tokens(true, true));
+            assert_eq!(text(&buffer), "fn a() {\n    // x();\n\n    //     y();\n}\n");
+
+            toggle_line_comment_with(&buffer, &tokens(true, true));
+            assert_eq!(text(&buffer), original);
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn partly_commented_lines_are_all_commented() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_selection("// a\nb\n", (0, 0), (1, 1));
+            toggle_line_comment_with(&buffer, &tokens(true, true));
+            assert_eq!(text(&buffer), "// // a\n// b\n");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn blank_line_is_left_alone() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_selection("a\n   \nb", (1, 1), (1, 1));
+            toggle_line_comment_with(&buffer, &tokens(true, true));
+            assert_eq!(text(&buffer), "a\n   \nb");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn line_toggle_without_line_comments_uses_a_block() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_selection("  x = 1\n", (0, 3), (0, 3));
+            toggle_line_comment_with(&buffer, &tokens(false, true));
+            assert_eq!(text(&buffer), "  /* x = 1 */\n");
+
+            toggle_line_comment_with(&buffer, &tokens(false, true));
+            assert_eq!(text(&buffer), "  x = 1\n");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn block_comment_wraps_a_selection_within_a_line() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_selection("let a = b + c;", (0, 8), (0, 13));
+            toggle_block_comment_with(&buffer, &tokens(true, true));
+            assert_eq!(text(&buffer), "let a = /* b + c */;");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn block_comment_spans_whole_indented_lines() {
+        run_with_gtk(|| {
+            let original = "    a\n      b\nc\n";
+            let buffer = buffer_with_selection(original, (0, 2), (2, 0));
+            toggle_block_comment_with(&buffer, &tokens(true, true));
+            assert_eq!(text(&buffer), "    /* a\n      b */\nc\n");
+
+            let buffer = buffer_with_selection("    /* a\n      b */\nc\n", (0, 0), (2, 0));
+            toggle_block_comment_with(&buffer, &tokens(true, true));
+            assert_eq!(text(&buffer), original);
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn block_toggle_without_block_comments_uses_line_comments() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_selection("a\nb\n", (0, 0), (1, 1));
+            toggle_block_comment_with(&buffer, &tokens(true, false));
+            assert_eq!(text(&buffer), "// a\n// b\n");
+        });
+    }
+}
@@ -0,0 +1,812 @@
+//! Module for word completion
+//!
+//! Without a language server, completion suggests the identifiers found in all
+//! open buffers together with the keywords of the buffer's syntax, ranked by
+//! their distance from the cursor and by how often they occur. Each buffer has
+//! a token index that is updated line by line from the changed lines recorded
+//! by its `ChangeTracker`. The popup opens automatically once a word reaches a
+//! configurable length, or on request.
+
+use gtk4::gdk;
+use gtk4::prelude::*;
+use gtk4::{
+    EventControllerFocus, EventControllerKey, GestureClick, Label, ListBox, Popover, ScrolledWindow,
+    TextBuffer, TextView,
+};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+use syntect::parsing::syntax_definition::Pattern;
+use syntect::parsing::{Scope, SyntaxSet};
+
+use crate::AppContext;
+use crate::change_tracker::LineSplice;
+use crate::line_operations::line_text;
+use crate::snippets::Snippet;
+
+/// Maximum number of suggestions shown in the popup
+const MAX_CANDIDATES: usize = 50;
+/// Number of lines around the cursor searched for nearby occurrences
+const PROXIMITY_WINDOW: i32 = 200;
+/// Score of a keyword that does not occur in any buffer
+const KEYWORD_SCORE: f64 = 0.5;
+/// Score of a snippet whose prefix matches, ranking snippets first
+const SNIPPET_SCORE: f64 = 3.0;
+/// Maximum height of the popup in pixels
+const POPUP_MAX_HEIGHT: i32 = 240;
+
+/// Tokens of a buffer, kept up to date line by line
+#[derive(Default, Debug, PartialEq)]
+struct TokenIndex {
+    /// Tokens of each line
+    line_tokens: Vec<Vec<String>>,
+    /// Number of occurrences of each token in the buffer
+    counts: HashMap<String, usize>,
+}
+
+impl TokenIndex {
+    /// Builds the index of a whole buffer
+    fn build(buffer: &TextBuffer) -> Self {
+        Self::build_text(&buffer.text(&buffer.start_iter(), &buffer.end_iter(), true))
+    }
+
+    /// Builds the index of a text
+    fn build_text(text: &str) -> Self {
+        let mut index = Self::default();
+        for line in text.split('\n') {
+            let tokens = tokenize(line);
+            index.count_tokens(&tokens, true);
+            index.line_tokens.push(tokens);
+        }
+        index
+    }
+
+    /// Adds or removes the occurrences of some tokens from the counts
+    fn count_tokens(&mut self, tokens: &[String], add: bool) {
+        for token in tokens {
+            if add {
+                *self.counts.entry(token.clone()).or_insert(0) += 1;
+            } else if let Some(count) = self.counts.get_mut(token) {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(token);
+                }
+            }
+        }
+    }
+
+    /// Replaces the lines changed by some edits, then tokenizes the new lines
+    ///
+    /// # Arguments
+    ///
+    /// * `splices` - The lines replaced by the edits, in order
+    /// * `line_text` - Returns the text of a line after the edits
+    /// * `line_count` - Number of lines after the edits
+    ///
+    /// # Returns
+    ///
+    /// Whether the edits matched the indexed lines; if not, the index must be rebuilt
+    fn splice(&mut self, splices: &[LineSplice], line_text: impl Fn(i32) -> String, line_count: usize) -> bool {
+        // Lines still to be tokenized are `None`
+        let mut lines: Vec<Option<Vec<String>>> = std::mem::take(&mut self.line_tokens).into_iter().map(Some).collect();
+        for splice in splices {
+            let start = splice.start.max(0) as usize;
+            let end = start + splice.removed.max(0) as usize;
+            if end > lines.len() {
+                return false;
+            }
+            let inserted = std::iter::repeat_n(None, splice.inserted.max(0) as usize);
+            for tokens in lines.splice(start..end, inserted).flatten() {
+                self.count_tokens(&tokens, false);
+            }
+        }
+        if lines.len() != line_count {
+            return false;
+        }
+
+        let mut line_tokens = Vec::with_capacity(lines.len());
+        for (line, tokens) in lines.into_iter().enumerate() {
+            let tokens = tokens.unwrap_or_else(|| {
+                let tokens = tokenize(&line_text(line as i32));
+                self.count_tokens(&tokens, true);
+                tokens
+            });
+            line_tokens.push(tokens);
+        }
+        self.line_tokens = line_tokens;
+        true
+    }
+}
+
+/// Why the completion popup is being updated
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    /// A word character was typed
+    Typed,
+    /// The text or the cursor changed otherwise; only updates a visible popup
+    Refresh,
+    /// The user asked for completion
+    Manual,
+}
+
+/// What accepting a suggestion does
+#[derive(Clone)]
+enum CandidateKind {
+    /// Replaces the word before the cursor
+    Word,
+    /// Replaces the word before the cursor with the expanded snippet
+    Snippet(Snippet),
+    /// Replaces the selected placeholder of a snippet
+    Choice,
+}
+
+/// A suggestion of the popup
+#[derive(Clone)]
+struct Candidate {
+    text: String,
+    label: String,
+    kind: CandidateKind,
+}
+
+/// The visible completion popup
+struct CompletionPopup {
+    text_view: TextView,
+    popover: Popover,
+    list_box: ListBox,
+    scrolled_window: ScrolledWindow,
+    candidates: Vec<Candidate>,
+    /// Whether the popup was opened on request, so that it stays open for empty words
+    manual: bool,
+}
+
+/// Completion state of the application
+#[derive(Default)]
+pub struct Completion {
+    /// Token index of each buffer
+    indexes: HashMap<TextBuffer, TokenIndex>,
+    /// Keywords of each syntax, keyed by syntax name, extracted on first use
+    keywords: Option<HashMap<String, Vec<String>>>,
+    popup: Option<CompletionPopup>,
+}
+
+/// Shared completion state
+pub type CompletionState = Rc<RefCell<Completion>>;
+
+/// Checks whether a character can be part of an identifier
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Splits a line into identifiers of at least two characters
+fn tokenize(line: &str) -> Vec<String> {
+    line.split(|c: char| !is_word_char(c))
+        .filter(|word| word.chars().count() >= 2 && !word.starts_with(|c: char| c.is_numeric()))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Extracts the literal words listed as alternatives in a regular expression
+///
+/// Keyword patterns are usually written as `\b(?:if|else|while)\b`; a word
+/// counts when it is delimited by alternation bars, groups or word boundaries.
+fn regex_words(regex: &str) -> Vec<String> {
+    let cleaned = regex
+        .replace("\\b", "|")
+        .replace("(?:", "(")
+        .replace("(?x)", "")
+        .replace("(?i)", "");
+    let is_delimiter = |c: Option<char>| c.is_none_or(|c| c == '|' || c == '(' || c == ')' || c.is_whitespace());
+    let chars: Vec<char> = cleaned.chars().collect();
+    let mut words = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        if !is_word_char(chars[start]) {
+            start += 1;
+            continue;
+        }
+        let mut end = start;
+        while end < chars.len() && is_word_char(chars[end]) {
+            end += 1;
+        }
+        let previous = start.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(end).copied();
+        if is_delimiter(previous) && is_delimiter(next) && end - start >= 2 && !chars[start].is_numeric() {
+            words.push(chars[start..end].iter().collect());
+        }
+        start = end;
+    }
+    words
+}
+
+/// Extracts the keywords of every syntax of a syntax set
+///
+/// Keywords are the literal words matched by patterns scoped as keywords,
+/// storage modifiers and types, or language constants.
+fn syntax_keywords(ps: &SyntaxSet) -> HashMap<String, Vec<String>> {
+    let keyword_scopes = ["keyword", "storage", "constant.language"].map(|scope| Scope::new(scope).expect("valid scope"));
+    let builder = ps.clone().into_builder();
+    builder
+        .syntaxes()
+        .iter()
+        .map(|syntax| {
+            let mut keywords = BTreeSet::new();
+            for context in syntax.contexts.values() {
+                for pattern in &context.patterns {
+                    if let Pattern::Match(match_pattern) = pattern
+                        && match_pattern
+                            .scope
+                            .iter()
+                            .any(|scope| keyword_scopes.iter().any(|prefix| prefix.is_prefix_of(*scope)))
+                    {
+                        keywords.extend(regex_words(match_pattern.regex.regex_str()));
+                    }
+                }
+            }
+            (syntax.name.clone(), keywords.into_iter().collect())
+        })
+        .collect()
+}
+
+/// Returns the buffers of all open tabs
+fn open_buffers(app_context: &Rc<RefCell<AppContext>>) -> Vec<TextBuffer> {
+    let notebook = app_context.borrow().notebook.clone();
+    (0..notebook.n_pages())
+        .filter_map(|i| notebook.nth_page(Some(i)))
+        .filter_map(|page| crate::ui::helpers::get_text_view_from_page(&page))
+        .map(|text_view| text_view.buffer())
+        .collect()
+}
+
+/// Brings the token index of a buffer up to date
+///
+/// The lines replaced by the edits recorded by the change tracker are spliced
+/// into the index and only they are tokenized. The whole buffer is indexed
+/// the first time, or if the edits couldn't be followed.
+fn update_index(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let context = app_context.borrow();
+    let splices = context
+        .change_trackers
+        .borrow_mut()
+        .get_mut(buffer)
+        .map_or(Some(Vec::new()), |tracker| tracker.take_completion_splices());
+    let mut completion = context.completion.borrow_mut();
+    let line_count = buffer.line_count() as usize;
+    let updated = match (completion.indexes.get_mut(buffer), splices) {
+        (Some(index), Some(splices)) => index.splice(&splices, |line| line_text(buffer, line), line_count),
+        _ => false,
+    };
+    if !updated {
+        completion.indexes.insert(buffer.clone(), TokenIndex::build(buffer));
+    }
+}
+
+/// Returns the part of the word before the cursor and the whole word around it
+fn word_at_cursor(buffer: &TextBuffer) -> (String, String) {
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut start = cursor;
+    while start.backward_char() {
+        if !is_word_char(start.char()) {
+            start.forward_char();
+            break;
+        }
+    }
+    let mut end = cursor;
+    while !end.is_end() && is_word_char(end.char()) {
+        end.forward_char();
+    }
+    (
+        buffer.text(&start, &cursor, true).to_string(),
+        buffer.text(&start, &end, true).to_string(),
+    )
+}
+
+/// Returns the suggestions for the word at the cursor, best first
+fn rank_candidates(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, prefix: &str, word: &str) -> Vec<Candidate> {
+    let buffer = text_view.buffer();
+    let snippets = crate::snippets::snippets_for_buffer(app_context, &buffer);
+    let buffers = open_buffers(app_context);
+    for open_buffer in &buffers {
+        update_index(app_context, open_buffer);
+    }
+    let syntax_name = crate::syntax_highlighting::syntax_name_for_buffer(app_context, &buffer);
+
+    let context = app_context.borrow();
+    let mut completion = context.completion.borrow_mut();
+    if completion.keywords.is_none() {
+        completion.keywords = Some(syntax_keywords(&context.syntax_context.borrow().ps));
+    }
+
+    let prefix_lower = prefix.to_lowercase();
+    let matches = |token: &str| token != prefix && token.to_lowercase().starts_with(&prefix_lower);
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for index in buffers.iter().filter_map(|open_buffer| completion.indexes.get(open_buffer)) {
+        for (token, count) in &index.counts {
+            if matches(token) {
+                *counts.entry(token.as_str()).or_insert(0) += count;
+            }
+        }
+    }
+    // The word being typed is indexed too; it only counts if it occurs elsewhere
+    if counts.get(word) == Some(&1) {
+        counts.remove(word);
+    }
+
+    let mut distances: HashMap<&str, i32> = HashMap::new();
+    if let Some(index) = completion.indexes.get(&buffer) {
+        let cursor_line = buffer.iter_at_mark(&buffer.get_insert()).line();
+        for distance in 0..=PROXIMITY_WINDOW {
+            for line in [cursor_line - distance, cursor_line + distance] {
+                let Some(tokens) = usize::try_from(line).ok().and_then(|line| index.line_tokens.get(line)) else {
+                    continue;
+                };
+                for token in tokens {
+                    if counts.contains_key(token.as_str()) {
+                        distances.entry(token.as_str()).or_insert(distance);
+                    }
+                }
+            }
+        }
+    }
+
+    let word_candidate = |token: &str| Candidate {
+        text: token.to_string(),
+        label: token.to_string(),
+        kind: CandidateKind::Word,
+    };
+    let mut scored: Vec<(f64, Candidate)> = counts
+        .iter()
+        .map(|(token, count)| {
+            let proximity = distances
+                .get(token)
+                .map_or(0.0, |distance| 1.0 - *distance as f64 / (PROXIMITY_WINDOW as f64 + 1.0));
+            (2.0 * proximity + (1.0 + *count as f64).ln(), word_candidate(token))
+        })
+        .collect();
+    let keywords = completion
+        .keywords
+        .as_ref()
+        .and_then(|keywords| keywords.get(&syntax_name));
+    for keyword in keywords.into_iter().flatten() {
+        if matches(keyword) && !counts.contains_key(keyword.as_str()) {
+            scored.push((KEYWORD_SCORE, word_candidate(keyword)));
+        }
+    }
+    for snippet in snippets {
+        if snippet.prefix.to_lowercase().starts_with(&prefix_lower) {
+            let description = if snippet.description.is_empty() { "snippet" } else { &snippet.description };
+            let label = format!("{}  ({})", snippet.prefix, description);
+            scored.push((
+                SNIPPET_SCORE,
+                Candidate {
+                    text: snippet.prefix.clone(),
+                    label,
+                    kind: CandidateKind::Snippet(snippet),
+                },
+            ));
+        }
+    }
+
+    scored.sort_by(|a, b| {
+        b.0.total_cmp(&a.0)
+            .then_with(|| a.1.text.len().cmp(&b.1.text.len()))
+            .then_with(|| a.1.text.cmp(&b.1.text))
+    });
+    scored
+        .into_iter()
+        .take(MAX_CANDIDATES)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+/// Creates the popup of a text view
+fn create_popup(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, manual: bool) -> CompletionPopup {
+    let list_box = ListBox::new();
+    list_box.set_selection_mode(gtk4::SelectionMode::Single);
+    let scrolled_window = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Never)
+        .max_content_height(POPUP_MAX_HEIGHT)
+        .propagate_natural_height(true)
+        .propagate_natural_width(true)
+        .child(&list_box)
+        .build();
+    // The popup must not take the focus away from the text view
+    let popover = Popover::builder()
+        .child(&scrolled_window)
+        .autohide(false)
+        .has_arrow(false)
+        .can_focus(false)
+        .position(gtk4::PositionType::Bottom)
+        .build();
+    popover.set_parent(text_view);
+
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    list_box.connect_row_activated(move |list_box, row| {
+        list_box.select_row(Some(row));
+        accept_completion(&app_context_clone, &text_view_clone);
+    });
+
+    CompletionPopup {
+        text_view: text_view.clone(),
+        popover,
+        list_box,
+        scrolled_window,
+        candidates: Vec::new(),
+        manual,
+    }
+}
+
+/// Fills the popup with suggestions and points it at the cursor
+fn fill_popup(popup: &mut CompletionPopup, candidates: Vec<Candidate>) {
+    while let Some(row) = popup.list_box.first_child() {
+        popup.list_box.remove(&row);
+    }
+    for candidate in &candidates {
+        let label = Label::new(Some(&candidate.label));
+        label.set_xalign(0.0);
+        popup.list_box.append(&label);
+    }
+    popup.list_box.select_row(popup.list_box.row_at_index(0).as_ref());
+    popup.scrolled_window.vadjustment().set_value(0.0);
+    popup.candidates = candidates;
+
+    let buffer = popup.text_view.buffer();
+    let location = popup.text_view.iter_location(&buffer.iter_at_mark(&buffer.get_insert()));
+    let (x, y) = popup.text_view.buffer_to_window_coords(gtk4::TextWindowType::Widget, location.x(), location.y());
+    popup
+        .popover
+        .set_pointing_to(Some(&gdk::Rectangle::new(x, y, 1, location.height())));
+    popup.popover.popup();
+}
+
+/// Closes the completion popup, if it is open
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn hide_completion(app_context: &Rc<RefCell<AppContext>>) {
+    let popup = app_context.borrow().completion.borrow_mut().popup.take();
+    if let Some(popup) = popup {
+        popup.popover.popdown();
+        popup.popover.unparent();
+    }
+}
+
+/// Opens, updates or closes the popup of a text view
+fn update_completion(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, trigger: Trigger) {
+    let visible_manual = {
+        let context = app_context.borrow();
+        let completion = context.completion.borrow();
+        completion
+            .popup
+            .as_ref()
+            .filter(|popup| popup.text_view == *text_view)
+            .map(|popup| popup.manual)
+    };
+    let min_chars = match (trigger, visible_manual) {
+        (Trigger::Manual, _) | (_, Some(true)) => 0,
+        (_, Some(false)) => 1,
+        (Trigger::Refresh, None) => return,
+        (Trigger::Typed, None) => {
+            let min_chars = app_context.borrow().app_settings.borrow().completion_min_chars;
+            if min_chars == 0 {
+                return;
+            }
+            min_chars
+        }
+    };
+
+    let buffer = text_view.buffer();
+    let (prefix, word) = word_at_cursor(&buffer);
+    if buffer.has_selection() || prefix.chars().count() < min_chars {
+        hide_completion(app_context);
+        return;
+    }
+    let candidates = rank_candidates(app_context, text_view, &prefix, &word);
+    if candidates.is_empty() {
+        hide_completion(app_context);
+        return;
+    }
+
+    if visible_manual.is_none() {
+        hide_completion(app_context);
+        let popup = create_popup(app_context, text_view, trigger == Trigger::Manual);
+        app_context.borrow().completion.borrow_mut().popup = Some(popup);
+    }
+    let context = app_context.borrow();
+    let mut completion = context.completion.borrow_mut();
+    if let Some(popup) = completion.popup.as_mut() {
+        fill_popup(popup, candidates);
+    }
+}
+
+/// Opens the completion popup for the word at the cursor
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to complete in
+pub fn show_completion(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    update_completion(app_context, text_view, Trigger::Manual);
+}
+
+/// Replaces the word before the cursor with the selected suggestion
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to complete in
+///
+/// # Returns
+///
+/// Whether the popup of the text view was open and a suggestion was inserted
+pub fn accept_completion(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) -> bool {
+    let candidate = {
+        let context = app_context.borrow();
+        let completion = context.completion.borrow();
+        completion
+            .popup
+            .as_ref()
+            .filter(|popup| popup.text_view == *text_view)
+            .and_then(|popup| {
+                let index = popup.list_box.selected_row()?.index();
+                popup.candidates.get(usize::try_from(index).ok()?).cloned()
+            })
+    };
+    let Some(candidate) = candidate else {
+        return false;
+    };
+    hide_completion(app_context);
+
+    let buffer = text_view.buffer();
+    let (mut start, mut end) = match buffer.selection_bounds() {
+        Some(bounds) if matches!(candidate.kind, CandidateKind::Choice) => bounds,
+        _ => {
+            let (prefix, _) = word_at_cursor(&buffer);
+            let end = buffer.iter_at_mark(&buffer.get_insert());
+            let mut start = end;
+            start.backward_chars(prefix.chars().count() as i32);
+            (start, end)
+        }
+    };
+    match candidate.kind {
+        CandidateKind::Snippet(snippet) => {
+            buffer.select_range(&start, &end);
+            crate::snippets::insert_snippet(app_context, text_view, &snippet);
+        }
+        CandidateKind::Word | CandidateKind::Choice => {
+            buffer.begin_user_action();
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &candidate.text);
+            buffer.end_user_action();
+        }
+    }
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+    true
+}
+
+/// Offers the choices of a snippet tab stop in the popup
+///
+/// Accepting a choice replaces the selected placeholder.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view holding the snippet
+/// * `choices` - The values to offer
+pub fn show_choices(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, choices: Vec<String>) {
+    let candidates: Vec<Candidate> = choices
+        .into_iter()
+        .map(|choice| Candidate {
+            text: choice.clone(),
+            label: choice,
+            kind: CandidateKind::Choice,
+        })
+        .collect();
+    if candidates.is_empty() {
+        return;
+    }
+    hide_completion(app_context);
+    let mut popup = create_popup(app_context, text_view, true);
+    fill_popup(&mut popup, candidates);
+    app_context.borrow().completion.borrow_mut().popup = Some(popup);
+}
+
+/// Moves the selection of the popup by some rows, returning whether the popup is open
+fn move_selection(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, rows: i32) -> bool {
+    let context = app_context.borrow();
+    let completion = context.completion.borrow();
+    let Some(popup) = completion.popup.as_ref().filter(|popup| popup.text_view == *text_view) else {
+        return false;
+    };
+    let count = popup.candidates.len() as i32;
+    let current = popup.list_box.selected_row().map_or(0, |row| row.index());
+    let index = (current + rows).rem_euclid(count.max(1));
+    if let Some(row) = popup.list_box.row_at_index(index) {
+        popup.list_box.select_row(Some(&row));
+        if let Some(bounds) = row.compute_bounds(&popup.list_box) {
+            let top = bounds.y() as f64;
+            popup.scrolled_window.vadjustment().clamp_page(top, top + bounds.height() as f64);
+        }
+    }
+    true
+}
+
+/// Forgets the token index of a buffer, once its tab is closed
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer of the closed tab
+pub fn forget_buffer(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    app_context.borrow().completion.borrow_mut().indexes.remove(buffer);
+}
+
+/// Schedules an update of the popup once the current change is complete
+fn schedule_update(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, trigger: Trigger) {
+    let app_context = app_context.clone();
+    let text_view = text_view.clone();
+    glib::idle_add_local_once(move || update_completion(&app_context, &text_view, trigger));
+}
+
+/// Adds the completion controllers to a text view
+///
+/// While the popup is open, Up and Down change the selected suggestion, Enter
+/// and Tab insert it and Escape closes the popup. This controller must be added
+/// before the other key controllers of the text view, so that it sees Enter
+/// first.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to add the controllers to
+pub fn add_completion_controller(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let key_controller = EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    key_controller.connect_key_pressed(move |_, keyval, _, _| {
+        let handled = match keyval {
+            gdk::Key::Up => move_selection(&app_context_clone, &text_view_clone, -1),
+            gdk::Key::Down => move_selection(&app_context_clone, &text_view_clone, 1),
+            gdk::Key::Return | gdk::Key::KP_Enter | gdk::Key::ISO_Enter | gdk::Key::Tab => {
+                accept_completion(&app_context_clone, &text_view_clone)
+            }
+            gdk::Key::Escape => {
+                let visible = app_context_clone.borrow().completion.borrow().popup.is_some();
+                hide_completion(&app_context_clone);
+                visible
+            }
+            gdk::Key::Left | gdk::Key::Right | gdk::Key::Home | gdk::Key::End | gdk::Key::Page_Up | gdk::Key::Page_Down => {
+                hide_completion(&app_context_clone);
+                false
+            }
+            _ => false,
+        };
+        if handled {
+            glib::Propagation::Stop
+        } else {
+            glib::Propagation::Proceed
+        }
+    });
+    text_view.add_controller(key_controller);
+
+    let click_gesture = GestureClick::new();
+    click_gesture.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_clone = app_context.clone();
+    click_gesture.connect_pressed(move |_, _, _, _| hide_completion(&app_context_clone));
+    text_view.add_controller(click_gesture);
+
+    let focus_controller = EventControllerFocus::new();
+    let app_context_clone = app_context.clone();
+    focus_controller.connect_leave(move |_| hide_completion(&app_context_clone));
+    text_view.add_controller(focus_controller);
+
+    let buffer = text_view.buffer();
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    buffer.connect_insert_text(move |_, _, text| {
+        let mut chars = text.chars();
+        let typed_word_char = matches!((chars.next(), chars.next()), (Some(c), None) if is_word_char(c));
+        if typed_word_char && text_view_clone.has_focus() {
+            schedule_update(&app_context_clone, &text_view_clone, Trigger::Typed);
+        } else {
+            // Any other insertion ends the word being completed
+            let app_context = app_context_clone.clone();
+            glib::idle_add_local_once(move || hide_completion(&app_context));
+        }
+    });
+
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    buffer.connect_delete_range(move |_, _, _| {
+        schedule_update(&app_context_clone, &text_view_clone, Trigger::Refresh);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::change_tracker::ChangeTracker;
+    use crate::test_support::run_with_gtk;
+
+    /// Splices the index of a text and compares it with the index of the edited text
+    fn assert_splice(text: &str, edited: &str, splices: &[LineSplice]) {
+        let mut index = TokenIndex::build_text(text);
+        let lines: Vec<&str> = edited.split('\n').collect();
+        assert!(index.splice(splices, |line| lines[line as usize].to_string(), lines.len()));
+        assert_eq!(index, TokenIndex::build_text(edited));
+    }
+
+    /// Lines replaced by an edit
+    fn splice(start: i32, removed: i32, inserted: i32) -> LineSplice {
+        LineSplice { start, removed, inserted }
+    }
+
+    #[test]
+    fn typing_in_a_line_retokenizes_it() {
+        assert_splice("alpha beta\ngamma\n", "alpha betamax\ngamma\n", &[splice(0, 1, 1)]);
+    }
+
+    #[test]
+    fn inserted_lines_are_spliced_in() {
+        assert_splice(
+            "alpha\ngamma delta\nomega",
+            "alpha\nbeta\nzeta gamma delta\nomega",
+            &[splice(1, 1, 2)],
+        );
+    }
+
+    #[test]
+    fn deleted_lines_are_spliced_out() {
+        assert_splice("alpha\nbeta\ngamma\ndelta", "almma\ndelta", &[splice(0, 3, 1)]);
+    }
+
+    #[test]
+    fn edits_are_spliced_in_order() {
+        // "beta\n" is inserted before "gamma", then "alpha" and "beta" are joined
+        assert_splice(
+            "alpha\ngamma\ndelta",
+            "alphabeta\ngamma\ndelta",
+            &[splice(1, 1, 2), splice(0, 2, 1)],
+        );
+    }
+
+    #[test]
+    fn edits_past_the_indexed_lines_are_refused() {
+        let mut index = TokenIndex::build_text("alpha\nbeta");
+        assert!(!index.splice(&[splice(5, 1, 1)], |_| String::new(), 2));
+        let mut index = TokenIndex::build_text("alpha\nbeta");
+        assert!(!index.splice(&[splice(0, 1, 2)], |_| String::new(), 2));
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn recorded_edits_keep_the_index_up_to_date() {
+        run_with_gtk(|| {
+            let buffer = TextBuffer::new(None);
+            buffer.set_text("fn alpha() {\n    beta();\n}\n");
+            let index = Rc::new(RefCell::new(TokenIndex::build(&buffer)));
+            let tracker = Rc::new(RefCell::new(ChangeTracker::new()));
+            let tracker_insert = tracker.clone();
+            buffer.connect_insert_text(move |_, pos, text| {
+                let mut end = *pos;
+                end.forward_chars(text.chars().count() as i32);
+                tracker_insert.borrow_mut().record_insertion(pos, &end, text);
+            });
+            let tracker_delete = tracker.clone();
+            buffer.connect_delete_range(move |_, start, end| tracker_delete.borrow_mut().record_deletion(start, end));
+
+            buffer.insert(&mut buffer.iter_at_line(2).unwrap(), "    gamma();\r\n    délta(日本);\n");
+            buffer.insert(&mut buffer.iter_at_line_offset(1, 8).unwrap(), "_two");
+            let (mut start, mut end) = (buffer.iter_at_line_offset(0, 6).unwrap(), buffer.iter_at_line_offset(1, 4).unwrap());
+            buffer.delete(&mut start, &mut end);
+
+            let splices = tracker.borrow_mut().take_completion_splices().expect("the edits were recorded");
+            let line_count = buffer.line_count() as usize;
+            assert!(index.borrow_mut().splice(&splices, |line| line_text(&buffer, line), line_count));
+            assert_eq!(*index.borrow(), TokenIndex::build(&buffer));
+        });
+    }
+}
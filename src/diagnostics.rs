@@ -0,0 +1,511 @@
+//! Module for diagnostics
+//!
+//! Diagnostics are problems located at a range of a file, reported by the
+//! language servers or parsed from the output of compilers and formatters.
+//! They are listed in the Problems panel and, in the open files, underlined
+//! with a wavy line coloured by severity and marked in the gutter next to the
+//! line numbers. Hovering an underlined range shows its messages. The ranges
+//! are tracked with marks, so they follow the edits until the next report.
+
+use gtk4::gdk;
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextIter, TextMark, TextView, TextWindowType};
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::LazyLock;
+
+use crate::problems::{self, Problem, Severity};
+use crate::AppContext;
+
+/// A problem located at a range of a file
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    /// File the diagnostic is about, `None` for untitled buffers
+    pub path: Option<PathBuf>,
+    /// Start of the range, as a line and a column in characters, counted from 0
+    pub start: (i32, i32),
+    /// End of the range; an empty range covers the word at its start
+    pub end: (i32, i32),
+    /// Severity of the diagnostic
+    pub severity: Severity,
+    /// Description of the diagnostic
+    pub message: String,
+    /// Tool that reported the diagnostic, e.g. "rust-analyzer"
+    pub source: String,
+}
+
+/// A diagnostic shown in a buffer
+struct RenderedDiagnostic {
+    /// Start of the underlined range
+    start: TextMark,
+    /// End of the underlined range
+    end: TextMark,
+    /// Severity of the diagnostic
+    severity: Severity,
+    /// Text shown in the tooltip
+    message: String,
+}
+
+/// Diagnostics of all files and those shown in each buffer
+#[derive(Default)]
+pub struct Diagnostics {
+    /// Diagnostics by reporting tool and file
+    entries: HashMap<(String, Option<PathBuf>), Vec<Diagnostic>>,
+    /// Diagnostics shown in each buffer
+    rendered: HashMap<TextBuffer, Vec<RenderedDiagnostic>>,
+}
+
+/// Shared diagnostics state
+pub type DiagnosticsState = Rc<RefCell<Diagnostics>>;
+
+/// Returns the colour of the underlines and gutter marks of a severity
+///
+/// # Arguments
+///
+/// * `severity` - The severity
+pub fn severity_color(severity: Severity) -> gdk::RGBA {
+    match severity {
+        Severity::Error => gdk::RGBA::new(0.86, 0.2, 0.18, 1.0),
+        Severity::Warning => gdk::RGBA::new(0.9, 0.6, 0.1, 1.0),
+        Severity::Info => gdk::RGBA::new(0.2, 0.5, 0.9, 1.0),
+    }
+}
+
+/// Returns the name of the tag underlining diagnostics of a severity
+fn tag_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "diagnostic_error",
+        Severity::Warning => "diagnostic_warning",
+        Severity::Info => "diagnostic_info",
+    }
+}
+
+/// Replaces the diagnostics reported by a tool for a file
+///
+/// The diagnostics are listed in the Problems panel and shown in the file's
+/// buffer if it is open.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `source` - Tool reporting the diagnostics
+/// * `path` - File the diagnostics are about, `None` for untitled buffers
+/// * `diagnostics` - The new diagnostics, empty to clear them
+/// * `reveal` - Whether to show the Problems panel if there are diagnostics
+pub fn set_diagnostics(
+    app_context: &Rc<RefCell<AppContext>>,
+    source: &str,
+    path: Option<&Path>,
+    diagnostics: Vec<Diagnostic>,
+    reveal: bool,
+) {
+    let problems: Vec<Problem> = diagnostics
+        .iter()
+        .map(|diagnostic| Problem {
+            source: source.to_string(),
+            path: diagnostic.path.clone(),
+            line: Some(diagnostic.start.0),
+            column: Some(diagnostic.start.1),
+            severity: diagnostic.severity,
+            message: diagnostic.message.clone(),
+        })
+        .collect();
+    {
+        let context = app_context.borrow();
+        let mut state = context.diagnostics.borrow_mut();
+        let key = (source.to_string(), path.map(Path::to_path_buf));
+        if diagnostics.is_empty() {
+            state.entries.remove(&key);
+        } else {
+            state.entries.insert(key, diagnostics);
+        }
+    }
+    if reveal {
+        problems::set_problems(app_context, source, path, problems);
+    } else {
+        problems::update_problems(app_context, source, path, problems);
+    }
+
+    let Some(path) = path else {
+        return;
+    };
+    let text_view = crate::ui::helpers::get_text_views(&app_context.borrow().notebook)
+        .into_iter()
+        .find(|text_view| app_context.borrow().buffer_paths.borrow().get(&text_view.buffer()).map(PathBuf::as_path) == Some(path));
+    if let Some(text_view) = text_view {
+        show_diagnostics(app_context, &text_view);
+    }
+}
+
+/// Removes all the diagnostics reported by a tool
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `source` - Tool that reported the diagnostics
+pub fn clear_diagnostics(app_context: &Rc<RefCell<AppContext>>, source: &str) {
+    let paths: Vec<Option<PathBuf>> = app_context
+        .borrow()
+        .diagnostics
+        .borrow()
+        .entries
+        .keys()
+        .filter(|(entry_source, _)| entry_source == source)
+        .map(|(_, path)| path.clone())
+        .collect();
+    for path in paths {
+        set_diagnostics(app_context, source, path.as_deref(), Vec::new(), false);
+    }
+}
+
+/// Returns the end of the range underlined for a diagnostic with an empty range
+///
+/// This is the end of the word at the start, or the next character, or the
+/// previous one at the end of a line.
+fn word_end(start: &mut TextIter) -> TextIter {
+    let mut end = *start;
+    if end.inside_word() || end.starts_word() {
+        end.forward_word_end();
+    }
+    if end.offset() == start.offset() && !end.ends_line() {
+        end.forward_char();
+    }
+    if end.offset() == start.offset() {
+        start.backward_char();
+    }
+    end
+}
+
+/// Shows the diagnostics of a text view's file in its buffer
+///
+/// The previous underlines are removed first, and the gutter is redrawn.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view showing the file
+pub fn show_diagnostics(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    forget_buffer(app_context, &buffer);
+    let Some(path) = app_context.borrow().buffer_paths.borrow().get(&buffer).cloned() else {
+        return;
+    };
+
+    let diagnostics: Vec<Diagnostic> = app_context
+        .borrow()
+        .diagnostics
+        .borrow()
+        .entries
+        .iter()
+        .filter(|((_, diagnostic_path), _)| diagnostic_path.as_ref() == Some(&path))
+        .flat_map(|(_, diagnostics)| diagnostics.iter().cloned())
+        .collect();
+
+    let mut rendered = Vec::with_capacity(diagnostics.len());
+    for diagnostic in diagnostics {
+        let position = |(line, column): (i32, i32)| {
+            let mut iter = buffer.iter_at_line(line.max(0)).unwrap_or_else(|| buffer.end_iter());
+            let mut line_end = iter;
+            if !line_end.ends_line() {
+                line_end.forward_to_line_end();
+            }
+            iter.set_line_offset(column.clamp(0, line_end.line_offset()));
+            iter
+        };
+        let mut start = position(diagnostic.start);
+        let mut end = position(diagnostic.end);
+        if end.offset() <= start.offset() {
+            end = word_end(&mut start);
+        }
+        buffer.apply_tag_by_name(tag_name(diagnostic.severity), &start, &end);
+        rendered.push(RenderedDiagnostic {
+            start: buffer.create_mark(None, &start, true),
+            end: buffer.create_mark(None, &end, false),
+            severity: diagnostic.severity,
+            message: format!("{}: {}", diagnostic.source, diagnostic.message),
+        });
+    }
+    app_context.borrow().diagnostics.borrow_mut().rendered.insert(buffer.clone(), rendered);
+
+    if let Some(line_numbers_area) = crate::ui::helpers::get_line_numbers_area(text_view) {
+        line_numbers_area.queue_draw();
+    }
+}
+
+/// Removes the diagnostics shown in a buffer
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer, usually one whose tab was closed
+pub fn forget_buffer(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let rendered = app_context.borrow().diagnostics.borrow_mut().rendered.remove(buffer);
+    for diagnostic in rendered.into_iter().flatten() {
+        buffer.delete_mark(&diagnostic.start);
+        buffer.delete_mark(&diagnostic.end);
+    }
+    for severity in [Severity::Error, Severity::Warning, Severity::Info] {
+        buffer.remove_tag_by_name(tag_name(severity), &buffer.start_iter(), &buffer.end_iter());
+    }
+}
+
+/// Returns the most severe diagnostic starting on each line of a buffer
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer
+///
+/// # Returns
+///
+/// The severity of each line with diagnostics
+pub fn line_severities(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> HashMap<i32, Severity> {
+    let context = app_context.borrow();
+    let state = context.diagnostics.borrow();
+    let mut severities: HashMap<i32, Severity> = HashMap::new();
+    for diagnostic in state.rendered.get(buffer).into_iter().flatten() {
+        let line = buffer.iter_at_mark(&diagnostic.start).line();
+        severities
+            .entry(line)
+            .and_modify(|severity| *severity = (*severity).min(diagnostic.severity))
+            .or_insert(diagnostic.severity);
+    }
+    severities
+}
+
+/// Returns the messages of the diagnostics covering a position
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `iter` - The position
+pub fn messages_at(app_context: &Rc<RefCell<AppContext>>, iter: &TextIter) -> Vec<String> {
+    let buffer = iter.buffer();
+    let context = app_context.borrow();
+    let state = context.diagnostics.borrow();
+    state
+        .rendered
+        .get(&buffer)
+        .into_iter()
+        .flatten()
+        .filter(|diagnostic| {
+            let start = buffer.iter_at_mark(&diagnostic.start);
+            let end = buffer.iter_at_mark(&diagnostic.end);
+            start.offset() <= iter.offset() && iter.offset() < end.offset()
+        })
+        .map(|diagnostic| diagnostic.message.clone())
+        .collect()
+}
+
+/// Shows the messages of the diagnostics under the pointer in a tooltip
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to show tooltips for
+pub fn connect_diagnostics(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    text_view.set_has_tooltip(true);
+    let app_context_clone = app_context.clone();
+    text_view.connect_query_tooltip(move |text_view, x, y, keyboard_mode, tooltip| {
+        let buffer = text_view.buffer();
+        let iter = if keyboard_mode {
+            Some(buffer.iter_at_mark(&buffer.get_insert()))
+        } else {
+            let (buffer_x, buffer_y) = text_view.window_to_buffer_coords(TextWindowType::Widget, x, y);
+            text_view.iter_at_location(buffer_x, buffer_y)
+        };
+        let Some(iter) = iter else {
+            return false;
+        };
+        let messages = messages_at(&app_context_clone, &iter);
+        if messages.is_empty() {
+            return false;
+        }
+        tooltip.set_text(Some(&messages.join("\n")));
+        true
+    });
+}
+
+/// Resolves a file name found in tool output
+fn resolve_path(name: &str, base_dir: Option<&Path>, stdin_path: Option<&Path>) -> Option<PathBuf> {
+    if matches!(name, "<stdin>" | "-" | "stdin") {
+        return stdin_path.map(Path::to_path_buf);
+    }
+    let path = PathBuf::from(name);
+    match base_dir {
+        Some(base_dir) if path.is_relative() => Some(base_dir.join(path)),
+        _ => Some(path),
+    }
+}
+
+/// Returns the severity named in tool output
+fn parse_severity(name: &str) -> Severity {
+    match name {
+        "warning" => Severity::Warning,
+        "note" | "help" | "info" => Severity::Info,
+        _ => Severity::Error,
+    }
+}
+
+/// Position at the start of a line, as `file:line:column:` or `file:line:`
+static LOCATION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<file>[^\s:][^:]*):(?P<line>\d+):(?:(?P<column>\d+):?)?\s*(?P<rest>.*)$").expect("valid regex")
+});
+
+/// Severity and message, as `error: message` or `error[E0308]: message`
+static HEADER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:fatal )?(?P<severity>error|warning|note|help|info)(?:\[[^\]]*\])?:\s*(?P<message>.*)$")
+        .expect("valid regex")
+});
+
+/// Location that rustc prints below a header
+static ARROW: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\s*--> (?P<file>.+?):(?P<line>\d+):(?P<column>\d+)$").expect("valid regex"));
+
+/// Position in a Python traceback frame
+static FRAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"^\s*File "(?P<file>[^"<]+)", line (?P<line>\d+)"#).expect("valid regex"));
+
+/// Parses the diagnostics in the output of a compiler or similar tool
+///
+/// Lines of the form `file:line:column: severity: message` (as printed by
+/// GCC, Clang and many linters) are recognized, as well as the
+/// `severity: message` line followed by `--> file:line:column` that rustc
+/// prints. The column is optional, and the severity defaults to error. A
+/// Python traceback makes one diagnostic at its innermost frame, described by
+/// the exception that ends it.
+///
+/// # Arguments
+///
+/// * `output` - The output of the tool
+/// * `base_dir` - Directory that relative file names are resolved against
+/// * `stdin_path` - File standing for the tool's standard input, if any
+/// * `source` - Name of the tool
+///
+/// # Returns
+///
+/// The diagnostics, in the order of the output
+pub fn parse_compiler_output(
+    output: &str,
+    base_dir: Option<&Path>,
+    stdin_path: Option<&Path>,
+    source: &str,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut push = |file: &str, line: &str, column: Option<&str>, severity: Severity, message: &str| {
+        let line = line.parse::<i32>().unwrap_or(1) - 1;
+        let column = column.and_then(|column| column.parse::<i32>().ok()).unwrap_or(1) - 1;
+        diagnostics.push(Diagnostic {
+            path: resolve_path(file, base_dir, stdin_path),
+            start: (line.max(0), column.max(0)),
+            end: (line.max(0), column.max(0)),
+            severity,
+            message: message.trim().to_string(),
+            source: source.to_string(),
+        });
+    };
+
+    // The rustc header waiting for the location on a following line
+    let mut pending: Option<(Severity, String)> = None;
+    // The innermost traceback frame waiting for the exception that ends the traceback
+    let mut pending_frame: Option<(String, String)> = None;
+    for line in output.lines() {
+        if let Some(captures) = FRAME.captures(line) {
+            pending_frame = Some((captures["file"].to_string(), captures["line"].to_string()));
+        } else if !line.trim().is_empty()
+            && !line.starts_with(char::is_whitespace)
+            && !line.starts_with("Traceback")
+            && let Some((file, line_number)) = pending_frame.take()
+        {
+            push(&file, &line_number, None, Severity::Error, line);
+        } else if let Some(captures) = HEADER.captures(line) {
+            pending = Some((parse_severity(&captures["severity"]), captures["message"].to_string()));
+        } else if let Some(captures) = ARROW.captures(line) {
+            if let Some((severity, message)) = pending.take() {
+                push(&captures["file"], &captures["line"], Some(&captures["column"]), severity, &message);
+            }
+        } else if let Some(captures) = LOCATION.captures(line) {
+            let rest = &captures["rest"];
+            let (severity, message) = match HEADER.captures(rest) {
+                Some(rest) => (parse_severity(&rest["severity"]), rest["message"].to_string()),
+                None => (Severity::Error, rest.to_string()),
+            };
+            let column = captures.name("column").map(|column| column.as_str());
+            push(&captures["file"], &captures["line"], column, severity, &message);
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses tool output from `/project` and returns the fields of each diagnostic
+    fn parse(output: &str) -> Vec<(Option<PathBuf>, (i32, i32), Severity, String)> {
+        parse_compiler_output(output, Some(Path::new("/project")), Some(Path::new("/project/input.rs")), "tool")
+            .into_iter()
+            .map(|diagnostic| (diagnostic.path, diagnostic.start, diagnostic.severity, diagnostic.message))
+            .collect()
+    }
+
+    fn expected(path: &str, start: (i32, i32), severity: Severity, message: &str) -> (Option<PathBuf>, (i32, i32), Severity, String) {
+        (Some(PathBuf::from(path)), start, severity, message.to_string())
+    }
+
+    #[test]
+    fn compiler_output_is_parsed() {
+        let cases = [
+            // GCC and Clang, with and without a column
+            (
+                "main.c:3:5: error: expected ';' before '}' token\n",
+                vec![expected("/project/main.c", (2, 4), Severity::Error, "expected ';' before '}' token")],
+            ),
+            (
+                "src/util.c:10:1: warning: unused variable 'x' [-Wunused-variable]\n/abs/lib.h:4: note: declared here\n",
+                vec![
+                    expected("/project/src/util.c", (9, 0), Severity::Warning, "unused variable 'x' [-Wunused-variable]"),
+                    expected("/abs/lib.h", (3, 0), Severity::Info, "declared here"),
+                ],
+            ),
+            // Linters without a severity, and the standard input
+            (
+                "app.py:7:80: line too long\n<stdin>:2:1: fatal error: oops\n",
+                vec![
+                    expected("/project/app.py", (6, 79), Severity::Error, "line too long"),
+                    expected("/project/input.rs", (1, 0), Severity::Error, "oops"),
+                ],
+            ),
+            // rustc, with the location below the header and the source excerpt ignored
+            (
+                "error[E0425]: cannot find value `x` in this scope\n  --> src/main.rs:2:5\n   |\n2  |     x\n   |     ^ not found\n\nwarning: unused variable: `y`\n --> src/lib.rs:7:9\n",
+                vec![
+                    expected("/project/src/main.rs", (1, 4), Severity::Error, "cannot find value `x` in this scope"),
+                    expected("/project/src/lib.rs", (6, 8), Severity::Warning, "unused variable: `y`"),
+                ],
+            ),
+            // rustc headers without a location make no diagnostic
+            ("error: aborting due to 1 previous error\nwarning: 2 warnings emitted\n", vec![]),
+            (
+                "warning: build script failed\nerror[E0308]: mismatched types\n --> a.rs:1:1\n",
+                vec![expected("/project/a.rs", (0, 0), Severity::Error, "mismatched types")],
+            ),
+            // A Python traceback makes one diagnostic at its innermost frame
+            (
+                "Traceback (most recent call last):\n  File \"/app/main.py\", line 10, in <module>\n    main()\n  File \"lib.py\", line 3, in main\n    raise ValueError(\"bad\")\nValueError: bad\n",
+                vec![expected("/project/lib.py", (2, 0), Severity::Error, "ValueError: bad")],
+            ),
+            // Frames of the standard input have no file, and other lines are ignored
+            (
+                "Traceback (most recent call last):\n  File \"<stdin>\", line 1, in <module>\nNameError: name 'x' is not defined\nmake: *** [all] Error 1\n",
+                vec![],
+            ),
+        ];
+        for (output, diagnostics) in cases {
+            assert_eq!(parse(output), diagnostics, "{}", output);
+        }
+    }
+}
@@ -1,10 +1,10 @@
 //! Module for creating and managing dialogs
 //!
 //! This module provides functions for creating various types of dialogs
-//! used throughout the application, such as error dialogs.
+//! used throughout the application, such as error and confirmation dialogs.
 
 use gtk4::prelude::*;
-use gtk4::{ButtonsType, MessageDialog};
+use gtk4::{ButtonsType, MessageDialog, ResponseType};
 
 /// Creates and shows an error dialog
 pub fn show_error_dialog(
@@ -26,4 +26,45 @@ pub fn show_error_dialog(
     
     dialog.present();
     dialog
-}
\ No newline at end of file
+}
+
+/// Creates and shows a dialog asking to confirm an action
+///
+/// # Arguments
+///
+/// * `parent` - The window the dialog belongs to
+/// * `title` - The question asked
+/// * `message` - What the action does
+/// * `confirm_label` - Label of the button confirming the action
+/// * `on_confirm` - Called if the action is confirmed
+pub fn show_confirm_dialog(
+    parent: &impl IsA<gtk4::Window>,
+    title: &str,
+    message: &str,
+    confirm_label: &str,
+    on_confirm: impl FnOnce() + 'static,
+) -> MessageDialog {
+    let dialog = MessageDialog::builder()
+        .transient_for(parent)
+        .modal(true)
+        .buttons(ButtonsType::None)
+        .text(title)
+        .secondary_text(message)
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button(confirm_label, ResponseType::Accept);
+    dialog.set_default_response(ResponseType::Cancel);
+
+    let on_confirm = std::cell::Cell::new(Some(on_confirm));
+    dialog.connect_response(move |dialog, response| {
+        dialog.close();
+        if response == ResponseType::Accept
+            && let Some(on_confirm) = on_confirm.take()
+        {
+            on_confirm();
+        }
+    });
+
+    dialog.present();
+    dialog
+}
@@ -0,0 +1,755 @@
+//! Module for EditorConfig support
+//!
+//! The `.editorconfig` files found in the directory of an opened file and in
+//! its ancestors, up to the first one declaring `root = true`, are merged into
+//! an `EditorConfig`, with nearer files and later sections taking precedence.
+//! The indentation properties seed the buffer's indentation style and tab
+//! stops, `max_line_length` draws a guide in the text view, `charset` and
+//! `end_of_line` are applied when the file is read and saved, and the
+//! whitespace properties are applied by the `whitespace` module on save.
+
+use gtk4::prelude::*;
+use gtk4::{DrawingArea, TextBuffer, TextView};
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::AppContext;
+
+/// Name of the configuration files
+const FILE_NAME: &str = ".editorconfig";
+/// Largest `{n..m}` range expanded into an alternation
+const MAX_RANGE: i64 = 1000;
+
+/// Line terminator written when saving
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndOfLine {
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl EndOfLine {
+    /// Returns the characters of the terminator
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EndOfLine::Lf => "\n",
+            EndOfLine::CrLf => "\r\n",
+            EndOfLine::Cr => "\r",
+        }
+    }
+}
+
+/// Encoding of a file on disk
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Charset {
+    Utf8,
+    Utf8Bom,
+    Latin1,
+    Utf16Le,
+    Utf16Be,
+}
+
+/// Effective EditorConfig properties of a file
+///
+/// Properties that no section sets are `None`, and leave the editor's own
+/// behaviour unchanged.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EditorConfig {
+    /// Whether to indent with tabs rather than spaces
+    pub use_tabs: Option<bool>,
+    /// Number of columns of one indentation level
+    pub indent_size: Option<usize>,
+    /// Number of columns a tab character is displayed as
+    pub tab_width: Option<usize>,
+    /// Line terminator written when saving
+    pub end_of_line: Option<EndOfLine>,
+    /// Encoding used to read and save the file
+    pub charset: Option<Charset>,
+    /// Whether to remove whitespace at the end of lines when saving
+    pub trim_trailing_whitespace: Option<bool>,
+    /// Whether the file must (or must not) end with a newline
+    pub insert_final_newline: Option<bool>,
+    /// Column at which the line length guide is drawn
+    pub max_line_length: Option<usize>,
+}
+
+impl EditorConfig {
+    /// Checks whether no property is set
+    pub fn is_empty(&self) -> bool {
+        *self == EditorConfig::default()
+    }
+
+    /// Builds the configuration from the raw properties of the matching sections
+    fn from_properties(properties: &HashMap<String, String>) -> Self {
+        let number = |key: &str| {
+            properties
+                .get(key)
+                .and_then(|value| value.parse::<usize>().ok())
+                .filter(|&n| n > 0)
+        };
+        let flag = |key: &str| match properties.get(key).map(String::as_str) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+
+        let use_tabs = match properties.get("indent_style").map(String::as_str) {
+            Some("tab") => Some(true),
+            Some("space") => Some(false),
+            _ => None,
+        };
+        let mut tab_width = number("tab_width");
+        let mut indent_size = if properties.get("indent_size").map(String::as_str) == Some("tab") {
+            tab_width
+        } else {
+            number("indent_size")
+        };
+        // Each size defaults to the other, as the specification requires
+        if indent_size.is_none() && use_tabs == Some(true) {
+            indent_size = tab_width;
+        }
+        if tab_width.is_none() {
+            tab_width = indent_size;
+        }
+
+        let end_of_line = match properties.get("end_of_line").map(String::as_str) {
+            Some("lf") => Some(EndOfLine::Lf),
+            Some("crlf") => Some(EndOfLine::CrLf),
+            Some("cr") => Some(EndOfLine::Cr),
+            _ => None,
+        };
+        let charset = match properties.get("charset").map(String::as_str) {
+            Some("utf-8") => Some(Charset::Utf8),
+            Some("utf-8-bom") => Some(Charset::Utf8Bom),
+            Some("latin1") => Some(Charset::Latin1),
+            Some("utf-16le") => Some(Charset::Utf16Le),
+            Some("utf-16be") => Some(Charset::Utf16Be),
+            _ => None,
+        };
+
+        EditorConfig {
+            use_tabs,
+            indent_size,
+            tab_width,
+            end_of_line,
+            charset,
+            trim_trailing_whitespace: flag("trim_trailing_whitespace"),
+            insert_final_newline: flag("insert_final_newline"),
+            max_line_length: number("max_line_length"),
+        }
+    }
+
+    /// Returns a short description of the properties, for the status bar
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        match (self.use_tabs, self.indent_size) {
+            (Some(true), _) => parts.push("tabs".to_string()),
+            (Some(false), Some(size)) => parts.push(format!("spaces: {}", size)),
+            (Some(false), None) => parts.push("spaces".to_string()),
+            (None, Some(size)) => parts.push(format!("indent: {}", size)),
+            (None, None) => {}
+        }
+        if let Some(width) = self.tab_width {
+            parts.push(format!("tab width: {}", width));
+        }
+        if let Some(end_of_line) = self.end_of_line {
+            parts.push(
+                match end_of_line {
+                    EndOfLine::Lf => "LF",
+                    EndOfLine::CrLf => "CRLF",
+                    EndOfLine::Cr => "CR",
+                }
+                .to_string(),
+            );
+        }
+        if let Some(charset) = self.charset {
+            parts.push(
+                match charset {
+                    Charset::Utf8 => "UTF-8",
+                    Charset::Utf8Bom => "UTF-8 with BOM",
+                    Charset::Latin1 => "Latin-1",
+                    Charset::Utf16Le => "UTF-16 LE",
+                    Charset::Utf16Be => "UTF-16 BE",
+                }
+                .to_string(),
+            );
+        }
+        if self.trim_trailing_whitespace == Some(true) {
+            parts.push("trim".to_string());
+        }
+        match self.insert_final_newline {
+            Some(true) => parts.push("final newline".to_string()),
+            Some(false) => parts.push("no final newline".to_string()),
+            None => {}
+        }
+        if let Some(length) = self.max_line_length {
+            parts.push(format!("max: {}", length));
+        }
+        format!("EditorConfig: {}", parts.join(", "))
+    }
+}
+
+/// Map of buffers to the EditorConfig properties of their file
+pub type EditorConfigs = Rc<RefCell<HashMap<TextBuffer, EditorConfig>>>;
+
+/// A section of a `.editorconfig` file, as the regex built from its glob and its properties
+type Section = (Option<Regex>, Vec<(String, String)>);
+
+/// A parsed `.editorconfig` file
+struct ConfigFile {
+    /// Whether the search for files stops at this one
+    root: bool,
+    /// Sections, in file order
+    sections: Vec<Section>,
+}
+
+/// Parses a `.editorconfig` file
+///
+/// # Arguments
+///
+/// * `path` - Path of the file
+///
+/// # Returns
+///
+/// The parsed file, or `None` if it can't be read
+fn parse_file(path: &Path) -> Option<ConfigFile> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let directory = path.parent().unwrap_or(Path::new("/"));
+    let mut file = ConfigFile { root: false, sections: Vec::new() };
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            let glob = &line[1..line.len() - 1];
+            file.sections.push((section_regex(directory, glob), Vec::new()));
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let key = key.trim().to_lowercase();
+        let value = value.trim().to_lowercase();
+        match file.sections.last_mut() {
+            Some((_, properties)) => properties.push((key, value)),
+            // Only `root` is allowed before the first section
+            None if key == "root" => file.root = value == "true",
+            None => {}
+        }
+    }
+    Some(file)
+}
+
+/// Builds the regex matching the paths a section applies to
+///
+/// Globs without a slash match file names in any subdirectory, and the others
+/// are relative to the directory of the `.editorconfig` file.
+///
+/// # Arguments
+///
+/// * `directory` - Directory of the `.editorconfig` file
+/// * `glob` - Glob of the section
+fn section_regex(directory: &Path, glob: &str) -> Option<Regex> {
+    let glob = if glob.contains('/') {
+        glob.strip_prefix('/').unwrap_or(glob).to_string()
+    } else {
+        format!("**/{}", glob)
+    };
+    let chars: Vec<char> = glob.chars().collect();
+    let directory = directory.to_string_lossy();
+    let pattern = format!(
+        "^{}/{}$",
+        regex::escape(directory.trim_end_matches('/')),
+        glob_to_regex(&chars)
+    );
+    Regex::new(&pattern).ok()
+}
+
+/// Translates an EditorConfig glob to a regex
+///
+/// Supports `*`, `**`, `?`, `[...]`, `[!...]`, `{a,b}`, `{n..m}` and
+/// backslash escapes.
+fn glob_to_regex(chars: &[char]) -> String {
+    let mut regex = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 1;
+            }
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                // `**/` also matches no directory at all
+                if chars.get(i + 2) == Some(&'/') {
+                    regex.push_str("(?:.*/)?");
+                    i += 2;
+                } else {
+                    regex.push_str(".*");
+                    i += 1;
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(length) if length > 0 => {
+                    let class = &chars[i + 1..i + 1 + length];
+                    let (negated, class) = match class.first() {
+                        Some('!') | Some('^') => (true, &class[1..]),
+                        _ => (false, class),
+                    };
+                    regex.push('[');
+                    if negated {
+                        regex.push('^');
+                    }
+                    for &c in class {
+                        if matches!(c, '\\' | '[' | ']' | '^' | '&' | '~') {
+                            regex.push('\\');
+                        }
+                        regex.push(c);
+                    }
+                    regex.push(']');
+                    i += length + 1;
+                }
+                _ => regex.push_str("\\["),
+            },
+            '{' => match matching_brace(chars, i) {
+                Some(end) => {
+                    regex.push_str(&brace_to_regex(&chars[i + 1..end]));
+                    i = end;
+                }
+                None => regex.push_str("\\{"),
+            },
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex
+}
+
+/// Returns the index of the brace closing the one at `open`
+fn matching_brace(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Translates the content of a `{...}` group to a regex
+fn brace_to_regex(content: &[char]) -> String {
+    let text: String = content.iter().collect();
+    if let Some((start, end)) = text.split_once("..")
+        && let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>())
+    {
+        let (low, high) = (start.min(end), start.max(end));
+        if high - low <= MAX_RANGE {
+            let numbers: Vec<String> = (low..=high).map(|n| n.to_string()).collect();
+            return format!("(?:{})", numbers.join("|"));
+        }
+        return "[+-]?[0-9]+".to_string();
+    }
+
+    // Split at the commas outside nested groups
+    let mut alternatives = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+    while i < content.len() {
+        match content[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&content[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if alternatives.is_empty() {
+        // A group without alternatives is taken literally
+        return format!("\\{{{}\\}}", glob_to_regex(content));
+    }
+    alternatives.push(&content[start..]);
+    let alternatives: Vec<String> = alternatives.into_iter().map(glob_to_regex).collect();
+    format!("(?:{})", alternatives.join("|"))
+}
+
+/// Resolves the EditorConfig properties of a file
+///
+/// # Arguments
+///
+/// * `path` - Path of the file
+///
+/// # Returns
+///
+/// The effective properties, empty if no `.editorconfig` file applies
+pub fn config_for_path(path: &Path) -> EditorConfig {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut files = Vec::new();
+    for directory in path.ancestors().skip(1) {
+        if let Some(file) = parse_file(&directory.join(FILE_NAME)) {
+            let root = file.root;
+            files.push(file);
+            if root {
+                break;
+            }
+        }
+    }
+
+    // Apply the outermost file first, so that nearer files override it
+    let path_text = path.to_string_lossy();
+    let mut properties = HashMap::new();
+    for file in files.iter().rev() {
+        for (regex, section_properties) in &file.sections {
+            if !regex.as_ref().is_some_and(|regex| regex.is_match(&path_text)) {
+                continue;
+            }
+            for (key, value) in section_properties {
+                if value == "unset" {
+                    properties.remove(key);
+                } else {
+                    properties.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    EditorConfig::from_properties(&properties)
+}
+
+/// Reads a file, decoding it with the configured charset
+///
+/// Files without a configured charset must be valid UTF-8, and their byte
+/// order mark, if any, is kept.
+///
+/// # Arguments
+///
+/// * `path` - Path of the file
+/// * `config` - EditorConfig properties of the file
+pub fn read_file(path: &Path, config: &EditorConfig) -> io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    decode(bytes, config.charset)
+}
+
+/// Writes a file, encoding it with the configured charset
+///
+/// # Arguments
+///
+/// * `path` - Path of the file
+/// * `text` - The text to write, with the line terminators it should have
+/// * `config` - EditorConfig properties of the file
+pub fn write_file(path: &Path, text: &str, config: &EditorConfig) -> io::Result<()> {
+    std::fs::write(path, encode(text, config))
+}
+
+/// Decodes the bytes of a file
+fn decode(bytes: Vec<u8>, charset: Option<Charset>) -> io::Result<String> {
+    let invalid = |_| io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid text");
+    match charset {
+        None => String::from_utf8(bytes).map_err(invalid),
+        Some(Charset::Utf8) | Some(Charset::Utf8Bom) => {
+            let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&bytes);
+            String::from_utf8(bytes.to_vec()).map_err(invalid)
+        }
+        Some(Charset::Latin1) => Ok(bytes.iter().map(|&b| b as char).collect()),
+        Some(Charset::Utf16Le) | Some(Charset::Utf16Be) => {
+            let little_endian = charset == Some(Charset::Utf16Le);
+            let bom: &[u8] = if little_endian { b"\xFF\xFE" } else { b"\xFE\xFF" };
+            let bytes = bytes.strip_prefix(bom).unwrap_or(&bytes);
+            if !bytes.len().is_multiple_of(2) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated UTF-16 stream"));
+            }
+            let units = bytes.chunks_exact(2).map(|pair| {
+                if little_endian {
+                    u16::from_le_bytes([pair[0], pair[1]])
+                } else {
+                    u16::from_be_bytes([pair[0], pair[1]])
+                }
+            });
+            char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid UTF-16 stream"))
+        }
+    }
+}
+
+/// Normalizes the line terminators of a text to the configured one
+///
+/// # Arguments
+///
+/// * `text` - The text to normalize
+/// * `config` - EditorConfig properties of the file
+///
+/// # Returns
+///
+/// The text unchanged when `end_of_line` is not set
+pub fn normalize_line_endings(text: &str, config: &EditorConfig) -> String {
+    match config.end_of_line {
+        Some(end_of_line) => text
+            .replace("\r\n", "\n")
+            .replace('\r', "\n")
+            .replace('\n', end_of_line.as_str()),
+        None => text.to_string(),
+    }
+}
+
+/// Encodes a text with the configured charset
+///
+/// Characters that Latin-1 can't represent are written as `?`.
+///
+/// # Arguments
+///
+/// * `text` - The text to encode, with its final line terminators
+/// * `config` - EditorConfig properties of the file
+pub fn encode(text: &str, config: &EditorConfig) -> Vec<u8> {
+    match config.charset {
+        None | Some(Charset::Utf8) => text.as_bytes().to_vec(),
+        Some(Charset::Utf8Bom) => {
+            let mut bytes = b"\xEF\xBB\xBF".to_vec();
+            bytes.extend_from_slice(text.as_bytes());
+            bytes
+        }
+        Some(Charset::Latin1) => text
+            .chars()
+            .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+            .collect(),
+        Some(Charset::Utf16Le) => {
+            let mut bytes = b"\xFF\xFE".to_vec();
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+            bytes
+        }
+        Some(Charset::Utf16Be) => {
+            let mut bytes = b"\xFE\xFF".to_vec();
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+            bytes
+        }
+    }
+}
+
+/// Applies the EditorConfig properties of a file to its text view
+///
+/// The indentation properties take precedence over the style detected from
+/// the buffer's content and over the settings. The properties are remembered for the status bar.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view showing the file
+/// * `config` - EditorConfig properties of the file
+pub fn apply_config(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, config: EditorConfig) {
+    let buffer = text_view.buffer();
+
+    if config.use_tabs.is_some() || config.indent_size.is_some() {
+        let (detected_tabs, detected_width) = match config.use_tabs {
+            Some(_) => (false, app_context.borrow().app_settings.borrow().tab_width),
+            None => crate::indentation::detect_indent_style(app_context, &buffer),
+        };
+        let style = (
+            config.use_tabs.unwrap_or(detected_tabs),
+            config.indent_size.unwrap_or(detected_width),
+        );
+        app_context.borrow().indent_styles.borrow_mut().insert(buffer.clone(), style);
+    }
+
+    if let Some(max_line_length) = config.max_line_length {
+        add_line_length_guide(text_view, max_line_length);
+    }
+
+    app_context.borrow().editor_configs.borrow_mut().insert(buffer, config);
+    crate::indentation::update_tab_stops(app_context, text_view);
+    update_status(app_context);
+}
+
+/// Draws a vertical guide after the given column of a text view
+///
+/// The guide is an overlay spanning the whole buffer, moved and resized
+/// whenever the buffer changes.
+fn add_line_length_guide(text_view: &TextView, column: usize) {
+    let guide = DrawingArea::new();
+    guide.set_content_width(1);
+    guide.set_can_target(false);
+    guide.set_draw_func(|_, cr, width, height| {
+        cr.set_source_rgba(0.5, 0.5, 0.5, 0.4);
+        cr.rectangle(0.0, 0.0, width as f64, height as f64);
+        let _ = cr.fill();
+    });
+    text_view.add_overlay(&guide, 0, 0);
+
+    let place_guide = move |text_view: &TextView, guide: &DrawingArea| {
+        let buffer = text_view.buffer();
+        let (y, height) = text_view.line_yrange(&buffer.end_iter());
+        let x = text_view.iter_location(&buffer.start_iter()).x()
+            + (column as f64 * crate::ui::helpers::char_width(text_view)) as i32;
+        guide.set_content_height((y + height).max(1));
+        text_view.move_overlay(guide, x, 0);
+    };
+    place_guide(text_view, &guide);
+
+    let text_view_clone = text_view.clone();
+    text_view.buffer().connect_changed(move |_| {
+        let text_view = text_view_clone.clone();
+        let guide = guide.clone();
+        // Line heights are only known once the view has been laid out again
+        glib::idle_add_local_once(move || place_guide(&text_view, &guide));
+    });
+}
+
+/// Shows the EditorConfig properties of the current tab in the status bar
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn update_status(app_context: &Rc<RefCell<AppContext>>) {
+    let context = app_context.borrow();
+    let summary = crate::ui::helpers::get_current_text_view(&context.notebook)
+        .and_then(|text_view| {
+            context
+                .editor_configs
+                .borrow()
+                .get(&text_view.buffer())
+                .filter(|config| !config.is_empty())
+                .map(EditorConfig::summary)
+        })
+        .unwrap_or_default();
+    context.editorconfig_status.set_text(&summary);
+}
+
+/// Forgets the EditorConfig properties of a closed buffer
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer that was closed
+pub fn forget_config(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    app_context.borrow().editor_configs.borrow_mut().remove(buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+    use std::fs;
+
+    /// Checks whether a section glob of `/project/.editorconfig` matches a path
+    fn matches(glob: &str, path: &str) -> bool {
+        section_regex(Path::new("/project"), glob).expect("valid regex").is_match(path)
+    }
+
+    fn to_regex(glob: &str) -> String {
+        glob_to_regex(&glob.chars().collect::<Vec<char>>())
+    }
+
+    #[test]
+    fn globs_without_a_slash_match_in_any_directory() {
+        assert!(matches("*.rs", "/project/main.rs"));
+        assert!(matches("*.rs", "/project/src/deep/main.rs"));
+        assert!(!matches("*.rs", "/project/main.rsx"));
+        assert!(!matches("*.rs", "/other/main.rs"));
+        assert!(matches("?.c", "/project/a.c"));
+        assert!(!matches("?.c", "/project/ab.c"));
+    }
+
+    #[test]
+    fn globs_with_a_slash_are_relative_to_the_file() {
+        assert!(matches("/*.md", "/project/README.md"));
+        assert!(!matches("/*.md", "/project/docs/guide.md"));
+        assert!(matches("src/*.rs", "/project/src/main.rs"));
+        assert!(!matches("src/*.rs", "/project/src/ui/main.rs"));
+    }
+
+    #[test]
+    fn double_stars_match_any_number_of_directories() {
+        assert_eq!(to_regex("**/a"), "(?:.*/)?a");
+        assert!(matches("src/**/*.rs", "/project/src/main.rs"));
+        assert!(matches("src/**/*.rs", "/project/src/ui/widgets/main.rs"));
+        assert!(!matches("src/**/*.rs", "/project/lib/main.rs"));
+        assert!(matches("docs/**", "/project/docs/a/b.txt"));
+    }
+
+    #[test]
+    fn braces_give_alternatives_and_ranges() {
+        assert_eq!(brace_to_regex(&['a', ',', 'b']), "(?:a|b)");
+        assert_eq!(brace_to_regex(&"1..3".chars().collect::<Vec<char>>()), "(?:1|2|3)");
+        assert_eq!(brace_to_regex(&"3..1".chars().collect::<Vec<char>>()), "(?:1|2|3)");
+        assert_eq!(brace_to_regex(&"0..5000".chars().collect::<Vec<char>>()), "[+-]?[0-9]+");
+        assert_eq!(to_regex("{a,{b,c}}"), "(?:a|(?:b|c))");
+
+        assert!(matches("*.{js,ts}", "/project/app.ts"));
+        assert!(!matches("*.{js,ts}", "/project/app.rs"));
+        assert!(matches("file{1..3}.txt", "/project/file2.txt"));
+        assert!(!matches("file{1..3}.txt", "/project/file4.txt"));
+        assert!(!matches("file{1..3}.txt", "/project/file12.txt"));
+        // Without a comma, braces are taken literally, and so are unclosed ones
+        assert!(matches("{single}", "/project/{single}"));
+        assert!(matches("{open", "/project/{open"));
+    }
+
+    #[test]
+    fn character_classes_and_escapes() {
+        assert!(matches("[ab].c", "/project/a.c"));
+        assert!(!matches("[ab].c", "/project/x.c"));
+        assert!(matches("[!x].c", "/project/a.c"));
+        assert!(!matches("[!x].c", "/project/x.c"));
+        assert!(matches("a\\*b", "/project/a*b"));
+        assert!(!matches("a\\*b", "/project/axb"));
+        assert!(matches("[", "/project/["));
+    }
+
+    #[test]
+    fn nearer_files_override_outer_ones_and_unset_removes() {
+        let root = temp_dir("editorconfig-override");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(
+            root.join(FILE_NAME),
+            "root = true\n[*]\nindent_style = space\nindent_size = 4\n\n[*.md]\ntrim_trailing_whitespace = false\n",
+        )
+        .unwrap();
+        fs::write(root.join("sub").join(FILE_NAME), "# Rust\n[*.rs]\nindent_size = unset\nEnd_Of_Line = CRLF\n").unwrap();
+
+        let config = config_for_path(&root.join("sub").join("main.rs"));
+        assert_eq!(config.use_tabs, Some(false));
+        assert_eq!(config.indent_size, None);
+        assert_eq!(config.tab_width, None);
+        assert_eq!(config.end_of_line, Some(EndOfLine::CrLf));
+
+        let config = config_for_path(&root.join("notes.md"));
+        assert_eq!(config.indent_size, Some(4));
+        assert_eq!(config.tab_width, Some(4));
+        assert_eq!(config.trim_trailing_whitespace, Some(false));
+        assert_eq!(config.end_of_line, None);
+    }
+
+    #[test]
+    fn the_search_stops_at_a_root_file() {
+        let outer = temp_dir("editorconfig-root");
+        fs::create_dir_all(outer.join("inner")).unwrap();
+        fs::create_dir_all(outer.join("other")).unwrap();
+        fs::write(outer.join(FILE_NAME), "root = true\n[*]\ncharset = latin1\n").unwrap();
+        fs::write(outer.join("inner").join(FILE_NAME), "root = true\n[*]\nindent_style = tab\n").unwrap();
+
+        let config = config_for_path(&outer.join("inner").join("a.c"));
+        assert_eq!(config.charset, None);
+        assert_eq!(config.use_tabs, Some(true));
+        let config = config_for_path(&outer.join("other").join("a.c"));
+        assert_eq!(config.charset, Some(Charset::Latin1));
+    }
+}
@@ -103,12 +103,8 @@ pub fn update_tab_label(
 }
 
 /// Opens a file chooser dialog for saving files
-pub fn save_file_dialog(
-    parent: gtk4::Window,
-    buffer: gtk4::TextBuffer,
-    buffer_paths: Rc<RefCell<std::collections::HashMap<gtk4::TextBuffer, PathBuf>>>,
-    notebook: Option<gtk4::Notebook>, // Optional notebook to update tab label
-) {
+pub fn save_file_dialog(app_context: &Rc<RefCell<AppContext>>, buffer: gtk4::TextBuffer) {
+    let parent = app_context.borrow().window.clone();
     let file_chooser = FileChooserDialog::builder()
         .title("Save File")
         .transient_for(&parent)
@@ -120,32 +116,34 @@ pub fn save_file_dialog(
     file_chooser.add_button("Save", ResponseType::Accept);
 
     // Clone values for the closure
+    let app_context_clone = app_context.clone();
     let buffer_clone = buffer.clone();
-    let buffer_paths_clone = buffer_paths.clone();
-    let notebook_clone = notebook.clone();
+    let buffer_paths_clone = app_context.borrow().buffer_paths.clone();
+    let notebook_clone = app_context.borrow().notebook.clone();
 
     file_chooser.connect_response(move |dialog, response| {
         if response == ResponseType::Accept {
             if let Some(file) = dialog.file() {
                 if let Some(path) = file.path() {
-                    // Save the buffer content to the file
-                    let start = buffer_clone.start_iter();
-                    let end = buffer_clone.end_iter();
-                    let content = buffer_clone.text(&start, &end, false).to_string();
+                    // Update the buffer_paths map with the new path first, so that
+                    // the undo history is persisted under it once the buffer is saved
+                    let previous_path = buffer_paths_clone
+                        .borrow_mut()
+                        .insert(buffer_clone.clone(), path.clone());
 
-                    match std::fs::write(&path, content) {
+                    // Save the buffer content to the file
+                    match crate::tab_manager::save_buffer_to_file(&app_context_clone, &buffer_clone, &path) {
                         Ok(_) => {
-                            // Update the buffer_paths map with the new path
-                            buffer_paths_clone
-                                .borrow_mut()
-                                .insert(buffer_clone.clone(), path.clone());
-
-                            // Update tab label with filename if notebook is provided
-                            if let Some(notebook) = &notebook_clone {
-                                update_tab_label(notebook, &buffer_clone, &path);
-                            }
+                            update_tab_label(&notebook_clone, &buffer_clone, &path);
+                            crate::lsp::open_document(&app_context_clone, &buffer_clone);
                         }
                         Err(e) => {
+                            let mut buffer_paths = buffer_paths_clone.borrow_mut();
+                            match previous_path {
+                                Some(previous_path) => buffer_paths.insert(buffer_clone.clone(), previous_path),
+                                None => buffer_paths.remove(&buffer_clone),
+                            };
+                            drop(buffer_paths);
                             crate::dialogs::show_error_dialog(
                                 &parent,
                                 "Error saving file",
@@ -220,12 +218,14 @@ pub fn populate_tree_view(parent: &impl IsA<gtk4::Window>, tree_store: &TreeStor
 /// Utility function to check if a buffer has unsaved changes
 pub fn is_buffer_modified(buffer: &gtk4::TextBuffer, file_path: Option<&PathBuf>) -> bool {
     if let Some(path) = file_path {
-        // Try to read the file content
-        if let Ok(content_on_disk) = std::fs::read_to_string(path) {
-            let buffer_content = buffer
-                .text(&buffer.start_iter(), &buffer.end_iter(), false)
-                .to_string();
-            return buffer_content != content_on_disk;
+        // Compare the file content with the bytes the buffer would be saved as
+        if let Ok(content_on_disk) = std::fs::read(path) {
+            let editor_config = crate::editorconfig::config_for_path(path);
+            let buffer_content = crate::editorconfig::normalize_line_endings(
+                &buffer.text(&buffer.start_iter(), &buffer.end_iter(), true),
+                &editor_config,
+            );
+            return crate::editorconfig::encode(&buffer_content, &editor_config) != content_on_disk;
         }
         // If we can't read the file, it might have been deleted
         // In that case, if the buffer has content, we consider it modified
@@ -233,5 +233,5 @@ pub fn is_buffer_modified(buffer: &gtk4::TextBuffer, file_path: Option<&PathBuf>
     // If file doesn't exist on disk or can't be read, consider it modified if it has content
     let start = buffer.start_iter();
     let end = buffer.end_iter();
-    !buffer.text(&start, &end, false).is_empty()
+    !buffer.text(&start, &end, true).is_empty()
 }
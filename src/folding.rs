@@ -0,0 +1,772 @@
+//! Module for code folding
+//!
+//! Foldable regions are computed from bracket pairs and multi-line comments,
+//! using the syntect scopes of the buffer's language to skip brackets inside
+//! strings and comments, and from indentation for languages without brackets.
+//! Folding a region keeps its first line visible and hides the following lines
+//! with the invisible `folded` tag. The folded lines of each file are stored in
+//! the settings when regions are folded or unfolded and when the tab is
+//! closed, so that they are restored when the file is opened again.
+//!
+//! The bracket and comment scan is resumed from the last line saved before an
+//! edit, with the parser states shared with the rest of the editor, so that
+//! only the edited part of a buffer is parsed again.
+
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextMark, TextView};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Duration;
+use syntect::parsing::{Scope, ScopeStack};
+
+use crate::AppContext;
+use crate::syntax_highlighting::{LineScan, ParsedLine};
+
+/// Delay after the last change before the regions are recomputed, in milliseconds
+const REFRESH_DELAY_MS: u64 = 300;
+/// Number of columns a tab counts for when comparing indentation
+const TAB_WIDTH: usize = 4;
+
+/// A foldable region
+///
+/// Folding the region hides the lines after `start_line` up to and including
+/// `end_line`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FoldRegion {
+    pub start_line: i32,
+    pub end_line: i32,
+}
+
+/// State of the bracket and comment scan at the start of a line
+#[derive(Clone, Default)]
+struct BracketScan {
+    /// Brackets still open, with the line of each
+    open_brackets: Vec<(char, i32)>,
+    /// First line of the comment the line is in
+    comment_start: Option<i32>,
+}
+
+/// Folding state of a buffer
+#[derive(Default)]
+pub struct FoldState {
+    /// Foldable regions, sorted by start line
+    regions: Vec<FoldRegion>,
+    /// Regions delimited by brackets and comments, found by a scan resumed after edits
+    syntax_scan: LineScan<BracketScan, FoldRegion>,
+    /// Path of the buffer's file as last seen, to save the folds once the tab is closed
+    path: Option<PathBuf>,
+    /// Marks at the start of the first line of each folded region
+    folded: Vec<TextMark>,
+    /// Folded lines restored from the settings, applied once the regions are known
+    pending_lines: Option<Vec<i32>>,
+    /// Folded lines last written to the settings
+    saved_lines: Vec<i32>,
+    /// Pending recomputation of the regions
+    refresh_source: Option<glib::SourceId>,
+}
+
+/// Folding state of each buffer
+pub type FoldStates = Rc<RefCell<HashMap<TextBuffer, FoldState>>>;
+
+/// Adds a region if it hides at least one line
+fn push_region(regions: &mut Vec<FoldRegion>, start_line: i32, end_line: i32) {
+    if end_line > start_line {
+        regions.push(FoldRegion { start_line, end_line });
+    }
+}
+
+/// Checks whether a scope stack contains a scope starting with a prefix
+fn has_scope(scope_stack: &ScopeStack, prefix: Scope) -> bool {
+    scope_stack.as_slice().iter().any(|scope| prefix.is_prefix_of(*scope))
+}
+
+/// Scans a line for the regions delimited by brackets and multi-line comments
+///
+/// The closing line of a bracket pair stays visible, and so does the line
+/// ending a comment when code follows the comment on it.
+///
+/// # Returns
+///
+/// Whether the scopes of the line could be followed
+fn scan_line(state: &mut BracketScan, regions: &mut Vec<FoldRegion>, parsed: ParsedLine<'_>) -> bool {
+    let string_scope = Scope::new("string").expect("valid scope");
+    let comment_scope = Scope::new("comment").expect("valid scope");
+    let line_number = parsed.line;
+    let mut scope_stack = parsed.scopes.clone();
+    let mut ops = parsed.ops.iter().peekable();
+
+    for (byte, c) in parsed.text.char_indices() {
+        while let Some((_, op)) = ops.next_if(|(index, _)| *index <= byte) {
+            if scope_stack.apply(op).is_err() {
+                return false;
+            }
+        }
+
+        let in_comment = has_scope(&scope_stack, comment_scope);
+        match (in_comment, state.comment_start) {
+            (true, None) => state.comment_start = Some(line_number),
+            (false, Some(start)) if !c.is_whitespace() => {
+                push_region(regions, start, line_number - 1);
+                state.comment_start = None;
+            }
+            _ => {}
+        }
+        if in_comment || has_scope(&scope_stack, string_scope) {
+            continue;
+        }
+
+        let opener = match c {
+            '(' | '[' | '{' => {
+                state.open_brackets.push((c, line_number));
+                continue;
+            }
+            ')' => '(',
+            ']' => '[',
+            '}' => '{',
+            _ => continue,
+        };
+        if let Some(position) = state.open_brackets.iter().rposition(|(open, _)| *open == opener) {
+            let (_, start) = state.open_brackets[position];
+            state.open_brackets.truncate(position);
+            push_region(regions, start, line_number - 1);
+        }
+    }
+
+    // Scope changes after the last character, such as the end of a line comment
+    for (_, op) in ops {
+        if scope_stack.apply(op).is_err() {
+            return false;
+        }
+    }
+    if let Some(start) = state.comment_start
+        && !has_scope(&scope_stack, comment_scope)
+    {
+        push_region(regions, start, line_number);
+        state.comment_start = None;
+    }
+    true
+}
+
+/// Returns the indentation width of a line, counting tabs as `TAB_WIDTH` columns
+fn indentation_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum()
+}
+
+/// Computes the regions formed by lines indented deeper than the line before them
+///
+/// Blank lines belong to the region around them, except at its end.
+fn indentation_regions(text: &str) -> Vec<FoldRegion> {
+    let mut regions = Vec::new();
+    let mut open: Vec<(i32, usize)> = Vec::new();
+    let mut last_non_blank = 0;
+
+    for (line_index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = line_index as i32;
+        let indent = indentation_width(line);
+        while let Some(&(start, _)) = open.last().filter(|(_, start_indent)| *start_indent >= indent) {
+            open.pop();
+            push_region(&mut regions, start, last_non_blank);
+        }
+        open.push((line_number, indent));
+        last_non_blank = line_number;
+    }
+    for (start, _) in open {
+        push_region(&mut regions, start, last_non_blank);
+    }
+    regions
+}
+
+/// Merges the bracket and comment regions of a text with its indentation regions,
+/// sorted by start line
+///
+/// At most one region starts on each line. Bracket and comment regions take
+/// precedence over indentation regions.
+fn merge_regions(syntax_regions: &[FoldRegion], text: &str) -> Vec<FoldRegion> {
+    let mut regions: BTreeMap<i32, i32> = BTreeMap::new();
+    for region in syntax_regions {
+        regions
+            .entry(region.start_line)
+            .and_modify(|end_line| *end_line = (*end_line).max(region.end_line))
+            .or_insert(region.end_line);
+    }
+    for region in indentation_regions(text) {
+        regions.entry(region.start_line).or_insert(region.end_line);
+    }
+    regions
+        .into_iter()
+        .map(|(start_line, end_line)| FoldRegion { start_line, end_line })
+        .collect()
+}
+
+/// Returns the region starting on a line
+fn region_at(regions: &[FoldRegion], line: i32) -> Option<FoldRegion> {
+    regions
+        .binary_search_by_key(&line, |region| region.start_line)
+        .ok()
+        .map(|index| regions[index])
+}
+
+/// Returns the nesting level of a region, 1 for top-level regions
+fn region_level(regions: &[FoldRegion], region: &FoldRegion) -> usize {
+    1 + regions
+        .iter()
+        .filter(|other| other.start_line < region.start_line && other.end_line >= region.end_line)
+        .count()
+}
+
+/// Returns the lines on which the folded regions of a state start
+fn folded_lines(buffer: &TextBuffer, state: &FoldState) -> Vec<i32> {
+    let mut lines: Vec<i32> = state
+        .folded
+        .iter()
+        .map(|mark| buffer.iter_at_mark(mark).line())
+        .collect();
+    lines.sort_unstable();
+    lines.dedup();
+    lines
+}
+
+/// Re-applies the `folded` tag to the folded regions of a buffer
+///
+/// Folds whose line no longer starts a region are dropped.
+fn apply_folds(buffer: &TextBuffer, state: &mut FoldState) {
+    buffer.remove_tag_by_name("folded", &buffer.start_iter(), &buffer.end_iter());
+    let regions = &state.regions;
+    let mut seen_lines = HashSet::new();
+    state.folded.retain(|mark| {
+        let line = buffer.iter_at_mark(mark).line();
+        let region = region_at(regions, line).filter(|_| seen_lines.insert(line));
+        let Some(region) = region else {
+            buffer.delete_mark(mark);
+            return false;
+        };
+        if let Some(start) = buffer.iter_at_line(region.start_line + 1) {
+            let end = buffer
+                .iter_at_line(region.end_line + 1)
+                .unwrap_or_else(|| buffer.end_iter());
+            buffer.apply_tag_by_name("folded", &start, &end);
+        }
+        true
+    });
+}
+
+/// Folds the region starting on a line, if there is one and it is not folded yet
+fn fold_line(buffer: &TextBuffer, state: &mut FoldState, line: i32) {
+    if region_at(&state.regions, line).is_none() || folded_lines(buffer, state).contains(&line) {
+        return;
+    }
+    if let Some(iter) = buffer.iter_at_line(line) {
+        state.folded.push(buffer.create_mark(None, &iter, true));
+    }
+}
+
+/// Unfolds every folded region matching a predicate
+fn unfold_where(buffer: &TextBuffer, state: &mut FoldState, predicate: impl Fn(&FoldRegion) -> bool) {
+    let regions = &state.regions;
+    state.folded.retain(|mark| {
+        let line = buffer.iter_at_mark(mark).line();
+        if region_at(regions, line).is_some_and(|region| predicate(&region)) {
+            buffer.delete_mark(mark);
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Queues a redraw of the line numbers area next to a text view
+fn queue_gutter_draw(text_view: &TextView) {
+    if let Some(line_numbers_area) = text_view
+        .parent()
+        .and_then(|scrolled_window| scrolled_window.parent())
+        .and_then(|tab_box| tab_box.first_child())
+    {
+        line_numbers_area.queue_draw();
+    }
+}
+
+/// Writes the folded lines of a buffer to the settings if they changed
+///
+/// The lines are stored under the path of the buffer's file, as last seen by
+/// the state.
+fn save_folds(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer, state: &mut FoldState) {
+    let context = app_context.borrow();
+    if let Some(path) = context.buffer_paths.borrow().get(buffer) {
+        state.path = Some(path.clone());
+    }
+    // Folds restored from the settings but not applied yet are still saved
+    let Some(path) = state.path.clone().filter(|_| state.pending_lines.is_none()) else {
+        return;
+    };
+    let lines = folded_lines(buffer, state);
+    if lines == state.saved_lines {
+        return;
+    }
+
+    let mut app_settings = context.app_settings.borrow_mut();
+    if lines.is_empty() {
+        app_settings.folded_lines.remove(&path);
+    } else {
+        app_settings.folded_lines.insert(path, lines.clone());
+    }
+    crate::settings::save_settings(&app_settings);
+    state.saved_lines = lines;
+}
+
+/// Moves the cursor out of folded text, to the end of the first line of its fold
+fn move_cursor_out_of_folds(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let cursor_line = buffer.iter_at_mark(&buffer.get_insert()).line();
+    let header_line = {
+        let context = app_context.borrow();
+        let fold_states = context.fold_states.borrow();
+        let Some(state) = fold_states.get(buffer) else {
+            return;
+        };
+        folded_lines(buffer, state)
+            .into_iter()
+            .filter_map(|line| region_at(&state.regions, line))
+            .find(|region| region.start_line < cursor_line && cursor_line <= region.end_line)
+            .map(|region| region.start_line)
+    };
+    if let Some(mut iter) = header_line.and_then(|line| buffer.iter_at_line(line)) {
+        if !iter.ends_line() {
+            iter.forward_to_line_end();
+        }
+        buffer.place_cursor(&iter);
+    }
+}
+
+/// Applies a change to the folding state of a text view's buffer
+///
+/// The folds are re-applied, the cursor is moved out of any folded text and
+/// the folded lines are saved.
+fn change_folds(
+    app_context: &Rc<RefCell<AppContext>>,
+    text_view: &TextView,
+    change: impl FnOnce(&TextBuffer, &mut FoldState),
+) {
+    let buffer = text_view.buffer();
+    {
+        let context = app_context.borrow();
+        let mut fold_states = context.fold_states.borrow_mut();
+        let Some(state) = fold_states.get_mut(&buffer) else {
+            return;
+        };
+        change(&buffer, state);
+        apply_folds(&buffer, state);
+    }
+    move_cursor_out_of_folds(app_context, &buffer);
+    {
+        let context = app_context.borrow();
+        if let Some(state) = context.fold_states.borrow_mut().get_mut(&buffer) {
+            save_folds(app_context, &buffer, state);
+        }
+    }
+    queue_gutter_draw(text_view);
+}
+
+/// Recomputes the regions of a text view's buffer and re-applies its folds
+///
+/// The folded lines aren't saved here, as their lines only move with edits.
+fn refresh_regions(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let Some(mut syntax_scan) = app_context
+        .borrow()
+        .fold_states
+        .borrow_mut()
+        .get_mut(&buffer)
+        .map(|state| std::mem::take(&mut state.syntax_scan))
+    else {
+        return;
+    };
+    crate::syntax_highlighting::scan_lines(app_context, &buffer, &mut syntax_scan, scan_line);
+    let text = buffer
+        .text(&buffer.start_iter(), &buffer.end_iter(), true)
+        .to_string();
+    let regions = merge_regions(&syntax_scan.items, &text);
+    {
+        let context = app_context.borrow();
+        let mut fold_states = context.fold_states.borrow_mut();
+        let Some(state) = fold_states.get_mut(&buffer) else {
+            return;
+        };
+        state.refresh_source = None;
+        state.syntax_scan = syntax_scan;
+        state.regions = regions;
+        if let Some(lines) = state.pending_lines.take() {
+            state.saved_lines = lines.clone();
+            for line in lines {
+                fold_line(&buffer, state, line);
+            }
+        }
+        apply_folds(&buffer, state);
+    }
+    reveal_cursor(app_context, &buffer);
+    queue_gutter_draw(text_view);
+}
+
+/// Unfolds the folded regions hiding the cursor
+fn reveal_cursor(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let Some(folded_tag) = buffer.tag_table().lookup("folded") else {
+        return;
+    };
+    if !cursor.has_tag(&folded_tag) {
+        return;
+    }
+    let cursor_line = cursor.line();
+    let context = app_context.borrow();
+    let mut fold_states = context.fold_states.borrow_mut();
+    if let Some(state) = fold_states.get_mut(buffer) {
+        unfold_where(buffer, state, |region| {
+            region.start_line < cursor_line && cursor_line <= region.end_line
+        });
+        apply_folds(buffer, state);
+    }
+}
+
+/// Connects folding to the buffer of a text view
+///
+/// The regions are recomputed shortly after each change, rescanning from the
+/// edited lines, and the folds saved for the buffer's file are restored once
+/// the regions are first known. When
+/// the cursor moves into folded text, for example through a search, the folds
+/// hiding it are opened.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view whose buffer gets folding
+pub fn connect_folding(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    {
+        let context = app_context.borrow();
+        let path = context.buffer_paths.borrow().get(&buffer).cloned();
+        let pending_lines = path
+            .as_ref()
+            .and_then(|path| context.app_settings.borrow().folded_lines.get(path).cloned());
+        context.fold_states.borrow_mut().insert(
+            buffer.clone(),
+            FoldState {
+                path,
+                pending_lines,
+                ..Default::default()
+            },
+        );
+    }
+
+    let fold_states = app_context.borrow().fold_states.clone();
+    buffer.connect_insert_text(move |buffer, pos, _| {
+        if let Some(state) = fold_states.borrow_mut().get_mut(buffer) {
+            state.syntax_scan.invalidate_from(pos.line());
+        }
+    });
+    let fold_states = app_context.borrow().fold_states.clone();
+    buffer.connect_delete_range(move |buffer, start, _| {
+        if let Some(state) = fold_states.borrow_mut().get_mut(buffer) {
+            state.syntax_scan.invalidate_from(start.line());
+        }
+    });
+
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    buffer.connect_changed(move |_| {
+        schedule_refresh(&app_context_clone, &text_view_clone, REFRESH_DELAY_MS);
+    });
+
+    let app_context_clone = app_context.clone();
+    buffer.connect_mark_set(move |buffer, _, mark| {
+        if *mark != buffer.get_insert() {
+            return;
+        }
+        let app_context = app_context_clone.clone();
+        let buffer = buffer.clone();
+        glib::idle_add_local_once(move || {
+            if let Some(text_view) = crate::ui::helpers::get_current_text_view(&app_context.borrow().notebook)
+                && text_view.buffer() == buffer
+            {
+                reveal_cursor(&app_context, &buffer);
+                queue_gutter_draw(&text_view);
+            }
+        });
+    });
+
+    schedule_refresh(app_context, text_view, 0);
+}
+
+/// Schedules the recomputation of the regions of a text view's buffer
+fn schedule_refresh(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, delay_ms: u64) {
+    let buffer = text_view.buffer();
+    let context = app_context.borrow();
+    let mut fold_states = context.fold_states.borrow_mut();
+    let Some(state) = fold_states.get_mut(&buffer) else {
+        return;
+    };
+    if let Some(source) = state.refresh_source.take() {
+        source.remove();
+    }
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    state.refresh_source = Some(glib::timeout_add_local_once(
+        Duration::from_millis(delay_ms),
+        move || refresh_regions(&app_context_clone, &text_view_clone),
+    ));
+}
+
+/// Saves the folded lines of a buffer and forgets its folding state, once its
+/// tab is closed
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer of the closed tab
+pub fn forget_folds(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let state = app_context.borrow().fold_states.borrow_mut().remove(buffer);
+    let Some(mut state) = state else {
+        return;
+    };
+    if let Some(source) = state.refresh_source.take() {
+        source.remove();
+    }
+    save_folds(app_context, buffer, &mut state);
+}
+
+/// Returns the lines starting a region, with whether each region is folded
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to look up
+pub fn fold_markers(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> HashMap<i32, bool> {
+    let context = app_context.borrow();
+    let fold_states = context.fold_states.borrow();
+    let Some(state) = fold_states.get(buffer) else {
+        return HashMap::new();
+    };
+    let folded = folded_lines(buffer, state);
+    state
+        .regions
+        .iter()
+        .map(|region| (region.start_line, folded.contains(&region.start_line)))
+        .collect()
+}
+
+/// Folds or unfolds the region starting on a line
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to fold in
+/// * `line` - The first line of the region
+pub fn toggle_fold(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, line: i32) {
+    change_folds(app_context, text_view, |buffer, state| {
+        if folded_lines(buffer, state).contains(&line) {
+            unfold_where(buffer, state, |region| region.start_line == line);
+        } else {
+            fold_line(buffer, state, line);
+        }
+    });
+}
+
+/// Folds the innermost unfolded region containing the cursor
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to fold in
+pub fn fold_at_cursor(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let cursor_line = text_view.buffer().iter_at_mark(&text_view.buffer().get_insert()).line();
+    change_folds(app_context, text_view, |buffer, state| {
+        let folded = folded_lines(buffer, state);
+        let region = state
+            .regions
+            .iter()
+            .rev()
+            .find(|region| {
+                region.start_line <= cursor_line
+                    && cursor_line <= region.end_line
+                    && !folded.contains(&region.start_line)
+            })
+            .copied();
+        if let Some(region) = region {
+            fold_line(buffer, state, region.start_line);
+        }
+    });
+}
+
+/// Unfolds the folded region starting on the cursor's line, or containing it
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to unfold in
+pub fn unfold_at_cursor(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let cursor_line = text_view.buffer().iter_at_mark(&text_view.buffer().get_insert()).line();
+    change_folds(app_context, text_view, |buffer, state| {
+        let folded = folded_lines(buffer, state);
+        let region = state
+            .regions
+            .iter()
+            .rev()
+            .find(|region| {
+                region.start_line <= cursor_line
+                    && cursor_line <= region.end_line
+                    && folded.contains(&region.start_line)
+            })
+            .copied();
+        if let Some(region) = region {
+            unfold_where(buffer, state, |other| *other == region);
+        }
+    });
+}
+
+/// Folds every region
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to fold in
+pub fn fold_all(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    fold_to_level(app_context, text_view, 1);
+}
+
+/// Unfolds every region
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to unfold in
+pub fn unfold_all(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    change_folds(app_context, text_view, |buffer, state| {
+        unfold_where(buffer, state, |_| true);
+    });
+}
+
+/// Folds the regions nested at a level or deeper, unfolding the others
+///
+/// Level 1 folds the top-level regions, leaving only the outline visible.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to fold in
+/// * `level` - The shallowest level to fold, starting at 1
+pub fn fold_to_level(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, level: usize) {
+    change_folds(app_context, text_view, |buffer, state| {
+        let regions = state.regions.clone();
+        unfold_where(buffer, state, |region| region_level(&regions, region) < level);
+        let mut folded: HashSet<i32> = folded_lines(buffer, state).into_iter().collect();
+        for region in &regions {
+            if region_level(&regions, region) >= level
+                && folded.insert(region.start_line)
+                && let Some(iter) = buffer.iter_at_line(region.start_line)
+            {
+                state.folded.push(buffer.create_mark(None, &iter, true));
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax_highlighting::ScopeCache;
+    use syntect::parsing::SyntaxSet;
+
+    fn region(start_line: i32, end_line: i32) -> FoldRegion {
+        FoldRegion { start_line, end_line }
+    }
+
+    /// Scans lines with a syntax, resuming a previous scan
+    fn resume_scan(
+        scan: &mut LineScan<BracketScan, FoldRegion>,
+        cache: &mut ScopeCache,
+        syntax_name: &str,
+        lines: &[String],
+    ) -> Vec<FoldRegion> {
+        let ps = SyntaxSet::load_defaults_newlines();
+        let syntax = ps.find_syntax_by_name(syntax_name).expect("a default syntax");
+        let line_text = |line: i32| lines.get(line as usize).cloned().unwrap_or_default();
+        scan.resume(cache, syntax, &ps, line_text, lines.len() as i32 - 1, scan_line);
+        let mut regions = scan.items.clone();
+        regions.sort_by_key(|region| region.start_line);
+        regions
+    }
+
+    /// Returns the bracket and comment regions of a text
+    fn syntax_regions(syntax_name: &str, text: &str) -> Vec<FoldRegion> {
+        let lines: Vec<String> = text.split_inclusive('\n').map(str::to_string).collect();
+        resume_scan(&mut LineScan::default(), &mut ScopeCache::default(), syntax_name, &lines)
+    }
+
+    #[test]
+    fn indented_blocks_are_regions() {
+        let text = "def f():\n    a = 1\n\n    if a:\n        b = 2\n\nc = 3\n";
+        assert_eq!(indentation_regions(text), vec![region(3, 4), region(0, 4)]);
+        // Tabs count as several columns
+        assert_eq!(indentation_regions("a:\n\tb\n    c\nd\n"), vec![region(0, 2)]);
+        assert_eq!(indentation_regions("a\nb\n"), Vec::new());
+    }
+
+    #[test]
+    fn brackets_and_block_comments_are_regions() {
+        let text = "/* one\n   two */\nfn main() {\n    let v = [\n        1,\n    ];\n}\n";
+        assert_eq!(syntax_regions("Rust", text), vec![region(0, 1), region(2, 5), region(3, 4)]);
+    }
+
+    #[test]
+    fn brackets_in_strings_and_comments_are_ignored() {
+        let text = "fn main() {\n    let s = \"{\";\n    // (\n    call(\n        1)\n}\n";
+        assert_eq!(syntax_regions("Rust", text), vec![region(0, 4)]);
+    }
+
+    #[test]
+    fn code_after_a_comment_keeps_its_last_line_visible() {
+        let text = "int a; /* one\ntwo\nthree */ int b;\nint c;\n";
+        assert_eq!(syntax_regions("C", text), vec![region(0, 1)]);
+        let text = "/* one\ntwo\nthree */  \nint c;\n";
+        assert_eq!(syntax_regions("C", text), vec![region(0, 2)]);
+    }
+
+    #[test]
+    fn a_resumed_scan_matches_a_full_scan() {
+        let mut lines = vec!["fn main() {\n".to_string()];
+        for index in 0..120 {
+            lines.push(format!("    if x{} {{\n", index));
+            lines.push("        y();\n".to_string());
+            lines.push("    }\n".to_string());
+        }
+        lines.push("}\n".to_string());
+
+        let mut scan = LineScan::default();
+        let mut cache = ScopeCache::default();
+        resume_scan(&mut scan, &mut cache, "Rust", &lines);
+
+        // Opening a block comment over several checkpoints changes the later regions
+        lines[130] = "    /*\n".to_string();
+        lines[250] = "    */\n".to_string();
+        scan.invalidate_from(130);
+        cache.invalidate_from(130);
+        let resumed = resume_scan(&mut scan, &mut cache, "Rust", &lines);
+        let text = lines.concat();
+        assert_eq!(resumed, syntax_regions("Rust", &text));
+        assert!(resumed.contains(&region(130, 250)));
+    }
+
+    #[test]
+    fn bracket_regions_take_precedence_over_indentation() {
+        let text = "a {\n  b\n  c\n}\n";
+        let merged = merge_regions(&[region(0, 2)], text);
+        assert_eq!(merged, vec![region(0, 2)]);
+        let merged = merge_regions(&[], "a\n  b\n  c\n");
+        assert_eq!(merged, vec![region(0, 2)]);
+    }
+}
@@ -0,0 +1,503 @@
+//! Module for formatting documents with external formatters
+//!
+//! Each language can name a formatter command in its settings, such as
+//! `rustfmt --emit stdout` or `black -`. The document is written to the
+//! formatter's standard input and replaced by its standard output. Only the
+//! lines that differ are replaced, so the cursor and the marks on the other
+//! lines keep their positions. Whatever the formatter writes on its standard
+//! error is reported as diagnostics.
+
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextView};
+use regex::Regex;
+use similar::{DiffTag, TextDiff};
+use std::cell::RefCell;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use crate::diagnostics::{Diagnostic, set_diagnostics};
+use crate::problems::{self, Problem, Severity};
+use crate::AppContext;
+
+/// Source name of the problems reported by formatters
+const PROBLEM_SOURCE: &str = "formatter";
+
+/// Placeholder replaced by the path of the file in formatter commands
+const FILE_PLACEHOLDER: &str = "{file}";
+
+/// Longest time a formatter may take before a save
+const SAVE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Output of a formatter run: its standard output and standard error
+type FormatterOutput = Result<(Option<glib::GString>, Option<glib::GString>), glib::Error>;
+
+/// A formatter command ready to be spawned
+struct FormatterCommand {
+    /// Program and arguments
+    argv: Vec<OsString>,
+    /// Directory the formatter runs in, so that it finds its configuration files
+    cwd: Option<PathBuf>,
+}
+
+/// Builds the formatter command of a buffer's language
+///
+/// # Returns
+///
+/// `Ok(None)` if the language has no formatter, or an error message if the
+/// command can't be used
+fn formatter_command(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    file_path: Option<&Path>,
+) -> Result<Option<FormatterCommand>, String> {
+    let Some(command_line) = crate::syntax_highlighting::language_settings_for_buffer(app_context, buffer).formatter
+    else {
+        return Ok(None);
+    };
+    let argv = glib::shell_parse_argv(command_line.as_str())
+        .map_err(|e| format!("Invalid formatter command \"{}\": {}", command_line, e))?;
+    if argv.is_empty() {
+        return Ok(None);
+    }
+
+    let argv = argv
+        .into_iter()
+        .map(|arg| match arg.to_str() {
+            Some(text) if text.contains(FILE_PLACEHOLDER) => {
+                let path = file_path.ok_or("The formatter needs the file to be saved first")?;
+                Ok(OsString::from(text.replace(FILE_PLACEHOLDER, &path.to_string_lossy())))
+            }
+            _ => Ok(arg),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let cwd = file_path.and_then(Path::parent).map(Path::to_path_buf);
+    Ok(Some(FormatterCommand { argv, cwd }))
+}
+
+/// Starts a formatter with pipes for its standard streams
+fn spawn(command: &FormatterCommand) -> Result<gio::Subprocess, glib::Error> {
+    let launcher = gio::SubprocessLauncher::new(
+        gio::SubprocessFlags::STDIN_PIPE | gio::SubprocessFlags::STDOUT_PIPE | gio::SubprocessFlags::STDERR_PIPE,
+    );
+    if let Some(cwd) = &command.cwd {
+        launcher.set_cwd(cwd);
+    }
+    let argv: Vec<&std::ffi::OsStr> = command.argv.iter().map(OsString::as_os_str).collect();
+    launcher.spawn(&argv)
+}
+
+/// Position in formatter output, as `line:column`
+static POSITION: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b(\d+):(\d+)\b").expect("valid regex"));
+
+/// Turns the standard error of a formatter into diagnostics
+///
+/// Compiler-style messages are recognized first. Otherwise the output is split
+/// into blocks separated by blank lines, each making one diagnostic described
+/// by its first line and placed at the first `line:column` pair it contains,
+/// or at the start of the file.
+fn parse_diagnostics(stderr: &str, path: Option<&Path>, severity: Severity) -> Vec<Diagnostic> {
+    let mut diagnostics =
+        crate::diagnostics::parse_compiler_output(stderr, path.and_then(Path::parent), path, PROBLEM_SOURCE);
+    if diagnostics.is_empty() {
+        diagnostics = stderr
+            .split("\n\n")
+            .filter_map(|block| {
+                let message = block.lines().map(str::trim).find(|line| !line.is_empty())?;
+                let captures = POSITION.captures(block);
+                let number = |index: usize| {
+                    captures
+                        .as_ref()
+                        .and_then(|captures| captures[index].parse::<i32>().ok())
+                        .map_or(0, |number| (number - 1).max(0))
+                };
+                Some(Diagnostic {
+                    path: None,
+                    start: (number(1), number(2)),
+                    end: (number(1), number(2)),
+                    severity,
+                    message: message.to_string(),
+                    source: PROBLEM_SOURCE.to_string(),
+                })
+            })
+            .collect();
+    }
+    // The formatter only sees the formatted file, whatever name it gives it
+    for diagnostic in &mut diagnostics {
+        diagnostic.path = path.map(Path::to_path_buf);
+    }
+    diagnostics
+}
+
+/// Reports the outcome of a formatter run and extracts the formatted text
+///
+/// # Returns
+///
+/// The formatted text if the formatter succeeded
+fn finish(
+    app_context: &Rc<RefCell<AppContext>>,
+    file_path: Option<&Path>,
+    subprocess: &gio::Subprocess,
+    result: FormatterOutput,
+) -> Option<String> {
+    let (stdout, stderr) = match result {
+        Ok(output) => output,
+        Err(e) => {
+            report_error(app_context, file_path, format!("Failed to run the formatter: {}", e));
+            return None;
+        }
+    };
+    let stderr = stderr.map(|stderr| stderr.to_string()).unwrap_or_default();
+
+    if subprocess.is_successful() {
+        let diagnostics = parse_diagnostics(&stderr, file_path, Severity::Warning);
+        set_diagnostics(app_context, PROBLEM_SOURCE, file_path, diagnostics, true);
+        Some(stdout.map(|stdout| stdout.to_string()).unwrap_or_default())
+    } else {
+        let diagnostics = parse_diagnostics(&stderr, file_path, Severity::Error);
+        if diagnostics.is_empty() {
+            report_error(
+                app_context,
+                file_path,
+                format!("The formatter exited with status {}", subprocess.exit_status()),
+            );
+        } else {
+            set_diagnostics(app_context, PROBLEM_SOURCE, file_path, diagnostics, true);
+        }
+        None
+    }
+}
+
+/// Reports a problem that prevented the formatter from running
+fn report_error(app_context: &Rc<RefCell<AppContext>>, file_path: Option<&Path>, message: String) {
+    report(app_context, file_path, Severity::Error, message);
+}
+
+/// Reports a single formatter problem without a position
+///
+/// The diagnostics of the previous run are removed, as they are out of date.
+fn report(app_context: &Rc<RefCell<AppContext>>, file_path: Option<&Path>, severity: Severity, message: String) {
+    set_diagnostics(app_context, PROBLEM_SOURCE, file_path, Vec::new(), false);
+    let problem = Problem {
+        source: PROBLEM_SOURCE.to_string(),
+        path: file_path.map(Path::to_path_buf),
+        line: None,
+        column: None,
+        severity,
+        message,
+    };
+    problems::set_problems(app_context, PROBLEM_SOURCE, file_path, vec![problem]);
+}
+
+/// Replaces the text of a buffer, editing only the lines that differ
+///
+/// The edit is a single undoable action. A cursor on a replaced line moves to
+/// the same line and column of the replacement, as far as it exists.
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer to edit
+/// * `new_text` - The new text of the buffer
+pub fn apply_formatted_text(buffer: &TextBuffer, new_text: &str) {
+    let old_text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string();
+    if old_text == new_text {
+        return;
+    }
+    let diff = TextDiff::from_lines(old_text.as_str(), new_text);
+    let old_lines = diff.old_slices();
+    let new_lines = diff.new_slices();
+
+    // Character offset of the start of each old line, plus the end of the text
+    let mut line_offsets = Vec::with_capacity(old_lines.len() + 1);
+    let mut offset = 0;
+    line_offsets.push(offset);
+    for line in old_lines {
+        offset += line.chars().count() as i32;
+        line_offsets.push(offset);
+    }
+
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let (cursor_line, cursor_column) = (cursor.line() as usize, cursor.line_offset());
+    let mut moved_cursor = None;
+
+    buffer.begin_user_action();
+    for op in diff.ops().iter().rev() {
+        if op.tag() == DiffTag::Equal {
+            continue;
+        }
+        let (old_range, new_range) = (op.old_range(), op.new_range());
+        if old_range.contains(&cursor_line) && !new_range.is_empty() {
+            let line = new_range.start + (cursor_line - old_range.start).min(new_range.len() - 1);
+            moved_cursor = Some(line as i32);
+        }
+        let mut start = buffer.iter_at_offset(line_offsets[old_range.start]);
+        let mut end = buffer.iter_at_offset(line_offsets[old_range.end]);
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &new_lines[new_range].concat());
+    }
+    buffer.end_user_action();
+
+    if let Some(line) = moved_cursor
+        && let Some(mut iter) = buffer.iter_at_line(line)
+    {
+        let mut line_end = iter;
+        if !line_end.ends_line() {
+            line_end.forward_to_line_end();
+        }
+        iter.set_line_offset(cursor_column.min(line_end.line_offset()));
+        buffer.place_cursor(&iter);
+    }
+}
+
+/// Formats the document of a text view with its language's formatter
+///
+/// The formatter runs in the background. Its output is discarded if the
+/// document changes before it finishes.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view showing the document
+pub fn format_document(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let file_path = app_context.borrow().buffer_paths.borrow().get(&buffer).cloned();
+    let command = match formatter_command(app_context, &buffer, file_path.as_deref()) {
+        Ok(Some(command)) => command,
+        Ok(None) => {
+            let language = crate::syntax_highlighting::syntax_name_for_buffer(app_context, &buffer);
+            report(
+                app_context,
+                file_path.as_deref(),
+                Severity::Info,
+                format!("No formatter is configured for {}", language),
+            );
+            return;
+        }
+        Err(message) => {
+            report_error(app_context, file_path.as_deref(), message);
+            return;
+        }
+    };
+    let subprocess = match spawn(&command) {
+        Ok(subprocess) => subprocess,
+        Err(e) => {
+            report_error(app_context, file_path.as_deref(), format!("Failed to run the formatter: {}", e));
+            return;
+        }
+    };
+
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string();
+    let app_context_clone = app_context.clone();
+    let subprocess_clone = subprocess.clone();
+    subprocess.communicate_utf8_async(Some(text.clone()), None::<&gio::Cancellable>, move |result| {
+        let formatted = finish(&app_context_clone, file_path.as_deref(), &subprocess_clone, result);
+        let unchanged = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true) == text;
+        if let Some(formatted) = formatted
+            && unchanged
+        {
+            apply_formatted_text(&buffer, &formatted);
+        }
+    });
+}
+
+/// Writes a text to a formatter and waits for its output
+///
+/// The main loop runs meanwhile, so that the window stays responsive and the
+/// timeout can cancel the run. A formatter that is cancelled is killed.
+/// Saving the buffer again or closing its tab meanwhile is refused by
+/// [`crate::tab_manager::save_buffer_to_file`] and its callers.
+///
+/// # Arguments
+///
+/// * `subprocess` - The formatter
+/// * `text` - The text to format
+/// * `timeout` - Longest time the formatter may take
+///
+/// # Returns
+///
+/// The output of the formatter, or `None` if it didn't finish in time
+fn communicate_with_timeout(subprocess: &gio::Subprocess, text: &str, timeout: Duration) -> Option<FormatterOutput> {
+    let cancellable = gio::Cancellable::new();
+    let cancellable_clone = cancellable.clone();
+    let timeout_id = glib::timeout_add_local_once(timeout, move || cancellable_clone.cancel());
+
+    let output: Rc<RefCell<Option<FormatterOutput>>> = Rc::new(RefCell::new(None));
+    let output_clone = output.clone();
+    subprocess.communicate_utf8_async(Some(text.to_string()), Some(&cancellable), move |result| {
+        *output_clone.borrow_mut() = Some(result);
+    });
+    let main_context = glib::MainContext::default();
+    while output.borrow().is_none() {
+        main_context.iteration(true);
+    }
+
+    let output = output.take().expect("the formatter finished");
+    if !cancellable.is_cancelled() {
+        timeout_id.remove();
+    } else if output.as_ref().is_err_and(|e| e.matches(gio::IOErrorEnum::Cancelled)) {
+        subprocess.force_exit();
+        return None;
+    }
+    Some(output)
+}
+
+/// Formats a buffer about to be saved, if its language asks for it
+///
+/// The save waits for the formatter, so that the formatted text is saved. If
+/// the formatter fails, takes too long, or the buffer is edited meanwhile, the
+/// buffer is saved as it is.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer about to be saved
+/// * `file_path` - Path of the file the buffer is saved to
+pub fn format_before_save(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer, file_path: &Path) {
+    if !crate::syntax_highlighting::language_settings_for_buffer(app_context, buffer).format_on_save {
+        return;
+    }
+    let command = match formatter_command(app_context, buffer, Some(file_path)) {
+        Ok(Some(command)) => command,
+        Ok(None) => return,
+        Err(message) => {
+            report_error(app_context, Some(file_path), message);
+            return;
+        }
+    };
+    let subprocess = match spawn(&command) {
+        Ok(subprocess) => subprocess,
+        Err(e) => {
+            report_error(app_context, Some(file_path), format!("Failed to run the formatter: {}", e));
+            return;
+        }
+    };
+
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+    let Some(result) = communicate_with_timeout(&subprocess, &text, SAVE_TIMEOUT) else {
+        let message = format!(
+            "The formatter didn't finish within {} seconds, so the file was saved unformatted",
+            SAVE_TIMEOUT.as_secs()
+        );
+        report(app_context, Some(file_path), Severity::Warning, message);
+        return;
+    };
+    let formatted = finish(app_context, Some(file_path), &subprocess, result);
+    let unchanged = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true) == text;
+    if let Some(formatted) = formatted
+        && unchanged
+    {
+        apply_formatted_text(buffer, &formatted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{fixture, run_on_main_thread, run_with_gtk};
+    use gtk4::TextMark;
+
+    /// Runs `tests/fixtures/fake_formatter.py` on a text
+    ///
+    /// # Returns
+    ///
+    /// The formatted text, or `None` if the formatter didn't finish in time
+    fn run_fake_formatter(text: &str, args: &[&str], timeout: Duration) -> Option<String> {
+        let mut argv = vec![OsString::from("python3"), fixture("fake_formatter.py").into_os_string()];
+        argv.extend(args.iter().map(OsString::from));
+        let subprocess = spawn(&FormatterCommand { argv, cwd: None }).expect("python3 runs the fake formatter");
+        let (stdout, _) = communicate_with_timeout(&subprocess, text, timeout)?.expect("the fake formatter runs");
+        assert!(subprocess.is_successful());
+        Some(stdout.map(String::from).unwrap_or_default())
+    }
+
+    /// Formats a buffer with the fake formatter
+    fn format_buffer(buffer: &TextBuffer) {
+        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+        let formatted = run_fake_formatter(&text, &[], Duration::from_secs(5)).expect("the fake formatter finishes");
+        apply_formatted_text(buffer, &formatted);
+    }
+
+    /// Creates a buffer with a text and the cursor at a line and column
+    fn buffer_with_cursor(text: &str, line: i32, column: i32) -> TextBuffer {
+        let buffer = TextBuffer::new(None);
+        buffer.set_text(text);
+        buffer.place_cursor(&buffer.iter_at_line_offset(line, column).unwrap());
+        buffer
+    }
+
+    /// Returns the line and column of a mark
+    fn position(buffer: &TextBuffer, mark: &TextMark) -> (i32, i32) {
+        let iter = buffer.iter_at_mark(mark);
+        (iter.line(), iter.line_offset())
+    }
+
+    #[test]
+    fn output_of_the_formatter_is_read() {
+        run_on_main_thread(|| {
+            let formatted = run_fake_formatter("\tlet é = \"日本😀\";  \n", &[], Duration::from_secs(5));
+            assert_eq!(formatted.as_deref(), Some("    let é = \"日本😀\";\n"));
+        });
+    }
+
+    #[test]
+    fn a_formatter_that_hangs_is_cancelled() {
+        run_on_main_thread(|| {
+            let start = std::time::Instant::now();
+            assert_eq!(run_fake_formatter("x\n", &["--sleep", "30"], Duration::from_millis(200)), None);
+            assert!(start.elapsed() < Duration::from_secs(5));
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn marks_on_unchanged_lines_keep_their_position() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_cursor("fn main() {\n\tlet é = \"日本😀\";   \n    call();\n}\n", 2, 6);
+            let before = buffer.create_mark(None, &buffer.iter_at_line_offset(0, 3).unwrap(), true);
+            let after = buffer.create_mark(None, &buffer.iter_at_line_offset(3, 1).unwrap(), false);
+            format_buffer(&buffer);
+
+            assert_eq!(
+                buffer.text(&buffer.start_iter(), &buffer.end_iter(), true),
+                "fn main() {\n    let é = \"日本😀\";\n    call();\n}\n"
+            );
+            assert_eq!(position(&buffer, &buffer.get_insert()), (2, 6));
+            assert_eq!(position(&buffer, &before), (0, 3));
+            assert_eq!(position(&buffer, &after), (3, 1));
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn cursor_on_a_changed_line_stays_on_it() {
+        run_with_gtk(|| {
+            // The cursor keeps its column rather than the character it was at
+            let buffer = buffer_with_cursor("a\n\t日本😀  \nb\n", 1, 6);
+            format_buffer(&buffer);
+
+            assert_eq!(buffer.text(&buffer.start_iter(), &buffer.end_iter(), true), "a\n    日本😀\nb\n");
+            assert_eq!(position(&buffer, &buffer.get_insert()), (1, 6));
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn crlf_line_endings_are_kept() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_cursor("a  \r\n\tb\r\n日本😀\t\r\nc\r\n", 3, 1);
+            let mark = buffer.create_mark(None, &buffer.iter_at_line_offset(2, 3).unwrap(), true);
+            format_buffer(&buffer);
+
+            assert_eq!(
+                buffer.text(&buffer.start_iter(), &buffer.end_iter(), true),
+                "a\r\n    b\r\n日本😀\r\nc\r\n"
+            );
+            assert_eq!(position(&buffer, &buffer.get_insert()), (3, 1));
+            assert_eq!(buffer.line_count(), 5);
+            // The mark was on a replaced line, and stays at its start
+            assert_eq!(position(&buffer, &mark).0, 2);
+        });
+    }
+}
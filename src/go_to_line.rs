@@ -0,0 +1,316 @@
+//! Module for going to a line
+//!
+//! Go to line (Ctrl+G, or a click on the cursor position in the status bar)
+//! asks where to move the cursor: a line, a line and a column separated by a
+//! colon, a number of lines to move down (`+N`) or up (`-N`), or a percentage
+//! of the file (`N%`). A position copied from a compiler message, such as
+//! `src/main.rs:412:7`, opens that file first. The target line is centred in
+//! the view and briefly highlighted.
+
+use gtk4::prelude::*;
+use gtk4::{Box, Dialog, Entry, Label, Orientation, ResponseType, TextView};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+
+use crate::AppContext;
+
+/// How long the target line stays highlighted
+const FLASH_DURATION: Duration = Duration::from_millis(800);
+
+/// A position to go to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// File to open first, as typed
+    pub path: Option<String>,
+    /// Line of the position, counted from 0
+    pub line: i32,
+    /// Column of the position in characters, counted from 0, if one was given
+    pub column: Option<i32>,
+}
+
+/// Reads a line, absolute or relative to the current line
+fn parse_line(text: &str, current_line: i32) -> Option<i32> {
+    let text = text.trim();
+    if let Some(offset) = text.strip_prefix('+') {
+        return offset.parse::<i32>().ok().map(|offset| current_line.saturating_add(offset));
+    }
+    if let Some(offset) = text.strip_prefix('-') {
+        return offset.parse::<i32>().ok().map(|offset| current_line.saturating_sub(offset));
+    }
+    text.parse::<i32>().ok().map(|line| line - 1)
+}
+
+/// Reads a column, counted from 1
+fn parse_column(text: &str) -> Option<i32> {
+    text.trim().parse::<i32>().ok().map(|column| (column - 1).max(0))
+}
+
+/// Reads a position typed in the Go to line prompt
+///
+/// The line is clamped to the buffer, except when a file is given, since the
+/// position then refers to that file.
+///
+/// # Arguments
+///
+/// * `input` - The typed text, e.g. `412`, `412:7`, `+10`, `-3`, `50%` or `src/main.rs:412:7`
+/// * `current_line` - Line of the cursor, counted from 0
+/// * `line_count` - Number of lines of the buffer
+///
+/// # Returns
+///
+/// The position, or `None` if the text isn't one
+pub fn parse_target(input: &str, current_line: i32, line_count: i32) -> Option<Target> {
+    // Compiler messages often follow the position with a colon
+    let input = input.trim().trim_end_matches(':');
+    if input.is_empty() {
+        return None;
+    }
+    let last_line = (line_count - 1).max(0);
+
+    if let Some(percentage) = input.strip_suffix('%') {
+        let percentage = percentage.trim().parse::<f64>().ok()?.clamp(0.0, 100.0);
+        return Some(Target {
+            path: None,
+            line: (percentage / 100.0 * last_line as f64).round() as i32,
+            column: None,
+        });
+    }
+
+    let parts: Vec<&str> = input.rsplitn(3, ':').collect();
+    let (path, line, column) = match parts.as_slice() {
+        [line] => (None, parse_line(line, current_line)?, None),
+        [second, first] => match parse_line(first, current_line) {
+            Some(line) => (None, line, Some(parse_column(second)?)),
+            None => (Some(first.to_string()), second.trim().parse::<i32>().ok()? - 1, None),
+        },
+        [column, line, path] => (
+            Some(path.to_string()),
+            line.trim().parse::<i32>().ok()? - 1,
+            Some(parse_column(column)?),
+        ),
+        _ => return None,
+    };
+    let line = if path.is_some() { line.max(0) } else { line.clamp(0, last_line) };
+    Some(Target { path, line, column })
+}
+
+/// Resolves a typed file path against the directory of the current file,
+/// then against the working directory
+fn resolve_path(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    if path.is_absolute() {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let current_dir = app_context
+        .borrow()
+        .buffer_paths
+        .borrow()
+        .get(&text_view.buffer())
+        .and_then(|file| file.parent().map(Path::to_path_buf));
+    current_dir
+        .into_iter()
+        .chain(std::env::current_dir().ok())
+        .map(|dir| dir.join(path))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Highlights a line of a text view for a moment
+///
+/// # Arguments
+///
+/// * `text_view` - The text view
+/// * `line` - The line, counted from 0
+pub fn flash_line(text_view: &TextView, line: i32) {
+    let buffer = text_view.buffer();
+    if buffer.tag_table().lookup("line_flash").is_none() {
+        return;
+    }
+    let Some(start) = buffer.iter_at_line(line) else {
+        return;
+    };
+    let mut end = start;
+    end.forward_line();
+    buffer.remove_tag_by_name("line_flash", &buffer.start_iter(), &buffer.end_iter());
+    buffer.apply_tag_by_name("line_flash", &start, &end);
+    glib::timeout_add_local_once(FLASH_DURATION, move || {
+        buffer.remove_tag_by_name("line_flash", &buffer.start_iter(), &buffer.end_iter());
+    });
+}
+
+/// Moves the cursor to a position, opening its file first if it has one
+///
+/// The location left is remembered for Go back.
+fn go_to_target(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, target: &Target) {
+    crate::navigation::push_current_location(app_context);
+    let text_view = match &target.path {
+        Some(path) => {
+            let Some(path) = resolve_path(app_context, text_view, path) else {
+                crate::dialogs::show_error_dialog(
+                    &app_context.borrow().window,
+                    "Go to line failed",
+                    &format!("File not found: {}", path),
+                );
+                return;
+            };
+            crate::tab_manager::open_file_at_position(&path, target.line, target.column.unwrap_or(0), app_context);
+            let current = crate::ui::helpers::get_current_text_view(&app_context.borrow().notebook);
+            match current {
+                Some(current) if app_context.borrow().buffer_paths.borrow().get(&current.buffer()) == Some(&path) => {
+                    current
+                }
+                _ => return,
+            }
+        }
+        None => {
+            crate::tab_manager::go_to_position(text_view, target.line, target.column.unwrap_or(0));
+            text_view.clone()
+        }
+    };
+    let line = text_view.buffer().iter_at_mark(&text_view.buffer().get_insert()).line();
+    flash_line(&text_view, line);
+}
+
+/// Asks for a position and moves the cursor of a text view to it
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view whose cursor moves
+pub fn show_go_to_line_dialog(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let (current_line, line_count) = (cursor.line(), buffer.line_count());
+
+    let dialog = Dialog::builder()
+        .title("Go to Line")
+        .transient_for(&app_context.borrow().window)
+        .modal(true)
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Go", ResponseType::Ok);
+    dialog.set_default_response(ResponseType::Ok);
+
+    let vbox = Box::new(Orientation::Vertical, 6);
+    vbox.set_margin_top(10);
+    vbox.set_margin_bottom(10);
+    vbox.set_margin_start(10);
+    vbox.set_margin_end(10);
+    let entry = Entry::builder()
+        .text(format!("{}:{}", current_line + 1, cursor.line_offset() + 1))
+        .placeholder_text("line[:column], +N, -N or N%")
+        .hexpand(true)
+        .activates_default(true)
+        .build();
+    let hint = Label::new(Some(&format!(
+        "Line {} of {}. Type a line, line:column, +N or -N lines, or N% of the file.",
+        current_line + 1,
+        line_count
+    )));
+    hint.set_halign(gtk4::Align::Start);
+    hint.add_css_class("dim-label");
+    vbox.append(&entry);
+    vbox.append(&hint);
+    dialog.content_area().append(&vbox);
+
+    // Go is only offered for text that is a position
+    let dialog_clone = dialog.clone();
+    entry.connect_changed(move |entry| {
+        let valid = parse_target(&entry.text(), current_line, line_count).is_some();
+        dialog_clone.set_response_sensitive(ResponseType::Ok, valid);
+        if valid || entry.text().is_empty() {
+            entry.remove_css_class("error");
+        } else {
+            entry.add_css_class("error");
+        }
+    });
+
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    let entry_clone = entry.clone();
+    dialog.connect_response(move |dialog, response| {
+        let target = parse_target(&entry_clone.text(), current_line, line_count);
+        dialog.close();
+        if response == ResponseType::Ok
+            && let Some(target) = target
+        {
+            go_to_target(&app_context_clone, &text_view_clone, &target);
+        }
+    });
+    dialog.present();
+    entry.grab_focus();
+    entry.select_region(0, -1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(path: Option<&str>, line: i32, column: Option<i32>) -> Option<Target> {
+        Some(Target {
+            path: path.map(String::from),
+            line,
+            column,
+        })
+    }
+
+    #[test]
+    fn targets_are_parsed() {
+        // (input, current line, line count, target)
+        let cases = [
+            // Lines and columns, counted from 1
+            ("412", 10, 1000, target(None, 411, None)),
+            (" 412 ", 10, 1000, target(None, 411, None)),
+            ("412:7", 10, 1000, target(None, 411, Some(6))),
+            // Moves from the current line, also with a column
+            ("+5", 10, 1000, target(None, 15, None)),
+            ("-3", 10, 1000, target(None, 7, None)),
+            ("+5:3", 10, 1000, target(None, 15, Some(2))),
+            // Percentages of the file
+            ("50%", 10, 101, target(None, 50, None)),
+            ("100%", 10, 101, target(None, 100, None)),
+            ("0%", 10, 101, target(None, 0, None)),
+            // Positions in a file
+            ("src/main.rs:412", 10, 20, target(Some("src/main.rs"), 411, None)),
+            ("src/main.rs:412:7", 10, 20, target(Some("src/main.rs"), 411, Some(6))),
+            // A colon after the position, as in compiler messages
+            ("412:", 10, 1000, target(None, 411, None)),
+            ("412:7:", 10, 1000, target(None, 411, Some(6))),
+            ("src/main.rs:412:7:", 10, 20, target(Some("src/main.rs"), 411, Some(6))),
+            // Text that isn't a position
+            ("", 10, 1000, None),
+            (":", 10, 1000, None),
+            ("main", 10, 1000, None),
+            ("412:x", 10, 1000, None),
+            ("x%", 10, 1000, None),
+            ("src/main.rs:x:7", 10, 1000, None),
+        ];
+        for (input, current_line, line_count, expected) in cases {
+            assert_eq!(parse_target(input, current_line, line_count), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn out_of_range_targets_are_clamped() {
+        // (input, current line, line count, target)
+        let cases = [
+            // Lines are clamped to the buffer
+            ("5000", 10, 100, target(None, 99, None)),
+            ("0", 10, 100, target(None, 0, None)),
+            ("-30", 10, 100, target(None, 0, None)),
+            ("+500", 10, 100, target(None, 99, None)),
+            ("150%", 10, 101, target(None, 100, None)),
+            ("-20%", 10, 101, target(None, 0, None)),
+            ("1", 0, 0, target(None, 0, None)),
+            // Columns before the first one
+            ("3:0", 10, 100, target(None, 2, Some(0))),
+            // Lines of another file are only kept positive
+            ("lib.rs:5000", 10, 100, target(Some("lib.rs"), 4999, None)),
+            ("lib.rs:0:0", 10, 100, target(Some("lib.rs"), 0, Some(0))),
+        ];
+        for (input, current_line, line_count, expected) in cases {
+            assert_eq!(parse_target(input, current_line, line_count), expected, "{:?}", input);
+        }
+    }
+}
@@ -1,9 +1,12 @@
 //! Module for text indentation operations
 //!
 //! This module provides functions for indenting and outdenting selected text
-//! or the current line in the text editor.
+//! or the current line in the text editor, and for automatic indentation while
+//! typing.
 
-use gtk4::{TextBuffer};
+use gtk4::gdk;
+use gtk4::pango;
+use gtk4::{EventControllerKey, TextBuffer, TextIter, TextView};
 use gtk4::prelude::*;
 use std::collections::HashMap;
 use std::rc::Rc;
@@ -30,7 +33,7 @@ pub fn detect_indent_style(app_context: &Rc<RefCell<AppContext>>, buffer: &TextB
     while line_count < 20 {
         let mut end_iter = start_iter.clone();
         end_iter.forward_to_line_end();
-        let line_text = buffer.text(&start_iter, &end_iter, false).to_string();
+        let line_text = buffer.text(&start_iter, &end_iter, true).to_string();
 
         if !line_text.trim().is_empty() {
             if line_text.starts_with('\t') { 
@@ -70,7 +73,9 @@ pub fn detect_indent_style(app_context: &Rc<RefCell<AppContext>>, buffer: &TextB
         }
         (false, indent_width) // Space indentation
     } else {
-        (false, 4) // Default to 4 spaces
+        // Nothing to detect, so use the configured defaults
+        let settings = context.app_settings.borrow();
+        (!settings.insert_spaces, settings.tab_width)
     };
 
     indent_styles.insert(buffer.clone(), style);
@@ -79,9 +84,9 @@ pub fn detect_indent_style(app_context: &Rc<RefCell<AppContext>>, buffer: &TextB
 
 /// Indents the selected text or the current line
 ///
-/// This function adds indentation to the selected text or the current line.
-/// It detects the current indentation style (tabs or spaces) and uses that
-/// for the indentation.
+/// This function adds indentation to the selected text or the current line,
+/// including the lines of any secondary carets. It detects the current
+/// indentation style (tabs or spaces) and uses that for the indentation.
 ///
 /// # Arguments
 ///
@@ -106,6 +111,8 @@ pub fn indent_selection(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuff
 
     let start_line = start_iter.line();
     let end_line = end_iter.line();
+    // Also indent the lines of any secondary carets
+    let lines = crate::multi_cursor::lines_with_carets(app_context, buffer, start_line, end_line);
 
     // Store the original selection marks to restore them later
     let (original_selection_start_mark, original_selection_end_mark) = if initial_selection_bounds {
@@ -120,7 +127,7 @@ pub fn indent_selection(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuff
     buffer.begin_user_action();
 
     // Iterate from bottom to top
-    for current_line_num in (start_line..=end_line).rev() {
+    for current_line_num in lines {
         if let Some(mut line_start_iter) = buffer.iter_at_line(current_line_num) {
             buffer.insert(&mut line_start_iter, &indent_string);
         }
@@ -143,9 +150,9 @@ pub fn indent_selection(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuff
 
 /// Outdents the selected text or the current line
 ///
-/// This function removes indentation from the selected text or the current line.
-/// It detects the current indentation style (tabs or spaces) and uses that
-/// for the outdentation.
+/// This function removes indentation from the selected text or the current line,
+/// including the lines of any secondary carets. It detects the current
+/// indentation style (tabs or spaces) and uses that for the outdentation.
 ///
 /// # Arguments
 ///
@@ -172,6 +179,8 @@ pub fn outdent_selection(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuf
 
     let start_line = start_iter.line();
     let end_line = end_iter.line();
+    // Also outdent the lines of any secondary carets
+    let lines = crate::multi_cursor::lines_with_carets(app_context, buffer, start_line, end_line);
 
     // Store the original selection marks to restore them later
     let (original_selection_start_mark, original_selection_end_mark) = if initial_selection_bounds {
@@ -186,11 +195,11 @@ pub fn outdent_selection(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuf
     buffer.begin_user_action();
 
     // Iterate from bottom to top
-    for current_line_num in (start_line..=end_line).rev() {
+    for current_line_num in lines {
         if let Some(mut line_start_iter) = buffer.iter_at_line(current_line_num) {
             let mut line_end_iter = line_start_iter.clone(); 
             line_end_iter.forward_to_line_end();
-            let line_text = buffer.text(&line_start_iter, &line_end_iter, false).to_string();
+            let line_text = buffer.text(&line_start_iter, &line_end_iter, true).to_string();
 
             if line_text.starts_with(indent_prefix) {
                 let mut delete_end = line_start_iter.clone(); 
@@ -227,4 +236,472 @@ pub fn outdent_selection(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuf
             buffer.delete_mark(&end_mark);   
         }
     }
-}
\ No newline at end of file
+}
+/// Returns the string for one indentation level of a buffer
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to look up
+pub fn indent_unit(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> String {
+    let (is_tab_indent, indent_width) = detect_indent_style(app_context, buffer);
+    if is_tab_indent {
+        "\t".to_string()
+    } else {
+        " ".repeat(indent_width)
+    }
+}
+
+/// Returns the number of columns a tab character is displayed as in a buffer
+///
+/// The EditorConfig `tab_width` of the buffer's file takes precedence over the
+/// `tab_width` setting.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to look up
+pub fn tab_width(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> usize {
+    let context = app_context.borrow();
+    let tab_width = context
+        .editor_configs
+        .borrow()
+        .get(buffer)
+        .and_then(|config| config.tab_width);
+    tab_width.unwrap_or_else(|| context.app_settings.borrow().tab_width).max(1)
+}
+
+/// Sets the tab stops of a text view to its buffer's tab width
+///
+/// Tab stops are measured in pixels with the editor font, so this must be
+/// called again whenever the font changes; the stops are only replaced when
+/// their width differs. The font is set on the measured layout rather than
+/// taken from the text view, whose style only follows a font change at its
+/// next frame.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to update
+pub fn update_tab_stops(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let columns = tab_width(app_context, &text_view.buffer());
+    let layout = text_view.create_pango_layout(Some(&" ".repeat(columns)));
+    layout.set_font_description(Some(&app_context.borrow().current_font_desc.borrow()));
+    let width = layout.pixel_size().0.max(1);
+    if text_view.tabs().is_some_and(|tabs| tabs.size() == 1 && tabs.tab(0).1 == width) {
+        return;
+    }
+    let mut tabs = pango::TabArray::new(1, true);
+    tabs.set_tab(0, pango::TabAlign::Left, width);
+    text_view.set_tabs(&tabs);
+}
+
+/// Updates the tab stops of every open tab, after a font or tab width change
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn update_all_tab_stops(app_context: &Rc<RefCell<AppContext>>) {
+    let text_views = crate::ui::helpers::get_text_views(&app_context.borrow().notebook);
+    for text_view in text_views {
+        update_tab_stops(app_context, &text_view);
+    }
+}
+
+/// Applies changed tab width and insert-spaces settings to the open tabs
+///
+/// The cached indentation styles that don't come from EditorConfig are
+/// forgotten, so that they are detected again with the new defaults.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn apply_indent_settings(app_context: &Rc<RefCell<AppContext>>) {
+    {
+        let context = app_context.borrow();
+        let editor_configs = context.editor_configs.borrow();
+        context.indent_styles.borrow_mut().retain(|buffer, _| {
+            editor_configs
+                .get(buffer)
+                .is_some_and(|config| config.use_tabs.is_some() || config.indent_size.is_some())
+        });
+    }
+    update_all_tab_stops(app_context);
+}
+
+/// Inserts one indentation level at the cursor, replacing the selection
+///
+/// With spaces, the cursor moves to the next multiple of the indentation
+/// width rather than by a fixed number of spaces.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to insert into
+fn insert_indent_at_cursor(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let (is_tab_indent, indent_width) = detect_indent_style(app_context, buffer);
+    let tab_width = tab_width(app_context, buffer);
+
+    buffer.begin_user_action();
+    buffer.delete_selection(true, true);
+    let mut cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let indent = if is_tab_indent {
+        "\t".to_string()
+    } else {
+        let mut line_start = cursor;
+        line_start.set_line_offset(0);
+        let column = buffer
+            .text(&line_start, &cursor, true)
+            .chars()
+            .fold(0, |column, c| if c == '\t' { column + tab_width - column % tab_width } else { column + 1 });
+        let indent_width = indent_width.max(1);
+        " ".repeat(indent_width - column % indent_width)
+    };
+    buffer.insert(&mut cursor, &indent);
+    buffer.end_user_action();
+}
+
+/// Handles Tab and Shift+Tab in a text view
+///
+/// Tab indents the selected lines when the selection spans several lines (or
+/// a block selection is active), inserts one indentation level at every caret
+/// when there are secondary carets, and otherwise inserts one indentation
+/// level at the cursor. Shift+Tab outdents the selected lines.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view where the key was pressed
+/// * `outdent` - Whether Shift was held
+fn handle_tab_key(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, outdent: bool) {
+    let buffer = text_view.buffer();
+    if outdent {
+        outdent_selection(app_context, &buffer);
+        return;
+    }
+
+    let multi_line = buffer
+        .selection_bounds()
+        .is_some_and(|(start, end)| start.line() != end.line());
+    if multi_line || crate::block_selection::has_block_selection(app_context, &buffer) {
+        indent_selection(app_context, &buffer);
+    } else if crate::multi_cursor::has_secondary_carets(app_context, &buffer) {
+        let indent = indent_unit(app_context, &buffer);
+        crate::multi_cursor::insert_at_carets(app_context, text_view, &[indent]);
+    } else {
+        insert_indent_at_cursor(app_context, &buffer);
+    }
+}
+
+/// Adds the Tab key controller to a text view
+///
+/// Tab first moves between snippet tab stops or expands a snippet prefix,
+/// then indents; Shift+Tab outdents. The keys are handled by the text view
+/// itself, so Tab keeps moving the focus in dialogs and other widgets. This
+/// controller must be added after the completion and snippet controllers, so
+/// that they see Tab and Shift+Tab first.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to add the controller to
+pub fn add_tab_controller(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let key_controller = EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    key_controller.connect_key_pressed(move |_, keyval, _, state| {
+        let shortcut_modifiers = gdk::ModifierType::CONTROL_MASK
+            | gdk::ModifierType::ALT_MASK
+            | gdk::ModifierType::SUPER_MASK;
+        if state.intersects(shortcut_modifiers) || !text_view_clone.is_editable() {
+            return glib::Propagation::Proceed;
+        }
+
+        let shift = state.contains(gdk::ModifierType::SHIFT_MASK);
+        match keyval {
+            gdk::Key::Tab | gdk::Key::KP_Tab if !shift => {
+                if !crate::snippets::handle_tab(&app_context_clone, &text_view_clone) {
+                    handle_tab_key(&app_context_clone, &text_view_clone, false);
+                }
+                glib::Propagation::Stop
+            }
+            gdk::Key::Tab | gdk::Key::KP_Tab | gdk::Key::ISO_Left_Tab => {
+                handle_tab_key(&app_context_clone, &text_view_clone, true);
+                glib::Propagation::Stop
+            }
+            _ => glib::Propagation::Proceed,
+        }
+    });
+    text_view.add_controller(key_controller);
+}
+
+/// Inserts a newline that keeps the indentation of the current line
+///
+/// The new line is indented one more level when the text before the cursor
+/// ends with one of the language's `indent_after` tokens. When the cursor is
+/// right before a closing character, that character is moved to its own line
+/// at the original indentation.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to insert the newline into
+pub fn insert_newline_with_indent(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let language = crate::syntax_highlighting::language_settings_for_buffer(app_context, &buffer);
+    let unit = indent_unit(app_context, &buffer);
+
+    buffer.begin_user_action();
+    buffer.delete_selection(true, text_view.is_editable());
+    let mut cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut line_start = cursor;
+    line_start.set_line_offset(0);
+    let mut line_end = cursor;
+    if !line_end.ends_line() {
+        line_end.forward_to_line_end();
+    }
+    let before = buffer.text(&line_start, &cursor, true).to_string();
+    let after = buffer.text(&cursor, &line_end, true).to_string();
+
+    let base: String = before.chars().take_while(|c| *c == ' ' || *c == '\t').collect();
+    let trimmed_before = before.trim_end();
+    let opens_block = language
+        .indent_after
+        .iter()
+        .any(|token| !token.is_empty() && trimmed_before.ends_with(token.as_str()));
+    let closes_block = language
+        .dedent_on
+        .iter()
+        .any(|token| !token.is_empty() && after.trim_start().starts_with(token.as_str()));
+
+    let mut new_text = format!("\n{}", base);
+    if opens_block {
+        new_text.push_str(&unit);
+    }
+    let cursor_offset = cursor.offset() + new_text.chars().count() as i32;
+    if opens_block && closes_block {
+        new_text.push('\n');
+        new_text.push_str(&base);
+    }
+    buffer.insert(&mut cursor, &new_text);
+    buffer.place_cursor(&buffer.iter_at_offset(cursor_offset));
+    buffer.end_user_action();
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+}
+
+/// Returns the indentation a line gets when a closer is typed at a position
+///
+/// This is the indentation of the line of the matching opener, found by
+/// counting the openers and closers before the position, leaving out those
+/// inside strings and comments.
+///
+/// # Arguments
+///
+/// * `cursor` - Where the closer is typed
+/// * `opener` - The opener of the pair
+/// * `closer` - The closer of the pair
+/// * `is_in_string_or_comment` - Whether a position lies inside a string or a comment
+///
+/// # Returns
+///
+/// The indentation, or `None` if every opener before the position is closed
+fn closer_indentation(
+    cursor: &TextIter,
+    opener: char,
+    closer: char,
+    is_in_string_or_comment: impl Fn(&TextIter) -> bool,
+) -> Option<String> {
+    let mut iter = *cursor;
+    let mut depth = 0;
+    loop {
+        if !iter.backward_char() {
+            return None;
+        }
+        let c = iter.char();
+        if (c != opener && c != closer) || is_in_string_or_comment(&iter) {
+            continue;
+        }
+        match c {
+            c if c == closer => depth += 1,
+            _ if depth == 0 => break,
+            _ => depth -= 1,
+        }
+    }
+
+    let mut line_start = iter;
+    line_start.set_line_offset(0);
+    let mut indent_end = line_start;
+    while !indent_end.ends_line() && (indent_end.char() == ' ' || indent_end.char() == '\t') {
+        indent_end.forward_char();
+    }
+    Some(line_start.slice(&indent_end).to_string())
+}
+
+/// Types a closing character, dedenting the line first
+///
+/// The line is only dedented when the character is one of the language's
+/// `dedent_on` characters and nothing but indentation precedes the cursor. A
+/// closer of one of the language's pairs gets the indentation of the line of
+/// its opener; another character removes one level.
+///
+/// # Returns
+///
+/// Whether the character was inserted
+fn insert_closing_with_dedent(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, c: char) -> bool {
+    let buffer = text_view.buffer();
+    if buffer.has_selection() {
+        return false;
+    }
+    let language = crate::syntax_highlighting::language_settings_for_buffer(app_context, &buffer);
+    if !language.dedent_on.iter().any(|token| token.chars().eq(std::iter::once(c))) {
+        return false;
+    }
+
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut line_start = cursor;
+    line_start.set_line_offset(0);
+    let before = buffer.text(&line_start, &cursor, true).to_string();
+    if before.is_empty() || !before.chars().all(|c| c == ' ' || c == '\t') {
+        return false;
+    }
+    if crate::syntax_highlighting::is_in_string_or_comment(app_context, &cursor) {
+        return false;
+    }
+
+    let opener = language.pairs.iter().find_map(|pair| {
+        let mut chars = pair.chars();
+        match (chars.next(), chars.next(), chars.next()) {
+            (Some(opener), Some(closer), None) if closer == c && opener != closer => Some(opener),
+            _ => None,
+        }
+    });
+    let indentation = match opener {
+        Some(opener) => match closer_indentation(&cursor, opener, c, |iter| {
+            crate::syntax_highlighting::is_in_string_or_comment(app_context, iter)
+        }) {
+            Some(indentation) => indentation,
+            None => return false,
+        },
+        // Remove one tab, or up to one level of spaces
+        None => {
+            let unit_width = indent_unit(app_context, &buffer).chars().count();
+            let remove = if before.ends_with('\t') {
+                1
+            } else {
+                before.chars().rev().take_while(|c| *c == ' ').count().min(unit_width)
+            };
+            before.chars().take(before.chars().count() - remove).collect()
+        }
+    };
+    if indentation == before {
+        return false;
+    }
+
+    buffer.begin_user_action();
+    let mut start = line_start;
+    let mut end = cursor;
+    buffer.delete(&mut start, &mut end);
+    buffer.insert(&mut start, &format!("{}{}", indentation, c));
+    buffer.end_user_action();
+    true
+}
+
+/// Adds the auto-indent controller to a text view
+///
+/// Enter keeps the indentation of the current line, indenting one more level
+/// after openers, and typing a closing character on an indentation-only line
+/// dedents it. The rules come from the language settings of the buffer.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to add the controller to
+pub fn add_auto_indent_controller(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let key_controller = EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    key_controller.connect_key_pressed(move |_, keyval, _, state| {
+        let shortcut_modifiers = gdk::ModifierType::CONTROL_MASK
+            | gdk::ModifierType::ALT_MASK
+            | gdk::ModifierType::SUPER_MASK;
+        if state.intersects(shortcut_modifiers) || !text_view_clone.is_editable() {
+            return glib::Propagation::Proceed;
+        }
+
+        match keyval {
+            gdk::Key::Return | gdk::Key::KP_Enter | gdk::Key::ISO_Enter => {
+                insert_newline_with_indent(&app_context_clone, &text_view_clone);
+                glib::Propagation::Stop
+            }
+            _ => match keyval.to_unicode() {
+                Some(c) if insert_closing_with_dedent(&app_context_clone, &text_view_clone, c) => {
+                    glib::Propagation::Stop
+                }
+                _ => glib::Propagation::Proceed,
+            },
+        }
+    });
+    text_view.add_controller(key_controller);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::run_with_gtk;
+
+    /// Returns the indentation a `}` typed at a line and column gets
+    fn brace_indentation(text: &str, line: i32, column: i32) -> Option<String> {
+        let buffer = TextBuffer::new(None);
+        buffer.set_text(text);
+        closer_indentation(&buffer.iter_at_line_offset(line, column).unwrap(), '{', '}', |_| false)
+    }
+
+    /// Returns the indentation a `}` typed at a line and column gets, leaving
+    /// out the braces between double quotes or after `//` on their line
+    fn brace_indentation_outside_strings(text: &str, line: i32, column: i32) -> Option<String> {
+        let buffer = TextBuffer::new(None);
+        buffer.set_text(text);
+        let is_in_string_or_comment = |iter: &TextIter| {
+            let mut line_start = *iter;
+            line_start.set_line_offset(0);
+            let before = line_start.slice(iter).to_string();
+            before.contains("//") || before.matches('"').count() % 2 == 1
+        };
+        closer_indentation(&buffer.iter_at_line_offset(line, column).unwrap(), '{', '}', is_in_string_or_comment)
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn closer_takes_the_indentation_of_its_opener() {
+        run_with_gtk(|| {
+            // The closer line is indented two levels too deep
+            let text = "fn main() {\n    if x {\n        y();\n            \n";
+            assert_eq!(brace_indentation(text, 3, 12).as_deref(), Some("    "));
+            assert_eq!(brace_indentation("fn main() {\n\tlet s = S {};\n\t", 2, 1).as_deref(), Some(""));
+            assert_eq!(brace_indentation("\t\tmatch x {\n\t\t\tA => {}\n", 2, 0).as_deref(), Some("\t\t"));
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn closer_without_an_open_opener_has_no_indentation() {
+        run_with_gtk(|| {
+            assert_eq!(brace_indentation("fn main() {}\n    ", 1, 4), None);
+            assert_eq!(brace_indentation("    ", 0, 4), None);
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn braces_in_strings_and_comments_are_not_counted() {
+        run_with_gtk(|| {
+            let text = "fn main() {\n    let s = \"}\";\n    // {\n    if x {\n        y();\n    }\n        ";
+            assert_eq!(brace_indentation_outside_strings(text, 6, 8).as_deref(), Some(""));
+            // Counted as text, the braces of the string and the comment cancel out
+            assert_eq!(brace_indentation("fn main() {\n    \"}\"\n  ", 2, 2), None);
+            assert_eq!(brace_indentation_outside_strings("fn main() {\n    \"}\"\n  ", 2, 2).as_deref(), Some(""));
+        });
+    }
+}
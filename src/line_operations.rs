@@ -0,0 +1,544 @@
+//! Module for line operations
+//!
+//! This module provides commands that work on whole lines: moving, duplicating,
+//! deleting, joining, sorting, reversing and deduplicating lines, and inserting
+//! new lines. Each command applies to the current line or to the lines of the
+//! selection, and is a single undo step. Lines keep the terminators of the
+//! buffer, so that CRLF and mixed line endings survive the commands.
+
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextIter, TextView};
+
+/// Sort order for `sort_lines`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SortMode {
+    /// Lexicographic, case-sensitive
+    CaseSensitive,
+    /// Lexicographic, ignoring case
+    CaseInsensitive,
+    /// By the number at the start of each line; lines without one come last
+    Numeric,
+}
+
+/// Returns the first and last line covered by the selection or the cursor
+///
+/// A selection ending at the start of a line does not include that line.
+pub fn selected_lines(buffer: &TextBuffer) -> (i32, i32) {
+    match buffer.selection_bounds() {
+        Some((start, end)) => {
+            let end_line = if end.starts_line() && end.line() > start.line() {
+                end.line() - 1
+            } else {
+                end.line()
+            };
+            (start.line(), end_line)
+        }
+        None => {
+            let line = buffer.iter_at_mark(&buffer.get_insert()).line();
+            (line, line)
+        }
+    }
+}
+
+/// Returns an iterator at the start of a line
+fn line_start(buffer: &TextBuffer, line: i32) -> TextIter {
+    buffer.iter_at_line(line).unwrap_or_else(|| buffer.end_iter())
+}
+
+/// Returns an iterator at the end of a line, before the line terminator
+fn line_end(buffer: &TextBuffer, line: i32) -> TextIter {
+    let mut iter = line_start(buffer, line);
+    if !iter.ends_line() {
+        iter.forward_to_line_end();
+    }
+    iter
+}
+
+/// Returns the text of a line, without the line terminator
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer
+/// * `line` - The line, which may be past the end of the buffer
+pub fn line_text(buffer: &TextBuffer, line: i32) -> String {
+    let Some(start) = buffer.iter_at_line(line) else {
+        return String::new();
+    };
+    let mut end = start;
+    if !end.ends_line() {
+        end.forward_to_line_end();
+    }
+    buffer.text(&start, &end, true).to_string()
+}
+
+/// Returns the texts of a range of lines, without line terminators
+fn lines_text(buffer: &TextBuffer, start_line: i32, end_line: i32) -> Vec<String> {
+    (start_line..=end_line).map(|line| line_text(buffer, line)).collect()
+}
+
+/// Returns the terminator of a line, such as "\n" or "\r\n", empty for the last line
+fn line_terminator(buffer: &TextBuffer, line: i32) -> String {
+    let mut next_line_start = line_start(buffer, line);
+    if !next_line_start.forward_line() {
+        return String::new();
+    }
+    buffer.text(&line_end(buffer, line), &next_line_start, true).to_string()
+}
+
+/// Returns the terminator of a new line added after a line
+///
+/// This is the terminator of the line itself, or for the last line that of
+/// the first line of the buffer, "\n" if the buffer has a single line.
+fn new_line_terminator(buffer: &TextBuffer, line: i32) -> String {
+    [line_terminator(buffer, line), line_terminator(buffer, 0)]
+        .into_iter()
+        .find(|terminator| !terminator.is_empty())
+        .unwrap_or_else(|| "\n".to_string())
+}
+
+/// Joins lines with terminators
+///
+/// The n-th terminator follows the n-th line, so that the terminators stay
+/// where they are when lines are reordered; lines past the terminators get
+/// `default_terminator`.
+///
+/// # Arguments
+///
+/// * `lines` - The lines, without terminators
+/// * `terminators` - The terminators
+/// * `default_terminator` - Terminator of the lines with none of `terminators`
+fn join_with_terminators(lines: &[String], terminators: &[String], default_terminator: &str) -> String {
+    let mut text = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            text.push_str(terminators.get(i - 1).map_or(default_terminator, String::as_str));
+        }
+        text.push_str(line);
+    }
+    text
+}
+
+/// Replaces a range of lines with new lines
+///
+/// The terminators between the lines of the range are reused in order, and
+/// the terminator of the last line of the range is kept.
+fn replace_lines(buffer: &TextBuffer, start_line: i32, end_line: i32, lines: &[String]) {
+    let terminators: Vec<String> = (start_line..end_line).map(|line| line_terminator(buffer, line)).collect();
+    let text = join_with_terminators(lines, &terminators, &new_line_terminator(buffer, start_line));
+    let mut start = line_start(buffer, start_line);
+    let mut end = line_end(buffer, end_line);
+    buffer.delete(&mut start, &mut end);
+    buffer.insert(&mut start, &text);
+}
+
+/// Returns the (line, column) position of an iterator
+fn position(iter: &TextIter) -> (i32, i32) {
+    (iter.line(), iter.line_offset())
+}
+
+/// Returns an iterator at a (line, column) position, clamped to the line's length
+fn iter_at_position(buffer: &TextBuffer, (line, column): (i32, i32)) -> TextIter {
+    let mut iter = line_start(buffer, line);
+    let line_length = line_end(buffer, line).line_offset();
+    iter.set_line_offset(column.min(line_length));
+    iter
+}
+
+/// Returns the positions of the selection bound and the cursor
+fn selection_positions(buffer: &TextBuffer) -> ((i32, i32), (i32, i32)) {
+    (
+        position(&buffer.iter_at_mark(&buffer.selection_bound())),
+        position(&buffer.iter_at_mark(&buffer.get_insert())),
+    )
+}
+
+/// Restores the selection bound and the cursor, shifted by a number of lines
+fn restore_selection(buffer: &TextBuffer, (bound, cursor): ((i32, i32), (i32, i32)), line_delta: i32) {
+    let bound = iter_at_position(buffer, (bound.0 + line_delta, bound.1));
+    let cursor = iter_at_position(buffer, (cursor.0 + line_delta, cursor.1));
+    buffer.select_range(&cursor, &bound);
+}
+
+/// Selects whole lines if there was a selection, otherwise keeps the cursor on the first line
+fn select_lines(buffer: &TextBuffer, start_line: i32, end_line: i32, had_selection: bool) {
+    if had_selection {
+        buffer.select_range(&line_start(buffer, start_line), &line_end(buffer, end_line));
+    } else {
+        buffer.place_cursor(&line_start(buffer, start_line));
+    }
+}
+
+/// Moves the current line or the selected lines up or down by one line
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer to edit
+/// * `up` - Whether to move the lines up
+pub fn move_lines(buffer: &TextBuffer, up: bool) {
+    let (start_line, end_line) = selected_lines(buffer);
+    if (up && start_line == 0) || (!up && end_line >= buffer.line_count() - 1) {
+        return;
+    }
+    let selection = selection_positions(buffer);
+
+    buffer.begin_user_action();
+    if up {
+        let mut lines = lines_text(buffer, start_line - 1, end_line);
+        lines.rotate_left(1);
+        replace_lines(buffer, start_line - 1, end_line, &lines);
+    } else {
+        let mut lines = lines_text(buffer, start_line, end_line + 1);
+        lines.rotate_right(1);
+        replace_lines(buffer, start_line, end_line + 1, &lines);
+    }
+    buffer.end_user_action();
+
+    restore_selection(buffer, selection, if up { -1 } else { 1 });
+}
+
+/// Duplicates the selection, or the current or selected lines
+///
+/// A selection within a single line is duplicated right after itself and the
+/// copy is selected. Otherwise the lines are duplicated below themselves and
+/// the cursor or selection moves to the copy.
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer to edit
+pub fn duplicate_lines(buffer: &TextBuffer) {
+    if let Some((start, end)) = buffer.selection_bounds()
+        && start.line() == end.line()
+    {
+        let text = buffer.text(&start, &end, true).to_string();
+        let start_offset = end.offset();
+        buffer.begin_user_action();
+        buffer.insert(&mut buffer.iter_at_offset(start_offset), &text);
+        buffer.end_user_action();
+        let end_offset = start_offset + text.chars().count() as i32;
+        buffer.select_range(&buffer.iter_at_offset(start_offset), &buffer.iter_at_offset(end_offset));
+        return;
+    }
+
+    let (start_line, end_line) = selected_lines(buffer);
+    let selection = selection_positions(buffer);
+    let lines = lines_text(buffer, start_line, end_line);
+    let terminators: Vec<String> = (start_line..end_line).map(|line| line_terminator(buffer, line)).collect();
+    let terminator = new_line_terminator(buffer, end_line);
+    let copy = join_with_terminators(&lines, &terminators, &terminator);
+    buffer.begin_user_action();
+    let mut end = line_end(buffer, end_line);
+    buffer.insert(&mut end, &format!("{}{}", terminator, copy));
+    buffer.end_user_action();
+    restore_selection(buffer, selection, end_line - start_line + 1);
+}
+
+/// Deletes the current line or the selected lines
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer to edit
+pub fn delete_lines(buffer: &TextBuffer) {
+    let (start_line, end_line) = selected_lines(buffer);
+    let column = buffer.iter_at_mark(&buffer.get_insert()).line_offset();
+
+    let mut start = line_start(buffer, start_line);
+    let mut end = line_start(buffer, end_line);
+    if !end.forward_line() {
+        // The last line has no terminator, so remove the previous line's one
+        end = buffer.end_iter();
+        if start_line > 0 {
+            start = line_end(buffer, start_line - 1);
+        }
+    }
+    buffer.begin_user_action();
+    buffer.delete(&mut start, &mut end);
+    buffer.end_user_action();
+
+    let line = start_line.min(buffer.line_count() - 1);
+    buffer.place_cursor(&iter_at_position(buffer, (line, column)));
+}
+
+/// Joins the selected lines, or the current line with the next one
+///
+/// Leading whitespace of the joined lines is replaced with a single space.
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer to edit
+pub fn join_lines(buffer: &TextBuffer) {
+    let (start_line, mut end_line) = selected_lines(buffer);
+    if start_line == end_line {
+        end_line += 1;
+    }
+    if end_line >= buffer.line_count() {
+        return;
+    }
+    let had_selection = buffer.has_selection();
+
+    let lines = lines_text(buffer, start_line, end_line);
+    let mut joined = lines[0].trim_end().to_string();
+    let mut join_column = joined.chars().count() as i32;
+    for line in &lines[1..] {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        join_column = joined.chars().count() as i32;
+        if !joined.is_empty() {
+            joined.push(' ');
+        }
+        joined.push_str(line);
+    }
+
+    buffer.begin_user_action();
+    replace_lines(buffer, start_line, end_line, &[joined]);
+    buffer.end_user_action();
+
+    if had_selection {
+        select_lines(buffer, start_line, start_line, true);
+    } else {
+        buffer.place_cursor(&iter_at_position(buffer, (start_line, join_column)));
+    }
+}
+
+/// Returns the range of lines for commands that default to the whole buffer
+fn lines_or_whole_buffer(buffer: &TextBuffer) -> (i32, i32) {
+    if buffer.has_selection() {
+        selected_lines(buffer)
+    } else {
+        (0, buffer.line_count() - 1)
+    }
+}
+
+/// Returns the number at the start of a line, for numeric sorting
+fn leading_number(line: &str) -> Option<f64> {
+    let trimmed = line.trim_start();
+    let end = trimmed
+        .char_indices()
+        .find(|(i, c)| !(c.is_ascii_digit() || *c == '.' || (*i == 0 && (*c == '-' || *c == '+'))))
+        .map_or(trimmed.len(), |(i, _)| i);
+    trimmed[..end].parse().ok()
+}
+
+/// Sorts the selected lines, or the whole buffer without a selection
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer to edit
+/// * `mode` - The sort order
+pub fn sort_lines(buffer: &TextBuffer, mode: SortMode) {
+    let (start_line, end_line) = lines_or_whole_buffer(buffer);
+    let had_selection = buffer.has_selection();
+    let mut lines = lines_text(buffer, start_line, end_line);
+    match mode {
+        SortMode::CaseSensitive => lines.sort(),
+        SortMode::CaseInsensitive => lines.sort_by_key(|line| line.to_lowercase()),
+        SortMode::Numeric => lines.sort_by(|a, b| match (leading_number(a), leading_number(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => std::cmp::Ordering::Equal,
+        }),
+    }
+
+    buffer.begin_user_action();
+    replace_lines(buffer, start_line, end_line, &lines);
+    buffer.end_user_action();
+    select_lines(buffer, start_line, end_line, had_selection);
+}
+
+/// Reverses the order of the selected lines, or of the whole buffer
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer to edit
+pub fn reverse_lines(buffer: &TextBuffer) {
+    let (start_line, end_line) = lines_or_whole_buffer(buffer);
+    let had_selection = buffer.has_selection();
+    let mut lines = lines_text(buffer, start_line, end_line);
+    lines.reverse();
+
+    buffer.begin_user_action();
+    replace_lines(buffer, start_line, end_line, &lines);
+    buffer.end_user_action();
+    select_lines(buffer, start_line, end_line, had_selection);
+}
+
+/// Removes duplicate lines from the selection, or from the whole buffer
+///
+/// The first occurrence of each line is kept.
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer to edit
+pub fn remove_duplicate_lines(buffer: &TextBuffer) {
+    let (start_line, end_line) = lines_or_whole_buffer(buffer);
+    let had_selection = buffer.has_selection();
+    let mut seen = std::collections::HashSet::new();
+    let lines: Vec<String> = lines_text(buffer, start_line, end_line)
+        .into_iter()
+        .filter(|line| seen.insert(line.clone()))
+        .collect();
+    let new_end_line = start_line + lines.len() as i32 - 1;
+
+    buffer.begin_user_action();
+    replace_lines(buffer, start_line, end_line, &lines);
+    buffer.end_user_action();
+    select_lines(buffer, start_line, new_end_line, had_selection);
+}
+
+/// Inserts an empty line above or below the current line
+///
+/// The new line gets the indentation of the current line, and the cursor is
+/// placed at its end.
+///
+/// # Arguments
+///
+/// * `text_view` - The text view to edit
+/// * `above` - Whether to insert the line above the current one
+pub fn insert_line(text_view: &TextView, above: bool) {
+    let buffer = text_view.buffer();
+    let line = buffer.iter_at_mark(&buffer.get_insert()).line();
+    let indentation: String = line_text(&buffer, line)
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect();
+    let terminator = new_line_terminator(&buffer, line);
+
+    buffer.begin_user_action();
+    let new_line = if above {
+        let mut start = line_start(&buffer, line);
+        buffer.insert(&mut start, &format!("{}{}", indentation, terminator));
+        line
+    } else {
+        let mut end = line_end(&buffer, line);
+        buffer.insert(&mut end, &format!("{}{}", terminator, indentation));
+        line + 1
+    };
+    buffer.end_user_action();
+
+    buffer.place_cursor(&line_end(&buffer, new_line));
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::run_with_gtk;
+
+    /// Returns a buffer with a text and the cursor at a (line, column) position
+    fn buffer_with_cursor(text: &str, line: i32, column: i32) -> TextBuffer {
+        let buffer = TextBuffer::new(None);
+        buffer.set_text(text);
+        buffer.place_cursor(&buffer.iter_at_line_offset(line, column).unwrap());
+        buffer
+    }
+
+    /// Returns the whole text of a buffer
+    fn text(buffer: &TextBuffer) -> String {
+        buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string()
+    }
+
+    /// Returns strings from string slices
+    fn strings(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[test]
+    fn terminators_stay_in_place() {
+        let lines = strings(&["b", "a", "c"]);
+        assert_eq!(join_with_terminators(&lines, &strings(&["\r\n", "\n"]), "\n"), "b\r\na\nc");
+        assert_eq!(join_with_terminators(&lines, &strings(&["\r\n"]), "\r\n"), "b\r\na\r\nc");
+        assert_eq!(join_with_terminators(&strings(&["joined"]), &strings(&["\r\n"]), "\n"), "joined");
+        assert_eq!(join_with_terminators(&[], &[], "\n"), "");
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn moving_lines_keeps_crlf_terminators() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_cursor("A\r\nB\r\n", 1, 0);
+            move_lines(&buffer, true);
+            assert_eq!(text(&buffer), "B\r\nA\r\n");
+            assert_eq!(buffer.iter_at_mark(&buffer.get_insert()).line(), 0);
+
+            move_lines(&buffer, false);
+            assert_eq!(text(&buffer), "A\r\nB\r\n");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn moving_the_last_line_without_terminator() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_cursor("a\nb\nc", 2, 1);
+            move_lines(&buffer, true);
+            assert_eq!(text(&buffer), "a\nc\nb");
+            move_lines(&buffer, false);
+            assert_eq!(text(&buffer), "a\nb\nc");
+            move_lines(&buffer, false);
+            assert_eq!(text(&buffer), "a\nb\nc");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn duplicating_lines_uses_their_terminator() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_cursor("a\r\nb\r\n", 0, 0);
+            duplicate_lines(&buffer);
+            assert_eq!(text(&buffer), "a\r\na\r\nb\r\n");
+            assert_eq!(buffer.iter_at_mark(&buffer.get_insert()).line(), 1);
+
+            // The last line has none, so it gets that of the first line
+            let buffer = buffer_with_cursor("x\r\ny", 1, 0);
+            duplicate_lines(&buffer);
+            assert_eq!(text(&buffer), "x\r\ny\r\ny");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn deleting_lines_removes_their_whole_terminator() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_cursor("a\r\nb\r\nc", 1, 0);
+            delete_lines(&buffer);
+            assert_eq!(text(&buffer), "a\r\nc");
+
+            // The last line takes the terminator of the line before it
+            assert_eq!(buffer.iter_at_mark(&buffer.get_insert()).line(), 1);
+            delete_lines(&buffer);
+            assert_eq!(text(&buffer), "a");
+
+            let buffer = buffer_with_cursor("only", 0, 2);
+            delete_lines(&buffer);
+            assert_eq!(text(&buffer), "");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn joining_lines_keeps_the_following_terminator() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_cursor("a  \r\n    b\r\nc\r\n", 0, 0);
+            join_lines(&buffer);
+            assert_eq!(text(&buffer), "a b\r\nc\r\n");
+            assert_eq!(buffer.iter_at_mark(&buffer.get_insert()).line_offset(), 1);
+
+            // Nothing follows the last line
+            let buffer = buffer_with_cursor("a\nb", 1, 0);
+            join_lines(&buffer);
+            assert_eq!(text(&buffer), "a\nb");
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn sorting_keeps_crlf_terminators() {
+        run_with_gtk(|| {
+            let buffer = buffer_with_cursor("c\r\nb\r\na", 0, 0);
+            sort_lines(&buffer, SortMode::CaseSensitive);
+            assert_eq!(text(&buffer), "a\r\nb\r\nc");
+        });
+    }
+}
@@ -0,0 +1,290 @@
+//! Module for the local file history
+//!
+//! Every time a file is saved, a snapshot of its content is stored under the
+//! user's data directory. Each file has its own history directory holding an
+//! index of snapshots and the gzip-compressed contents, which are deduplicated
+//! by content hash. Old snapshots are pruned according to retention limits.
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Maximum number of snapshots kept for a file
+const MAX_SNAPSHOTS_PER_FILE: usize = 50;
+/// Snapshots older than this many seconds are pruned (30 days)
+const MAX_SNAPSHOT_AGE_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// A snapshot of a file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    /// Time of the save, in seconds since the Unix epoch
+    pub timestamp: i64,
+    /// SHA-256 hash of the content, naming the compressed content file
+    pub hash: String,
+}
+
+/// Index of the snapshots of a file, oldest first
+#[derive(Serialize, Deserialize)]
+struct HistoryIndex {
+    path: PathBuf,
+    snapshots: Vec<Snapshot>,
+}
+
+/// Returns the SHA-256 hash of some bytes as a hex string
+fn hash_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Returns the directory holding the histories of all files, in the user's data directory
+pub fn history_root() -> Option<PathBuf> {
+    let mut root = dirs::data_dir()?;
+    root.push("e4code");
+    root.push("history");
+    Some(root)
+}
+
+/// Returns the history directory of a file, creating it if needed
+///
+/// # Arguments
+///
+/// * `root` - Directory holding the histories of all files
+/// * `path` - The file
+fn history_dir(root: &Path, path: &Path) -> Option<PathBuf> {
+    let dir = root.join(hash_hex(path.to_string_lossy().as_bytes()));
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir)
+}
+
+/// Reads the snapshot index of a history directory
+fn read_index(dir: &Path, path: &Path) -> HistoryIndex {
+    fs::read_to_string(dir.join("index.json"))
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_else(|| HistoryIndex {
+            path: path.to_path_buf(),
+            snapshots: Vec::new(),
+        })
+}
+
+/// Writes the snapshot index of a history directory
+///
+/// The index is written to a temporary file first, so that a crash never
+/// leaves a truncated index behind.
+fn write_index(dir: &Path, index: &HistoryIndex) -> std::io::Result<()> {
+    let json = serde_json::to_string_pretty(index)?;
+    let temp_path = dir.join("index.json.tmp");
+    fs::write(&temp_path, json)?;
+    fs::rename(temp_path, dir.join("index.json"))
+}
+
+/// Returns the current time in seconds since the Unix epoch
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64)
+}
+
+/// Records a snapshot of a file that has just been saved
+///
+/// Nothing is recorded if the content is identical to the latest snapshot.
+/// Errors are ignored, since the local history must never prevent saving.
+///
+/// # Arguments
+///
+/// * `root` - Directory holding the histories of all files
+/// * `path` - Path of the saved file
+/// * `content` - Content written to the file
+pub fn record_snapshot(root: &Path, path: &Path, content: &str) {
+    let Some(dir) = history_dir(root, path) else {
+        return;
+    };
+    let mut index = read_index(&dir, path);
+    let hash = hash_hex(content.as_bytes());
+    if index.snapshots.last().is_some_and(|snapshot| snapshot.hash == hash) {
+        return;
+    }
+
+    let content_path = dir.join(format!("{}.gz", hash));
+    if !content_path.exists() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let compressed = encoder
+            .write_all(content.as_bytes())
+            .and_then(|_| encoder.finish());
+        match compressed {
+            Ok(bytes) => {
+                if fs::write(&content_path, bytes).is_err() {
+                    return;
+                }
+            }
+            Err(_) => return,
+        }
+    }
+
+    index.snapshots.push(Snapshot {
+        timestamp: now(),
+        hash,
+    });
+    prune_snapshots(&dir, &mut index);
+    write_index(&dir, &index).ok();
+}
+
+/// Applies the retention limits to an index and removes unreferenced contents
+///
+/// The latest snapshot is always kept.
+fn prune_snapshots(dir: &Path, index: &mut HistoryIndex) {
+    let oldest_allowed = now() - MAX_SNAPSHOT_AGE_SECS;
+    let latest = index.snapshots.len().saturating_sub(1);
+    let mut kept: Vec<Snapshot> = index
+        .snapshots
+        .drain(..)
+        .enumerate()
+        .filter(|(i, snapshot)| *i == latest || snapshot.timestamp >= oldest_allowed)
+        .map(|(_, snapshot)| snapshot)
+        .collect();
+    if kept.len() > MAX_SNAPSHOTS_PER_FILE {
+        kept.drain(..kept.len() - MAX_SNAPSHOTS_PER_FILE);
+    }
+    index.snapshots = kept;
+
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if let Some(hash) = file_name.strip_suffix(".gz")
+                && !index.snapshots.iter().any(|snapshot| snapshot.hash == hash)
+            {
+                fs::remove_file(entry.path()).ok();
+            }
+        }
+    }
+}
+
+/// Lists the snapshots of a file, newest first
+///
+/// # Arguments
+///
+/// * `root` - Directory holding the histories of all files
+/// * `path` - The file
+pub fn list_snapshots(root: &Path, path: &Path) -> Vec<Snapshot> {
+    let Some(dir) = history_dir(root, path) else {
+        return Vec::new();
+    };
+    let mut snapshots = read_index(&dir, path).snapshots;
+    snapshots.reverse();
+    snapshots
+}
+
+/// Loads the content of a snapshot of a file
+///
+/// # Arguments
+///
+/// * `root` - Directory holding the histories of all files
+/// * `path` - The file
+/// * `snapshot` - The snapshot, as listed by `list_snapshots`
+pub fn load_snapshot(root: &Path, path: &Path, snapshot: &Snapshot) -> Option<String> {
+    let compressed = fs::read(history_dir(root, path)?.join(format!("{}.gz", snapshot.hash))).ok()?;
+    let mut content = String::new();
+    GzDecoder::new(compressed.as_slice())
+        .read_to_string(&mut content)
+        .ok()?;
+    Some(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the names of the compressed contents kept in a history directory
+    fn content_files(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.ends_with(".gz"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn snapshots_are_listed_newest_first_and_restored() {
+        let root = crate::test_support::temp_dir("history-snapshots");
+        let path = Path::new("/project/notes.txt");
+        record_snapshot(&root, path, "first\n");
+        record_snapshot(&root, path, "second é日本🦀\n");
+
+        let snapshots = list_snapshots(&root, path);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(load_snapshot(&root, path, &snapshots[0]).as_deref(), Some("second é日本🦀\n"));
+        assert_eq!(load_snapshot(&root, path, &snapshots[1]).as_deref(), Some("first\n"));
+        assert!(list_snapshots(&root, Path::new("/project/other.txt")).is_empty());
+    }
+
+    #[test]
+    fn unchanged_content_is_not_recorded_again() {
+        let root = crate::test_support::temp_dir("history-unchanged");
+        let path = Path::new("/project/notes.txt");
+        record_snapshot(&root, path, "same");
+        record_snapshot(&root, path, "same");
+        assert_eq!(list_snapshots(&root, path).len(), 1);
+
+        // Going back to earlier content is a new snapshot sharing its content file
+        record_snapshot(&root, path, "other");
+        record_snapshot(&root, path, "same");
+        assert_eq!(list_snapshots(&root, path).len(), 3);
+        assert_eq!(content_files(&history_dir(&root, path).unwrap()).len(), 2);
+    }
+
+    #[test]
+    fn missing_snapshot_content_is_not_loaded() {
+        let root = crate::test_support::temp_dir("history-missing");
+        let path = Path::new("/project/notes.txt");
+        let snapshot = Snapshot { timestamp: now(), hash: hash_hex(b"never saved") };
+        assert_eq!(load_snapshot(&root, path, &snapshot), None);
+    }
+
+    #[test]
+    fn only_the_latest_snapshots_are_kept() {
+        let root = crate::test_support::temp_dir("history-count");
+        let path = Path::new("/project/notes.txt");
+        for version in 0..MAX_SNAPSHOTS_PER_FILE + 5 {
+            record_snapshot(&root, path, &format!("version {}", version));
+        }
+
+        let snapshots = list_snapshots(&root, path);
+        assert_eq!(snapshots.len(), MAX_SNAPSHOTS_PER_FILE);
+        let latest = format!("version {}", MAX_SNAPSHOTS_PER_FILE + 4);
+        assert_eq!(load_snapshot(&root, path, &snapshots[0]), Some(latest));
+        assert_eq!(load_snapshot(&root, path, &snapshots[MAX_SNAPSHOTS_PER_FILE - 1]).as_deref(), Some("version 5"));
+        assert_eq!(content_files(&history_dir(&root, path).unwrap()).len(), MAX_SNAPSHOTS_PER_FILE);
+    }
+
+    #[test]
+    fn old_snapshots_are_pruned_but_the_latest_is_kept() {
+        let dir = crate::test_support::temp_dir("history-age");
+        let old = now() - MAX_SNAPSHOT_AGE_SECS - 60;
+        for hash in ["a", "b", "c"] {
+            fs::write(dir.join(format!("{}.gz", hash)), b"").unwrap();
+        }
+        let mut index = HistoryIndex {
+            path: PathBuf::from("/project/notes.txt"),
+            snapshots: vec![
+                Snapshot { timestamp: old, hash: "a".to_string() },
+                Snapshot { timestamp: now(), hash: "b".to_string() },
+            ],
+        };
+        prune_snapshots(&dir, &mut index);
+        assert_eq!(index.snapshots.iter().map(|snapshot| snapshot.hash.as_str()).collect::<Vec<_>>(), vec!["b"]);
+        assert_eq!(content_files(&dir), vec!["b.gz"]);
+
+        index.snapshots = vec![Snapshot { timestamp: old, hash: "b".to_string() }];
+        prune_snapshots(&dir, &mut index);
+        assert_eq!(index.snapshots.len(), 1);
+        assert_eq!(content_files(&dir), vec!["b.gz"]);
+    }
+}
@@ -0,0 +1,342 @@
+//! JSON-RPC connection to a language server process
+//!
+//! Messages are framed with a `Content-Length` header and exchanged over the
+//! standard input and output of the server. Reading and writing run as tasks
+//! on the GTK main loop, so the editor never blocks on a busy server. Requests
+//! and notifications sent before the server answered `initialize` are held
+//! back until it did.
+
+use gio::prelude::*;
+use serde_json::{Value, json};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::rc::Rc;
+
+/// JSON-RPC error code for requests of unknown methods
+pub const METHOD_NOT_FOUND: i64 = -32601;
+
+/// JSON-RPC error code used when the server exited before answering
+const SERVER_EXITED: i64 = -32099;
+
+/// Error answered to a request
+#[derive(Clone, Debug)]
+pub struct ResponseError {
+    /// JSON-RPC error code
+    pub code: i64,
+    /// Description of the error
+    pub message: String,
+}
+
+/// Function receiving the answer to a request
+type ResponseCallback = Box<dyn FnOnce(Result<Value, ResponseError>)>;
+
+/// Function handling a notification, given its method and parameters
+type NotificationHandler = Box<dyn Fn(&str, Value)>;
+
+/// Function answering a request, given its method and parameters
+type RequestHandler = Box<dyn Fn(&str, Value) -> Result<Value, ResponseError>>;
+
+/// Functions handling what the server sends on its own
+pub struct Handlers {
+    /// Handles the notifications
+    pub notification: NotificationHandler,
+    /// Answers the requests
+    pub request: RequestHandler,
+    /// Called once the server closed its output, usually because it exited
+    pub exited: Box<dyn Fn()>,
+}
+
+/// A running language server
+pub struct LanguageServer {
+    /// Name of the server program, used in messages
+    pub name: String,
+    /// The server process
+    process: gio::Subprocess,
+    /// Standard input of the server
+    stdin: gio::OutputStream,
+    /// Functions handling what the server sends on its own
+    handlers: Handlers,
+    /// Identifier of the next request
+    next_id: Cell<i64>,
+    /// Callbacks of the requests waiting for an answer, by request identifier
+    pending: RefCell<HashMap<i64, ResponseCallback>>,
+    /// Framed messages waiting to be written
+    outgoing: RefCell<VecDeque<Vec<u8>>>,
+    /// Whether a task is writing the outgoing messages
+    writing: Cell<bool>,
+    /// Messages held back until the server is initialized
+    deferred: RefCell<Vec<Value>>,
+    /// Capabilities announced by the server, once it is initialized
+    capabilities: RefCell<Option<Value>>,
+    /// Whether the server was asked to shut down
+    stopping: Cell<bool>,
+    /// Whether the server closed its output
+    exited: Cell<bool>,
+}
+
+impl LanguageServer {
+    /// Starts a language server
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the server, used in messages
+    /// * `argv` - Program and arguments of the server
+    /// * `cwd` - Directory the server runs in
+    /// * `handlers` - Functions handling what the server sends on its own
+    ///
+    /// # Returns
+    ///
+    /// The running server, or the error that prevented starting it
+    pub fn start(name: String, argv: &[OsString], cwd: &Path, handlers: Handlers) -> Result<Rc<Self>, glib::Error> {
+        let launcher = gio::SubprocessLauncher::new(
+            gio::SubprocessFlags::STDIN_PIPE | gio::SubprocessFlags::STDOUT_PIPE | gio::SubprocessFlags::STDERR_SILENCE,
+        );
+        launcher.set_cwd(cwd);
+        let args: Vec<&OsStr> = argv.iter().map(OsString::as_os_str).collect();
+        let process = launcher.spawn(&args)?;
+        let stdin = process.stdin_pipe().expect("stdin is piped");
+        let stdout = gio::DataInputStream::new(&process.stdout_pipe().expect("stdout is piped"));
+
+        let server = Rc::new(Self {
+            name,
+            process,
+            stdin,
+            handlers,
+            next_id: Cell::new(1),
+            pending: RefCell::new(HashMap::new()),
+            outgoing: RefCell::new(VecDeque::new()),
+            writing: Cell::new(false),
+            deferred: RefCell::new(Vec::new()),
+            capabilities: RefCell::new(None),
+            stopping: Cell::new(false),
+            exited: Cell::new(false),
+        });
+        glib::MainContext::default().spawn_local(read_messages(server.clone(), stdout));
+        Ok(server)
+    }
+
+    /// Sends the `initialize` request, then the `initialized` notification
+    /// and the messages held back meanwhile
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - Parameters of the `initialize` request
+    /// * `callback` - Called with the result of the request once the server is ready
+    pub fn initialize(self: &Rc<Self>, params: Value, callback: impl FnOnce(Result<Value, ResponseError>) + 'static) {
+        let id = self.register(Box::new({
+            let server = self.clone();
+            move |result| {
+                if let Ok(result) = &result {
+                    *server.capabilities.borrow_mut() = Some(result["capabilities"].clone());
+                    server.send(json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}));
+                    for message in server.deferred.take() {
+                        server.send(message);
+                    }
+                }
+                callback(result);
+            }
+        }));
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": "initialize", "params": params}));
+    }
+
+    /// Returns whether the server answered the `initialize` request
+    pub fn is_initialized(&self) -> bool {
+        self.capabilities.borrow().is_some()
+    }
+
+    /// Returns the capabilities announced by the server, or `Value::Null`
+    /// before it is initialized
+    pub fn capabilities(&self) -> Value {
+        self.capabilities.borrow().clone().unwrap_or_default()
+    }
+
+    /// Sends a request
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - Method of the request
+    /// * `params` - Parameters of the request
+    /// * `callback` - Called with the answer, or with an error if the server exits first
+    pub fn request(
+        self: &Rc<Self>,
+        method: &str,
+        params: Value,
+        callback: impl FnOnce(Result<Value, ResponseError>) + 'static,
+    ) {
+        let id = self.register(Box::new(callback));
+        self.enqueue(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+    }
+
+    /// Sends a notification
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - Method of the notification
+    /// * `params` - Parameters of the notification
+    pub fn notify(self: &Rc<Self>, method: &str, params: Value) {
+        self.enqueue(json!({"jsonrpc": "2.0", "method": method, "params": params}));
+    }
+
+    /// Asks the server to shut down, then to exit
+    ///
+    /// A server that isn't initialized yet can't be asked, so it is killed.
+    pub fn shutdown(self: &Rc<Self>) {
+        if self.stopping.replace(true) {
+            return;
+        }
+        if !self.is_initialized() {
+            self.kill();
+            return;
+        }
+        let server = self.clone();
+        self.request("shutdown", Value::Null, move |_| server.notify("exit", Value::Null));
+    }
+
+    /// Returns whether the server was asked to shut down
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.get()
+    }
+
+    /// Returns whether the server closed its output
+    pub fn has_exited(&self) -> bool {
+        self.exited.get()
+    }
+
+    /// Kills the server process
+    pub fn kill(&self) {
+        self.process.force_exit();
+    }
+
+    /// Stores the callback of a new request and returns the request's identifier
+    fn register(&self, callback: ResponseCallback) -> i64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.pending.borrow_mut().insert(id, callback);
+        id
+    }
+
+    /// Sends a message, or holds it back until the server is initialized
+    fn enqueue(self: &Rc<Self>, message: Value) {
+        if self.is_initialized() {
+            self.send(message);
+        } else {
+            self.deferred.borrow_mut().push(message);
+        }
+    }
+
+    /// Frames a message and queues it for writing
+    fn send(self: &Rc<Self>, message: Value) {
+        if self.exited.get() {
+            return;
+        }
+        let body = message.to_string();
+        let mut bytes = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+        bytes.extend_from_slice(body.as_bytes());
+        self.outgoing.borrow_mut().push_back(bytes);
+
+        if !self.writing.replace(true) {
+            let server = self.clone();
+            glib::MainContext::default().spawn_local(async move {
+                loop {
+                    let Some(bytes) = server.outgoing.borrow_mut().pop_front() else {
+                        break;
+                    };
+                    if server.stdin.write_all_future(bytes, glib::Priority::DEFAULT).await.is_err() {
+                        // The server stopped reading, which the reader will notice
+                        server.outgoing.borrow_mut().clear();
+                        break;
+                    }
+                }
+                server.writing.set(false);
+            });
+        }
+    }
+
+    /// Handles a message received from the server
+    fn dispatch(self: &Rc<Self>, message: Value) {
+        let method = message["method"].as_str();
+        match (method, message.get("id")) {
+            (Some(method), Some(id)) => {
+                let reply = match (self.handlers.request)(method, message["params"].clone()) {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    Err(error) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": error.code, "message": error.message},
+                    }),
+                };
+                self.send(reply);
+            }
+            (Some(method), None) => (self.handlers.notification)(method, message["params"].clone()),
+            (None, Some(id)) => {
+                let callback = id.as_i64().and_then(|id| self.pending.borrow_mut().remove(&id));
+                if let Some(callback) = callback {
+                    callback(match message.get("error") {
+                        Some(error) => Err(ResponseError {
+                            code: error["code"].as_i64().unwrap_or_default(),
+                            message: error["message"].as_str().unwrap_or_default().to_string(),
+                        }),
+                        None => Ok(message["result"].clone()),
+                    });
+                }
+            }
+            (None, None) => {}
+        }
+    }
+}
+
+/// Reads the framed messages of a server until it closes its output
+async fn read_messages(server: Rc<LanguageServer>, stdout: gio::DataInputStream) {
+    while let Ok(Some(body)) = read_message(&stdout).await {
+        // A malformed message is skipped rather than ending the connection
+        if let Ok(message) = serde_json::from_slice::<Value>(&body) {
+            server.dispatch(message);
+        }
+    }
+
+    server.exited.set(true);
+    server.outgoing.borrow_mut().clear();
+    let pending: Vec<ResponseCallback> = server.pending.borrow_mut().drain().map(|(_, callback)| callback).collect();
+    for callback in pending {
+        callback(Err(ResponseError {
+            code: SERVER_EXITED,
+            message: format!("{} exited", server.name),
+        }));
+    }
+    (server.handlers.exited)();
+}
+
+/// Reads the body of the next framed message
+///
+/// # Returns
+///
+/// `None` at the end of the stream
+async fn read_message(stdout: &gio::DataInputStream) -> Result<Option<Vec<u8>>, glib::Error> {
+    let mut length = None;
+    loop {
+        let Some(line) = stdout.read_line_utf8_future(glib::Priority::DEFAULT).await? else {
+            return Ok(None);
+        };
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("Content-Length")
+        {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.unwrap_or_default();
+    let (body, read, _) = stdout
+        .read_all_future(vec![0; length], glib::Priority::DEFAULT)
+        .await
+        .map_err(|(_, e)| e)?;
+    Ok((read == length).then_some(body))
+}
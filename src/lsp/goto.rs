@@ -0,0 +1,200 @@
+//! Go to definition and find references
+//!
+//! Both ask the server of the current buffer about the symbol at the cursor.
+//! The servers answer with ranges whose columns count UTF-16 code units; they
+//! are converted to characters with the file's buffer if it is open, else with
+//! the file's text on disk.
+
+use gtk4::prelude::*;
+use gtk4::TextView;
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use super::{buffer_for_path, iter_at_position, request_at, uri_to_path, utf16_byte_index};
+use crate::navigation;
+use crate::references::{self, Reference};
+use crate::AppContext;
+
+/// A range of a file given by a server
+struct Target {
+    /// The file
+    path: PathBuf,
+    /// Start of the range, as a line and a UTF-16 column
+    start: (u32, u32),
+    /// End of the range, as a line and a UTF-16 column
+    end: (u32, u32),
+}
+
+/// Reads an LSP position
+fn position(value: &Value) -> (u32, u32) {
+    (
+        value["line"].as_u64().unwrap_or_default() as u32,
+        value["character"].as_u64().unwrap_or_default() as u32,
+    )
+}
+
+/// Reads the `Location`s or `LocationLink`s answered by a server
+fn parse_targets(result: &Value) -> Vec<Target> {
+    let items: Vec<&Value> = match result {
+        Value::Array(items) => items.iter().collect(),
+        Value::Null => Vec::new(),
+        item => vec![item],
+    };
+    items
+        .into_iter()
+        .filter_map(|item| {
+            // A link gives the whole definition and, as the selection range, its name
+            let (uri, range) = match item.get("targetUri") {
+                Some(uri) if item["targetSelectionRange"].is_object() => (uri, &item["targetSelectionRange"]),
+                Some(uri) => (uri, &item["targetRange"]),
+                None => (&item["uri"], &item["range"]),
+            };
+            Some(Target {
+                path: uri.as_str().and_then(uri_to_path)?,
+                start: position(&range["start"]),
+                end: position(&range["end"]),
+            })
+        })
+        .collect()
+}
+
+/// Converts ranges to places showing the text of their line
+fn to_references(app_context: &Rc<RefCell<AppContext>>, targets: Vec<Target>) -> Vec<Reference> {
+    // Files that aren't open are read once, however many places they hold
+    let mut files: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    targets
+        .into_iter()
+        .map(|target| {
+            let (line, character) = target.start;
+            if let Some(buffer) = buffer_for_path(app_context, &target.path) {
+                let iter = iter_at_position(&buffer, line, character);
+                let mut line_start = iter;
+                line_start.set_line_offset(0);
+                let mut line_end = iter;
+                if !line_end.ends_line() {
+                    line_end.forward_to_line_end();
+                }
+                return Reference {
+                    line: iter.line(),
+                    column: iter.line_offset(),
+                    preview: buffer.text(&line_start, &line_end, true).to_string(),
+                    path: target.path,
+                };
+            }
+            let lines = files.entry(target.path.clone()).or_insert_with(|| {
+                std::fs::read_to_string(&target.path)
+                    .map(|text| text.lines().map(String::from).collect())
+                    .unwrap_or_default()
+            });
+            let preview = lines.get(line as usize).cloned().unwrap_or_default();
+            Reference {
+                line: line as i32,
+                column: preview[..utf16_byte_index(&preview, character)].chars().count() as i32,
+                preview,
+                path: target.path,
+            }
+        })
+        .collect()
+}
+
+/// Opens the file of a range and selects the range
+fn open_target(app_context: &Rc<RefCell<AppContext>>, target: &Target) {
+    crate::tab_manager::open_file_in_new_tab(&target.path, app_context);
+    let Some(text_view) = crate::ui::helpers::get_current_text_view(&app_context.borrow().notebook) else {
+        return;
+    };
+    let buffer = text_view.buffer();
+    // The file could not be opened if the current tab shows another file
+    if app_context.borrow().buffer_paths.borrow().get(&buffer) != Some(&target.path) {
+        return;
+    }
+    let start = iter_at_position(&buffer, target.start.0, target.start.1);
+    let end = iter_at_position(&buffer, target.end.0, target.end.1);
+    crate::tab_manager::go_to_position(&text_view, start.line(), start.line_offset());
+    buffer.move_mark(&buffer.selection_bound(), &end);
+}
+
+/// Goes to the definition of the symbol at the cursor
+///
+/// The file of the definition is opened and its name selected; the location
+/// left is remembered in the navigation history. Several definitions are
+/// listed in the References panel instead. The error bell rings if there is
+/// no server to ask or it knows no definition.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view whose cursor is on the symbol
+pub fn go_to_definition(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let origin = navigation::location_of(app_context, text_view);
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    let sent = request_at(
+        app_context,
+        &buffer,
+        &cursor,
+        "definitionProvider",
+        "textDocument/definition",
+        json!({}),
+        move |result| {
+            let targets = result.map(|result| parse_targets(&result)).unwrap_or_default();
+            match targets.as_slice() {
+                [] => text_view_clone.error_bell(),
+                [target] => {
+                    if let Some(origin) = origin {
+                        navigation::push_location(&app_context_clone, origin);
+                    }
+                    open_target(&app_context_clone, target);
+                }
+                _ => {
+                    let definitions = to_references(&app_context_clone, targets);
+                    references::show_references(&app_context_clone, "Definitions", definitions);
+                }
+            }
+        },
+    );
+    if !sent {
+        text_view.error_bell();
+    }
+}
+
+/// Lists the references to the symbol at the cursor in the References panel
+///
+/// The declaration of the symbol is included. The error bell rings if there
+/// is no server to ask or it finds no reference.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view whose cursor is on the symbol
+pub fn find_references(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    let sent = request_at(
+        app_context,
+        &buffer,
+        &cursor,
+        "referencesProvider",
+        "textDocument/references",
+        json!({"context": {"includeDeclaration": true}}),
+        move |result| {
+            let targets = result.map(|result| parse_targets(&result)).unwrap_or_default();
+            if targets.is_empty() {
+                text_view_clone.error_bell();
+                return;
+            }
+            let found = to_references(&app_context_clone, targets);
+            references::show_references(&app_context_clone, "References", found);
+        },
+    );
+    if !sent {
+        text_view.error_bell();
+    }
+}
@@ -0,0 +1,357 @@
+//! Documentation shown when hovering a symbol
+//!
+//! Once the pointer rests on a symbol, its server is asked for the `hover`
+//! information, which is rendered from markdown to Pango markup and shown in
+//! the text view's tooltip, after the messages of the diagnostics there. The
+//! answer is kept for the range it covers until the text changes.
+
+use gtk4::pango;
+use gtk4::prelude::*;
+use gtk4::{EventControllerMotion, TextIter, TextView, TextWindowType};
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use super::{iter_at_position, request_at};
+use crate::AppContext;
+
+/// Time the pointer rests on a symbol before its documentation is requested
+const HOVER_DELAY: Duration = Duration::from_millis(500);
+
+/// Number of lines of documentation shown at most
+const MAX_LINES: usize = 30;
+
+/// Hover state of a text view
+#[derive(Default)]
+struct HoverState {
+    /// Request waiting for the pointer to rest
+    timeout: Option<glib::SourceId>,
+    /// Offset of the character under the pointer
+    pointer: Option<i32>,
+    /// Number of edits of the buffer, telling whether an answer is out of date
+    edits: u64,
+    /// Documentation shown, with the range of offsets it is about
+    shown: Option<(i32, i32, String)>,
+}
+
+/// Returns the offsets of the word around a position
+fn word_range(iter: &TextIter) -> (i32, i32) {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut start = *iter;
+    while start.backward_char() {
+        if !is_word(start.char()) {
+            start.forward_char();
+            break;
+        }
+    }
+    let mut end = *iter;
+    while is_word(end.char()) && end.forward_char() {}
+    (start.offset(), end.offset().max(iter.offset() + 1))
+}
+
+/// Renders the inline spans of a markdown line
+///
+/// Code spans, emphasis, strong emphasis and links are supported; links show
+/// their text underlined.
+fn render_inline(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut markup = String::new();
+    let mut open: Vec<&str> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        match c {
+            '\\' if next.is_some_and(|next| next.is_ascii_punctuation()) => {
+                markup.push_str(&glib::markup_escape_text(&next.unwrap_or_default().to_string()));
+                i += 2;
+                continue;
+            }
+            '`' => {
+                if let Some(length) = chars[i + 1..].iter().position(|&c| c == '`') {
+                    let code: String = chars[i + 1..i + 1 + length].iter().collect();
+                    markup.push_str(&format!("<tt>{}</tt>", glib::markup_escape_text(&code)));
+                    i += length + 2;
+                    continue;
+                }
+            }
+            '[' => {
+                let rest: String = chars[i + 1..].iter().collect();
+                if let Some(middle) = rest.find("](")
+                    && let Some(length) = rest[middle..].find(')')
+                {
+                    markup.push_str(&format!("<u>{}</u>", render_inline(&rest[..middle])));
+                    i += 1 + rest[..middle + length + 1].chars().count();
+                    continue;
+                }
+            }
+            '*' | '_' => {
+                let strong = next == Some(c);
+                // An underscore inside a word, as in snake_case, is literal
+                let in_word = c == '_'
+                    && i > 0
+                    && chars[i - 1].is_alphanumeric()
+                    && chars.get(i + 1 + strong as usize).is_some_and(|c| c.is_alphanumeric());
+                let tag = if strong { "b" } else { "i" };
+                if !in_word && (open.last() == Some(&tag) || !open.contains(&tag)) {
+                    if open.last() == Some(&tag) {
+                        open.pop();
+                        markup.push_str(&format!("</{}>", tag));
+                    } else {
+                        open.push(tag);
+                        markup.push_str(&format!("<{}>", tag));
+                    }
+                    i += 1 + strong as usize;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        markup.push_str(&glib::markup_escape_text(&c.to_string()));
+        i += 1;
+    }
+    for tag in open.iter().rev() {
+        markup.push_str(&format!("</{}>", tag));
+    }
+    markup
+}
+
+/// Renders markdown as Pango markup, one rendered line per source line
+fn render_markdown(markdown: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut in_code = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            lines.push(format!("<tt>{}</tt>", glib::markup_escape_text(line)));
+        } else if trimmed.len() >= 3 && trimmed.chars().all(|c| c == '-' || c == '*' || c == '_') {
+            lines.push("―".repeat(20));
+        } else if trimmed.starts_with('#') {
+            lines.push(format!("<b>{}</b>", render_inline(trimmed.trim_start_matches('#').trim())));
+        } else if let Some(item) = trimmed.strip_prefix("- ").or_else(|| trimmed.strip_prefix("* ")) {
+            lines.push(format!("• {}", render_inline(item)));
+        } else {
+            lines.push(render_inline(line.trim_end()));
+        }
+    }
+    lines
+}
+
+/// Renders the `contents` of a hover answer as lines of Pango markup
+///
+/// The contents are markup, markdown or plain text, or a list of those.
+fn render_contents(contents: &Value) -> Vec<String> {
+    let escape = |text: &str| text.lines().map(|line| glib::markup_escape_text(line).to_string()).collect();
+    match contents {
+        Value::String(markdown) => render_markdown(markdown),
+        Value::Array(items) => {
+            let mut lines = Vec::new();
+            for item_lines in items.iter().map(render_contents).filter(|lines| !lines.is_empty()) {
+                if !lines.is_empty() {
+                    lines.push(String::new());
+                }
+                lines.extend(item_lines);
+            }
+            lines
+        }
+        Value::Object(item) => {
+            let value = item.get("value").and_then(Value::as_str).unwrap_or_default();
+            match (item.get("kind").and_then(Value::as_str), item.get("language")) {
+                (Some("markdown"), _) => render_markdown(value),
+                // A code snippet in some language
+                (None, Some(_)) => value
+                    .lines()
+                    .map(|line| format!("<tt>{}</tt>", glib::markup_escape_text(line)))
+                    .collect(),
+                _ => escape(value),
+            }
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Returns the text of the `contents` of a hover answer, without rendering
+fn raw_text(contents: &Value) -> String {
+    match contents {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(raw_text).collect::<Vec<_>>().join("\n\n"),
+        Value::Object(item) => item.get("value").and_then(Value::as_str).unwrap_or_default().to_string(),
+        _ => String::new(),
+    }
+}
+
+/// Turns rendered lines into the markup of a tooltip
+///
+/// Blank lines are collapsed and long documentation is cut. Markup that
+/// Pango rejects is shown as plain text.
+fn tooltip_markup(lines: Vec<String>, plain: &str) -> Option<String> {
+    let mut kept: Vec<String> = Vec::new();
+    for line in lines {
+        if line.trim().is_empty() && kept.last().is_none_or(|last| last.trim().is_empty()) {
+            continue;
+        }
+        kept.push(line);
+    }
+    while kept.last().is_some_and(|last| last.trim().is_empty()) {
+        kept.pop();
+    }
+    if kept.is_empty() {
+        return None;
+    }
+    if kept.len() > MAX_LINES {
+        kept.truncate(MAX_LINES);
+        kept.push("…".to_string());
+    }
+    let markup = kept.join("\n");
+    if pango::parse_markup(&markup, '\0').is_ok() {
+        Some(markup)
+    } else {
+        Some(glib::markup_escape_text(plain).to_string())
+    }
+}
+
+/// Asks the server of a text view's buffer about the character at an offset
+fn request_hover(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, state: &Rc<RefCell<HoverState>>, offset: i32) {
+    let buffer = text_view.buffer();
+    let iter = buffer.iter_at_offset(offset);
+    let edits = state.borrow().edits;
+    let text_view_clone = text_view.clone();
+    let state = state.clone();
+    request_at(
+        app_context,
+        &buffer,
+        &iter,
+        "hoverProvider",
+        "textDocument/hover",
+        json!({}),
+        move |result| {
+            let Ok(result) = result else {
+                return;
+            };
+            let plain = raw_text(&result["contents"]);
+            let Some(markup) = tooltip_markup(render_contents(&result["contents"]), &plain) else {
+                return;
+            };
+            let buffer = text_view_clone.buffer();
+            let range = &result["range"];
+            let (start, end) = if range.is_object() {
+                let position = |position: &Value| {
+                    let line = position["line"].as_u64().unwrap_or_default() as u32;
+                    let character = position["character"].as_u64().unwrap_or_default() as u32;
+                    iter_at_position(&buffer, line, character).offset()
+                };
+                (position(&range["start"]), position(&range["end"]).max(offset + 1))
+            } else {
+                word_range(&buffer.iter_at_offset(offset))
+            };
+
+            {
+                let mut state = state.borrow_mut();
+                let pointer_inside = state.pointer.is_some_and(|pointer| start <= pointer && pointer < end);
+                if state.edits != edits || !pointer_inside {
+                    return;
+                }
+                state.shown = Some((start, end, markup));
+            }
+            text_view_clone.trigger_tooltip_query();
+        },
+    );
+}
+
+/// Shows the documentation of the symbol under the pointer in a tooltip
+///
+/// This must be connected before the diagnostics' tooltip, so that both the
+/// messages and the documentation are shown when both apply.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to show documentation for
+pub fn connect_hover(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let state = Rc::new(RefCell::new(HoverState::default()));
+    text_view.set_has_tooltip(true);
+
+    let motion = EventControllerMotion::new();
+    let app_context_clone = app_context.clone();
+    let state_clone = state.clone();
+    let text_view_clone = text_view.clone();
+    motion.connect_motion(move |_, x, y| {
+        let (buffer_x, buffer_y) =
+            text_view_clone.window_to_buffer_coords(TextWindowType::Widget, x as i32, y as i32);
+        let offset = text_view_clone
+            .iter_at_location(buffer_x, buffer_y)
+            .filter(|iter| !iter.char().is_whitespace())
+            .map(|iter| iter.offset());
+
+        let mut state = state_clone.borrow_mut();
+        if state.pointer == offset {
+            return;
+        }
+        state.pointer = offset;
+        if let Some(timeout) = state.timeout.take() {
+            timeout.remove();
+        }
+        let Some(offset) = offset else {
+            return;
+        };
+        if state.shown.as_ref().is_some_and(|(start, end, _)| *start <= offset && offset < *end) {
+            return;
+        }
+        state.shown = None;
+
+        let app_context = app_context_clone.clone();
+        let text_view = text_view_clone.clone();
+        let hover_state = state_clone.clone();
+        state.timeout = Some(glib::timeout_add_local_once(HOVER_DELAY, move || {
+            // The source is gone once it ran, so it must not be removed
+            hover_state.borrow_mut().timeout = None;
+            request_hover(&app_context, &text_view, &hover_state, offset);
+        }));
+    });
+    let state_clone = state.clone();
+    motion.connect_leave(move |_| {
+        let mut state = state_clone.borrow_mut();
+        state.pointer = None;
+        if let Some(timeout) = state.timeout.take() {
+            timeout.remove();
+        }
+    });
+    text_view.add_controller(motion);
+
+    let state_clone = state.clone();
+    text_view.buffer().connect_changed(move |_| {
+        let mut state = state_clone.borrow_mut();
+        state.edits += 1;
+        state.shown = None;
+    });
+
+    let app_context_clone = app_context.clone();
+    text_view.connect_query_tooltip(move |text_view, x, y, keyboard_mode, tooltip| {
+        if keyboard_mode {
+            return false;
+        }
+        let (buffer_x, buffer_y) = text_view.window_to_buffer_coords(TextWindowType::Widget, x, y);
+        let Some(iter) = text_view.iter_at_location(buffer_x, buffer_y) else {
+            return false;
+        };
+        let markup = match &state.borrow().shown {
+            Some((start, end, markup)) if *start <= iter.offset() && iter.offset() < *end => markup.clone(),
+            _ => return false,
+        };
+        let mut text: Vec<String> = crate::diagnostics::messages_at(&app_context_clone, &iter)
+            .iter()
+            .map(|message| glib::markup_escape_text(message).to_string())
+            .collect();
+        if !text.is_empty() {
+            text.push("―".repeat(20));
+        }
+        text.push(markup);
+        tooltip.set_markup(Some(&text.join("\n")));
+        true
+    });
+}
@@ -0,0 +1,1217 @@
+//! Module for the Language Server Protocol client
+//!
+//! Each language can name a language server in its settings. A server is
+//! started for each language and project root the first time a file of that
+//! language is opened, and every open file is synchronized with it: the text
+//! is sent with `didOpen`, then the edits recorded by the buffer's
+//! `ChangeTracker` are sent with `didChange` shortly after they happen. A
+//! server that exits unexpectedly is restarted a few times, and its files are
+//! opened again. The servers are shut down when the application quits.
+//!
+//! The diagnostics published by the servers are shown with the `diagnostics`
+//! module. The servers also answer the requests for the definitions and the
+//! references of a symbol, for the documentation shown when hovering it, for
+//! the edits renaming it, and for the symbols listed in the outline.
+
+mod client;
+mod goto;
+mod hover;
+mod rename;
+mod symbols;
+
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextIter};
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::change_tracker::TextChange;
+use crate::diagnostics::{self, Diagnostic};
+use crate::problems::{self, Problem, Severity};
+use crate::settings::LanguageServerSettings;
+use crate::AppContext;
+use client::{Handlers, LanguageServer, METHOD_NOT_FOUND, ResponseError};
+
+pub use goto::{find_references, go_to_definition};
+pub use hover::connect_hover;
+pub use rename::{FileChanges, edit_buffer, edit_text, rename};
+pub use symbols::document_symbols;
+
+/// Delay after an edit before the changes are sent to the server
+const CHANGE_DELAY: Duration = Duration::from_millis(150);
+
+/// Delay before restarting a server that exited unexpectedly
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Number of times a server is restarted before giving up
+const MAX_RESTARTS: u32 = 3;
+
+/// Time a server must run after initializing for its earlier crashes to be forgotten
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+/// Time the servers are given to exit when the application quits
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// `TextDocumentSyncKind` values
+const SYNC_NONE: i64 = 0;
+const SYNC_FULL: i64 = 1;
+
+/// Identifies a server: one runs for each language and project root
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct ServerKey {
+    /// Syntax name of the language
+    language: String,
+    /// Root directory of the project
+    root: PathBuf,
+}
+
+/// A started server
+struct ServerEntry {
+    /// Unique number of this start of the server
+    id: u64,
+    /// The server
+    server: Rc<LanguageServer>,
+    /// Time the server finished initializing
+    initialized_at: Option<Instant>,
+}
+
+/// What is done about a server that exited
+#[derive(Debug, PartialEq, Eq)]
+enum ExitAction {
+    /// Nothing, as the server was stopped or disabled
+    Nothing,
+    /// The server crashed and is restarted
+    Restart,
+    /// The server crashed too often and is disabled, with the number of crashes
+    GiveUp(u32),
+}
+
+/// A server that exited
+struct ExitedServer {
+    /// The server
+    server: Rc<LanguageServer>,
+    /// Buffers that were synchronized with the server
+    buffers: Vec<TextBuffer>,
+    /// What is done about the exit
+    action: ExitAction,
+}
+
+/// A buffer synchronized with a server
+struct Document {
+    /// Server the buffer is synchronized with
+    key: ServerKey,
+    /// URI of the buffer's file
+    uri: String,
+    /// Version of the text last sent to the server
+    version: i32,
+    /// Whether sending the changes is scheduled
+    flush_scheduled: bool,
+}
+
+/// Language servers and the buffers synchronized with them
+#[derive(Default)]
+pub struct Lsp {
+    /// Running servers
+    servers: HashMap<ServerKey, ServerEntry>,
+    /// Synchronized buffers
+    documents: HashMap<TextBuffer, Document>,
+    /// Buffers whose edits are watched
+    watched: HashSet<TextBuffer>,
+    /// Number of times each server was restarted
+    restarts: HashMap<ServerKey, u32>,
+    /// Servers that failed to start or exited too often
+    disabled: HashSet<ServerKey>,
+    /// Number of the last started server
+    last_server_id: u64,
+}
+
+impl Lsp {
+    /// Records a started server, replacing any previous start of it
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The server
+    /// * `id` - Unique number of this start, from `last_server_id`
+    /// * `server` - The started server
+    fn add_server(&mut self, key: &ServerKey, id: u64, server: Rc<LanguageServer>) {
+        let entry = ServerEntry {
+            id,
+            server,
+            initialized_at: None,
+        };
+        self.servers.insert(key.clone(), entry);
+    }
+
+    /// Records that a start of a server finished initializing
+    fn server_initialized(&mut self, key: &ServerKey, id: u64) {
+        if let Some(entry) = self.servers.get_mut(key).filter(|entry| entry.id == id) {
+            entry.initialized_at = Some(Instant::now());
+        }
+    }
+
+    /// Forgets a server that exited and its documents, and decides whether to restart it
+    ///
+    /// Crashes are counted from the last time the server ran for `STABLE_RUN`
+    /// after initializing.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The server
+    /// * `id` - Number of the start of the server that exited
+    ///
+    /// # Returns
+    ///
+    /// The server, or `None` if a later start of it is running
+    fn server_exited(&mut self, key: &ServerKey, id: u64) -> Option<ExitedServer> {
+        if self.servers.get(key).is_none_or(|entry| entry.id != id) {
+            return None;
+        }
+        let entry = self.servers.remove(key).expect("the server is running");
+        let buffers: Vec<TextBuffer> = self
+            .documents
+            .iter()
+            .filter(|(_, document)| &document.key == key)
+            .map(|(buffer, _)| buffer.clone())
+            .collect();
+        for buffer in &buffers {
+            self.documents.remove(buffer);
+        }
+
+        let action = if entry.server.is_stopping() || self.disabled.contains(key) {
+            ExitAction::Nothing
+        } else {
+            if entry.initialized_at.is_some_and(|time| time.elapsed() >= STABLE_RUN) {
+                self.restarts.remove(key);
+            }
+            match self.count_restart(key) {
+                restarts if restarts > MAX_RESTARTS => ExitAction::GiveUp(restarts),
+                _ => ExitAction::Restart,
+            }
+        };
+        Some(ExitedServer {
+            server: entry.server,
+            buffers,
+            action,
+        })
+    }
+
+    /// Counts an unexpected exit of a server
+    ///
+    /// A server that exited more than `MAX_RESTARTS` times is disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The server
+    ///
+    /// # Returns
+    ///
+    /// The number of times the server exited unexpectedly
+    fn count_restart(&mut self, key: &ServerKey) -> u32 {
+        let restarts = self.restarts.entry(key.clone()).or_default();
+        *restarts += 1;
+        let restarts = *restarts;
+        if restarts > MAX_RESTARTS {
+            self.disabled.insert(key.clone());
+        }
+        restarts
+    }
+}
+
+/// Shared language server state
+pub type LspState = Rc<RefCell<Lsp>>;
+
+/// Returns the URI of a file
+///
+/// # Arguments
+///
+/// * `path` - Path of the file
+pub fn path_to_uri(path: &Path) -> Option<String> {
+    glib::filename_to_uri(path, None).ok().map(String::from)
+}
+
+/// Returns the path of a file URI
+///
+/// # Arguments
+///
+/// * `uri` - URI of the file
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    glib::filename_from_uri(uri).ok().map(|(path, _)| path)
+}
+
+/// Returns the byte index of a UTF-16 column of a line
+///
+/// Columns past the end of the line are clamped before its line ending.
+///
+/// # Arguments
+///
+/// * `line` - The text of the line, from its start
+/// * `character` - The column in UTF-16 code units
+pub fn utf16_byte_index(line: &str, character: u32) -> usize {
+    let mut units = 0;
+    for (index, c) in line.char_indices() {
+        if units >= character || c == '\n' || c == '\r' {
+            return index;
+        }
+        units += c.len_utf16() as u32;
+    }
+    line.len()
+}
+
+/// Returns the position of a buffer at a line and UTF-16 column
+///
+/// Positions past the end of the line or of the buffer are clamped.
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer
+/// * `line` - Line of the position
+/// * `character` - Column of the position in UTF-16 code units
+pub fn iter_at_position(buffer: &TextBuffer, line: u32, character: u32) -> TextIter {
+    let Some(mut iter) = buffer.iter_at_line(line as i32) else {
+        return buffer.end_iter();
+    };
+    let mut line_end = iter;
+    if !line_end.ends_line() {
+        line_end.forward_to_line_end();
+    }
+    let text = iter.slice(&line_end);
+    iter.forward_chars(text[..utf16_byte_index(&text, character)].chars().count() as i32);
+    iter
+}
+
+/// Returns the line and UTF-16 column of a position
+///
+/// # Arguments
+///
+/// * `iter` - The position
+pub fn position_at_iter(iter: &TextIter) -> (u32, u32) {
+    let mut line_start = *iter;
+    line_start.set_line_offset(0);
+    let column = line_start.slice(iter).encode_utf16().count();
+    (iter.line() as u32, column as u32)
+}
+
+/// Returns the open buffer of a file
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `path` - Path of the file
+pub fn buffer_for_path(app_context: &Rc<RefCell<AppContext>>, path: &Path) -> Option<TextBuffer> {
+    app_context
+        .borrow()
+        .buffer_paths
+        .borrow()
+        .iter()
+        .find(|(_, buffer_path)| buffer_path.as_path() == path)
+        .map(|(buffer, _)| buffer.clone())
+}
+
+/// Returns the directory a server for a file runs in
+///
+/// This is the nearest directory containing one of the root markers, else the
+/// open directory if it contains the file, else the file's directory.
+fn workspace_root(app_context: &Rc<RefCell<AppContext>>, path: &Path, root_markers: &[String]) -> PathBuf {
+    let directory = path.parent().unwrap_or(path);
+    if let Some(root) = directory
+        .ancestors()
+        .find(|ancestor| root_markers.iter().any(|marker| ancestor.join(marker).exists()))
+    {
+        return root.to_path_buf();
+    }
+    let opened_directory = app_context.borrow().app_settings.borrow().last_opened_directory.clone();
+    match opened_directory {
+        Some(opened_directory) if path.starts_with(&opened_directory) => opened_directory,
+        _ => directory.to_path_buf(),
+    }
+}
+
+/// Reports a message about a server in the Problems panel
+fn report(app_context: &Rc<RefCell<AppContext>>, server_name: &str, severity: Severity, message: String) {
+    let problem = Problem {
+        source: server_name.to_string(),
+        path: None,
+        line: None,
+        column: None,
+        severity,
+        message,
+    };
+    problems::set_problems(app_context, server_name, None, vec![problem]);
+}
+
+/// Returns the capabilities announced to servers
+fn client_capabilities() -> Value {
+    json!({
+        "general": {"positionEncodings": ["utf-16"]},
+        "textDocument": {
+            "synchronization": {"dynamicRegistration": false, "didSave": true},
+            "publishDiagnostics": {"relatedInformation": false},
+            "definition": {"linkSupport": true},
+            "references": {},
+            "hover": {"contentFormat": ["markdown", "plaintext"]},
+            "rename": {"prepareSupport": false},
+            "documentSymbol": {"hierarchicalDocumentSymbolSupport": true},
+        },
+        "workspace": {"configuration": true, "workspaceEdit": {"documentChanges": true}},
+    })
+}
+
+/// Returns the parameters of the `initialize` request
+fn initialize_params(root: &Path, config: &LanguageServerSettings) -> Value {
+    let root_uri = path_to_uri(root);
+    let root_name = root.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    json!({
+        "processId": std::process::id(),
+        "clientInfo": {"name": "e4code", "version": env!("CARGO_PKG_VERSION")},
+        "rootPath": root,
+        "rootUri": root_uri,
+        "workspaceFolders": [{"uri": root_uri, "name": root_name}],
+        "initializationOptions": config.initialization_options,
+        "capabilities": client_capabilities(),
+    })
+}
+
+/// Answers the requests a server sends to the editor
+fn handle_request(method: &str, params: Value) -> Result<Value, ResponseError> {
+    match method {
+        // No settings are stored for servers, so each item gets the default
+        "workspace/configuration" => {
+            let items = params["items"].as_array().map_or(0, Vec::len);
+            Ok(Value::Array(vec![Value::Null; items]))
+        }
+        "client/registerCapability" | "client/unregisterCapability" | "window/workDoneProgress/create"
+        | "window/showMessageRequest" => Ok(Value::Null),
+        _ => Err(ResponseError {
+            code: METHOD_NOT_FOUND,
+            message: format!("Unsupported method {}", method),
+        }),
+    }
+}
+
+/// Handles the notifications a server sends to the editor
+fn handle_notification(app_context: &Rc<RefCell<AppContext>>, server_name: &str, method: &str, params: Value) {
+    match method {
+        "textDocument/publishDiagnostics" => publish_diagnostics(app_context, server_name, &params),
+        "window/showMessage" => {
+            let severity = match params["type"].as_i64() {
+                Some(1) => Severity::Error,
+                Some(2) => Severity::Warning,
+                _ => return,
+            };
+            let message = params["message"].as_str().unwrap_or_default().to_string();
+            report(app_context, server_name, severity, message);
+        }
+        _ => {}
+    }
+}
+
+/// Shows the diagnostics a server published for a file
+///
+/// Positions are converted from UTF-16 columns using the file's buffer if it
+/// is open.
+fn publish_diagnostics(app_context: &Rc<RefCell<AppContext>>, server_name: &str, params: &Value) {
+    let Some(path) = params["uri"].as_str().and_then(uri_to_path) else {
+        return;
+    };
+    let buffer = buffer_for_path(app_context, &path);
+    let position = |position: &Value| {
+        let line = position["line"].as_u64().unwrap_or_default() as u32;
+        let character = position["character"].as_u64().unwrap_or_default() as u32;
+        match &buffer {
+            Some(buffer) => {
+                let iter = iter_at_position(buffer, line, character);
+                (iter.line(), iter.line_offset())
+            }
+            None => (line as i32, character as i32),
+        }
+    };
+
+    let diagnostics = params["diagnostics"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|diagnostic| {
+            let message = diagnostic["message"].as_str().unwrap_or_default();
+            let message = match diagnostic["source"].as_str() {
+                Some(source) if source != server_name => format!("{} ({})", message, source),
+                _ => message.to_string(),
+            };
+            Diagnostic {
+                path: Some(path.clone()),
+                start: position(&diagnostic["range"]["start"]),
+                end: position(&diagnostic["range"]["end"]),
+                severity: match diagnostic["severity"].as_i64() {
+                    Some(2) => Severity::Warning,
+                    Some(3) | Some(4) => Severity::Info,
+                    _ => Severity::Error,
+                },
+                message,
+                source: server_name.to_string(),
+            }
+        })
+        .collect();
+    // Servers publish after every change, so the Problems panel isn't forced open
+    diagnostics::set_diagnostics(app_context, server_name, Some(&path), diagnostics, false);
+}
+
+/// Starts the server of a language and project root
+fn start_server(
+    app_context: &Rc<RefCell<AppContext>>,
+    key: &ServerKey,
+    config: &LanguageServerSettings,
+) -> Option<Rc<LanguageServer>> {
+    let lsp_state = app_context.borrow().lsp.clone();
+    let argv = match glib::shell_parse_argv(config.command.as_str()) {
+        Ok(argv) if !argv.is_empty() => argv,
+        Ok(_) => return None,
+        Err(e) => {
+            lsp_state.borrow_mut().disabled.insert(key.clone());
+            let message = format!("Invalid language server command \"{}\": {}", config.command, e);
+            report(app_context, &config.command, Severity::Error, message);
+            return None;
+        }
+    };
+    let name = Path::new(&argv[0])
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let id = {
+        let mut lsp = lsp_state.borrow_mut();
+        lsp.last_server_id += 1;
+        lsp.last_server_id
+    };
+
+    let handlers = Handlers {
+        notification: Box::new({
+            let app_context = app_context.clone();
+            let name = name.clone();
+            move |method, params| handle_notification(&app_context, &name, method, params)
+        }),
+        request: Box::new(handle_request),
+        exited: Box::new({
+            let app_context = app_context.clone();
+            let key = key.clone();
+            move || server_exited(&app_context, &key, id)
+        }),
+    };
+    let server = match LanguageServer::start(name.clone(), &argv, &key.root, handlers) {
+        Ok(server) => server,
+        Err(e) => {
+            lsp_state.borrow_mut().disabled.insert(key.clone());
+            report(app_context, &name, Severity::Error, format!("Failed to start {}: {}", name, e));
+            return None;
+        }
+    };
+
+    let app_context_clone = app_context.clone();
+    let server_clone = server.clone();
+    let key_clone = key.clone();
+    server.initialize(initialize_params(&key.root, config), move |result| match result {
+        // A successful start clears the messages of a previous crash
+        Ok(_) => {
+            let lsp_state = app_context_clone.borrow().lsp.clone();
+            lsp_state.borrow_mut().server_initialized(&key_clone, id);
+            problems::set_problems(&app_context_clone, &server_clone.name, None, Vec::new());
+        }
+        Err(e) => {
+            let lsp_state = app_context_clone.borrow().lsp.clone();
+            lsp_state.borrow_mut().disabled.insert(key_clone.clone());
+            let message = format!("{} failed to initialize: {}", server_clone.name, e.message);
+            report(&app_context_clone, &server_clone.name, Severity::Error, message);
+            server_clone.kill();
+        }
+    });
+    lsp_state.borrow_mut().add_server(key, id, server.clone());
+    Some(server)
+}
+
+/// Handles the exit of a server, restarting it if it crashed
+fn server_exited(app_context: &Rc<RefCell<AppContext>>, key: &ServerKey, id: u64) {
+    let lsp_state = app_context.borrow().lsp.clone();
+    let Some(ExitedServer { server, buffers, action }) = lsp_state.borrow_mut().server_exited(key, id) else {
+        return;
+    };
+    for buffer in &buffers {
+        set_recording(app_context, buffer, false);
+    }
+    // The diagnostics of a stopped server are out of date
+    diagnostics::clear_diagnostics(app_context, &server.name);
+
+    match action {
+        ExitAction::Nothing => {}
+        ExitAction::GiveUp(restarts) => {
+            let message = format!(
+                "{} exited unexpectedly {} times and won't be restarted",
+                server.name, restarts
+            );
+            report(app_context, &server.name, Severity::Error, message);
+        }
+        ExitAction::Restart => {
+            let message = format!("{} exited unexpectedly and is being restarted", server.name);
+            report(app_context, &server.name, Severity::Warning, message);
+
+            let app_context = app_context.clone();
+            glib::timeout_add_local_once(RESTART_DELAY, move || {
+                for buffer in buffers {
+                    open_document(&app_context, &buffer);
+                }
+            });
+        }
+    }
+}
+
+/// Starts or stops recording the edits of a buffer for its server
+fn set_recording(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer, record: bool) {
+    if let Some(tracker) = app_context.borrow().change_trackers.borrow_mut().get_mut(buffer) {
+        tracker.record_text_changes = record;
+        tracker.text_changes.clear();
+    }
+}
+
+/// Returns the kind of text synchronization a server asked for
+fn sync_kind(capabilities: &Value) -> i64 {
+    let sync = &capabilities["textDocumentSync"];
+    sync.as_i64().or_else(|| sync["change"].as_i64()).unwrap_or(SYNC_NONE)
+}
+
+/// Synchronizes a buffer with the language server of its language
+///
+/// Nothing happens if the buffer has no file or its language has no server.
+/// The server is started if needed. A buffer whose file changed is closed on
+/// the server under its previous URI first.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to synchronize
+pub fn open_document(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let Some(path) = app_context.borrow().buffer_paths.borrow().get(buffer).cloned() else {
+        return;
+    };
+    let language = crate::syntax_highlighting::syntax_name_for_buffer(app_context, buffer);
+    let config = app_context.borrow().app_settings.borrow().language_settings(&language).language_server;
+    let Some(uri) = path_to_uri(&path) else {
+        return;
+    };
+
+    let lsp_state = app_context.borrow().lsp.clone();
+    let previous_uri = lsp_state.borrow().documents.get(buffer).map(|document| document.uri.clone());
+    match previous_uri {
+        Some(previous_uri) if previous_uri == uri => return,
+        Some(_) => close_document(app_context, buffer),
+        None => {}
+    }
+    let Some(config) = config.filter(|config| !config.command.trim().is_empty()) else {
+        return;
+    };
+
+    let key = ServerKey {
+        language: language.clone(),
+        root: workspace_root(app_context, &path, &config.root_markers),
+    };
+    let running = lsp_state.borrow().servers.get(&key).map(|entry| entry.server.clone());
+    let server = match running {
+        Some(server) => server,
+        None if lsp_state.borrow().disabled.contains(&key) => return,
+        None => match start_server(app_context, &key, &config) {
+            Some(server) => server,
+            None => return,
+        },
+    };
+
+    lsp_state.borrow_mut().documents.insert(
+        buffer.clone(),
+        Document {
+            key,
+            uri: uri.clone(),
+            version: 0,
+            flush_scheduled: false,
+        },
+    );
+    set_recording(app_context, buffer, true);
+    let language_id = config.language_id.unwrap_or_else(|| language.to_lowercase());
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+    server.notify(
+        "textDocument/didOpen",
+        json!({"textDocument": {"uri": uri, "languageId": language_id, "version": 0, "text": text.as_str()}}),
+    );
+
+    if lsp_state.borrow_mut().watched.insert(buffer.clone()) {
+        let app_context_clone = app_context.clone();
+        buffer.connect_changed(move |buffer| schedule_changes(&app_context_clone, buffer));
+    }
+}
+
+/// Schedules sending the recorded edits of a buffer to its server
+fn schedule_changes(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let lsp_state = app_context.borrow().lsp.clone();
+    {
+        let mut lsp = lsp_state.borrow_mut();
+        let Some(document) = lsp.documents.get_mut(buffer) else {
+            return;
+        };
+        if document.flush_scheduled {
+            return;
+        }
+        document.flush_scheduled = true;
+    }
+    let app_context = app_context.clone();
+    let buffer = buffer.clone();
+    glib::timeout_add_local_once(CHANGE_DELAY, move || flush_changes(&app_context, &buffer));
+}
+
+/// Returns the `contentChanges` of a `didChange` notification
+///
+/// # Arguments
+///
+/// * `buffer` - The edited buffer
+/// * `changes` - The edits recorded since the last notification
+/// * `kind` - Kind of text synchronization the server asked for
+fn content_changes(buffer: &TextBuffer, changes: Vec<TextChange>, kind: i64) -> Vec<Value> {
+    if kind == SYNC_FULL {
+        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+        return vec![json!({"text": text.as_str()})];
+    }
+    changes
+        .into_iter()
+        .map(|change| {
+            json!({
+                "range": {
+                    "start": {"line": change.start.0, "character": change.start.1},
+                    "end": {"line": change.end.0, "character": change.end.1},
+                },
+                "text": change.text,
+            })
+        })
+        .collect()
+}
+
+/// Sends the recorded edits of a buffer to its server right away
+///
+/// Requests about a buffer must be preceded by this, so that the server sees
+/// the current text. The edits are sent as ranges, unless the server asked for
+/// the whole text. They are kept while the server is initializing.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The edited buffer
+pub fn flush_changes(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let lsp_state = app_context.borrow().lsp.clone();
+    let (server, uri) = {
+        let mut lsp = lsp_state.borrow_mut();
+        let lsp = &mut *lsp;
+        let Some(document) = lsp.documents.get_mut(buffer) else {
+            return;
+        };
+        document.flush_scheduled = false;
+        let Some(entry) = lsp.servers.get(&document.key) else {
+            return;
+        };
+        (entry.server.clone(), document.uri.clone())
+    };
+    if !server.is_initialized() {
+        schedule_changes(app_context, buffer);
+        return;
+    }
+
+    let changes = app_context
+        .borrow()
+        .change_trackers
+        .borrow_mut()
+        .get_mut(buffer)
+        .map(|tracker| tracker.take_text_changes())
+        .unwrap_or_default();
+    let kind = sync_kind(&server.capabilities());
+    if changes.is_empty() || kind == SYNC_NONE {
+        return;
+    }
+    let content_changes = content_changes(buffer, changes, kind);
+
+    let version = {
+        let mut lsp = lsp_state.borrow_mut();
+        let Some(document) = lsp.documents.get_mut(buffer) else {
+            return;
+        };
+        document.version += 1;
+        document.version
+    };
+    server.notify(
+        "textDocument/didChange",
+        json!({"textDocument": {"uri": uri, "version": version}, "contentChanges": content_changes}),
+    );
+}
+
+/// Sends a request about a buffer to the buffer's server
+///
+/// The pending edits of the buffer are sent first, so that the request
+/// applies to the server's current text.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer
+/// * `provider` - Server capability required by the request, e.g. "hoverProvider"
+/// * `method` - Method of the request
+/// * `params` - Parameters besides the document
+/// * `callback` - Called with the answer of the server
+///
+/// # Returns
+///
+/// Whether the request was sent, which requires a server able to answer it
+fn request_for(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    provider: &str,
+    method: &str,
+    mut params: Value,
+    callback: impl FnOnce(Result<Value, ResponseError>) + 'static,
+) -> bool {
+    flush_changes(app_context, buffer);
+    let lsp_state = app_context.borrow().lsp.clone();
+    let (server, uri) = {
+        let lsp = lsp_state.borrow();
+        let Some(document) = lsp.documents.get(buffer) else {
+            return false;
+        };
+        let Some(entry) = lsp.servers.get(&document.key) else {
+            return false;
+        };
+        (entry.server.clone(), document.uri.clone())
+    };
+    // The capabilities are only known once the server is initialized
+    let capability = &server.capabilities()[provider];
+    if server.is_initialized() && (capability.is_null() || capability == &Value::Bool(false)) {
+        return false;
+    }
+
+    params["textDocument"] = json!({"uri": uri});
+    server.request(method, params, callback);
+    true
+}
+
+/// Sends a request about a position of a buffer to the buffer's server
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer
+/// * `iter` - The position
+/// * `provider` - Server capability required by the request, e.g. "hoverProvider"
+/// * `method` - Method of the request
+/// * `params` - Parameters besides the document and the position
+/// * `callback` - Called with the answer of the server
+///
+/// # Returns
+///
+/// Whether the request was sent, which requires a server able to answer it
+fn request_at(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    iter: &TextIter,
+    provider: &str,
+    method: &str,
+    mut params: Value,
+    callback: impl FnOnce(Result<Value, ResponseError>) + 'static,
+) -> bool {
+    let (line, character) = position_at_iter(iter);
+    params["position"] = json!({"line": line, "character": character});
+    request_for(app_context, buffer, provider, method, params, callback)
+}
+
+/// Tells the server of a buffer that its file was saved
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The saved buffer
+/// * `file_path` - Path of the file the buffer was saved to
+pub fn did_save(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer, file_path: &Path) {
+    flush_changes(app_context, buffer);
+    let lsp_state = app_context.borrow().lsp.clone();
+    let server = {
+        let lsp = lsp_state.borrow();
+        let Some(document) = lsp.documents.get(buffer) else {
+            return;
+        };
+        // After "Save as", the buffer is opened again under its new URI
+        if path_to_uri(file_path).as_ref() != Some(&document.uri) {
+            return;
+        }
+        let Some(entry) = lsp.servers.get(&document.key) else {
+            return;
+        };
+        entry.server.clone()
+    };
+
+    let save = &server.capabilities()["textDocumentSync"]["save"];
+    if save.is_null() || save == &Value::Bool(false) {
+        return;
+    }
+    let uri = path_to_uri(file_path);
+    let params = if save["includeText"] == Value::Bool(true) {
+        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+        json!({"textDocument": {"uri": uri}, "text": text.as_str()})
+    } else {
+        json!({"textDocument": {"uri": uri}})
+    };
+    server.notify("textDocument/didSave", params);
+}
+
+/// Stops synchronizing a buffer with its server
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer, usually one whose tab was closed
+pub fn close_document(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let lsp_state = app_context.borrow().lsp.clone();
+    let closed = {
+        let mut lsp = lsp_state.borrow_mut();
+        lsp.documents.remove(buffer).and_then(|document| {
+            lsp.servers
+                .get(&document.key)
+                .map(|entry| (entry.server.clone(), document.uri))
+        })
+    };
+    set_recording(app_context, buffer, false);
+    if let Some((server, uri)) = closed {
+        server.notify("textDocument/didClose", json!({"textDocument": {"uri": uri}}));
+    }
+}
+
+/// Shuts down all servers
+///
+/// The main loop is run until the servers exit, for a short while at most,
+/// and the servers still running after that are killed.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn shutdown_all(app_context: &Rc<RefCell<AppContext>>) {
+    let lsp_state = app_context.borrow().lsp.clone();
+    let servers: Vec<Rc<LanguageServer>> = {
+        let mut lsp = lsp_state.borrow_mut();
+        lsp.documents.clear();
+        lsp.servers.drain().map(|(_, entry)| entry.server).collect()
+    };
+    for server in &servers {
+        server.shutdown();
+    }
+
+    let main_context = glib::MainContext::default();
+    let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+    while servers.iter().any(|server| !server.has_exited()) && Instant::now() < deadline {
+        if !main_context.iteration(false) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+    for server in servers.iter().filter(|server| !server.has_exited()) {
+        server.kill();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::change_tracker::ChangeTracker;
+    use crate::test_support::{fixture, run_on_main_thread, run_with_gtk, run_until};
+    use std::cell::Cell;
+    use std::ffi::OsString;
+
+    /// `TextDocumentSyncKind` of servers receiving the edited ranges
+    const SYNC_INCREMENTAL: i64 = 2;
+
+    #[test]
+    fn utf16_columns_are_converted_to_byte_indices() {
+        // "é" is two bytes and one unit, "😀" four bytes and two units
+        let line = "aé😀b\r\nnext";
+        let cases = [(0, 0), (1, 1), (2, 3), (4, 7), (5, 8), (6, 8), (100, 8)];
+        for (character, index) in cases {
+            assert_eq!(utf16_byte_index(line, character), index, "column {}", character);
+        }
+        assert_eq!(utf16_byte_index("", 3), 0);
+        assert_eq!(utf16_byte_index("ab", 9), 2);
+    }
+
+    /// What the mock server reported
+    #[derive(Default)]
+    struct Received {
+        /// Messages the server received, in order
+        messages: RefCell<Vec<Value>>,
+        /// Whether the server closed its output
+        exited: Cell<bool>,
+    }
+
+    impl Received {
+        /// Returns the methods of the received messages, `None` for responses
+        fn methods(&self) -> Vec<Option<String>> {
+            self.messages
+                .borrow()
+                .iter()
+                .map(|message| message["method"].as_str().map(String::from))
+                .collect()
+        }
+    }
+
+    /// Starts `tests/fixtures/mock_lsp_server.py`
+    fn start_mock(args: &[&str]) -> (Rc<LanguageServer>, Rc<Received>) {
+        let received = Rc::new(Received::default());
+        let mut argv = vec![OsString::from("python3"), fixture("mock_lsp_server.py").into_os_string()];
+        argv.extend(args.iter().map(OsString::from));
+        let handlers = Handlers {
+            notification: Box::new({
+                let received = received.clone();
+                move |method, params| {
+                    if method == "mock/received" {
+                        received.messages.borrow_mut().push(params);
+                    }
+                }
+            }),
+            request: Box::new(handle_request),
+            exited: Box::new({
+                let received = received.clone();
+                move || received.exited.set(true)
+            }),
+        };
+        let server = LanguageServer::start("mock".to_string(), &argv, &std::env::temp_dir(), handlers)
+            .expect("python3 runs the mock server");
+        (server, received)
+    }
+
+    /// Starts the mock server and waits until it is initialized
+    fn start_initialized_mock(args: &[&str]) -> (Rc<LanguageServer>, Rc<Received>) {
+        let (server, received) = start_mock(args);
+        server.initialize(json!({"capabilities": {}}), |_| {});
+        assert!(run_until(|| server.is_initialized()));
+        (server, received)
+    }
+
+    /// Records the edits of a buffer the way the editor does
+    fn track(buffer: &TextBuffer) -> Rc<RefCell<ChangeTracker>> {
+        let tracker = Rc::new(RefCell::new(ChangeTracker::new()));
+        tracker.borrow_mut().record_text_changes = true;
+        let tracker_insert = tracker.clone();
+        buffer.connect_insert_text(move |_, pos, text| {
+            let mut end = *pos;
+            end.forward_chars(text.chars().count() as i32);
+            tracker_insert.borrow_mut().record_insertion(pos, &end, text);
+        });
+        let tracker_delete = tracker.clone();
+        buffer.connect_delete_range(move |_, start, end| tracker_delete.borrow_mut().record_deletion(start, end));
+        tracker
+    }
+
+    #[test]
+    fn messages_wait_for_the_initialize_handshake() {
+        run_on_main_thread(|| {
+            let (server, received) = start_mock(&[]);
+            server.notify("textDocument/didOpen", json!({"text": "é日本😀"}));
+            let answer = Rc::new(RefCell::new(None));
+            let answer_clone = answer.clone();
+            server.request("custom/method", Value::Null, move |result| {
+                *answer_clone.borrow_mut() = Some(result.map_err(|e| e.code));
+            });
+            assert!(!server.is_initialized());
+
+            let initialized = Rc::new(Cell::new(false));
+            let initialized_clone = initialized.clone();
+            server.initialize(json!({"capabilities": {}}), move |result| {
+                initialized_clone.set(result.is_ok());
+            });
+            assert!(run_until(|| answer.borrow().is_some()));
+
+            assert!(initialized.get());
+            assert_eq!(sync_kind(&server.capabilities()), SYNC_INCREMENTAL);
+            assert_eq!(
+                received.methods(),
+                vec![
+                    Some("initialize".to_string()),
+                    Some("initialized".to_string()),
+                    Some("textDocument/didOpen".to_string()),
+                    Some("custom/method".to_string()),
+                ]
+            );
+            // The multibyte text survived the framing in both directions
+            assert_eq!(received.messages.borrow()[2]["params"]["text"], "é日本😀");
+            assert_eq!(*answer.borrow(), Some(Err(METHOD_NOT_FOUND)));
+            server.kill();
+        });
+    }
+
+    #[test]
+    fn shutdown_asks_the_server_to_exit() {
+        run_on_main_thread(|| {
+            let (server, received) = start_initialized_mock(&[]);
+            server.shutdown();
+            assert!(run_until(|| received.exited.get()));
+
+            assert!(server.is_stopping());
+            assert!(server.has_exited());
+            let methods = received.methods();
+            assert_eq!(methods[methods.len() - 2..], [Some("shutdown".to_string()), Some("exit".to_string())]);
+        });
+    }
+
+    #[test]
+    fn a_crash_fails_the_pending_requests() {
+        run_on_main_thread(|| {
+            let (server, received) = start_initialized_mock(&[]);
+            let answer = Rc::new(RefCell::new(None));
+            let answer_clone = answer.clone();
+            server.request("mock/crash", Value::Null, move |result| {
+                *answer_clone.borrow_mut() = Some(result.map_err(|e| e.message));
+            });
+            assert!(run_until(|| received.exited.get()));
+
+            assert!(!server.is_stopping());
+            assert_eq!(*answer.borrow(), Some(Err("mock exited".to_string())));
+        });
+    }
+
+    /// Starts the mock server as the editor does, initialized, and records it
+    fn add_initialized_mock(lsp: &mut Lsp, key: &ServerKey) -> (u64, Rc<LanguageServer>, Rc<Received>) {
+        lsp.last_server_id += 1;
+        let id = lsp.last_server_id;
+        let (server, received) = start_initialized_mock(&[]);
+        lsp.add_server(key, id, server.clone());
+        lsp.server_initialized(key, id);
+        (id, server, received)
+    }
+
+    /// Crashes a recorded server and handles its exit
+    fn crash(lsp: &mut Lsp, key: &ServerKey, id: u64, server: &Rc<LanguageServer>, received: &Received) -> ExitAction {
+        server.request("mock/crash", Value::Null, |_| {});
+        assert!(run_until(|| received.exited.get()));
+        let exited = lsp.server_exited(key, id).expect("the crashed start is the running one");
+        // The exit of a start that is no longer running is ignored
+        assert!(lsp.server_exited(key, id).is_none());
+        exited.action
+    }
+
+    fn test_key() -> ServerKey {
+        ServerKey {
+            language: "Rust".to_string(),
+            root: std::env::temp_dir(),
+        }
+    }
+
+    #[test]
+    fn crashed_server_is_restarted_up_to_max_restarts() {
+        run_on_main_thread(|| {
+            let key = test_key();
+            let mut lsp = Lsp::default();
+            let mut starts = 0;
+            loop {
+                let (id, server, received) = add_initialized_mock(&mut lsp, &key);
+                starts += 1;
+                match crash(&mut lsp, &key, id, &server, &received) {
+                    ExitAction::Restart => assert!(!lsp.disabled.contains(&key)),
+                    ExitAction::GiveUp(restarts) => {
+                        assert_eq!(restarts, MAX_RESTARTS + 1);
+                        break;
+                    }
+                    ExitAction::Nothing => panic!("a crash is handled"),
+                }
+                assert!(!lsp.servers.contains_key(&key));
+            }
+
+            assert_eq!(starts, MAX_RESTARTS + 1);
+            assert!(lsp.disabled.contains(&key));
+        });
+    }
+
+    #[test]
+    fn crashes_are_forgotten_after_a_stable_run() {
+        run_on_main_thread(|| {
+            let key = test_key();
+            let mut lsp = Lsp::default();
+            for _ in 0..MAX_RESTARTS {
+                let (id, server, received) = add_initialized_mock(&mut lsp, &key);
+                assert_eq!(crash(&mut lsp, &key, id, &server, &received), ExitAction::Restart);
+            }
+
+            let (id, server, received) = add_initialized_mock(&mut lsp, &key);
+            let entry = lsp.servers.get_mut(&key).unwrap();
+            entry.initialized_at = Instant::now().checked_sub(STABLE_RUN);
+            assert_eq!(crash(&mut lsp, &key, id, &server, &received), ExitAction::Restart);
+            assert_eq!(lsp.restarts[&key], 1);
+        });
+    }
+
+    #[test]
+    fn a_stopped_server_is_not_restarted() {
+        run_on_main_thread(|| {
+            let key = test_key();
+            let mut lsp = Lsp::default();
+            let (id, server, received) = add_initialized_mock(&mut lsp, &key);
+            server.shutdown();
+            assert!(run_until(|| received.exited.get()));
+
+            let exited = lsp.server_exited(&key, id).expect("the stopped start is the running one");
+            assert_eq!(exited.action, ExitAction::Nothing);
+            assert!(!lsp.restarts.contains_key(&key));
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn incremental_changes_use_utf16_columns() {
+        run_with_gtk(|| {
+            let buffer = TextBuffer::new(None);
+            buffer.set_text("héllo\n日本😀x\n");
+            let tracker = track(&buffer);
+
+            // The emoji takes two UTF-16 code units
+            let mut iter = buffer.iter_at_line_offset(1, 3).unwrap();
+            buffer.insert(&mut iter, "é");
+            let mut start = buffer.iter_at_line_offset(1, 2).unwrap();
+            let mut end = buffer.iter_at_line_offset(1, 4).unwrap();
+            buffer.delete(&mut start, &mut end);
+            let mut iter = buffer.iter_at_line_offset(0, 2).unwrap();
+            buffer.insert(&mut iter, "\n");
+
+            let changes = content_changes(&buffer, tracker.borrow_mut().take_text_changes(), SYNC_INCREMENTAL);
+            let range = |start: (u32, u32), end: (u32, u32)| {
+                json!({
+                    "start": {"line": start.0, "character": start.1},
+                    "end": {"line": end.0, "character": end.1},
+                })
+            };
+            assert_eq!(
+                changes,
+                vec![
+                    json!({"range": range((1, 4), (1, 4)), "text": "é"}),
+                    json!({"range": range((1, 2), (1, 5)), "text": ""}),
+                    json!({"range": range((0, 2), (0, 2)), "text": "\n"}),
+                ]
+            );
+
+            let (server, received) = start_initialized_mock(&[]);
+            server.notify("textDocument/didChange", json!({"contentChanges": changes}));
+            assert!(run_until(|| received.messages.borrow().len() == 3));
+            assert_eq!(received.messages.borrow()[2]["params"]["contentChanges"], json!(changes));
+            server.kill();
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a display"]
+    fn full_sync_sends_the_whole_text() {
+        run_with_gtk(|| {
+            let buffer = TextBuffer::new(None);
+            buffer.set_text("日本\n");
+            let tracker = track(&buffer);
+            buffer.insert(&mut buffer.end_iter(), "😀");
+
+            let (server, _) = start_initialized_mock(&["--sync", "1"]);
+            let kind = sync_kind(&server.capabilities());
+            let changes = content_changes(&buffer, tracker.borrow_mut().take_text_changes(), kind);
+            assert_eq!(changes, vec![json!({"text": "日本\n😀"})]);
+            server.kill();
+        });
+    }
+}
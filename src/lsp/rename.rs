@@ -0,0 +1,198 @@
+//! Rename of a symbol by its language server
+//!
+//! The server answers a rename with a `WorkspaceEdit`: the text edits to make
+//! in each file. Their positions count UTF-16 code units, so they are applied
+//! with the helpers here, to open buffers or to the text of files on disk.
+
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextIter};
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use super::{iter_at_position, request_at, uri_to_path, utf16_byte_index};
+use crate::AppContext;
+
+/// A replacement of a range of a file
+#[derive(Clone, Debug)]
+pub struct TextEdit {
+    /// Start of the range, as a line and a UTF-16 column
+    pub start: (u32, u32),
+    /// End of the range, as a line and a UTF-16 column
+    pub end: (u32, u32),
+    /// Text replacing the range
+    pub new_text: String,
+}
+
+/// The edits of one file
+#[derive(Clone, Debug)]
+pub struct FileChanges {
+    /// The file
+    pub path: PathBuf,
+    /// The edits, in the order given by the server
+    pub edits: Vec<TextEdit>,
+}
+
+/// Reads an LSP position
+fn position(value: &Value) -> (u32, u32) {
+    (
+        value["line"].as_u64().unwrap_or_default() as u32,
+        value["character"].as_u64().unwrap_or_default() as u32,
+    )
+}
+
+/// Reads a list of LSP `TextEdit`s
+fn parse_text_edits(edits: &Value) -> Vec<TextEdit> {
+    edits
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|edit| TextEdit {
+            start: position(&edit["range"]["start"]),
+            end: position(&edit["range"]["end"]),
+            new_text: edit["newText"].as_str().unwrap_or_default().to_string(),
+        })
+        .collect()
+}
+
+/// Reads a `WorkspaceEdit`, merging the edits of each file
+///
+/// # Returns
+///
+/// The edits by file, or an error if the edit also creates, renames or
+/// deletes files
+fn parse_workspace_edit(edit: &Value) -> Result<Vec<FileChanges>, String> {
+    let mut documents: Vec<(&str, &Value)> = Vec::new();
+    if let Some(document_changes) = edit["documentChanges"].as_array() {
+        for change in document_changes {
+            if change.get("kind").is_some() {
+                return Err("The rename creates, renames or deletes files, which isn't supported".to_string());
+            }
+            documents.push((change["textDocument"]["uri"].as_str().unwrap_or_default(), &change["edits"]));
+        }
+    } else if let Some(changes) = edit["changes"].as_object() {
+        documents.extend(changes.iter().map(|(uri, edits)| (uri.as_str(), edits)));
+    }
+
+    let mut files: Vec<FileChanges> = Vec::new();
+    for (uri, edits) in documents {
+        let Some(path) = uri_to_path(uri) else {
+            return Err(format!("Unsupported location {}", uri));
+        };
+        let edits = parse_text_edits(edits);
+        match files.iter_mut().find(|file| file.path == path) {
+            Some(file) => file.edits.extend(edits),
+            None => files.push(FileChanges { path, edits }),
+        }
+    }
+    files.retain(|file| !file.edits.is_empty());
+    Ok(files)
+}
+
+/// Asks the server of a buffer to rename the symbol at a position
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer
+/// * `iter` - Position of the symbol
+/// * `new_name` - The new name of the symbol
+/// * `callback` - Called with the edits by file, or with the server's error
+///
+/// # Returns
+///
+/// Whether the request was sent, which requires a server able to rename
+pub fn rename(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    iter: &TextIter,
+    new_name: &str,
+    callback: impl FnOnce(Result<Vec<FileChanges>, String>) + 'static,
+) -> bool {
+    request_at(
+        app_context,
+        buffer,
+        iter,
+        "renameProvider",
+        "textDocument/rename",
+        json!({"newName": new_name}),
+        move |result| {
+            callback(match result {
+                Ok(Value::Null) => Ok(Vec::new()),
+                Ok(edit) => parse_workspace_edit(&edit),
+                Err(error) => Err(error.message),
+            })
+        },
+    )
+}
+
+/// Orders edits for applying them from the end of the text
+///
+/// Edits at the same position keep the order given by the server once applied.
+fn reversed<T: Copy + Ord>(mut ranges: Vec<(T, T, usize)>) -> impl Iterator<Item = (T, T, usize)> {
+    ranges.sort_by_key(|&(start, _, _)| start);
+    ranges.into_iter().rev()
+}
+
+/// Applies edits to a buffer
+///
+/// Call this within a user action, so that the edits form one undo step.
+///
+/// # Arguments
+///
+/// * `buffer` - The buffer
+/// * `edits` - The edits, whose positions refer to the text before any of them
+pub fn edit_buffer(buffer: &TextBuffer, edits: &[TextEdit]) {
+    let ranges: Vec<(i32, i32, usize)> = edits
+        .iter()
+        .enumerate()
+        .map(|(index, edit)| {
+            let start = iter_at_position(buffer, edit.start.0, edit.start.1).offset();
+            let end = iter_at_position(buffer, edit.end.0, edit.end.1).offset();
+            (start, end.max(start), index)
+        })
+        .collect();
+    for (start, end, index) in reversed(ranges) {
+        let mut start_iter = buffer.iter_at_offset(start);
+        let mut end_iter = buffer.iter_at_offset(end);
+        buffer.delete(&mut start_iter, &mut end_iter);
+        buffer.insert(&mut start_iter, &edits[index].new_text);
+    }
+}
+
+/// Returns the byte index of a line and UTF-16 column of a text
+///
+/// Positions past the end of the line or of the text are clamped.
+fn byte_index(text: &str, line_starts: &[usize], (line, character): (u32, u32)) -> usize {
+    match line_starts.get(line as usize) {
+        Some(&line_start) => line_start + utf16_byte_index(&text[line_start..], character),
+        None => text.len(),
+    }
+}
+
+/// Returns a text with edits applied
+///
+/// # Arguments
+///
+/// * `text` - The text, usually the content of a file on disk
+/// * `edits` - The edits, whose positions refer to the text before any of them
+pub fn edit_text(text: &str, edits: &[TextEdit]) -> String {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+        .collect();
+    let ranges: Vec<(usize, usize, usize)> = edits
+        .iter()
+        .enumerate()
+        .map(|(index, edit)| {
+            let start = byte_index(text, &line_starts, edit.start);
+            let end = byte_index(text, &line_starts, edit.end);
+            (start, end.max(start), index)
+        })
+        .collect();
+    let mut edited = text.to_string();
+    for (start, end, index) in reversed(ranges) {
+        edited.replace_range(start..end, &edits[index].new_text);
+    }
+    edited
+}
@@ -0,0 +1,114 @@
+//! Symbols of a document, for the outline
+//!
+//! Servers answer `documentSymbol` with a tree of `DocumentSymbol`s or with a
+//! flat list of `SymbolInformation`s; both are read into outline symbols, the
+//! nesting being worked out by the outline from their ranges.
+
+use gtk4::TextBuffer;
+use serde_json::{Value, json};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::{iter_at_position, request_for};
+use crate::outline::Symbol;
+use crate::AppContext;
+
+/// Names of the LSP `SymbolKind`s, which are counted from 1
+const KIND_NAMES: [&str; 26] = [
+    "file",
+    "module",
+    "namespace",
+    "package",
+    "class",
+    "method",
+    "property",
+    "field",
+    "constructor",
+    "enum",
+    "interface",
+    "function",
+    "variable",
+    "constant",
+    "string",
+    "number",
+    "boolean",
+    "array",
+    "object",
+    "key",
+    "null",
+    "enum member",
+    "struct",
+    "event",
+    "operator",
+    "type parameter",
+];
+
+/// Returns the line and the column in characters of an LSP position
+fn position(buffer: &TextBuffer, value: &Value) -> (i32, i32) {
+    let line = value["line"].as_u64().unwrap_or_default() as u32;
+    let character = value["character"].as_u64().unwrap_or_default() as u32;
+    let iter = iter_at_position(buffer, line, character);
+    (iter.line(), iter.line_offset())
+}
+
+/// Reads a list of symbols and, recursively, their children
+fn add_symbols(buffer: &TextBuffer, items: &Value, symbols: &mut Vec<Symbol>) {
+    for item in items.as_array().into_iter().flatten() {
+        // A `SymbolInformation` has a location, a `DocumentSymbol` a range and the range of its name
+        let (range, name_range) = if item["location"].is_object() {
+            (&item["location"]["range"], &item["location"]["range"])
+        } else if item["selectionRange"].is_object() {
+            (&item["range"], &item["selectionRange"])
+        } else {
+            (&item["range"], &item["range"])
+        };
+        let kind = item["kind"]
+            .as_u64()
+            .and_then(|kind| KIND_NAMES.get((kind as usize).wrapping_sub(1)))
+            .unwrap_or(&"symbol");
+        let (line, column) = position(buffer, &name_range["start"]);
+        symbols.push(Symbol {
+            name: item["name"].as_str().unwrap_or_default().to_string(),
+            kind: kind.to_string(),
+            depth: 0,
+            line,
+            column,
+            first_line: position(buffer, &range["start"]).0,
+            last_line: position(buffer, &range["end"]).0,
+        });
+        add_symbols(buffer, &item["children"], symbols);
+    }
+}
+
+/// Asks the server of a buffer for the symbols of its text
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer
+/// * `callback` - Called with the symbols, or with `None` if the server failed
+///
+/// # Returns
+///
+/// Whether the request was sent, which requires a server able to answer it
+pub fn document_symbols(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    callback: impl FnOnce(Option<Vec<Symbol>>) + 'static,
+) -> bool {
+    let buffer_clone = buffer.clone();
+    request_for(
+        app_context,
+        buffer,
+        "documentSymbolProvider",
+        "textDocument/documentSymbol",
+        json!({}),
+        move |result| {
+            callback(result.ok().map(|result| {
+                let mut symbols = Vec::new();
+                add_symbols(&buffer_clone, &result, &mut symbols);
+                symbols
+            }))
+        },
+    )
+}
@@ -5,27 +5,52 @@
 #![windows_subsystem = "windows"]
 
 mod actions;
+mod auto_pairs;
+mod block_selection;
 mod buffer_tags;
 mod change_tracker;
 mod clipboard;
+mod command_line;
+mod comments;
+mod diagnostics;
+mod completion;
 mod dialogs;
+mod editorconfig;
 mod file_operations;
+mod folding;
+mod formatter;
+mod go_to_line;
 mod indentation;
+mod line_operations;
+mod local_history;
+mod lsp;
 mod incremental_highlighting;
+mod multi_cursor;
+mod navigation;
+mod outline;
+mod problems;
+mod references;
+mod rename;
 pub mod search;
 mod settings;
+mod snippets;
 mod syntax_highlighting;
+mod tasks;
 pub mod tab_manager;
+#[cfg(test)]
+mod test_support;
 mod ui;
+mod undo_history;
 mod utils;
+mod whitespace;
 
 use gtk4::prelude::*;
 use gtk4::{
-    Application, ApplicationWindow, Box, HeaderBar, Label, MenuButton, Notebook, Orientation,
+    Application, ApplicationWindow, Box, GestureClick, HeaderBar, Label, MenuButton, Notebook, Orientation,
     Paned, PopoverMenu, ScrolledWindow, Settings, TextBuffer, TextIter, TextMark, TextView,
     TreeStore, TreeView,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use gtk4::pango;
 use syntect::highlighting::ThemeSet;
@@ -59,6 +84,8 @@ pub struct AppContext {
     pub buffer_paths: Rc<RefCell<HashMap<gtk4::TextBuffer, PathBuf>>>,
     /// Syntax highlighting context
     pub syntax_context: Rc<RefCell<SyntaxHighlightingContext>>,
+    /// Parser states of each buffer, to find strings and comments
+    pub scope_caches: syntax_highlighting::ScopeCaches,
     /// Current font description
     pub current_font_desc: Rc<RefCell<pango::FontDescription>>,
     /// Function to update the font
@@ -89,6 +116,42 @@ pub struct AppContext {
     pub indent_styles: Rc<RefCell<HashMap<TextBuffer, (bool, usize)>>>,
     /// Cache for compiled regex patterns
     pub regex_cache: Rc<RefCell<HashMap<String, regex::Regex>>>,
+    /// Secondary carets for each buffer
+    pub multi_cursors: multi_cursor::MultiCursors,
+    /// Rectangular selections for each buffer
+    pub block_selections: block_selection::BlockSelections,
+    /// Undo history of each buffer
+    pub undo_histories: undo_history::UndoHistories,
+    /// Folding state of each buffer
+    pub fold_states: folding::FoldStates,
+    /// Completion popup and token indexes
+    pub completion: completion::CompletionState,
+    /// Snippet definitions and active snippet sessions
+    pub snippets: snippets::SnippetState,
+    /// EditorConfig properties of each buffer's file
+    pub editor_configs: editorconfig::EditorConfigs,
+    /// Status bar label showing the EditorConfig properties of the current tab
+    pub editorconfig_status: Label,
+    /// Notebook holding the pages of the panel below the editor
+    pub bottom_panel: Notebook,
+    /// Problems reported about files and the panel listing them
+    pub problems: problems::ProblemsState,
+    /// Language servers and the buffers synchronized with them
+    pub lsp: lsp::LspState,
+    /// Diagnostics of all files and those shown in each buffer
+    pub diagnostics: diagnostics::DiagnosticsState,
+    /// References panel listing the places found by the language servers
+    pub references: references::ReferencesState,
+    /// Locations to go back and forward to
+    pub navigation: navigation::NavigationState,
+    /// Symbols of the buffers and the outline panel listing them
+    pub outline: outline::OutlineState,
+    /// Running task and the Output panel showing its output
+    pub tasks: tasks::TasksState,
+    /// Snapshots of a file and the History panel listing them
+    pub file_history: ui::file_history::FileHistoryState,
+    /// Buffers being saved, whose formatter may still be running
+    pub saving_buffers: Rc<RefCell<HashSet<TextBuffer>>>,
 }
 
 impl AppContext {
@@ -194,6 +257,20 @@ impl AppContext {
         notebook.set_hexpand(true);
         notebook.set_vexpand(true);
 
+        // The bottom panel stays hidden until one of its pages has something to show
+        let bottom_panel = Notebook::new();
+        bottom_panel.set_height_request(150);
+        bottom_panel.set_visible(false);
+        let problems = problems::create_problems_panel(&bottom_panel);
+        let references = references::create_references_panel(&bottom_panel);
+        let tasks = tasks::create_tasks_panel(&bottom_panel);
+        let file_history = ui::file_history::create_file_history_panel(&bottom_panel);
+
+        // The outline panel sits hidden on the right of the editor
+        let outline_paned = Paned::new(Orientation::Horizontal);
+        outline_paned.set_vexpand(true);
+        let outline = outline::create_outline_panel(&outline_paned);
+
         // Font Description Management
         let initial_font_desc = pango::FontDescription::from_string(&app_settings.borrow().font);
         let current_font_desc = Rc::new(RefCell::new(initial_font_desc));
@@ -281,6 +358,14 @@ impl AppContext {
         status_bar.borrow_mut().set_margin_end(5);
         status_bar.borrow_mut().set_margin_top(2);
         status_bar.borrow_mut().set_margin_bottom(2);
+        status_bar.borrow_mut().set_hexpand(true);
+
+        let editorconfig_status = Label::new(None);
+        editorconfig_status.set_halign(gtk4::Align::End);
+        editorconfig_status.set_margin_start(5);
+        editorconfig_status.set_margin_end(5);
+        editorconfig_status.set_margin_top(2);
+        editorconfig_status.set_margin_bottom(2);
 
         let last_line = Rc::new(RefCell::new(1u32));
         let last_col = Rc::new(RefCell::new(1u32));
@@ -291,6 +376,14 @@ impl AppContext {
         let change_trackers = Rc::new(RefCell::new(HashMap::<TextBuffer, ChangeTracker>::new()));
         let indent_styles = Rc::new(RefCell::new(HashMap::<TextBuffer, (bool, usize)>::new()));
         let regex_cache = Rc::new(RefCell::new(HashMap::<String, regex::Regex>::new()));
+        let multi_cursors: multi_cursor::MultiCursors = Rc::new(RefCell::new(HashMap::new()));
+        let block_selections: block_selection::BlockSelections = Rc::new(RefCell::new(HashMap::new()));
+        let undo_histories: undo_history::UndoHistories = Rc::new(RefCell::new(HashMap::new()));
+        let fold_states: folding::FoldStates = Rc::new(RefCell::new(HashMap::new()));
+        let scope_caches: syntax_highlighting::ScopeCaches = Rc::new(RefCell::new(HashMap::new()));
+        let completion: completion::CompletionState = Rc::new(RefCell::new(completion::Completion::default()));
+        let snippets: snippets::SnippetState = Rc::new(RefCell::new(snippets::Snippets::default()));
+        let editor_configs: editorconfig::EditorConfigs = Rc::new(RefCell::new(HashMap::new()));
 
         // Create syntax_context with highlight_closure
         let highlight_closure: Rc<dyn Fn(TextBuffer)> = Rc::new({
@@ -499,6 +592,7 @@ impl AppContext {
             app_settings,
             buffer_paths,
             syntax_context,
+            scope_caches,
             current_font_desc,
             update_font,
             initial_font_size,
@@ -514,6 +608,24 @@ impl AppContext {
             change_trackers,
             indent_styles,
             regex_cache,
+            multi_cursors,
+            block_selections,
+            undo_histories,
+            fold_states,
+            completion,
+            snippets,
+            editor_configs,
+            editorconfig_status: editorconfig_status.clone(),
+            bottom_panel: bottom_panel.clone(),
+            problems,
+            lsp: Rc::new(RefCell::new(lsp::Lsp::default())),
+            diagnostics: Rc::new(RefCell::new(diagnostics::Diagnostics::default())),
+            references,
+            navigation: Rc::new(RefCell::new(navigation::NavigationHistory::default())),
+            outline,
+            tasks,
+            file_history,
+            saving_buffers: Rc::new(RefCell::new(HashSet::new())),
         }));
 
 
@@ -525,6 +637,8 @@ impl AppContext {
         file_menu_model.append(Some("Open directory"), Some("app.open_directory"));
         file_menu_model.append(Some("Save"), Some("app.save"));
         file_menu_model.append(Some("Save as"), Some("app.save_as"));
+        file_menu_model.append(Some("Reload from disk"), Some("app.reload"));
+        file_menu_model.append(Some("File history"), Some("app.file_history"));
         file_menu_model.append(Some("Close this file"), Some("app.close_current_file"));
         file_menu_model.append(Some("Close all files"), Some("app.close_all_files"));
         file_menu_model.append(Some("Exit"), Some("app.quit"));
@@ -534,17 +648,81 @@ impl AppContext {
 
         let edit_menu_button = MenuButton::builder().label("Edit").build();
         let edit_menu_model = gio::Menu::new();
+        edit_menu_model.append(Some("Undo"), Some("app.undo"));
+        edit_menu_model.append(Some("Redo"), Some("app.redo"));
         edit_menu_model.append(Some("Search and replace"), Some("app.search_and_replace"));
         edit_menu_model.append(Some("Cut"), Some("app.cut"));
         edit_menu_model.append(Some("Copy"), Some("app.copy"));
         edit_menu_model.append(Some("Paste"), Some("app.paste"));
         edit_menu_model.append(Some("Indent"), Some("app.indent"));
         edit_menu_model.append(Some("Outdent"), Some("app.outdent"));
+        edit_menu_model.append(Some("Convert indentation to tabs"), Some("app.convert_indentation_to_tabs"));
+        edit_menu_model.append(Some("Convert indentation to spaces"), Some("app.convert_indentation_to_spaces"));
+        edit_menu_model.append(Some("Format document"), Some("app.format_document"));
+        edit_menu_model.append(Some("Rename symbol"), Some("app.rename_symbol"));
+        let lines_menu_model = gio::Menu::new();
+        lines_menu_model.append(Some("Move up"), Some("app.move_line_up"));
+        lines_menu_model.append(Some("Move down"), Some("app.move_line_down"));
+        lines_menu_model.append(Some("Duplicate"), Some("app.duplicate_lines"));
+        lines_menu_model.append(Some("Delete"), Some("app.delete_lines"));
+        lines_menu_model.append(Some("Join"), Some("app.join_lines"));
+        lines_menu_model.append(Some("Sort"), Some("app.sort_lines"));
+        lines_menu_model.append(Some("Sort (case-insensitive)"), Some("app.sort_lines_case_insensitive"));
+        lines_menu_model.append(Some("Sort (numeric)"), Some("app.sort_lines_numeric"));
+        lines_menu_model.append(Some("Reverse"), Some("app.reverse_lines"));
+        lines_menu_model.append(Some("Remove duplicates"), Some("app.remove_duplicate_lines"));
+        lines_menu_model.append(Some("Insert line above"), Some("app.insert_line_above"));
+        lines_menu_model.append(Some("Insert line below"), Some("app.insert_line_below"));
+        edit_menu_model.append_submenu(Some("Lines"), &lines_menu_model);
+        edit_menu_model.append(Some("Complete word"), Some("app.complete"));
+        edit_menu_model.append(Some("Edit snippets"), Some("app.edit_snippets"));
+        let folding_menu_model = gio::Menu::new();
+        folding_menu_model.append(Some("Fold"), Some("app.fold"));
+        folding_menu_model.append(Some("Unfold"), Some("app.unfold"));
+        folding_menu_model.append(Some("Fold all"), Some("app.fold_all"));
+        folding_menu_model.append(Some("Unfold all"), Some("app.unfold_all"));
+        folding_menu_model.append(Some("Fold to level 1"), Some("app.fold_to_level(1)"));
+        folding_menu_model.append(Some("Fold to level 2"), Some("app.fold_to_level(2)"));
+        folding_menu_model.append(Some("Fold to level 3"), Some("app.fold_to_level(3)"));
+        edit_menu_model.append_submenu(Some("Folding"), &folding_menu_model);
+        edit_menu_model.append(Some("Toggle line comment"), Some("app.toggle_line_comment"));
+        edit_menu_model.append(Some("Toggle block comment"), Some("app.toggle_block_comment"));
+        edit_menu_model.append(Some("Add next occurrence"), Some("app.add_next_occurrence"));
         edit_menu_model.append(Some("Word wrap"), Some("app.word_wrap"));
         let edit_popover = PopoverMenu::from_model(Some(&edit_menu_model));
         edit_menu_button.set_popover(Some(&edit_popover));
         header_bar.pack_start(&edit_menu_button);
 
+        let go_menu_button = MenuButton::builder().label("Go").build();
+        let go_menu_model = gio::Menu::new();
+        go_menu_model.append(Some("Go to definition"), Some("app.go_to_definition"));
+        go_menu_model.append(Some("Find references"), Some("app.find_references"));
+        go_menu_model.append(Some("Go to symbol"), Some("app.outline_popup"));
+        go_menu_model.append(Some("Go to line"), Some("app.go_to_line"));
+        go_menu_model.append(Some("Back"), Some("app.go_back"));
+        go_menu_model.append(Some("Forward"), Some("app.go_forward"));
+        let go_popover = PopoverMenu::from_model(Some(&go_menu_model));
+        go_menu_button.set_popover(Some(&go_popover));
+        header_bar.pack_start(&go_menu_button);
+
+        let run_menu_button = MenuButton::builder().label("Run").build();
+        let run_menu_model = gio::Menu::new();
+        run_menu_model.append(Some("Run task"), Some("app.run_task"));
+        run_menu_model.append(Some("Run last task"), Some("app.run_last_task"));
+        run_menu_model.append(Some("Stop task"), Some("app.stop_task"));
+        let run_popover = PopoverMenu::from_model(Some(&run_menu_model));
+        run_menu_button.set_popover(Some(&run_popover));
+        header_bar.pack_start(&run_menu_button);
+
+        let view_menu_button = MenuButton::builder().label("View").build();
+        let view_menu_model = gio::Menu::new();
+        view_menu_model.append(Some("Problems"), Some("app.toggle_problems"));
+        view_menu_model.append(Some("Outline"), Some("app.toggle_outline"));
+        view_menu_model.append(Some("Output"), Some("app.toggle_output"));
+        let view_popover = PopoverMenu::from_model(Some(&view_menu_model));
+        view_menu_button.set_popover(Some(&view_popover));
+        header_bar.pack_start(&view_menu_button);
+
         let settings_menu_button = MenuButton::builder().label("Settings").build();
         let settings_menu_model = gio::Menu::new();
         settings_menu_model.append(Some("Preferences"), Some("app.settings"));
@@ -565,6 +743,19 @@ impl AppContext {
         // Populate the tree view with the initial directory
         populate_tree_view(&window, &tree_store, &initial_directory);
 
+        problems::connect_problems_panel(&new_context_rc);
+        references::connect_references_panel(&new_context_rc);
+        outline::connect_outline_panel(&new_context_rc);
+        tasks::connect_tasks_panel(&new_context_rc);
+        ui::file_history::connect_file_history_panel(&new_context_rc);
+
+        // Language servers are asked to exit and the running task is stopped along with the application
+        let app_context_clone_for_shutdown = new_context_rc.clone();
+        app.connect_shutdown(move |_| {
+            lsp::shutdown_all(&app_context_clone_for_shutdown);
+            tasks::stop_task(&app_context_clone_for_shutdown);
+        });
+
         // --- Tree View Row Activation ---
         let app_context_clone_tree_view = new_context_rc.clone();
         tree_view.connect_row_activated(move |_, tree_path, _column| {
@@ -590,8 +781,30 @@ impl AppContext {
             }
         });
 
-        vbox.append(&notebook);
-        vbox.append(&*status_bar.borrow());
+        let editor_paned = Paned::new(Orientation::Vertical);
+        editor_paned.set_vexpand(true);
+        editor_paned.set_start_child(Some(&notebook));
+        editor_paned.set_end_child(Some(&bottom_panel));
+        editor_paned.set_resize_end_child(false);
+        editor_paned.set_shrink_end_child(false);
+        outline_paned.set_start_child(Some(&editor_paned));
+        vbox.append(&outline_paned);
+        let status_box = Box::new(Orientation::Horizontal, 0);
+        status_box.append(&*status_bar.borrow());
+        // Clicking the cursor position asks for a line to go to
+        status_bar.borrow().set_tooltip_text(Some("Go to line (Ctrl+G)"));
+        status_bar.borrow().set_cursor_from_name(Some("pointer"));
+        let status_bar_click = GestureClick::new();
+        let app_context_clone = new_context_rc.clone();
+        status_bar_click.connect_released(move |_, _, _, _| {
+            let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+            if let Some(text_view) = text_view {
+                go_to_line::show_go_to_line_dialog(&app_context_clone, &text_view);
+            }
+        });
+        status_bar.borrow().add_controller(status_bar_click);
+        status_box.append(&editorconfig_status);
+        vbox.append(&status_box);
         main_paned.set_end_child(Some(&vbox));
         window.set_child(Some(&main_paned));
 
@@ -635,14 +848,10 @@ impl AppContext {
             if has_unsaved_changes {
                 if let Some(buffer) = first_unsaved_buffer {
                     let app_context_clone_for_prompt = app_context_clone_for_window_close.clone();
-                    let context = app_context_clone_for_window_close.borrow();
-
                     tab_manager::prompt_save_changes_async(
-                        &context.window,
+                        &app_context_clone_for_window_close,
                         buffer,
                         first_unsaved_file_path,
-                        context.buffer_paths.clone(),
-                        context.notebook.clone(),
                         first_unsaved_page_index as u32,
                         move |proceed| {
                             if proceed {
@@ -671,9 +880,18 @@ impl AppContext {
 /// It handles both activation (when the app is launched without arguments) and
 /// opening files (when files are passed as command line arguments).
 fn main() -> glib::ExitCode {
+    // The command line is handled by the running instance, unless a new window is asked for
+    let arguments = match command_line::check_arguments(&env::args_os().skip(1).collect::<Vec<_>>()) {
+        Ok(arguments) => arguments,
+        Err(exit_code) => return exit_code,
+    };
+    let mut flags = gio::ApplicationFlags::HANDLES_COMMAND_LINE;
+    if arguments.new_window {
+        flags |= gio::ApplicationFlags::NON_UNIQUE;
+    }
     let app = Application::builder()
         .application_id("com.e4code.editor")
-        .flags(gio::ApplicationFlags::HANDLES_OPEN)
+        .flags(flags)
         .build();
 
     // Use a RefCell to allow mutable access to AppState from different closures
@@ -682,7 +900,7 @@ fn main() -> glib::ExitCode {
     app.connect_activate({
         let app_context_clone = app_context.clone();
         move |app: &Application| {
-            // Create AppContext only if it hasn't been created by connect_open
+            // Create AppContext only if it hasn't been created by connect_command_line
             if app_context_clone.borrow().is_none() {
                 let new_context = AppContext::new(app);
                 let mut opened_any_file = false;
@@ -727,33 +945,34 @@ fn main() -> glib::ExitCode {
         }
     });
 
-    app.connect_open({
+    app.connect_command_line({
         let app_context_clone = app_context.clone();
-        move |app, files, _| {
+        move |app, command_line| {
+            let args: Vec<_> = command_line.arguments().into_iter().skip(1).collect();
+            let cwd = command_line.cwd().or_else(|| env::current_dir().ok()).unwrap_or_default();
+            // The launching process has already reported errors in the arguments
+            let Ok(arguments) = command_line::parse_arguments(&args, &cwd) else {
+                return glib::ExitCode::FAILURE;
+            };
+            // Without files, the window opens as when the application is launched
+            if arguments.files.is_empty() {
+                app.activate();
+                return glib::ExitCode::SUCCESS;
+            }
+
             // Create AppContext only if it hasn't been created by connect_activate
             if app_context_clone.borrow().is_none() {
                 *app_context_clone.borrow_mut() = Some(AppContext::new(app));
             }
-
-            if let Some(context_ref) = app_context_clone.borrow().as_ref() {
-                let context = context_ref.borrow();
-                for file in files {
-                    if let Some(path) = file.path() {
-                        if path.is_file() {
-                            tab_manager::open_file_in_new_tab(
-                                &path,
-                                context_ref,
-                            );
-                        } else if path.is_dir() {
-                            open_directory_in_tree(
-                                &path,
-                                context_ref.clone(),
-                            );
-                        }
-                    }
-                }
-                context.window.present();
+            let Some(context_ref) = app_context_clone.borrow().clone() else {
+                return glib::ExitCode::FAILURE;
+            };
+            let opened = command_line::open_files(&context_ref, &arguments.files);
+            if arguments.wait {
+                command_line::wait_for_close(&context_ref, opened, command_line.clone());
             }
+            context_ref.borrow().window.present();
+            glib::ExitCode::SUCCESS
         }
     });
 
@@ -0,0 +1,598 @@
+//! Module for multi-cursor editing
+//!
+//! This module adds secondary carets on top of GTK's single insert mark.
+//! Carets can be added with Ctrl+click, by selecting the next occurrence of the
+//! word under the cursor (Ctrl+D) or on adjacent lines (Alt+Shift+Up/Down).
+//! While secondary carets exist, typing, deleting, paste, indent and outdent
+//! are applied to every caret at once as a single undo step.
+
+use gtk4::gdk;
+use gtk4::prelude::*;
+use gtk4::{DrawingArea, EventControllerKey, GestureClick, TextBuffer, TextIter, TextMark, TextView};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::rc::Rc;
+
+use crate::AppContext;
+
+/// A secondary caret, made of an insert mark and a selection anchor mark
+///
+/// When both marks are at the same position the caret has no selection.
+pub struct Caret {
+    /// Position of the caret
+    pub insert: TextMark,
+    /// Other end of the caret's selection
+    pub anchor: TextMark,
+}
+
+/// Secondary carets of a buffer and the widgets used to draw them
+#[derive(Default)]
+pub struct MultiCursor {
+    /// Secondary carets; the primary caret is the buffer's insert mark
+    pub carets: Vec<Caret>,
+    /// Overlay widgets drawing the secondary carets in the text view
+    overlays: Vec<DrawingArea>,
+    /// Whether every caret is being edited, so that insertions aren't repeated
+    editing: bool,
+}
+
+/// Map of buffers to their secondary carets
+pub type MultiCursors = Rc<RefCell<HashMap<TextBuffer, MultiCursor>>>;
+
+/// Returns the multi-cursor map of the application context
+fn multi_cursors(app_context: &Rc<RefCell<AppContext>>) -> MultiCursors {
+    app_context.borrow().multi_cursors.clone()
+}
+
+/// Checks whether a buffer currently has secondary carets
+pub fn has_secondary_carets(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> bool {
+    multi_cursors(app_context)
+        .borrow()
+        .get(buffer)
+        .is_some_and(|state| !state.carets.is_empty())
+}
+
+/// Returns the (insert, anchor) mark pairs of all carets, primary caret first
+fn all_carets(multi_cursors: &MultiCursors, buffer: &TextBuffer) -> Vec<(TextMark, TextMark)> {
+    let mut carets = vec![(buffer.get_insert(), buffer.selection_bound())];
+    if let Some(state) = multi_cursors.borrow().get(buffer) {
+        carets.extend(
+            state
+                .carets
+                .iter()
+                .map(|caret| (caret.insert.clone(), caret.anchor.clone())),
+        );
+    }
+    carets
+}
+
+/// Returns the ordered bounds of a caret's selection
+fn caret_bounds(buffer: &TextBuffer, insert: &TextMark, anchor: &TextMark) -> (TextIter, TextIter) {
+    let mut start = buffer.iter_at_mark(insert);
+    let mut end = buffer.iter_at_mark(anchor);
+    start.order(&mut end);
+    (start, end)
+}
+
+/// Adds a secondary caret, optionally with a selection starting at `anchor`
+pub fn add_caret(
+    app_context: &Rc<RefCell<AppContext>>,
+    text_view: &TextView,
+    position: &TextIter,
+    anchor: Option<&TextIter>,
+) {
+    let buffer = text_view.buffer();
+    let multi_cursors = multi_cursors(app_context);
+
+    // Never stack two carets on the same position
+    let offset = position.offset();
+    let occupied = all_carets(&multi_cursors, &buffer)
+        .iter()
+        .any(|(insert, _)| buffer.iter_at_mark(insert).offset() == offset);
+    if occupied {
+        return;
+    }
+
+    let caret = Caret {
+        insert: buffer.create_mark(None, position, false),
+        anchor: buffer.create_mark(None, anchor.unwrap_or(position), false),
+    };
+    multi_cursors
+        .borrow_mut()
+        .entry(buffer.clone())
+        .or_default()
+        .carets
+        .push(caret);
+    refresh_carets(&multi_cursors, text_view);
+}
+
+/// Removes all secondary carets of the text view's buffer
+pub fn clear_carets(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let multi_cursors = multi_cursors(app_context);
+    let buffer = text_view.buffer();
+    let carets = match multi_cursors.borrow_mut().get_mut(&buffer) {
+        Some(state) => std::mem::take(&mut state.carets),
+        None => return,
+    };
+    for caret in carets {
+        buffer.delete_mark(&caret.insert);
+        buffer.delete_mark(&caret.anchor);
+    }
+    refresh_carets(&multi_cursors, text_view);
+}
+
+/// Redraws the secondary carets and their selections
+///
+/// Carets are drawn as thin overlay widgets positioned in buffer coordinates,
+/// while their selections use the `multi_cursor_selection` tag.
+fn refresh_carets(multi_cursors: &MultiCursors, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let mut multi_cursors_borrowed = multi_cursors.borrow_mut();
+    let Some(state) = multi_cursors_borrowed.get_mut(&buffer) else {
+        return;
+    };
+
+    for overlay in state.overlays.drain(..) {
+        text_view.remove(&overlay);
+    }
+    buffer.remove_tag_by_name("multi_cursor_selection", &buffer.start_iter(), &buffer.end_iter());
+
+    for caret in &state.carets {
+        let (start, end) = caret_bounds(&buffer, &caret.insert, &caret.anchor);
+        if start != end {
+            buffer.apply_tag_by_name("multi_cursor_selection", &start, &end);
+        }
+
+        let location = text_view.iter_location(&buffer.iter_at_mark(&caret.insert));
+        let overlay = DrawingArea::new();
+        overlay.set_content_width(2);
+        overlay.set_content_height(location.height().max(1));
+        overlay.set_can_target(false);
+        let text_view_clone = text_view.clone();
+        overlay.set_draw_func(move |_, cr, width, height| {
+            let color = text_view_clone.style_context().color();
+            cr.set_source_rgba(
+                color.red() as f64,
+                color.green() as f64,
+                color.blue() as f64,
+                color.alpha() as f64,
+            );
+            cr.rectangle(0.0, 0.0, width as f64, height as f64);
+            cr.fill().ok();
+        });
+        text_view.add_overlay(&overlay, location.x(), location.y());
+        state.overlays.push(overlay);
+    }
+}
+
+/// Marks the carets of a buffer as being edited together, or no longer
+fn set_editing(multi_cursors: &MultiCursors, buffer: &TextBuffer, editing: bool) {
+    if let Some(state) = multi_cursors.borrow_mut().get_mut(buffer) {
+        state.editing = editing;
+    }
+}
+
+/// Removes secondary carets that ended up on the same position as another caret
+fn merge_overlapping_carets(multi_cursors: &MultiCursors, buffer: &TextBuffer) {
+    let mut multi_cursors_borrowed = multi_cursors.borrow_mut();
+    let Some(state) = multi_cursors_borrowed.get_mut(buffer) else {
+        return;
+    };
+
+    let mut seen = BTreeSet::new();
+    seen.insert(buffer.iter_at_mark(&buffer.get_insert()).offset());
+    state.carets.retain(|caret| {
+        let keep = seen.insert(buffer.iter_at_mark(&caret.insert).offset());
+        if !keep {
+            buffer.delete_mark(&caret.insert);
+            buffer.delete_mark(&caret.anchor);
+        }
+        keep
+    });
+}
+
+/// Inserts text at every caret, replacing their selections
+///
+/// If `texts` has one entry per caret, each caret receives its own text
+/// (carets ordered by position); otherwise every caret receives `texts` joined
+/// with newlines. The whole edit is a single user action.
+pub fn insert_at_carets(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, texts: &[String]) {
+    let buffer = text_view.buffer();
+    let multi_cursors = multi_cursors(app_context);
+    let mut carets = all_carets(&multi_cursors, &buffer);
+    carets.sort_by_key(|(insert, _)| buffer.iter_at_mark(insert).offset());
+    let joined = texts.join("\n");
+
+    set_editing(&multi_cursors, &buffer, true);
+    buffer.begin_user_action();
+    for (i, (insert, anchor)) in carets.iter().enumerate() {
+        let text = if texts.len() == carets.len() { &texts[i] } else { &joined };
+        let (mut start, mut end) = caret_bounds(&buffer, insert, anchor);
+        if start != end {
+            buffer.delete(&mut start, &mut end);
+        }
+        buffer.insert(&mut start, text);
+        // Collapse the selection after the inserted text
+        let position = buffer.iter_at_mark(insert);
+        buffer.move_mark(anchor, &position);
+    }
+    buffer.end_user_action();
+    set_editing(&multi_cursors, &buffer, false);
+
+    merge_overlapping_carets(&multi_cursors, &buffer);
+    refresh_carets(&multi_cursors, text_view);
+}
+
+/// Repeats at the secondary carets the text typed at the primary caret
+///
+/// The text view inserts typed text itself, so that input methods and dead
+/// keys work; this runs after each insertion and replaces the selections of
+/// the secondary carets with the same text. Insertions elsewhere than at the
+/// primary caret, and those made for all carets at once, are left alone.
+///
+/// # Arguments
+///
+/// * `multi_cursors` - The multi-cursor map
+/// * `buffer` - The buffer the text was inserted in
+/// * `end` - End of the inserted text
+/// * `text` - The inserted text
+fn repeat_at_secondary_carets(multi_cursors: &MultiCursors, buffer: &TextBuffer, end: &TextIter, text: &str) {
+    let carets: Vec<(TextMark, TextMark)> = match multi_cursors.borrow().get(buffer) {
+        Some(state) if !state.editing && !state.carets.is_empty() => state
+            .carets
+            .iter()
+            .map(|caret| (caret.insert.clone(), caret.anchor.clone()))
+            .collect(),
+        _ => return,
+    };
+    if end.offset() != buffer.iter_at_mark(&buffer.get_insert()).offset() {
+        return;
+    }
+
+    set_editing(multi_cursors, buffer, true);
+    for (insert, anchor) in &carets {
+        let (mut start, mut end) = caret_bounds(buffer, insert, anchor);
+        if start != end {
+            buffer.delete(&mut start, &mut end);
+        }
+        buffer.insert(&mut start, text);
+        let position = buffer.iter_at_mark(insert);
+        buffer.move_mark(anchor, &position);
+    }
+    set_editing(multi_cursors, buffer, false);
+    merge_overlapping_carets(multi_cursors, buffer);
+}
+
+/// Splits pasted text into one entry per line, for distributing it to carets
+///
+/// Exactly one line terminator ending the text is removed before splitting;
+/// it is then kept at the end of every entry, so that pasting whole lines
+/// gives each caret a whole line. Carriage returns before the newlines are
+/// treated as part of the terminator.
+fn split_pasted_lines(text: &str) -> Vec<String> {
+    let (body, terminator) = if let Some(body) = text.strip_suffix("\r\n") {
+        (body, "\r\n")
+    } else if let Some(body) = text.strip_suffix('\n') {
+        (body, "\n")
+    } else {
+        (text, "")
+    };
+    body.split('\n')
+        .map(|line| format!("{}{}", line.strip_suffix('\r').unwrap_or(line), terminator))
+        .collect()
+}
+
+/// Pastes text at every caret
+///
+/// If the text has one line per caret, each caret receives its own line
+/// (carets ordered by position); otherwise every caret receives the whole
+/// text, unchanged.
+pub fn paste_at_carets(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, text: &str) {
+    let caret_count = all_carets(&multi_cursors(app_context), &text_view.buffer()).len();
+    let lines = split_pasted_lines(text);
+    if lines.len() == caret_count {
+        insert_at_carets(app_context, text_view, &lines);
+    } else {
+        insert_at_carets(app_context, text_view, &[text.to_string()]);
+    }
+}
+
+/// Deletes at every caret: the selection if any, otherwise one character
+/// before (`forward == false`) or after (`forward == true`) the caret
+pub fn delete_at_carets(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, forward: bool) {
+    let buffer = text_view.buffer();
+    let multi_cursors = multi_cursors(app_context);
+    let carets = all_carets(&multi_cursors, &buffer);
+
+    buffer.begin_user_action();
+    for (insert, anchor) in &carets {
+        let (mut start, mut end) = caret_bounds(&buffer, insert, anchor);
+        if start == end {
+            let moved = if forward { end.forward_char() } else { start.backward_char() };
+            if !moved {
+                continue;
+            }
+        }
+        buffer.delete(&mut start, &mut end);
+        let position = buffer.iter_at_mark(insert);
+        buffer.move_mark(anchor, &position);
+    }
+    buffer.end_user_action();
+
+    merge_overlapping_carets(&multi_cursors, &buffer);
+    refresh_carets(&multi_cursors, text_view);
+}
+
+/// Moves every caret with a navigation key
+///
+/// With `extend` the selections are extended instead of collapsed, mirroring
+/// Shift+arrow behaviour for the primary caret.
+fn move_carets(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, keyval: gdk::Key, extend: bool) {
+    let buffer = text_view.buffer();
+    let multi_cursors = multi_cursors(app_context);
+
+    for (insert, anchor) in all_carets(&multi_cursors, &buffer) {
+        let mut iter = buffer.iter_at_mark(&insert);
+        match keyval {
+            gdk::Key::Left => {
+                iter.backward_char();
+            }
+            gdk::Key::Right => {
+                iter.forward_char();
+            }
+            gdk::Key::Up | gdk::Key::Down => {
+                let line_offset = iter.line_offset();
+                let line = iter.line() + if keyval == gdk::Key::Up { -1 } else { 1 };
+                if line < 0 || line >= buffer.line_count() {
+                    continue;
+                }
+                iter = line_iter_at_offset(&buffer, line, line_offset);
+            }
+            gdk::Key::Home => iter.set_line_offset(0),
+            gdk::Key::End => {
+                if !iter.ends_line() {
+                    iter.forward_to_line_end();
+                }
+            }
+            _ => return,
+        }
+        buffer.move_mark(&insert, &iter);
+        if !extend {
+            buffer.move_mark(&anchor, &iter);
+        }
+    }
+
+    merge_overlapping_carets(&multi_cursors, &buffer);
+    refresh_carets(&multi_cursors, text_view);
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+}
+
+/// Returns an iterator on `line` at `line_offset`, clamped to the line's length
+fn line_iter_at_offset(buffer: &TextBuffer, line: i32, line_offset: i32) -> TextIter {
+    let mut iter = buffer.iter_at_line(line).unwrap_or_else(|| buffer.end_iter());
+    let mut line_end = iter;
+    if !line_end.ends_line() {
+        line_end.forward_to_line_end();
+    }
+    iter.set_line_offset(line_offset.min(line_end.line_offset()));
+    iter
+}
+
+/// Returns the lines covered by all carets, in descending order
+///
+/// `start_line` and `end_line` describe the primary caret's lines; the lines
+/// of secondary carets and their selections are added to them. Used by
+/// indent and outdent so that they apply to every caret at once.
+pub fn lines_with_carets(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    start_line: i32,
+    end_line: i32,
+) -> Vec<i32> {
+    let multi_cursors = multi_cursors(app_context);
+    let mut lines: BTreeSet<i32> = (start_line..=end_line).collect();
+    if let Some(state) = multi_cursors.borrow().get(buffer) {
+        for caret in &state.carets {
+            let (start, end) = caret_bounds(buffer, &caret.insert, &caret.anchor);
+            lines.extend(start.line()..=end.line());
+        }
+    }
+    lines.into_iter().rev().collect()
+}
+
+/// Selects the next occurrence of the word under the cursor with a new caret
+///
+/// The first invocation selects the word returned by
+/// `search::get_selected_text_or_word`; following invocations add a caret on
+/// the next occurrence after the last added caret, wrapping around the buffer.
+pub fn add_next_occurrence(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+
+    if !buffer.has_selection() {
+        if let Some((start, end)) = crate::search::get_word_bounds_at_cursor(&buffer) {
+            buffer.select_range(&end, &start);
+        }
+        return;
+    }
+
+    let search_text = crate::search::get_selected_text_or_word(&buffer);
+    if search_text.is_empty() {
+        return;
+    }
+
+    let multi_cursors = multi_cursors(app_context);
+    let carets = all_carets(&multi_cursors, &buffer);
+    let selected_starts: Vec<i32> = carets
+        .iter()
+        .map(|(insert, anchor)| caret_bounds(&buffer, insert, anchor).0.offset())
+        .collect();
+    let last_end = carets
+        .last()
+        .map(|(insert, anchor)| caret_bounds(&buffer, insert, anchor).1)
+        .unwrap_or_else(|| buffer.end_iter());
+
+    let flags = gtk4::TextSearchFlags::VISIBLE_ONLY;
+    let next_match = last_end
+        .forward_search(&search_text, flags, None)
+        .into_iter()
+        .chain(buffer.start_iter().forward_search(&search_text, flags, None))
+        .find(|(start, _)| !selected_starts.contains(&start.offset()));
+
+    if let Some((start, end)) = next_match {
+        add_caret(app_context, text_view, &end, Some(&start));
+        let mut end_mut = end;
+        text_view.scroll_to_iter(&mut end_mut, 0.0, false, 0.0, 0.0);
+    }
+}
+
+/// Adds a caret on the line above the topmost caret, or below the bottommost one
+pub fn add_caret_on_adjacent_line(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, above: bool) {
+    let buffer = text_view.buffer();
+    let multi_cursors = multi_cursors(app_context);
+    let caret_lines: Vec<i32> = all_carets(&multi_cursors, &buffer)
+        .iter()
+        .map(|(insert, _)| buffer.iter_at_mark(insert).line())
+        .collect();
+
+    let line = if above {
+        caret_lines.iter().min().map(|line| line - 1)
+    } else {
+        caret_lines.iter().max().map(|line| line + 1)
+    };
+    let Some(line) = line.filter(|line| *line >= 0 && *line < buffer.line_count()) else {
+        return;
+    };
+
+    let column = buffer.iter_at_mark(&buffer.get_insert()).line_offset();
+    let mut iter = line_iter_at_offset(&buffer, line, column);
+    add_caret(app_context, text_view, &iter, None);
+    text_view.scroll_to_iter(&mut iter, 0.0, false, 0.0, 0.0);
+}
+
+/// Adds the multi-cursor controllers to a text view
+///
+/// This installs Ctrl+click to add carets, and a key controller that applies
+/// deletion, Enter and caret movement to all carets while secondary carets
+/// exist. Typed text, which the text view inserts at the primary caret, is
+/// then repeated at the secondary carets. Escape or a plain click removes the
+/// secondary carets.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to add the controllers to
+pub fn add_multi_cursor_controllers(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let click_gesture = GestureClick::new();
+    click_gesture.set_button(gdk::BUTTON_PRIMARY);
+    click_gesture.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_click = app_context.clone();
+    let text_view_click = text_view.clone();
+    click_gesture.connect_pressed(move |gesture, _n_press, x, y| {
+        if gesture.current_event_state().contains(gdk::ModifierType::CONTROL_MASK) {
+            let (buffer_x, buffer_y) = text_view_click.window_to_buffer_coords(
+                gtk4::TextWindowType::Widget,
+                x as i32,
+                y as i32,
+            );
+            if let Some(iter) = text_view_click.iter_at_location(buffer_x, buffer_y) {
+                add_caret(&app_context_click, &text_view_click, &iter, None);
+            }
+            gesture.set_state(gtk4::EventSequenceState::Claimed);
+        } else {
+            clear_carets(&app_context_click, &text_view_click);
+        }
+    });
+    text_view.add_controller(click_gesture);
+
+    let key_controller = EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_key = app_context.clone();
+    let text_view_key = text_view.clone();
+    key_controller.connect_key_pressed(move |_, keyval, _, state| {
+        let buffer = text_view_key.buffer();
+        if !has_secondary_carets(&app_context_key, &buffer) {
+            return glib::Propagation::Proceed;
+        }
+
+        let shortcut_modifiers = gdk::ModifierType::CONTROL_MASK
+            | gdk::ModifierType::ALT_MASK
+            | gdk::ModifierType::SUPER_MASK;
+        if state.intersects(shortcut_modifiers) {
+            return glib::Propagation::Proceed;
+        }
+
+        match keyval {
+            gdk::Key::Escape => clear_carets(&app_context_key, &text_view_key),
+            gdk::Key::BackSpace => delete_at_carets(&app_context_key, &text_view_key, false),
+            gdk::Key::Delete | gdk::Key::KP_Delete => {
+                delete_at_carets(&app_context_key, &text_view_key, true)
+            }
+            gdk::Key::Return | gdk::Key::KP_Enter => {
+                insert_at_carets(&app_context_key, &text_view_key, &["\n".to_string()])
+            }
+            gdk::Key::Left
+            | gdk::Key::Right
+            | gdk::Key::Up
+            | gdk::Key::Down
+            | gdk::Key::Home
+            | gdk::Key::End => move_carets(
+                &app_context_key,
+                &text_view_key,
+                keyval,
+                state.contains(gdk::ModifierType::SHIFT_MASK),
+            ),
+            // Text goes through the text view's input method
+            _ => return glib::Propagation::Proceed,
+        }
+        glib::Propagation::Stop
+    });
+    text_view.add_controller(key_controller);
+
+    // Runs after the default handler, when the text is in the buffer
+    let multi_cursors_typed = multi_cursors(app_context);
+    text_view.buffer().connect_local("insert-text", true, move |values| {
+        if let (Ok(buffer), Ok(end), Ok(text)) =
+            (values[0].get::<TextBuffer>(), values[1].get::<TextIter>(), values[2].get::<String>())
+        {
+            repeat_at_secondary_carets(&multi_cursors_typed, &buffer, &end, &text);
+        }
+        None
+    });
+
+    // Edits elsewhere move the caret marks, so reposition the overlays once idle
+    let multi_cursors = multi_cursors(app_context);
+    let text_view_changed = text_view.clone();
+    text_view.buffer().connect_changed(move |buffer| {
+        if multi_cursors.borrow().get(buffer).is_none_or(|state| state.carets.is_empty()) {
+            return;
+        }
+        let multi_cursors = multi_cursors.clone();
+        let text_view = text_view_changed.clone();
+        glib::idle_add_local_once(move || {
+            refresh_carets(&multi_cursors, &text_view);
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_pasted_lines;
+
+    #[test]
+    fn pasted_lines_keep_the_final_terminator() {
+        assert_eq!(split_pasted_lines("a\nb\n"), vec!["a\n", "b\n"]);
+        assert_eq!(split_pasted_lines("a\r\nb\r\n"), vec!["a\r\n", "b\r\n"]);
+    }
+
+    #[test]
+    fn pasted_lines_without_final_terminator() {
+        assert_eq!(split_pasted_lines("a\nb"), vec!["a", "b"]);
+        assert_eq!(split_pasted_lines("a\r\nb"), vec!["a", "b"]);
+        assert_eq!(split_pasted_lines("é日本🦀"), vec!["é日本🦀"]);
+    }
+
+    #[test]
+    fn only_one_terminator_is_stripped() {
+        assert_eq!(split_pasted_lines("a\n\n"), vec!["a\n", "\n"]);
+    }
+}
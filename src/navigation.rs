@@ -0,0 +1,144 @@
+//! Module for the navigation history
+//!
+//! Jumps to another place, such as going to a definition or to a reference,
+//! remember where the cursor was: Go back returns there and Go forward repeats
+//! the jump. Locations are kept as a file and a position, so they outlive the
+//! tabs that showed them.
+
+use gtk4::prelude::*;
+use gtk4::TextView;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::AppContext;
+
+/// Number of locations remembered in each direction
+const MAX_LOCATIONS: usize = 100;
+
+/// A position in a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    /// The file
+    pub path: PathBuf,
+    /// Line of the position, counted from 0
+    pub line: i32,
+    /// Column of the position in characters, counted from 0
+    pub column: i32,
+}
+
+/// Locations to go back and forward to
+#[derive(Default)]
+pub struct NavigationHistory {
+    /// Locations left by jumps, the most recent last
+    back: Vec<Location>,
+    /// Locations left by going back, the most recent last
+    forward: Vec<Location>,
+}
+
+/// Shared navigation history
+pub type NavigationState = Rc<RefCell<NavigationHistory>>;
+
+/// Returns the location of the cursor of a text view
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view
+///
+/// # Returns
+///
+/// The location, or `None` if the text view shows an untitled buffer
+pub fn location_of(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) -> Option<Location> {
+    let buffer = text_view.buffer();
+    let path = app_context.borrow().buffer_paths.borrow().get(&buffer).cloned()?;
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    Some(Location {
+        path,
+        line: cursor.line(),
+        column: cursor.line_offset(),
+    })
+}
+
+/// Returns the location of the cursor in the current tab
+fn current_location(app_context: &Rc<RefCell<AppContext>>) -> Option<Location> {
+    let text_view = crate::ui::helpers::get_current_text_view(&app_context.borrow().notebook)?;
+    location_of(app_context, &text_view)
+}
+
+/// Remembers a location left by a jump
+///
+/// The locations that could be gone forward to are forgotten.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `location` - The location the cursor leaves
+pub fn push_location(app_context: &Rc<RefCell<AppContext>>, location: Location) {
+    let navigation = app_context.borrow().navigation.clone();
+    let mut history = navigation.borrow_mut();
+    history.forward.clear();
+    if history.back.last() != Some(&location) {
+        history.back.push(location);
+    }
+    if history.back.len() > MAX_LOCATIONS {
+        history.back.remove(0);
+    }
+}
+
+/// Remembers the location of the cursor in the current tab before a jump
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn push_current_location(app_context: &Rc<RefCell<AppContext>>) {
+    if let Some(location) = current_location(app_context) {
+        push_location(app_context, location);
+    }
+}
+
+/// Returns to the location left by the last jump
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn go_back(app_context: &Rc<RefCell<AppContext>>) {
+    step(app_context, true);
+}
+
+/// Repeats the jump undone by the last Go back
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn go_forward(app_context: &Rc<RefCell<AppContext>>) {
+    step(app_context, false);
+}
+
+/// Goes to the next location in one direction, remembering the current
+/// location in the other
+fn step(app_context: &Rc<RefCell<AppContext>>, backward: bool) {
+    let current = current_location(app_context);
+    let target = {
+        let navigation = app_context.borrow().navigation.clone();
+        let mut history = navigation.borrow_mut();
+        let history = &mut *history;
+        let (from, to) = if backward {
+            (&mut history.back, &mut history.forward)
+        } else {
+            (&mut history.forward, &mut history.back)
+        };
+        // A location the cursor is already at isn't worth a step
+        while from.last().is_some() && from.last() == current.as_ref() {
+            from.pop();
+        }
+        let Some(target) = from.pop() else {
+            return;
+        };
+        if let Some(current) = current {
+            to.push(current);
+        }
+        target
+    };
+    crate::tab_manager::open_file_at_position(&target.path, target.line, target.column, app_context);
+}
@@ -0,0 +1,788 @@
+//! Module for the document outline
+//!
+//! The outline lists the symbols of the current buffer, such as functions,
+//! structs, impls and classes, nested as in the code. They come from the
+//! `documentSymbol` answer of the buffer's language server or, without one,
+//! from the names its syntax scopes as `entity.name.*`, nested by indentation.
+//! The symbols are listed in a side panel, which follows the cursor, and in a
+//! popup (Ctrl+Shift+O). Both filter them with a fuzzy match of the typed text
+//! and move the cursor to the activated symbol.
+
+use gtk4::gdk;
+use gtk4::prelude::*;
+use gtk4::{
+    Align, Box, EventControllerKey, Label, ListBox, Orientation, Paned, Popover, ScrolledWindow, SearchEntry,
+    TextBuffer, TextView,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+use syntect::parsing::{Scope, ScopeStack};
+
+use crate::AppContext;
+use crate::syntax_highlighting::{LineScan, ParsedLine};
+
+/// Delay after an edit before the side panel is refreshed
+const REFRESH_DELAY: Duration = Duration::from_millis(500);
+
+/// Maximum height of the popup's list, in pixels
+const POPUP_MAX_HEIGHT: i32 = 400;
+
+/// Indentation of a nesting level in the lists, in pixels
+const DEPTH_INDENT: i32 = 12;
+
+/// A symbol of a buffer
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    /// Name of the symbol
+    pub name: String,
+    /// Kind of the symbol, e.g. "function" or "struct"
+    pub kind: String,
+    /// Number of symbols the symbol is nested in
+    pub depth: usize,
+    /// Line of the symbol's name, counted from 0
+    pub line: i32,
+    /// Column of the symbol's name in characters, counted from 0
+    pub column: i32,
+    /// First line of the symbol's definition
+    pub first_line: i32,
+    /// Last line of the symbol's definition
+    pub last_line: i32,
+}
+
+/// Symbols of the buffers and the side panel listing them
+pub struct Outline {
+    /// Symbols of each buffer, as last loaded
+    symbols: HashMap<TextBuffer, Vec<Symbol>>,
+    /// Symbols of each buffer named by its syntax, with the indentation of
+    /// their line, found by a scan resumed after edits
+    syntax_scans: HashMap<TextBuffer, LineScan<(), (Symbol, usize)>>,
+    /// Scheduled refresh of the side panel
+    refresh_timer: Option<glib::SourceId>,
+    /// The side panel
+    panel: Box,
+    /// Entry filtering the side panel
+    filter: SearchEntry,
+    /// List of the side panel
+    list: ListBox,
+    /// Scrolled window holding the list of the side panel
+    scrolled_window: ScrolledWindow,
+    /// Buffer listed in the side panel
+    buffer: Option<TextBuffer>,
+    /// Symbols listed in the side panel, in row order
+    shown: Vec<Symbol>,
+}
+
+/// Shared outline state
+pub type OutlineState = Rc<RefCell<Outline>>;
+
+/// Creates the side panel, hidden, as the end child of a paned
+///
+/// # Arguments
+///
+/// * `paned` - Paned holding the editor as its start child
+///
+/// # Returns
+///
+/// The outline state
+pub fn create_outline_panel(paned: &Paned) -> OutlineState {
+    let filter = SearchEntry::builder().placeholder_text("Filter symbols").build();
+    let list = ListBox::new();
+    list.set_selection_mode(gtk4::SelectionMode::Single);
+    list.set_activate_on_single_click(true);
+    let scrolled_window = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Automatic)
+        .vscrollbar_policy(gtk4::PolicyType::Automatic)
+        .vexpand(true)
+        .child(&list)
+        .build();
+    let panel = Box::new(Orientation::Vertical, 4);
+    panel.set_width_request(220);
+    panel.append(&filter);
+    panel.append(&scrolled_window);
+    panel.set_visible(false);
+
+    paned.set_end_child(Some(&panel));
+    paned.set_resize_end_child(false);
+    paned.set_shrink_end_child(false);
+    Rc::new(RefCell::new(Outline {
+        symbols: HashMap::new(),
+        syntax_scans: HashMap::new(),
+        refresh_timer: None,
+        panel,
+        filter,
+        list,
+        scrolled_window,
+        buffer: None,
+        shown: Vec::new(),
+    }))
+}
+
+/// Scores how well a pattern matches a name, ignoring case
+///
+/// The characters of the pattern must appear in the name in order. Matches at
+/// the start of words and runs of consecutive matches score higher, so that
+/// "ofint" ranks `open_file_in_new_tab` well.
+///
+/// # Arguments
+///
+/// * `pattern` - The typed text
+/// * `name` - The name to match
+///
+/// # Returns
+///
+/// The score, higher for better matches, or `None` if the name doesn't match
+pub fn fuzzy_score(pattern: &str, name: &str) -> Option<i32> {
+    let pattern: Vec<char> = pattern.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase).collect();
+    let chars: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut matched = 0;
+    let mut last_match: Option<usize> = None;
+    for (index, &c) in chars.iter().enumerate() {
+        if matched == pattern.len() {
+            break;
+        }
+        if c.to_lowercase().next() != Some(pattern[matched]) {
+            continue;
+        }
+        score += 1;
+        if index > 0 && last_match == Some(index - 1) {
+            score += 5;
+        }
+        let word_start = index == 0
+            || !chars[index - 1].is_alphanumeric()
+            || (c.is_uppercase() && chars[index - 1].is_lowercase());
+        if word_start {
+            score += 8;
+        }
+        last_match = Some(index);
+        matched += 1;
+    }
+    // Among equal matches, shorter names are closer to what was typed
+    (matched == pattern.len()).then(|| score * 100 - chars.len() as i32)
+}
+
+/// Returns the symbols matching a filter, best first, or all symbols in
+/// document order if the filter is empty
+fn filter_symbols(symbols: &[Symbol], pattern: &str) -> Vec<Symbol> {
+    if pattern.trim().is_empty() {
+        return symbols.to_vec();
+    }
+    let mut scored: Vec<(i32, &Symbol)> = symbols
+        .iter()
+        .filter_map(|symbol| fuzzy_score(pattern, &symbol.name).map(|score| (score, symbol)))
+        .collect();
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().map(|(_, symbol)| symbol.clone()).collect()
+}
+
+/// Sets the nesting depth of symbols from their ranges, sorting them in
+/// document order
+fn nest(symbols: &mut [Symbol]) {
+    symbols.sort_by_key(|symbol| (symbol.first_line, symbol.line, symbol.column));
+    let mut open_last_lines: Vec<i32> = Vec::new();
+    for symbol in symbols.iter_mut() {
+        while open_last_lines.last().is_some_and(|&last_line| last_line < symbol.first_line) {
+            open_last_lines.pop();
+        }
+        symbol.depth = open_last_lines.len();
+        open_last_lines.push(symbol.last_line);
+    }
+}
+
+/// Returns the kind of the innermost `entity.name` scope of a stack
+fn entity_kind(stack: &ScopeStack, entity: Scope) -> Option<String> {
+    let scope = stack.as_slice().iter().rev().find(|scope| entity.is_prefix_of(**scope))?;
+    let name = scope.build_string();
+    let kind = name.split('.').nth(2).unwrap_or("symbol");
+    // Tags of markup languages aren't symbols
+    (kind != "tag").then(|| kind.to_string())
+}
+
+/// Finds the symbols named on a line by the scopes of its syntax
+///
+/// # Arguments
+///
+/// * `symbols` - Symbols found so far, with the indentation of their line
+/// * `parsed` - The line and its scopes
+///
+/// # Returns
+///
+/// Whether the scopes of the line could be followed
+fn scan_symbols(_: &mut (), symbols: &mut Vec<(Symbol, usize)>, parsed: ParsedLine<'_>) -> bool {
+    let entity = Scope::new("entity.name").expect("valid scope");
+    let line = parsed.text;
+    let line_number = parsed.line;
+    let mut stack = parsed.scopes.clone();
+    // Byte ranges of the names on the line, with their kind
+    let mut names: Vec<(usize, usize, String)> = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (index, op) in parsed.ops {
+        let before = entity_kind(&stack, entity);
+        if stack.apply(op).is_err() {
+            return false;
+        }
+        let after = entity_kind(&stack, entity);
+        if before != after {
+            if let Some((start, kind)) = current.take() {
+                names.push((start, *index, kind));
+            }
+            current = after.map(|kind| (*index, kind));
+        }
+    }
+    if let Some((start, kind)) = current {
+        names.push((start, line.trim_end().len(), kind));
+    }
+
+    for (start, end, kind) in names {
+        let column = line[..start].chars().count() as i32;
+        match symbols.last_mut() {
+            // Names of one kind on a line, as in `impl Display for Point`, make one symbol
+            Some((last, _)) if last.line == line_number && last.kind == kind => {
+                let last_start = line
+                    .char_indices()
+                    .nth(last.column as usize)
+                    .map_or(start, |(byte, _)| byte);
+                last.name = line[last_start..end.max(last_start)].trim().to_string();
+            }
+            Some((last, _)) if last.line == line_number => {}
+            _ if line[start..end].trim().is_empty() => {}
+            _ => {
+                let symbol = Symbol {
+                    name: line[start..end].trim().to_string(),
+                    kind,
+                    depth: 0,
+                    line: line_number,
+                    column,
+                    first_line: line_number,
+                    last_line: line_number,
+                };
+                let indent = line.chars().take_while(|c| c.is_whitespace() && *c != '\n').count();
+                symbols.push((symbol, indent));
+            }
+        }
+    }
+    true
+}
+
+/// Returns the symbols named by a syntax with the lines they span
+///
+/// A symbol spans the lines up to the next symbol indented as much or less.
+///
+/// # Arguments
+///
+/// * `scanned` - The symbols, with the indentation of their line
+/// * `last_line` - Last line of the text
+fn span_symbols(scanned: &[(Symbol, usize)], last_line: i32) -> Vec<Symbol> {
+    scanned
+        .iter()
+        .enumerate()
+        .map(|(index, (symbol, indent))| {
+            let end = scanned[index + 1..]
+                .iter()
+                .find(|(_, next_indent)| next_indent <= indent)
+                .map_or(last_line, |(next, _)| next.first_line - 1);
+            Symbol {
+                last_line: end.max(symbol.first_line),
+                ..symbol.clone()
+            }
+        })
+        .collect()
+}
+
+/// Finds the symbols of a buffer from the scopes of its syntax
+///
+/// The scan resumes from the last line before the edits since the previous one.
+fn syntax_symbols(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> Vec<Symbol> {
+    let outline_state = app_context.borrow().outline.clone();
+    let mut scan = outline_state.borrow_mut().syntax_scans.remove(buffer).unwrap_or_default();
+    crate::syntax_highlighting::scan_lines(app_context, buffer, &mut scan, scan_symbols);
+    let symbols = span_symbols(&scan.items, buffer.end_iter().line());
+    outline_state.borrow_mut().syntax_scans.insert(buffer.clone(), scan);
+    symbols
+}
+
+/// Loads the symbols of a buffer and remembers them
+///
+/// The language server is asked if there is one, else the syntax is used.
+fn load_symbols(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    callback: impl FnOnce(Vec<Symbol>) + 'static,
+) {
+    let store = |app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer, mut symbols: Vec<Symbol>| {
+        nest(&mut symbols);
+        let outline = app_context.borrow().outline.clone();
+        outline.borrow_mut().symbols.insert(buffer.clone(), symbols.clone());
+        symbols
+    };
+
+    // The callback runs when the server answers, or right away without a server
+    let callback = Rc::new(RefCell::new(Some(callback)));
+    let app_context_clone = app_context.clone();
+    let buffer_clone = buffer.clone();
+    let callback_clone = callback.clone();
+    let sent = crate::lsp::document_symbols(app_context, buffer, move |symbols| {
+        let symbols = symbols.unwrap_or_else(|| syntax_symbols(&app_context_clone, &buffer_clone));
+        let symbols = store(&app_context_clone, &buffer_clone, symbols);
+        if let Some(callback) = callback_clone.take() {
+            callback(symbols);
+        }
+    });
+    if !sent {
+        let symbols = store(app_context, buffer, syntax_symbols(app_context, buffer));
+        if let Some(callback) = callback.take() {
+            callback(symbols);
+        }
+    }
+}
+
+/// Fills a list with symbols
+///
+/// # Arguments
+///
+/// * `list` - The list
+/// * `symbols` - The symbols, one row each
+/// * `nested` - Whether nested symbols are indented
+fn fill_list(list: &ListBox, symbols: &[Symbol], nested: bool) {
+    while let Some(row) = list.row_at_index(0) {
+        list.remove(&row);
+    }
+    for symbol in symbols {
+        let row = Box::new(Orientation::Horizontal, 6);
+        row.set_margin_start(4 + if nested { symbol.depth as i32 * DEPTH_INDENT } else { 0 });
+        row.set_margin_end(4);
+        let name_label = Label::new(Some(&symbol.name));
+        name_label.set_halign(Align::Start);
+        row.append(&name_label);
+        let kind_label = Label::new(Some(&symbol.kind));
+        kind_label.add_css_class("dim-label");
+        kind_label.set_halign(Align::End);
+        kind_label.set_hexpand(true);
+        row.append(&kind_label);
+        list.append(&row);
+    }
+}
+
+/// Moves the cursor of a text view to a symbol, remembering the location left
+fn go_to_symbol(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, symbol: &Symbol) {
+    if let Some(location) = crate::navigation::location_of(app_context, text_view) {
+        crate::navigation::push_location(app_context, location);
+    }
+    crate::tab_manager::go_to_position(text_view, symbol.line, symbol.column);
+}
+
+/// Lists the symbols of the side panel's buffer matching its filter
+fn fill_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let outline_state = app_context.borrow().outline.clone();
+    let mut outline = outline_state.borrow_mut();
+    let outline = &mut *outline;
+    let symbols = outline
+        .buffer
+        .as_ref()
+        .and_then(|buffer| outline.symbols.get(buffer))
+        .cloned()
+        .unwrap_or_default();
+    let pattern = outline.filter.text();
+    outline.shown = filter_symbols(&symbols, &pattern);
+    fill_list(&outline.list, &outline.shown, pattern.trim().is_empty());
+}
+
+/// Selects the innermost symbol containing the cursor in the side panel
+fn follow_cursor(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let outline_state = app_context.borrow().outline.clone();
+    let outline = outline_state.borrow();
+    if outline.buffer.as_ref() != Some(buffer) || !outline.panel.is_visible() {
+        return;
+    }
+    let line = buffer.iter_at_mark(&buffer.get_insert()).line();
+    let index = outline
+        .shown
+        .iter()
+        .enumerate()
+        .filter(|(_, symbol)| symbol.first_line <= line && line <= symbol.last_line)
+        .max_by_key(|(index, symbol)| (symbol.depth, *index))
+        .map(|(index, _)| index as i32);
+    let Some(row) = index.and_then(|index| outline.list.row_at_index(index)) else {
+        outline.list.unselect_all();
+        return;
+    };
+    outline.list.select_row(Some(&row));
+    if let Some(bounds) = row.compute_bounds(&outline.list) {
+        let top = bounds.y() as f64;
+        outline
+            .scrolled_window
+            .vadjustment()
+            .clamp_page(top, top + bounds.height() as f64);
+    }
+}
+
+/// Reloads the side panel with the symbols of the current tab
+///
+/// Nothing happens while the panel is hidden.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn refresh_outline(app_context: &Rc<RefCell<AppContext>>) {
+    let outline_state = app_context.borrow().outline.clone();
+    if !outline_state.borrow().panel.is_visible() {
+        return;
+    }
+    let text_view = crate::ui::helpers::get_current_text_view(&app_context.borrow().notebook);
+    let buffer = text_view.map(|text_view| text_view.buffer());
+    outline_state.borrow_mut().buffer = buffer.clone();
+    let Some(buffer) = buffer else {
+        fill_panel(app_context);
+        return;
+    };
+    let app_context_clone = app_context.clone();
+    let buffer_clone = buffer.clone();
+    load_symbols(app_context, &buffer, move |_| {
+        fill_panel(&app_context_clone);
+        follow_cursor(&app_context_clone, &buffer_clone);
+    });
+}
+
+/// Refreshes the side panel shortly after an edit of its buffer
+fn schedule_refresh(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let outline_state = app_context.borrow().outline.clone();
+    let mut outline = outline_state.borrow_mut();
+    if outline.buffer.as_ref() != Some(buffer) || !outline.panel.is_visible() {
+        return;
+    }
+    if let Some(timer) = outline.refresh_timer.take() {
+        timer.remove();
+    }
+    let app_context = app_context.clone();
+    let outline_state_clone = outline_state.clone();
+    outline.refresh_timer = Some(glib::timeout_add_local_once(REFRESH_DELAY, move || {
+        // The source is gone once it ran, so it must not be removed
+        outline_state_clone.borrow_mut().refresh_timer = None;
+        refresh_outline(&app_context);
+    }));
+}
+
+/// Shows or hides the side panel
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn toggle_outline_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let panel = app_context.borrow().outline.borrow().panel.clone();
+    panel.set_visible(!panel.is_visible());
+    refresh_outline(app_context);
+}
+
+/// Connects the side panel's filter and list, and follows the current tab
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn connect_outline_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let (filter, list) = {
+        let context = app_context.borrow();
+        let outline = context.outline.borrow();
+        (outline.filter.clone(), outline.list.clone())
+    };
+
+    let app_context_clone = app_context.clone();
+    filter.connect_search_changed(move |_| {
+        fill_panel(&app_context_clone);
+        let buffer = app_context_clone.borrow().outline.borrow().buffer.clone();
+        if let Some(buffer) = buffer {
+            follow_cursor(&app_context_clone, &buffer);
+        }
+    });
+
+    let app_context_clone = app_context.clone();
+    list.connect_row_activated(move |_, row| {
+        let symbol = app_context_clone
+            .borrow()
+            .outline
+            .borrow()
+            .shown
+            .get(row.index() as usize)
+            .cloned();
+        let text_view = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook);
+        if let (Some(symbol), Some(text_view)) = (symbol, text_view) {
+            go_to_symbol(&app_context_clone, &text_view, &symbol);
+        }
+    });
+
+    let app_context_clone = app_context.clone();
+    app_context.borrow().notebook.connect_switch_page(move |_, _, _| {
+        let app_context_clone = app_context_clone.clone();
+        // The current page is only updated after the signal has been emitted
+        glib::idle_add_local_once(move || refresh_outline(&app_context_clone));
+    });
+}
+
+/// Keeps the side panel up to date with the edits and the cursor of a text view
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view of a new tab
+pub fn connect_outline(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let app_context_clone = app_context.clone();
+    buffer.connect_changed(move |buffer| schedule_refresh(&app_context_clone, buffer));
+    let outline_state = app_context.borrow().outline.clone();
+    buffer.connect_insert_text(move |buffer, pos, _| {
+        if let Some(scan) = outline_state.borrow_mut().syntax_scans.get_mut(buffer) {
+            scan.invalidate_from(pos.line());
+        }
+    });
+    let outline_state = app_context.borrow().outline.clone();
+    buffer.connect_delete_range(move |buffer, start, _| {
+        if let Some(scan) = outline_state.borrow_mut().syntax_scans.get_mut(buffer) {
+            scan.invalidate_from(start.line());
+        }
+    });
+    let app_context_clone = app_context.clone();
+    buffer.connect_mark_set(move |buffer, _, mark| {
+        if mark == &buffer.get_insert() {
+            follow_cursor(&app_context_clone, buffer);
+        }
+    });
+}
+
+/// Forgets the symbols of a closed buffer
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer whose tab was closed
+pub fn forget_buffer(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let outline_state = app_context.borrow().outline.clone();
+    let mut outline = outline_state.borrow_mut();
+    outline.symbols.remove(buffer);
+    outline.syntax_scans.remove(buffer);
+    if outline.buffer.as_ref() == Some(buffer) {
+        outline.buffer = None;
+    }
+}
+
+/// Moves the selection of a popup's list by a number of rows
+fn move_popup_selection(list: &ListBox, rows: i32) {
+    let count = {
+        let mut count = 0;
+        while list.row_at_index(count).is_some() {
+            count += 1;
+        }
+        count
+    };
+    if count == 0 {
+        return;
+    }
+    let index = list.selected_row().map_or(0, |row| (row.index() + rows).clamp(0, count - 1));
+    if let Some(row) = list.row_at_index(index) {
+        list.select_row(Some(&row));
+        if let Some(adjustment) = list.adjustment()
+            && let Some(bounds) = row.compute_bounds(list)
+        {
+            let top = bounds.y() as f64;
+            adjustment.clamp_page(top, top + bounds.height() as f64);
+        }
+    }
+}
+
+/// Opens the popup on a text view with symbols
+fn open_popup(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, symbols: Vec<Symbol>) {
+    let entry = SearchEntry::builder().placeholder_text("Go to symbol").build();
+    let list = ListBox::new();
+    list.set_selection_mode(gtk4::SelectionMode::Single);
+    list.set_activate_on_single_click(true);
+    let scrolled_window = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Never)
+        .max_content_height(POPUP_MAX_HEIGHT)
+        .min_content_width(300)
+        .propagate_natural_height(true)
+        .child(&list)
+        .build();
+    list.set_adjustment(Some(&scrolled_window.vadjustment()));
+    let vbox = Box::new(Orientation::Vertical, 6);
+    vbox.append(&entry);
+    vbox.append(&scrolled_window);
+    let popover = Popover::builder()
+        .child(&vbox)
+        .has_arrow(false)
+        .position(gtk4::PositionType::Bottom)
+        .build();
+    popover.set_parent(text_view);
+    popover.set_pointing_to(Some(&gdk::Rectangle::new(text_view.width() / 2, 0, 1, 1)));
+
+    let shown = Rc::new(RefCell::new(symbols.clone()));
+    fill_list(&list, &shown.borrow(), true);
+    list.select_row(list.row_at_index(0).as_ref());
+
+    let list_clone = list.clone();
+    let shown_clone = shown.clone();
+    entry.connect_search_changed(move |entry| {
+        let pattern = entry.text();
+        *shown_clone.borrow_mut() = filter_symbols(&symbols, &pattern);
+        fill_list(&list_clone, &shown_clone.borrow(), pattern.trim().is_empty());
+        list_clone.select_row(list_clone.row_at_index(0).as_ref());
+    });
+
+    // Up and Down move through the list while typing in the entry
+    let key_controller = EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let list_clone = list.clone();
+    key_controller.connect_key_pressed(move |_, key, _, _| match key {
+        gdk::Key::Up => {
+            move_popup_selection(&list_clone, -1);
+            glib::Propagation::Stop
+        }
+        gdk::Key::Down => {
+            move_popup_selection(&list_clone, 1);
+            glib::Propagation::Stop
+        }
+        _ => glib::Propagation::Proceed,
+    });
+    entry.add_controller(key_controller);
+
+    let activate = Rc::new({
+        let app_context = app_context.clone();
+        let text_view = text_view.clone();
+        let popover = popover.clone();
+        move |index: i32| {
+            let symbol = shown.borrow().get(index as usize).cloned();
+            popover.popdown();
+            if let Some(symbol) = symbol {
+                go_to_symbol(&app_context, &text_view, &symbol);
+            }
+        }
+    });
+    let activate_clone = activate.clone();
+    let list_clone = list.clone();
+    entry.connect_activate(move |_| {
+        if let Some(row) = list_clone.selected_row() {
+            activate_clone(row.index());
+        }
+    });
+    list.connect_row_activated(move |_, row| activate(row.index()));
+    let popover_clone = popover.clone();
+    entry.connect_stop_search(move |_| popover_clone.popdown());
+
+    let text_view_clone = text_view.clone();
+    popover.connect_closed(move |popover| {
+        let popover = popover.clone();
+        // The popover is still closing, so it is removed afterwards
+        glib::idle_add_local_once(move || popover.unparent());
+        text_view_clone.grab_focus();
+    });
+    popover.popup();
+    entry.grab_focus();
+}
+
+/// Shows the popup listing the symbols of a text view's buffer
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view
+pub fn show_outline_popup(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    load_symbols(app_context, &text_view.buffer(), move |symbols| {
+        open_popup(&app_context_clone, &text_view_clone, symbols);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::syntax_highlighting::ScopeCache;
+    use syntect::parsing::SyntaxSet;
+
+    fn symbol(name: &str, first_line: i32, last_line: i32) -> Symbol {
+        Symbol {
+            name: name.to_string(),
+            kind: "function".to_string(),
+            depth: 0,
+            line: first_line,
+            column: 0,
+            first_line,
+            last_line,
+        }
+    }
+
+    #[test]
+    fn fuzzy_score_matches_in_order_ignoring_case() {
+        assert!(fuzzy_score("ofint", "open_file_in_new_tab").is_some());
+        assert!(fuzzy_score("OFINT", "open_file_in_new_tab").is_some());
+        assert!(fuzzy_score("o f", "open_file").is_some());
+        assert!(fuzzy_score("", "anything").is_some());
+        assert_eq!(fuzzy_score("fo", "open_file"), None);
+        assert_eq!(fuzzy_score("files", "file"), None);
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_word_starts_runs_and_short_names() {
+        let score = |pattern, name| fuzzy_score(pattern, name).expect("a match");
+        // Start of words, also in camel case
+        assert!(score("ofn", "open_file_new") > score("ofn", "openfilenew"));
+        assert!(score("fp", "FilePath") > score("fp", "Filepath"));
+        // Runs of consecutive matches
+        assert!(score("ile", "mobile") > score("ile", "xixlxe"));
+        // Shorter names among equal matches
+        assert!(score("save", "save") > score("save", "save_all"));
+    }
+
+    #[test]
+    fn symbols_are_filtered_best_first() {
+        let symbols = vec![symbol("reload", 0, 0), symbol("load", 1, 1), symbol("save", 2, 2)];
+        let names = |pattern| -> Vec<String> {
+            filter_symbols(&symbols, pattern).into_iter().map(|symbol| symbol.name).collect()
+        };
+        assert_eq!(names("load"), vec!["load", "reload"]);
+        assert_eq!(names("  "), vec!["reload", "load", "save"]);
+    }
+
+    #[test]
+    fn nest_sets_depths_from_ranges() {
+        let mut symbols = vec![
+            symbol("method", 2, 4),
+            symbol("impl", 1, 8),
+            symbol("free", 10, 12),
+            symbol("inner", 3, 3),
+            symbol("other_method", 5, 7),
+        ];
+        nest(&mut symbols);
+        let depths: Vec<(&str, usize)> = symbols.iter().map(|symbol| (symbol.name.as_str(), symbol.depth)).collect();
+        assert_eq!(
+            depths,
+            vec![("impl", 0), ("method", 1), ("inner", 2), ("other_method", 1), ("free", 0)]
+        );
+    }
+
+    #[test]
+    fn syntax_names_make_symbols_spanning_their_block() {
+        let text = "struct Point {\n    x: i32,\n}\n\nimpl Point {\n    fn new() -> Self {\n        todo!()\n    }\n}\n";
+        let lines: Vec<String> = text.split_inclusive('\n').map(str::to_string).collect();
+        let ps = SyntaxSet::load_defaults_newlines();
+        let syntax = ps.find_syntax_by_name("Rust").expect("a default syntax");
+        let line_text = |line: i32| lines.get(line as usize).cloned().unwrap_or_default();
+        let mut scan = LineScan::default();
+        scan.resume(&mut ScopeCache::default(), syntax, &ps, line_text, lines.len() as i32, scan_symbols);
+
+        let symbols: Vec<(String, String, i32, i32, i32)> = span_symbols(&scan.items, lines.len() as i32)
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.kind, symbol.column, symbol.first_line, symbol.last_line))
+            .collect();
+        let expected = |name: &str, kind: &str, column, first_line, last_line| {
+            (name.to_string(), kind.to_string(), column, first_line, last_line)
+        };
+        assert_eq!(
+            symbols,
+            vec![
+                expected("Point", "struct", 7, 0, 3),
+                expected("Point", "impl", 5, 4, 9),
+                expected("new", "function", 7, 5, 9),
+            ]
+        );
+    }
+}
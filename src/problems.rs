@@ -0,0 +1,211 @@
+//! Module for the Problems panel
+//!
+//! Problems are messages about files reported by tools such as the document
+//! formatter or the language servers, each with a severity and, when known, a
+//! position in the file. They are grouped by source and file: reporting the
+//! problems of a source for a file replaces the previous ones. The panel lists
+//! them in a page of the bottom panel, and activating a row opens the file at
+//! the problem.
+
+use gtk4::prelude::*;
+use gtk4::{Align, Box, Image, Label, ListBox, Notebook, Orientation, ScrolledWindow};
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::AppContext;
+
+/// Severity of a problem
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    /// Returns the name of the icon shown for the severity
+    fn icon_name(self) -> &'static str {
+        match self {
+            Severity::Error => "dialog-error-symbolic",
+            Severity::Warning => "dialog-warning-symbolic",
+            Severity::Info => "dialog-information-symbolic",
+        }
+    }
+}
+
+/// A problem reported about a file
+#[derive(Clone, Debug)]
+pub struct Problem {
+    /// Tool that reported the problem, e.g. "formatter"
+    pub source: String,
+    /// File the problem is about, `None` for untitled buffers
+    pub path: Option<PathBuf>,
+    /// Line of the problem, counted from 0
+    pub line: Option<i32>,
+    /// Column of the problem in characters, counted from 0
+    pub column: Option<i32>,
+    /// Severity of the problem
+    pub severity: Severity,
+    /// Description of the problem
+    pub message: String,
+}
+
+/// Problems of all files and the widgets listing them
+pub struct Problems {
+    /// Problems in display order
+    entries: Vec<Problem>,
+    /// List showing one row per problem
+    list: ListBox,
+    /// Page of the bottom panel holding the list
+    page: ScrolledWindow,
+}
+
+/// Shared Problems panel state
+pub type ProblemsState = Rc<RefCell<Problems>>;
+
+/// Creates the Problems page and adds it to the bottom panel
+///
+/// # Arguments
+///
+/// * `bottom_panel` - Notebook holding the pages of the bottom panel
+///
+/// # Returns
+///
+/// The Problems panel state
+pub fn create_problems_panel(bottom_panel: &Notebook) -> ProblemsState {
+    let list = ListBox::new();
+    list.set_selection_mode(gtk4::SelectionMode::Single);
+    list.set_activate_on_single_click(true);
+    let page = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Automatic)
+        .vscrollbar_policy(gtk4::PolicyType::Automatic)
+        .child(&list)
+        .build();
+    bottom_panel.append_page(&page, Some(&Label::new(Some("Problems"))));
+    Rc::new(RefCell::new(Problems { entries: Vec::new(), list, page }))
+}
+
+/// Opens the file of a problem when its row is activated
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn connect_problems_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let list = app_context.borrow().problems.borrow().list.clone();
+    let app_context_clone = app_context.clone();
+    list.connect_row_activated(move |_, row| {
+        let problem = app_context_clone
+            .borrow()
+            .problems
+            .borrow()
+            .entries
+            .get(row.index() as usize)
+            .cloned();
+        if let Some(Problem { path: Some(path), line, column, .. }) = problem {
+            crate::tab_manager::open_file_at_position(
+                &path,
+                line.unwrap_or(0),
+                column.unwrap_or(0),
+                &app_context_clone,
+            );
+        }
+    });
+}
+
+/// Replaces the problems reported by a source for a file
+///
+/// The bottom panel is shown on the Problems page when problems are reported.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `source` - Tool reporting the problems
+/// * `path` - File the problems are about, `None` for untitled buffers
+/// * `problems` - The new problems, empty to clear them
+pub fn set_problems(app_context: &Rc<RefCell<AppContext>>, source: &str, path: Option<&Path>, problems: Vec<Problem>) {
+    let reported = !problems.is_empty();
+    update_problems(app_context, source, path, problems);
+    if reported {
+        let context = app_context.borrow();
+        let page_num = context.bottom_panel.page_num(&context.problems.borrow().page);
+        context.bottom_panel.set_current_page(page_num);
+        context.bottom_panel.set_visible(true);
+    }
+}
+
+/// Replaces the problems reported by a source for a file, without showing
+/// the panel
+///
+/// This suits sources that report often, such as language servers.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `source` - Tool reporting the problems
+/// * `path` - File the problems are about, `None` for untitled buffers
+/// * `problems` - The new problems, empty to clear them
+pub fn update_problems(app_context: &Rc<RefCell<AppContext>>, source: &str, path: Option<&Path>, problems: Vec<Problem>) {
+    let context = app_context.borrow();
+    let mut state = context.problems.borrow_mut();
+    state
+        .entries
+        .retain(|problem| problem.source != source || problem.path.as_deref() != path);
+    state.entries.extend(problems);
+    state
+        .entries
+        .sort_by(|a, b| (&a.path, a.line, a.column, a.severity).cmp(&(&b.path, b.line, b.column, b.severity)));
+    refresh_list(&state);
+}
+
+/// Rebuilds the rows of the problem list
+fn refresh_list(state: &Problems) {
+    while let Some(row) = state.list.row_at_index(0) {
+        state.list.remove(&row);
+    }
+    for problem in &state.entries {
+        let row = Box::new(Orientation::Horizontal, 6);
+        row.set_margin_start(4);
+        row.set_margin_end(4);
+        row.append(&Image::from_icon_name(problem.severity.icon_name()));
+
+        let location = match (&problem.path, problem.line) {
+            (Some(path), Some(line)) => format!(
+                "{}:{}:{}",
+                path.display(),
+                line + 1,
+                problem.column.unwrap_or(0) + 1
+            ),
+            (Some(path), None) => path.display().to_string(),
+            (None, Some(line)) => format!("Untitled:{}", line + 1),
+            (None, None) => "Untitled".to_string(),
+        };
+        let location_label = Label::new(Some(&location));
+        location_label.add_css_class("dim-label");
+        row.append(&location_label);
+
+        let message_label = Label::new(Some(&format!("{} [{}]", problem.message, problem.source)));
+        message_label.set_halign(Align::Start);
+        message_label.set_hexpand(true);
+        message_label.set_selectable(false);
+        row.append(&message_label);
+        state.list.append(&row);
+    }
+}
+
+/// Shows or hides the bottom panel on the Problems page
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn toggle_problems_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let context = app_context.borrow();
+    let page_num = context.bottom_panel.page_num(&context.problems.borrow().page);
+    let showing_problems = context.bottom_panel.is_visible() && context.bottom_panel.current_page() == page_num;
+    if showing_problems {
+        context.bottom_panel.set_visible(false);
+    } else {
+        context.bottom_panel.set_current_page(page_num);
+        context.bottom_panel.set_visible(true);
+    }
+}
@@ -0,0 +1,147 @@
+//! Module for the References panel
+//!
+//! The panel lists places found in the files, such as the references to a
+//! symbol or its definitions, in a page of the bottom panel. Each row shows
+//! the position and the text of its line, and activating a row opens the file
+//! there, remembering the location left in the navigation history.
+
+use gtk4::prelude::*;
+use gtk4::{Align, Box, Label, ListBox, Notebook, Orientation, ScrolledWindow};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::AppContext;
+
+/// A place found in a file
+#[derive(Clone, Debug)]
+pub struct Reference {
+    /// The file
+    pub path: PathBuf,
+    /// Line of the place, counted from 0
+    pub line: i32,
+    /// Column of the place in characters, counted from 0
+    pub column: i32,
+    /// Text of the line
+    pub preview: String,
+}
+
+/// The listed places and the widgets listing them
+pub struct References {
+    /// Places in display order
+    entries: Vec<Reference>,
+    /// List showing one row per place
+    list: ListBox,
+    /// Page of the bottom panel holding the list
+    page: ScrolledWindow,
+    /// Label of the page, naming what is listed
+    title: Label,
+}
+
+/// Shared References panel state
+pub type ReferencesState = Rc<RefCell<References>>;
+
+/// Creates the References page and adds it to the bottom panel
+///
+/// # Arguments
+///
+/// * `bottom_panel` - Notebook holding the pages of the bottom panel
+///
+/// # Returns
+///
+/// The References panel state
+pub fn create_references_panel(bottom_panel: &Notebook) -> ReferencesState {
+    let list = ListBox::new();
+    list.set_selection_mode(gtk4::SelectionMode::Single);
+    list.set_activate_on_single_click(true);
+    let page = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Automatic)
+        .vscrollbar_policy(gtk4::PolicyType::Automatic)
+        .child(&list)
+        .build();
+    let title = Label::new(Some("References"));
+    bottom_panel.append_page(&page, Some(&title));
+    Rc::new(RefCell::new(References {
+        entries: Vec::new(),
+        list,
+        page,
+        title,
+    }))
+}
+
+/// Opens the file of a place when its row is activated
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn connect_references_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let list = app_context.borrow().references.borrow().list.clone();
+    let app_context_clone = app_context.clone();
+    list.connect_row_activated(move |_, row| {
+        let reference = app_context_clone
+            .borrow()
+            .references
+            .borrow()
+            .entries
+            .get(row.index() as usize)
+            .cloned();
+        if let Some(reference) = reference {
+            crate::navigation::push_current_location(&app_context_clone);
+            crate::tab_manager::open_file_at_position(
+                &reference.path,
+                reference.line,
+                reference.column,
+                &app_context_clone,
+            );
+        }
+    });
+}
+
+/// Lists places in the References panel and shows it
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `title` - What the places are, e.g. "References"
+/// * `references` - The places, replacing those listed before
+pub fn show_references(app_context: &Rc<RefCell<AppContext>>, title: &str, mut references: Vec<Reference>) {
+    references.sort_by(|a, b| (&a.path, a.line, a.column).cmp(&(&b.path, b.line, b.column)));
+    let context = app_context.borrow();
+    let mut state = context.references.borrow_mut();
+    state.title.set_text(&format!("{} ({})", title, references.len()));
+    state.entries = references;
+    refresh_list(&state);
+
+    let page_num = context.bottom_panel.page_num(&state.page);
+    context.bottom_panel.set_current_page(page_num);
+    context.bottom_panel.set_visible(true);
+}
+
+/// Rebuilds the rows of the list
+fn refresh_list(state: &References) {
+    while let Some(row) = state.list.row_at_index(0) {
+        state.list.remove(&row);
+    }
+    for reference in &state.entries {
+        let row = Box::new(Orientation::Horizontal, 6);
+        row.set_margin_start(4);
+        row.set_margin_end(4);
+
+        let location = format!(
+            "{}:{}:{}",
+            reference.path.display(),
+            reference.line + 1,
+            reference.column + 1
+        );
+        let location_label = Label::new(Some(&location));
+        location_label.add_css_class("dim-label");
+        row.append(&location_label);
+
+        let preview_label = Label::new(Some(reference.preview.trim()));
+        preview_label.set_halign(Align::Start);
+        preview_label.set_hexpand(true);
+        preview_label.add_css_class("monospace");
+        row.append(&preview_label);
+        state.list.append(&row);
+    }
+}
@@ -0,0 +1,241 @@
+//! Module for renaming symbols
+//!
+//! Rename symbol asks for a new name for the word at the cursor. The language
+//! server of the buffer computes the edits, which are previewed by file before
+//! being applied: open files are edited in their buffers and the others on
+//! disk, as one step that a single undo reverts everywhere. If none of the
+//! files is open, the first one is opened to hold that step. Without a
+//! language server, the whole-word occurrences in the buffer are replaced.
+
+use gtk4::prelude::*;
+use gtk4::{Align, Box, Dialog, Entry, Label, Orientation, ResponseType, ScrolledWindow, TextBuffer, TextView};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::editorconfig;
+use crate::lsp::{self, FileChanges};
+use crate::undo_history::{self, FileEdit};
+use crate::AppContext;
+
+/// Asks for a new name
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `old_name` - The current name, shown selected
+/// * `callback` - Called with the new name, or with `None` if the rename is cancelled
+fn prompt_new_name(
+    app_context: &Rc<RefCell<AppContext>>,
+    old_name: &str,
+    callback: impl FnOnce(Option<String>) + 'static,
+) {
+    let dialog = Dialog::builder()
+        .title("Rename Symbol")
+        .transient_for(&app_context.borrow().window)
+        .modal(true)
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Rename", ResponseType::Ok);
+    dialog.set_default_response(ResponseType::Ok);
+
+    let hbox = Box::new(Orientation::Horizontal, 10);
+    hbox.set_margin_top(10);
+    hbox.set_margin_bottom(10);
+    hbox.set_margin_start(10);
+    hbox.set_margin_end(10);
+    let entry = Entry::builder().text(old_name).hexpand(true).activates_default(true).build();
+    hbox.append(&Label::new(Some("New name:")));
+    hbox.append(&entry);
+    dialog.content_area().append(&hbox);
+
+    let callback = RefCell::new(Some(callback));
+    let entry_clone = entry.clone();
+    dialog.connect_response(move |dialog, response| {
+        let new_name = entry_clone.text().trim().to_string();
+        dialog.close();
+        if let Some(callback) = callback.take() {
+            callback((response == ResponseType::Ok).then_some(new_name));
+        }
+    });
+    dialog.present();
+    entry.grab_focus();
+    entry.select_region(0, -1);
+}
+
+/// Lists the files a rename edits and applies the edits once confirmed
+fn preview_changes(app_context: &Rc<RefCell<AppContext>>, old_name: &str, new_name: &str, files: Vec<FileChanges>) {
+    let dialog = Dialog::builder()
+        .title(format!("Rename {} to {}", old_name, new_name))
+        .transient_for(&app_context.borrow().window)
+        .modal(true)
+        .default_width(500)
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Apply", ResponseType::Ok);
+    dialog.set_default_response(ResponseType::Ok);
+
+    let vbox = Box::new(Orientation::Vertical, 10);
+    vbox.set_margin_top(10);
+    vbox.set_margin_bottom(10);
+    vbox.set_margin_start(10);
+    vbox.set_margin_end(10);
+    let change_count: usize = files.iter().map(|file| file.edits.len()).sum();
+    let summary = Label::new(Some(&format!("{} changes in {} files:", change_count, files.len())));
+    summary.set_halign(Align::Start);
+    vbox.append(&summary);
+
+    let list = Box::new(Orientation::Vertical, 4);
+    let none_open = files.iter().all(|file| lsp::buffer_for_path(app_context, &file.path).is_none());
+    for (index, file) in files.iter().enumerate() {
+        // Files that aren't open are written to disk directly
+        let note = if index == 0 && none_open {
+            " (opened, so that the rename can be undone)"
+        } else if lsp::buffer_for_path(app_context, &file.path).is_none() {
+            " (not open, saved to disk)"
+        } else {
+            ""
+        };
+        let label = Label::new(Some(&format!(
+            "{}: {} {}{}",
+            file.path.display(),
+            file.edits.len(),
+            if file.edits.len() == 1 { "change" } else { "changes" },
+            note
+        )));
+        label.set_halign(Align::Start);
+        list.append(&label);
+    }
+    let scrolled_window = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Automatic)
+        .vscrollbar_policy(gtk4::PolicyType::Automatic)
+        .min_content_height(150)
+        .vexpand(true)
+        .child(&list)
+        .build();
+    vbox.append(&scrolled_window);
+    dialog.content_area().append(&vbox);
+
+    let app_context_clone = app_context.clone();
+    let files = RefCell::new(Some(files));
+    dialog.connect_response(move |dialog, response| {
+        dialog.close();
+        if response == ResponseType::Ok
+            && let Some(files) = files.take()
+        {
+            apply_changes(&app_context_clone, files);
+        }
+    });
+    dialog.present();
+}
+
+/// Applies the edits of a rename as one undo step
+///
+/// The files that aren't open are edited on disk first, with their EditorConfig
+/// charset and line endings; their previous content is kept with the undo step
+/// of the first edited buffer. If no file is open, the first one is opened, so
+/// that there is a buffer to keep the step; nothing is written if it can't be.
+fn apply_changes(app_context: &Rc<RefCell<AppContext>>, files: Vec<FileChanges>) {
+    let Some(first) = files.first() else {
+        return;
+    };
+    let is_open = |files: &[FileChanges]| files.iter().any(|file| lsp::buffer_for_path(app_context, &file.path).is_some());
+    if !is_open(&files) {
+        crate::tab_manager::open_file_in_new_tab(&first.path, app_context);
+        if !is_open(&files) {
+            crate::dialogs::show_error_dialog(
+                &app_context.borrow().window,
+                "Rename failed",
+                &format!("{} could not be opened to hold the rename.", first.path.display()),
+            );
+            return;
+        }
+    }
+
+    let mut buffers: Vec<(TextBuffer, FileChanges)> = Vec::new();
+    let mut file_edits = Vec::new();
+    let mut failures = Vec::new();
+    for file in files {
+        if let Some(buffer) = lsp::buffer_for_path(app_context, &file.path) {
+            buffers.push((buffer, file));
+            continue;
+        }
+        let config = editorconfig::config_for_path(&file.path);
+        let written = editorconfig::read_file(&file.path, &config).and_then(|original| {
+            let edited = editorconfig::normalize_line_endings(&lsp::edit_text(&original, &file.edits), &config);
+            editorconfig::write_file(&file.path, &edited, &config)?;
+            Ok(FileEdit {
+                path: file.path.clone(),
+                original,
+                edited,
+            })
+        });
+        match written {
+            Ok(file_edit) => file_edits.push(file_edit),
+            Err(e) => failures.push(format!("{}: {}", file.path.display(), e)),
+        }
+    }
+
+    let link = undo_history::new_link_id();
+    let mut file_edits = Some(file_edits);
+    for (buffer, file) in &buffers {
+        buffer.begin_user_action();
+        lsp::edit_buffer(buffer, &file.edits);
+        undo_history::link_current_step(app_context, buffer, link, file_edits.take().unwrap_or_default());
+        buffer.end_user_action();
+    }
+
+    if !failures.is_empty() {
+        crate::dialogs::show_error_dialog(
+            &app_context.borrow().window,
+            "Some files could not be renamed",
+            &failures.join("\n"),
+        );
+    }
+}
+
+/// Renames the symbol at the cursor
+///
+/// The language server of the buffer renames it in every file, after a
+/// preview of the files to edit. Without a server, the whole-word occurrences
+/// of the symbol in the buffer are replaced.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view whose cursor is on the symbol
+pub fn rename_symbol(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let buffer = text_view.buffer();
+    let Some((start, end)) = crate::search::get_word_bounds_at_cursor(&buffer) else {
+        text_view.error_bell();
+        return;
+    };
+    let old_name = buffer.text(&start, &end, true).to_string();
+    let mark = buffer.create_mark(None, &start, true);
+
+    let app_context_clone = app_context.clone();
+    prompt_new_name(app_context, &old_name.clone(), move |new_name| {
+        let iter = buffer.iter_at_mark(&mark);
+        buffer.delete_mark(&mark);
+        let Some(new_name) = new_name.filter(|new_name| !new_name.is_empty() && *new_name != old_name) else {
+            return;
+        };
+
+        let app_context = app_context_clone.clone();
+        let (old, new) = (old_name.clone(), new_name.clone());
+        let sent = lsp::rename(&app_context_clone, &buffer, &iter, &new_name, move |result| {
+            let window = app_context.borrow().window.clone();
+            match result {
+                Ok(files) if files.is_empty() => {
+                    crate::dialogs::show_error_dialog(&window, "Rename failed", "There is nothing to rename here.");
+                }
+                Ok(files) => preview_changes(&app_context, &old, &new, files),
+                Err(message) => {
+                    crate::dialogs::show_error_dialog(&window, "Rename failed", &message);
+                }
+            }
+        });
+        if !sent {
+            crate::search::replace_all_advanced(&buffer, &old_name, &new_name, true, true, false);
+        }
+    });
+}
@@ -4,6 +4,7 @@
 //! application settings such as theme, font, and last opened files.
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 
@@ -24,6 +25,185 @@ pub struct AppSettings {
     pub last_opened_directory: Option<PathBuf>,
     /// List of last opened files
     pub last_opened_files: Option<Vec<PathBuf>>,
+    /// Editing rules for each language, keyed by syntax name (e.g. "Python")
+    #[serde(default = "default_language_settings")]
+    pub languages: HashMap<String, LanguageSettings>,
+    /// First lines of the folded regions of each file
+    #[serde(default)]
+    pub folded_lines: HashMap<PathBuf, Vec<i32>>,
+    /// Word length at which the completion popup opens automatically (0 disables it)
+    #[serde(default = "default_completion_min_chars")]
+    pub completion_min_chars: usize,
+    /// Number of columns a tab character is displayed as
+    #[serde(default = "default_tab_width")]
+    pub tab_width: usize,
+    /// Whether new indentation uses spaces rather than tabs, for files whose
+    /// indentation can't be detected
+    #[serde(default = "default_insert_spaces")]
+    pub insert_spaces: bool,
+    /// Whitespace clean-up applied when saving, unless a language overrides it
+    #[serde(default)]
+    pub whitespace: WhitespaceSettings,
+}
+
+/// Default word length at which the completion popup opens automatically
+fn default_completion_min_chars() -> usize {
+    3
+}
+
+/// Default number of columns a tab character is displayed as
+fn default_tab_width() -> usize {
+    4
+}
+
+/// Default choice of spaces for new indentation
+fn default_insert_spaces() -> bool {
+    true
+}
+
+/// Whitespace clean-up applied when saving
+///
+/// Unset fields fall back to the global settings, and then to off.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct WhitespaceSettings {
+    /// Whether to remove whitespace at the end of lines
+    pub trim_trailing_whitespace: Option<bool>,
+    /// Whether to trim only the lines that differ from the file on disk
+    pub trim_modified_lines_only: Option<bool>,
+    /// Whether to make sure the file ends with a newline
+    pub ensure_final_newline: Option<bool>,
+    /// Whether to remove the blank lines at the end of the file
+    pub collapse_trailing_blank_lines: Option<bool>,
+}
+
+impl WhitespaceSettings {
+    /// Fills the unset fields from other settings
+    ///
+    /// # Arguments
+    ///
+    /// * `fallback` - Settings used for the fields that are not set
+    pub fn or(&self, fallback: &WhitespaceSettings) -> WhitespaceSettings {
+        WhitespaceSettings {
+            trim_trailing_whitespace: self.trim_trailing_whitespace.or(fallback.trim_trailing_whitespace),
+            trim_modified_lines_only: self.trim_modified_lines_only.or(fallback.trim_modified_lines_only),
+            ensure_final_newline: self.ensure_final_newline.or(fallback.ensure_final_newline),
+            collapse_trailing_blank_lines: self
+                .collapse_trailing_blank_lines
+                .or(fallback.collapse_trailing_blank_lines),
+        }
+    }
+}
+
+/// Editing rules for a language
+///
+/// Languages without an entry in `AppSettings::languages` use the default
+/// rules, which suit C-like languages.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct LanguageSettings {
+    /// Line endings after which the next line is indented one more level
+    pub indent_after: Vec<String>,
+    /// Characters that dedent the line when typed as its first character
+    pub dedent_on: Vec<String>,
+    /// Bracket and quote pairs, each written as the opener followed by the closer
+    pub pairs: Vec<String>,
+    /// Whether typing an opener also inserts its closer
+    pub auto_close: bool,
+    /// Whether typing a closer right before the same closer moves over it
+    pub overtype: bool,
+    /// Whether Backspace between an empty pair deletes both characters
+    pub delete_pairs: bool,
+    /// Whether typing an opener with a selection wraps the selection in the pair
+    pub wrap_selection: bool,
+    /// Line comment token, overriding the one from the syntax definition
+    pub line_comment: Option<String>,
+    /// Block comment start and end tokens, overriding those from the syntax definition
+    pub block_comment: Option<(String, String)>,
+    /// Whitespace clean-up applied when saving, overriding the global settings
+    pub whitespace: WhitespaceSettings,
+    /// Command line of the formatter, which reads the document on its standard
+    /// input and writes the formatted document on its standard output; `{file}`
+    /// is replaced by the path of the file
+    pub formatter: Option<String>,
+    /// Whether the document is formatted before it is saved
+    pub format_on_save: bool,
+    /// Language server providing IDE features for the language
+    pub language_server: Option<LanguageServerSettings>,
+}
+
+/// How to start the language server of a language
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct LanguageServerSettings {
+    /// Command line starting the server, which speaks the protocol on its
+    /// standard input and output
+    pub command: String,
+    /// Language identifier sent to the server, the lowercase syntax name by default
+    pub language_id: Option<String>,
+    /// Files or directories marking the root of a project, searched from the
+    /// file's directory upwards
+    pub root_markers: Vec<String>,
+    /// Options passed to the server when it is initialized
+    pub initialization_options: Option<serde_json::Value>,
+}
+
+impl Default for LanguageSettings {
+    /// Creates the default rules, used for languages without an entry
+    fn default() -> Self {
+        Self {
+            indent_after: vec!["{".to_string(), "(".to_string(), "[".to_string()],
+            dedent_on: vec!["}".to_string(), ")".to_string(), "]".to_string()],
+            pairs: vec!["()".to_string(), "[]".to_string(), "{}".to_string(), "\"\"".to_string()],
+            auto_close: true,
+            overtype: true,
+            delete_pairs: true,
+            wrap_selection: true,
+            line_comment: None,
+            block_comment: None,
+            whitespace: WhitespaceSettings::default(),
+            formatter: None,
+            format_on_save: false,
+            language_server: None,
+        }
+    }
+}
+
+/// Creates the built-in language entries
+fn default_language_settings() -> HashMap<String, LanguageSettings> {
+    let mut languages = HashMap::new();
+    let mut python = LanguageSettings::default();
+    python.indent_after.push(":".to_string());
+    python.pairs.push("''".to_string());
+    python.formatter = Some("black --quiet -".to_string());
+    python.language_server = Some(LanguageServerSettings {
+        command: "pylsp".to_string(),
+        root_markers: vec!["pyproject.toml".to_string(), "setup.py".to_string(), ".git".to_string()],
+        ..LanguageServerSettings::default()
+    });
+    languages.insert("Python".to_string(), python);
+    let rust = LanguageSettings {
+        formatter: Some("rustfmt --edition 2021 --emit stdout".to_string()),
+        language_server: Some(LanguageServerSettings {
+            command: "rust-analyzer".to_string(),
+            root_markers: vec!["Cargo.toml".to_string(), ".git".to_string()],
+            ..LanguageServerSettings::default()
+        }),
+        ..LanguageSettings::default()
+    };
+    languages.insert("Rust".to_string(), rust);
+    languages
+}
+
+impl AppSettings {
+    /// Returns the editing rules for a language, falling back to the defaults
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax_name` - Name of the syntax of the language
+    pub fn language_settings(&self, syntax_name: &str) -> LanguageSettings {
+        self.languages.get(syntax_name).cloned().unwrap_or_default()
+    }
 }
 
 impl Default for AppSettings {
@@ -34,6 +214,12 @@ impl Default for AppSettings {
             font: format!("Monospace {}", DEFAULT_FONT_SIZE),
             last_opened_directory: None,
             last_opened_files: None,
+            languages: default_language_settings(),
+            folded_lines: HashMap::new(),
+            completion_min_chars: default_completion_min_chars(),
+            tab_width: default_tab_width(),
+            insert_spaces: default_insert_spaces(),
+            whitespace: WhitespaceSettings::default(),
         }
     }
 }
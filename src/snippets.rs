@@ -0,0 +1,958 @@
+//! Module for snippets
+//!
+//! Snippets are defined by the user in `snippets.json` in the config directory,
+//! keyed by syntax name, with the `*` entry holding the snippets of every
+//! language. Bodies use the TextMate syntax: numbered tab stops (`$1`,
+//! `${1:placeholder}`), choices (`${1|one,two|}`), the final cursor position
+//! (`$0`) and variables (`$TM_FILENAME`, `${CLIPBOARD:default}`). A snippet is
+//! expanded by typing its prefix followed by Tab, or by picking it in the
+//! completion popup. Tab and Shift+Tab then move between the tab stops, and
+//! tab stops sharing a number mirror each other.
+
+use gtk4::gdk;
+use gtk4::prelude::*;
+use gtk4::{EventControllerKey, TextBuffer, TextMark, TextView};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::str::Chars;
+use std::sync::LazyLock;
+use std::time::SystemTime;
+
+use crate::AppContext;
+
+/// Key of the snippets available in every language
+const ALL_LANGUAGES: &str = "*";
+
+/// Body of a snippet, either a single string or one string per line
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum SnippetBody {
+    Text(String),
+    Lines(Vec<String>),
+}
+
+/// A snippet definition
+#[derive(Deserialize, Clone, Debug)]
+pub struct Snippet {
+    /// Text that expands to the snippet
+    pub prefix: String,
+    body: SnippetBody,
+    /// Description shown in the completion popup
+    #[serde(default)]
+    pub description: String,
+}
+
+impl Snippet {
+    /// Returns the body of the snippet as a single string
+    fn body(&self) -> String {
+        match &self.body {
+            SnippetBody::Text(text) => text.clone(),
+            SnippetBody::Lines(lines) => lines.join("\n"),
+        }
+    }
+}
+
+/// Snippets keyed by syntax name, then by snippet name
+type SnippetFile = HashMap<String, HashMap<String, Snippet>>;
+
+/// A tab stop of an expanded snippet
+struct TabStop {
+    /// Start and end marks of each occurrence; the first one is edited, the others mirror it
+    ranges: Vec<(TextMark, TextMark)>,
+    /// Values offered for the tab stop
+    choices: Option<Vec<String>>,
+}
+
+/// An expanded snippet whose tab stops are being visited
+struct SnippetSession {
+    /// Tab stops in visiting order, ending with the final cursor position
+    stops: Vec<TabStop>,
+    current: usize,
+    /// Whether the mirrors are being updated, so that the update is not mirrored again
+    mirroring: bool,
+}
+
+/// Snippet state of the application
+#[derive(Default)]
+pub struct Snippets {
+    /// Active snippet session of each buffer
+    sessions: HashMap<TextBuffer, SnippetSession>,
+    /// Snippets file contents, with the modification time they were read at
+    cache: Option<(SystemTime, SnippetFile)>,
+}
+
+/// Shared snippet state
+pub type SnippetState = Rc<RefCell<Snippets>>;
+
+/// Path of the snippets file, resolved once as it is looked up on every Tab
+static SNIPPETS_PATH: LazyLock<Option<PathBuf>> =
+    LazyLock::new(|| dirs::config_dir().map(|dir| dir.join("e4code").join("snippets.json")));
+
+/// Returns the path of the snippets file
+///
+/// The file and its directory may not exist yet.
+pub fn snippets_path() -> Option<PathBuf> {
+    SNIPPETS_PATH.clone()
+}
+
+/// Returns the snippets available in a buffer, for its language and for every language
+///
+/// The snippets file is read again whenever it has been modified.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to look up
+pub fn snippets_for_buffer(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> Vec<Snippet> {
+    let syntax_name = crate::syntax_highlighting::syntax_name_for_buffer(app_context, buffer);
+    let Some(path) = snippets_path() else {
+        return Vec::new();
+    };
+    let Ok(modified) = fs::metadata(&path).and_then(|metadata| metadata.modified()) else {
+        return Vec::new();
+    };
+
+    let context = app_context.borrow();
+    let mut snippets = context.snippets.borrow_mut();
+    if snippets.cache.as_ref().is_none_or(|(cached, _)| *cached != modified) {
+        let file: SnippetFile = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        snippets.cache = Some((modified, file));
+    }
+    let Some((_, file)) = snippets.cache.as_ref() else {
+        return Vec::new();
+    };
+    [syntax_name.as_str(), ALL_LANGUAGES]
+        .iter()
+        .filter_map(|language| file.get(*language))
+        .flat_map(|language_snippets| language_snippets.values().cloned())
+        .collect()
+}
+
+/// An element of a parsed snippet body
+#[derive(Debug, Clone, PartialEq)]
+enum Element {
+    Text(String),
+    TabStop {
+        number: u32,
+        placeholder: Vec<Element>,
+        choices: Option<Vec<String>>,
+    },
+    Variable {
+        name: String,
+        default: Option<Vec<Element>>,
+    },
+}
+
+/// Reads a tab stop number
+fn parse_number(chars: &mut Peekable<Chars>) -> Option<u32> {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits.parse().ok()
+}
+
+/// Reads a variable name
+fn parse_name(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    (!name.is_empty()).then_some(name)
+}
+
+/// Reads the comma-separated values of a choice, up to the closing `|}`
+fn parse_choices(chars: &mut Peekable<Chars>) -> Option<Vec<String>> {
+    let mut choices = Vec::new();
+    let mut choice = String::new();
+    loop {
+        match chars.next()? {
+            '\\' => choice.push(chars.next()?),
+            ',' => choices.push(std::mem::take(&mut choice)),
+            '|' if chars.next_if_eq(&'}').is_some() => {
+                choices.push(choice);
+                return Some(choices);
+            }
+            c => choice.push(c),
+        }
+    }
+}
+
+/// Parses what follows a `$`, or returns `None` if it is not a tab stop or a variable
+fn parse_dollar(chars: &mut Peekable<Chars>) -> Option<Element> {
+    match chars.peek()? {
+        c if c.is_ascii_digit() => Some(Element::TabStop {
+            number: parse_number(chars)?,
+            placeholder: Vec::new(),
+            choices: None,
+        }),
+        c if c.is_ascii_alphabetic() || *c == '_' => Some(Element::Variable {
+            name: parse_name(chars)?,
+            default: None,
+        }),
+        '{' => {
+            chars.next();
+            if chars.peek()?.is_ascii_digit() {
+                let number = parse_number(chars)?;
+                let (placeholder, choices) = match chars.next()? {
+                    '}' => (Vec::new(), None),
+                    ':' => {
+                        let placeholder = parse_elements(chars, true);
+                        chars.next_if_eq(&'}')?;
+                        (placeholder, None)
+                    }
+                    '|' => (Vec::new(), Some(parse_choices(chars)?)),
+                    _ => return None,
+                };
+                Some(Element::TabStop {
+                    number,
+                    placeholder,
+                    choices,
+                })
+            } else {
+                let name = parse_name(chars)?;
+                let default = match chars.next()? {
+                    '}' => None,
+                    ':' => {
+                        let default = parse_elements(chars, true);
+                        chars.next_if_eq(&'}')?;
+                        Some(default)
+                    }
+                    _ => return None,
+                };
+                Some(Element::Variable { name, default })
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parses snippet elements, up to a closing brace when inside a placeholder
+fn parse_elements(chars: &mut Peekable<Chars>, in_placeholder: bool) -> Vec<Element> {
+    let mut elements = Vec::new();
+    let mut text = String::new();
+    while let Some(&c) = chars.peek() {
+        match c {
+            '}' if in_placeholder => break,
+            '\\' => {
+                chars.next();
+                match chars.next_if(|c| matches!(c, '$' | '}' | '\\')) {
+                    Some(escaped) => text.push(escaped),
+                    None => text.push('\\'),
+                }
+            }
+            '$' => {
+                chars.next();
+                // Parse on a copy, so that a lone `$` is kept as text
+                let mut lookahead = chars.clone();
+                match parse_dollar(&mut lookahead) {
+                    Some(element) => {
+                        *chars = lookahead;
+                        if !text.is_empty() {
+                            elements.push(Element::Text(std::mem::take(&mut text)));
+                        }
+                        elements.push(element);
+                    }
+                    None => text.push('$'),
+                }
+            }
+            _ => {
+                text.push(c);
+                chars.next();
+            }
+        }
+    }
+    if !text.is_empty() {
+        elements.push(Element::Text(text));
+    }
+    elements
+}
+
+/// A tab stop occurrence in the rendered text, in characters from its start
+#[derive(Debug)]
+struct StopRange {
+    number: u32,
+    start: usize,
+    end: usize,
+    choices: Option<Vec<String>>,
+}
+
+/// Renders parsed elements to text, following the indentation of the buffer
+struct Renderer<'a> {
+    variables: &'a HashMap<&'static str, String>,
+    /// Indentation of the line the snippet is inserted on, added after each newline
+    base_indent: String,
+    /// One indentation level of the buffer, replacing each tab of the body
+    indent_unit: String,
+    /// Text of the first placeholder of each tab stop, used by its mirrors
+    defaults: HashMap<u32, String>,
+    text: String,
+    length: usize,
+    stops: Vec<StopRange>,
+}
+
+impl<'a> Renderer<'a> {
+    /// Creates a renderer for a snippet inserted on a line
+    ///
+    /// # Arguments
+    ///
+    /// * `variables` - Values of the snippet variables
+    /// * `base_indent` - Indentation of the line the snippet is inserted on
+    /// * `indent_unit` - One indentation level of the buffer
+    fn new(variables: &'a HashMap<&'static str, String>, base_indent: String, indent_unit: String) -> Self {
+        Renderer {
+            variables,
+            base_indent,
+            indent_unit,
+            defaults: HashMap::new(),
+            text: String::new(),
+            length: 0,
+            stops: Vec::new(),
+        }
+    }
+
+    /// Records the placeholder text of each tab stop, before rendering
+    fn collect_defaults(&mut self, elements: &[Element]) {
+        for element in elements {
+            match element {
+                Element::TabStop {
+                    number,
+                    placeholder,
+                    choices,
+                } => {
+                    let default = match choices {
+                        Some(choices) => choices.first().cloned().unwrap_or_default(),
+                        None => plain_text(placeholder, self.variables),
+                    };
+                    if !default.is_empty() {
+                        self.defaults.entry(*number).or_insert(default);
+                    }
+                    self.collect_defaults(placeholder);
+                }
+                Element::Variable {
+                    default: Some(default),
+                    ..
+                } => self.collect_defaults(default),
+                _ => {}
+            }
+        }
+    }
+
+    /// Appends text, indenting new lines and converting tabs to the buffer's indentation
+    fn push_text(&mut self, text: &str) {
+        for c in text.chars() {
+            let replacement = match c {
+                '\n' => format!("\n{}", self.base_indent),
+                '\t' => self.indent_unit.clone(),
+                c => c.to_string(),
+            };
+            self.length += replacement.chars().count();
+            self.text.push_str(&replacement);
+        }
+    }
+
+    fn render(&mut self, elements: &[Element]) {
+        for element in elements {
+            match element {
+                Element::Text(text) => self.push_text(text),
+                Element::TabStop {
+                    number,
+                    placeholder,
+                    choices,
+                } => {
+                    let start = self.length;
+                    if choices.is_some() || placeholder.is_empty() {
+                        let default = self.defaults.get(number).cloned().unwrap_or_default();
+                        self.push_text(&default);
+                    } else {
+                        self.render(placeholder);
+                    }
+                    self.stops.push(StopRange {
+                        number: *number,
+                        start,
+                        end: self.length,
+                        choices: choices.clone(),
+                    });
+                }
+                Element::Variable { name, default } => match self.variables.get(name.as_str()) {
+                    Some(value) => {
+                        let value = value.clone();
+                        self.push_text(&value);
+                    }
+                    None => {
+                        if let Some(default) = default {
+                            self.render(default);
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Renders elements to text as they are, without tab stops
+fn plain_text(elements: &[Element], variables: &HashMap<&'static str, String>) -> String {
+    elements
+        .iter()
+        .map(|element| match element {
+            Element::Text(text) => text.clone(),
+            Element::TabStop {
+                placeholder,
+                choices,
+                ..
+            } => match choices {
+                Some(choices) => choices.first().cloned().unwrap_or_default(),
+                None => plain_text(placeholder, variables),
+            },
+            Element::Variable { name, default } => variables
+                .get(name.as_str())
+                .cloned()
+                .or_else(|| default.as_ref().map(|default| plain_text(default, variables)))
+                .unwrap_or_default(),
+        })
+        .collect()
+}
+
+/// Returns the values of the snippet variables at the cursor
+fn snippet_variables(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    clipboard: Option<String>,
+) -> HashMap<&'static str, String> {
+    let mut variables = HashMap::new();
+    if let Some(path) = app_context.borrow().buffer_paths.borrow().get(buffer) {
+        let file_name = path.file_name().map(|name| name.to_string_lossy().to_string());
+        let file_stem = path.file_stem().map(|stem| stem.to_string_lossy().to_string());
+        let directory = path.parent().map(|parent| parent.to_string_lossy().to_string());
+        variables.insert("TM_FILEPATH", path.to_string_lossy().to_string());
+        variables.extend(file_name.map(|name| ("TM_FILENAME", name)));
+        variables.extend(file_stem.map(|stem| ("TM_FILENAME_BASE", stem)));
+        variables.extend(directory.map(|directory| ("TM_DIRECTORY", directory)));
+    }
+
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut line_start = cursor;
+    line_start.set_line_offset(0);
+    let mut line_end = cursor;
+    if !line_end.ends_line() {
+        line_end.forward_to_line_end();
+    }
+    variables.insert("TM_CURRENT_LINE", buffer.text(&line_start, &line_end, true).to_string());
+    variables.insert("TM_LINE_INDEX", cursor.line().to_string());
+    variables.insert("TM_LINE_NUMBER", (cursor.line() + 1).to_string());
+    if let Some((start, end)) = buffer.selection_bounds() {
+        variables.insert("TM_SELECTED_TEXT", buffer.text(&start, &end, true).to_string());
+    }
+    variables.extend(clipboard.map(|text| ("CLIPBOARD", text)));
+
+    if let Ok(now) = glib::DateTime::now_local() {
+        let format = |format: &str| now.format(format).map(|text| text.to_string()).unwrap_or_default();
+        variables.insert("CURRENT_YEAR", format("%Y"));
+        variables.insert("CURRENT_YEAR_SHORT", format("%y"));
+        variables.insert("CURRENT_MONTH", format("%m"));
+        variables.insert("CURRENT_MONTH_NAME", format("%B"));
+        variables.insert("CURRENT_MONTH_NAME_SHORT", format("%b"));
+        variables.insert("CURRENT_DATE", format("%d"));
+        variables.insert("CURRENT_DAY_NAME", format("%A"));
+        variables.insert("CURRENT_DAY_NAME_SHORT", format("%a"));
+        variables.insert("CURRENT_HOUR", format("%H"));
+        variables.insert("CURRENT_MINUTE", format("%M"));
+        variables.insert("CURRENT_SECOND", format("%S"));
+    }
+    variables
+}
+
+/// Groups the tab stop occurrences of a rendered snippet by number, in visiting order
+///
+/// The numbered tab stops come in increasing order, then the final position,
+/// which is the end of the text if the snippet has no `$0`.
+///
+/// # Arguments
+///
+/// * `stop_ranges` - The tab stop occurrences, in text order
+/// * `length` - Length of the rendered text, in characters
+fn visiting_order(mut stop_ranges: Vec<StopRange>, length: usize) -> Vec<Vec<StopRange>> {
+    if !stop_ranges.iter().any(|range| range.number == 0) {
+        stop_ranges.push(StopRange {
+            number: 0,
+            start: length,
+            end: length,
+            choices: None,
+        });
+    }
+    stop_ranges.sort_by_key(|range| (range.number == 0, range.number));
+    let mut stops: Vec<Vec<StopRange>> = Vec::new();
+    for range in stop_ranges {
+        match stops.last_mut() {
+            Some(stop) if stop[0].number == range.number => stop.push(range),
+            _ => stops.push(vec![range]),
+        }
+    }
+    stops
+}
+
+/// Inserts parsed snippet elements at the cursor and starts visiting their tab stops
+fn expand_elements(
+    app_context: &Rc<RefCell<AppContext>>,
+    text_view: &TextView,
+    elements: &[Element],
+    clipboard: Option<String>,
+) {
+    let buffer = text_view.buffer();
+    let variables = snippet_variables(app_context, &buffer, clipboard);
+
+    let insert_start = buffer
+        .selection_bounds()
+        .map_or_else(|| buffer.iter_at_mark(&buffer.get_insert()), |(start, _)| start);
+    let mut line_start = insert_start;
+    line_start.set_line_offset(0);
+    let base_indent: String = buffer
+        .text(&line_start, &insert_start, true)
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\t')
+        .collect();
+    let indent_unit = crate::indentation::indent_unit(app_context, &buffer);
+    let mut renderer = Renderer::new(&variables, base_indent, indent_unit);
+    renderer.collect_defaults(elements);
+    renderer.render(elements);
+
+    end_session(app_context, &buffer);
+    buffer.begin_user_action();
+    buffer.delete_selection(true, text_view.is_editable());
+    let start_offset = buffer.iter_at_mark(&buffer.get_insert()).offset();
+    buffer.insert(&mut buffer.iter_at_offset(start_offset), &renderer.text);
+    buffer.end_user_action();
+
+    let stops = visiting_order(renderer.stops, renderer.length)
+        .into_iter()
+        .map(|ranges| TabStop {
+            choices: ranges.iter().find_map(|range| range.choices.clone()),
+            ranges: ranges
+                .iter()
+                .map(|range| {
+                    let start = buffer.iter_at_offset(start_offset + range.start as i32);
+                    let end = buffer.iter_at_offset(start_offset + range.end as i32);
+                    (buffer.create_mark(None, &start, true), buffer.create_mark(None, &end, false))
+                })
+                .collect(),
+        })
+        .collect();
+
+    app_context.borrow().snippets.borrow_mut().sessions.insert(
+        buffer.clone(),
+        SnippetSession {
+            stops,
+            current: 0,
+            mirroring: false,
+        },
+    );
+    sync_mirrors(app_context, &buffer);
+    select_stop(app_context, text_view, 0);
+}
+
+/// Expands a snippet at the cursor, replacing the selection
+///
+/// The clipboard is read first if the snippet uses it.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to expand the snippet in
+/// * `snippet` - The snippet to expand
+pub fn insert_snippet(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, snippet: &Snippet) {
+    let body = snippet.body();
+    let elements = parse_elements(&mut body.chars().peekable(), false);
+    let display = gdk::Display::default();
+    match display.filter(|_| body.contains("CLIPBOARD")) {
+        Some(display) => {
+            let app_context = app_context.clone();
+            let text_view = text_view.clone();
+            display.clipboard().read_text_async(None::<&gio::Cancellable>, move |res| {
+                let clipboard = res.ok().flatten().map(|text| text.to_string());
+                expand_elements(&app_context, &text_view, &elements, clipboard);
+            });
+        }
+        None => expand_elements(app_context, text_view, &elements, None),
+    }
+}
+
+/// Selects a tab stop of the buffer's session
+///
+/// Reaching the final position ends the session. Choices are offered in the
+/// completion popup.
+fn select_stop(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, index: usize) {
+    let buffer = text_view.buffer();
+    let (start, end, choices, is_final) = {
+        let context = app_context.borrow();
+        let mut snippets = context.snippets.borrow_mut();
+        let Some(session) = snippets.sessions.get_mut(&buffer) else {
+            return;
+        };
+        let Some(stop) = session.stops.get(index) else {
+            return;
+        };
+        let (start_mark, end_mark) = &stop.ranges[0];
+        let selection = (
+            buffer.iter_at_mark(start_mark),
+            buffer.iter_at_mark(end_mark),
+            stop.choices.clone(),
+            index + 1 == session.stops.len(),
+        );
+        session.current = index;
+        selection
+    };
+
+    buffer.select_range(&end, &start);
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+    if is_final {
+        end_session(app_context, &buffer);
+    }
+    if let Some(choices) = choices {
+        // After the completion popup reacts to the insertion of the snippet
+        let app_context = app_context.clone();
+        let text_view = text_view.clone();
+        glib::idle_add_local_once(move || crate::completion::show_choices(&app_context, &text_view, choices));
+    }
+}
+
+/// Ends the snippet session of a buffer, if any
+fn end_session(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let session = app_context.borrow().snippets.borrow_mut().sessions.remove(buffer);
+    for stop in session.into_iter().flat_map(|session| session.stops) {
+        for (start, end) in stop.ranges {
+            buffer.delete_mark(&start);
+            buffer.delete_mark(&end);
+        }
+    }
+}
+
+/// Moves to the next or previous tab stop of the buffer's session
+///
+/// The session ends if the cursor has left the expanded snippet.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to move in
+/// * `forward` - Whether to move to the next tab stop
+///
+/// # Returns
+///
+/// Whether a snippet session was active
+pub fn jump_to_tab_stop(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, forward: bool) -> bool {
+    let buffer = text_view.buffer();
+    let cursor = buffer.iter_at_mark(&buffer.get_insert()).offset();
+    let target = {
+        let context = app_context.borrow();
+        let snippets = context.snippets.borrow();
+        let Some(session) = snippets.sessions.get(&buffer) else {
+            return false;
+        };
+        let offsets: Vec<i32> = session
+            .stops
+            .iter()
+            .flat_map(|stop| &stop.ranges)
+            .flat_map(|(start, end)| [buffer.iter_at_mark(start).offset(), buffer.iter_at_mark(end).offset()])
+            .collect();
+        let inside = offsets.iter().min().is_some_and(|start| *start <= cursor)
+            && offsets.iter().max().is_some_and(|end| cursor <= *end);
+        inside.then(|| {
+            if forward {
+                session.current + 1
+            } else {
+                session.current.saturating_sub(1)
+            }
+        })
+    };
+    match target {
+        Some(index) => {
+            select_stop(app_context, text_view, index);
+            true
+        }
+        None => {
+            end_session(app_context, &buffer);
+            false
+        }
+    }
+}
+
+/// Handles Tab for snippets
+///
+/// Moves to the next tab stop of an active snippet, or expands the snippet
+/// whose prefix ends right before the cursor.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view where Tab was pressed
+///
+/// # Returns
+///
+/// Whether Tab was consumed
+pub fn handle_tab(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) -> bool {
+    if jump_to_tab_stop(app_context, text_view, true) {
+        return true;
+    }
+    let buffer = text_view.buffer();
+    if buffer.has_selection() {
+        return false;
+    }
+
+    let cursor = buffer.iter_at_mark(&buffer.get_insert());
+    let mut line_start = cursor;
+    line_start.set_line_offset(0);
+    let before_cursor = buffer.text(&line_start, &cursor, true).to_string();
+    // The prefix must start at a word boundary; the longest one wins
+    let snippet = snippets_for_buffer(app_context, &buffer)
+        .into_iter()
+        .filter(|snippet| {
+            !snippet.prefix.is_empty()
+                && before_cursor.strip_suffix(snippet.prefix.as_str()).is_some_and(|rest| {
+                    !rest.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+                })
+        })
+        .max_by_key(|snippet| snippet.prefix.len());
+    let Some(snippet) = snippet else {
+        return false;
+    };
+
+    let mut prefix_start = cursor;
+    prefix_start.backward_chars(snippet.prefix.chars().count() as i32);
+    buffer.select_range(&prefix_start, &cursor);
+    insert_snippet(app_context, text_view, &snippet);
+    true
+}
+
+/// Copies the text of the current tab stop to its mirrors
+fn sync_mirrors(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let (text, mirrors) = {
+        let context = app_context.borrow();
+        let mut snippets = context.snippets.borrow_mut();
+        let Some(session) = snippets.sessions.get_mut(buffer) else {
+            return;
+        };
+        if session.mirroring {
+            return;
+        }
+        let Some(stop) = session.stops.get(session.current) else {
+            return;
+        };
+        let range_text = |(start, end): &(TextMark, TextMark)| {
+            buffer
+                .text(&buffer.iter_at_mark(start), &buffer.iter_at_mark(end), true)
+                .to_string()
+        };
+        let text = range_text(&stop.ranges[0]);
+        let mirrors: Vec<(TextMark, TextMark)> = stop.ranges[1..]
+            .iter()
+            .filter(|range| range_text(range) != text)
+            .cloned()
+            .collect();
+        if mirrors.is_empty() {
+            return;
+        }
+        session.mirroring = true;
+        (text, mirrors)
+    };
+
+    buffer.begin_user_action();
+    for (start_mark, end_mark) in &mirrors {
+        let mut start = buffer.iter_at_mark(start_mark);
+        let mut end = buffer.iter_at_mark(end_mark);
+        buffer.delete(&mut start, &mut end);
+        buffer.insert(&mut start, &text);
+    }
+    buffer.end_user_action();
+
+    if let Some(session) = app_context.borrow().snippets.borrow_mut().sessions.get_mut(buffer) {
+        session.mirroring = false;
+    }
+}
+
+/// Adds the snippet controller to a text view
+///
+/// Shift+Tab moves to the previous tab stop and Escape ends the snippet
+/// session. Edits of a tab stop are copied to its mirrors.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view to add the controller to
+pub fn add_snippet_controller(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let key_controller = EventControllerKey::new();
+    key_controller.set_propagation_phase(gtk4::PropagationPhase::Capture);
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    key_controller.connect_key_pressed(move |_, keyval, _, _| {
+        match keyval {
+            gdk::Key::ISO_Left_Tab if jump_to_tab_stop(&app_context_clone, &text_view_clone, false) => {
+                return glib::Propagation::Stop;
+            }
+            gdk::Key::Escape => end_session(&app_context_clone, &text_view_clone.buffer()),
+            _ => {}
+        }
+        glib::Propagation::Proceed
+    });
+    text_view.add_controller(key_controller);
+
+    let app_context_clone = app_context.clone();
+    text_view.buffer().connect_end_user_action(move |buffer| {
+        sync_mirrors(&app_context_clone, buffer);
+    });
+}
+
+/// Forgets the snippet session of a buffer, once its tab is closed
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer of the closed tab
+pub fn forget_session(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    app_context.borrow().snippets.borrow_mut().sessions.remove(buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(body: &str) -> Vec<Element> {
+        parse_elements(&mut body.chars().peekable(), false)
+    }
+
+    fn text(text: &str) -> Element {
+        Element::Text(text.to_string())
+    }
+
+    fn stop(number: u32, placeholder: Vec<Element>) -> Element {
+        Element::TabStop {
+            number,
+            placeholder,
+            choices: None,
+        }
+    }
+
+    /// Renders a body inserted on a line indented by two spaces, in a buffer indented by four
+    fn render<'a>(body: &str, variables: &'a HashMap<&'static str, String>) -> Renderer<'a> {
+        let elements = parse(body);
+        let mut renderer = Renderer::new(variables, "  ".to_string(), "    ".to_string());
+        renderer.collect_defaults(&elements);
+        renderer.render(&elements);
+        renderer
+    }
+
+    #[test]
+    fn tab_stops_and_placeholders_are_parsed() {
+        assert_eq!(parse("a $1 b $0"), vec![text("a "), stop(1, vec![]), text(" b "), stop(0, vec![])]);
+        assert_eq!(
+            parse("${1:outer ${2:inner}}!"),
+            vec![stop(1, vec![text("outer "), stop(2, vec![text("inner")])]), text("!")]
+        );
+        assert_eq!(parse("${12}"), vec![stop(12, vec![])]);
+    }
+
+    #[test]
+    fn choices_are_parsed_with_escaped_commas() {
+        assert_eq!(
+            parse("${1|one,t\\,wo,three|}"),
+            vec![Element::TabStop {
+                number: 1,
+                placeholder: Vec::new(),
+                choices: Some(vec!["one".to_string(), "t,wo".to_string(), "three".to_string()]),
+            }]
+        );
+    }
+
+    #[test]
+    fn variables_are_parsed_with_their_default() {
+        assert_eq!(
+            parse("$TM_FILENAME ${CLIPBOARD:none $1}"),
+            vec![
+                Element::Variable {
+                    name: "TM_FILENAME".to_string(),
+                    default: None,
+                },
+                text(" "),
+                Element::Variable {
+                    name: "CLIPBOARD".to_string(),
+                    default: Some(vec![text("none "), stop(1, vec![])]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_and_lone_dollars_are_text() {
+        assert_eq!(parse("\\$1 \\} \\\\ \\n"), vec![text("$1 } \\ \\n")]);
+        assert_eq!(parse("costs $ 5 $"), vec![text("costs $ 5 $")]);
+        assert_eq!(parse("${1:unclosed"), vec![text("${1:unclosed")]);
+        assert_eq!(parse("${|x|}"), vec![text("${|x|}")]);
+        // A closing brace outside a placeholder is text
+        assert_eq!(parse("} $1"), vec![text("} "), stop(1, vec![])]);
+    }
+
+    #[test]
+    fn rendering_indents_lines_and_expands_tabs() {
+        let variables = HashMap::new();
+        let renderer = render("if $1 {\n\t$0\n}", &variables);
+        assert_eq!(renderer.text, "if  {\n      \n  }");
+        assert_eq!(renderer.length, renderer.text.chars().count());
+    }
+
+    #[test]
+    fn mirrors_take_the_first_placeholder() {
+        let variables = HashMap::new();
+        let renderer = render("${1:name} = $1; ${2|a,b|} $2", &variables);
+        assert_eq!(renderer.text, "name = name; a a");
+        let ranges: Vec<(u32, usize, usize)> =
+            renderer.stops.iter().map(|range| (range.number, range.start, range.end)).collect();
+        assert_eq!(ranges, vec![(1, 0, 4), (1, 7, 11), (2, 13, 14), (2, 15, 16)]);
+        assert_eq!(renderer.stops[2].choices, Some(vec!["a".to_string(), "b".to_string()]));
+    }
+
+    #[test]
+    fn nested_placeholders_give_nested_ranges() {
+        let variables = HashMap::new();
+        let renderer = render("f(${1:x, ${2:y}})", &variables);
+        assert_eq!(renderer.text, "f(x, y)");
+        let ranges: Vec<(u32, usize, usize)> =
+            renderer.stops.iter().map(|range| (range.number, range.start, range.end)).collect();
+        assert_eq!(ranges, vec![(2, 5, 6), (1, 2, 6)]);
+    }
+
+    #[test]
+    fn variables_render_their_value_or_default() {
+        let variables = HashMap::from([("TM_FILENAME", "main.rs".to_string())]);
+        let renderer = render("// $TM_FILENAME ${CLIPBOARD:empty} $UNKNOWN.", &variables);
+        assert_eq!(renderer.text, "// main.rs empty .");
+    }
+
+    #[test]
+    fn tab_stops_are_visited_in_order_with_the_final_position_last() {
+        let variables = HashMap::new();
+        let renderer = render("$2 $0 $1 $2", &variables);
+        let length = renderer.length;
+        let order: Vec<Vec<(u32, usize)>> = visiting_order(renderer.stops, length)
+            .iter()
+            .map(|ranges| ranges.iter().map(|range| (range.number, range.start)).collect())
+            .collect();
+        assert_eq!(order, vec![vec![(1, 2)], vec![(2, 0), (2, 3)], vec![(0, 1)]]);
+    }
+
+    #[test]
+    fn the_final_position_defaults_to_the_end() {
+        let variables = HashMap::new();
+        let renderer = render("a${1:b}c", &variables);
+        let length = renderer.length;
+        let order = visiting_order(renderer.stops, length);
+        assert_eq!(order.len(), 2);
+        assert_eq!((order[1][0].number, order[1][0].start, order[1][0].end), (0, 3, 3));
+    }
+}
@@ -7,10 +7,15 @@ use gtk4::gdk;
 use gtk4::prelude::*;
 use gtk4::{TextBuffer, TextIter, TextTag};
 use syntect::highlighting::{Theme, ThemeSet};
-use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::rc::Rc;
 
+/// Number of lines between two parser states kept by a [`ScopeCache`]
+const SCOPE_CHECKPOINT_LINES: i32 = 50;
+
 /// Context for syntax highlighting, holding all necessary components.
 pub struct SyntaxHighlightingContext {
     /// Syntax set for syntax highlighting
@@ -62,7 +67,7 @@ pub fn apply_syntax_highlighting(
     ps: &SyntaxSet,
     theme: &Theme,
 ) {
-    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), false);
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
     let tag_table = buffer.tag_table();
 
     // Collect syntect tags to remove them efficiently
@@ -78,7 +83,7 @@ pub fn apply_syntax_highlighting(
         tags
     };
 
-    // Remove only syntect tags (diagnostics, highlight)
+    // Remove only syntect tags, keeping the diagnostic and highlight tags
     let start_iter = buffer.start_iter();
     let end_iter = buffer.end_iter();
     for tag in syntect_tags {
@@ -208,7 +213,7 @@ pub fn apply_incremental_syntax_highlighting(
     for line_num in 0..start_line {
         let line_start = buffer.iter_at_line(line_num).unwrap();
         let line_end = buffer.iter_at_line(line_num + 1).unwrap_or_else(|| buffer.end_iter());
-        let line_text = buffer.text(&line_start, &line_end, false);
+        let line_text = buffer.text(&line_start, &line_end, true);
         let _ = h.highlight_line(&line_text, ps); // Process to maintain state
     }
 
@@ -220,7 +225,7 @@ pub fn apply_incremental_syntax_highlighting(
         } else {
             buffer.end_iter()
         };
-        let line_text = buffer.text(&line_start, &line_end, false);
+        let line_text = buffer.text(&line_start, &line_end, true);
         
         if let Ok(ranges) = h.highlight_line(&line_text, ps) {
             let mut current_offset = 0;
@@ -410,3 +415,443 @@ pub fn find_matching_bracket(
 
     None
 }
+
+/// Returns the name of the syntax of a buffer's language
+///
+/// The syntax is chosen from the extension of the buffer's file. Buffers
+/// without a file, or with an unknown extension, use the default syntax.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to look up
+pub fn syntax_name_for_buffer(
+    app_context: &Rc<RefCell<crate::AppContext>>,
+    buffer: &TextBuffer,
+) -> String {
+    let context = app_context.borrow();
+    let path = context.buffer_paths.borrow().get(buffer).cloned();
+    let syntax_context = context.syntax_context.borrow();
+    path.as_deref()
+        .and_then(|path| path.extension())
+        .and_then(|extension| extension.to_str())
+        .and_then(|extension| syntax_context.ps.find_syntax_by_extension(extension))
+        .map_or_else(|| syntax_context.syntax.name.clone(), |syntax| syntax.name.clone())
+}
+
+/// Returns the editing rules of the language of a buffer
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to look up
+pub fn language_settings_for_buffer(
+    app_context: &Rc<RefCell<crate::AppContext>>,
+    buffer: &TextBuffer,
+) -> crate::settings::LanguageSettings {
+    let syntax_name = syntax_name_for_buffer(app_context, buffer);
+    app_context.borrow().app_settings.borrow().language_settings(&syntax_name)
+}
+
+/// Parser states saved at regular lines of a buffer
+///
+/// [`is_in_string_or_comment`] is called for every typed bracket or quote, so
+/// rather than parsing the buffer from its start each time, it resumes from the
+/// last state saved before the position. The states after an edited line are
+/// dropped.
+#[derive(Default)]
+pub struct ScopeCache {
+    /// Name of the syntax the states were parsed with
+    syntax_name: String,
+    /// Parser state and scopes at the start of every `SCOPE_CHECKPOINT_LINES`th line
+    checkpoints: Vec<(ParseState, ScopeStack)>,
+}
+
+/// Parser states of each buffer
+pub type ScopeCaches = Rc<RefCell<HashMap<TextBuffer, ScopeCache>>>;
+
+impl ScopeCache {
+    /// Drops the states an edit of a line makes out of date
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The edited line
+    pub fn invalidate_from(&mut self, line: i32) {
+        self.checkpoints.truncate((line / SCOPE_CHECKPOINT_LINES + 1) as usize);
+    }
+
+    /// Parses the lines of a text, from the last state saved at or before a line
+    ///
+    /// States are saved along the way, and parsing stops at the first line
+    /// that can't be parsed.
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax` - Syntax of the text
+    /// * `ps` - Syntax set the syntax belongs to
+    /// * `line_text` - Returns a line of the text, with its line terminator
+    /// * `from_line` - First line needed
+    /// * `last_line` - Last line to parse
+    /// * `visit` - Called for each parsed line, parsing stops when it returns false
+    fn parse_lines(
+        &mut self,
+        syntax: &SyntaxReference,
+        ps: &SyntaxSet,
+        line_text: impl Fn(i32) -> String,
+        from_line: i32,
+        last_line: i32,
+        mut visit: impl FnMut(ParsedLine<'_>) -> bool,
+    ) {
+        if self.syntax_name != syntax.name {
+            self.syntax_name = syntax.name.clone();
+            self.checkpoints.clear();
+        }
+        if self.checkpoints.is_empty() {
+            self.checkpoints.push((ParseState::new(syntax), ScopeStack::new()));
+        }
+
+        let index = ((from_line.max(0) / SCOPE_CHECKPOINT_LINES) as usize).min(self.checkpoints.len() - 1);
+        let (mut parse_state, mut scope_stack) = self.checkpoints[index].clone();
+        for line in index as i32 * SCOPE_CHECKPOINT_LINES..=last_line {
+            if line % SCOPE_CHECKPOINT_LINES == 0 && (line / SCOPE_CHECKPOINT_LINES) as usize == self.checkpoints.len() {
+                self.checkpoints.push((parse_state.clone(), scope_stack.clone()));
+            }
+            let text = line_text(line);
+            let Ok(ops) = parse_state.parse_line(&text, ps) else {
+                return;
+            };
+            if !visit(ParsedLine { line, text: &text, scopes: &scope_stack, ops: &ops }) {
+                return;
+            }
+            for (_, op) in &ops {
+                if scope_stack.apply(op).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns the scopes at a position of a text
+    ///
+    /// # Arguments
+    ///
+    /// * `syntax` - Syntax of the text
+    /// * `ps` - Syntax set the syntax belongs to
+    /// * `line_text` - Returns a line of the text, with its line terminator
+    /// * `line` - Line of the position
+    /// * `byte` - Byte offset of the position in its line
+    ///
+    /// # Returns
+    ///
+    /// The scopes, or `None` if the text couldn't be parsed
+    fn scopes_at(
+        &mut self,
+        syntax: &SyntaxReference,
+        ps: &SyntaxSet,
+        line_text: impl Fn(i32) -> String,
+        line: i32,
+        byte: usize,
+    ) -> Option<ScopeStack> {
+        let mut scopes = None;
+        self.parse_lines(syntax, ps, line_text, line, line, |parsed| {
+            if parsed.line == line {
+                let mut scope_stack = parsed.scopes.clone();
+                for (_, op) in parsed.ops.iter().take_while(|(op_byte, _)| *op_byte <= byte) {
+                    if scope_stack.apply(op).is_err() {
+                        return false;
+                    }
+                }
+                scopes = Some(scope_stack);
+            }
+            true
+        });
+        scopes
+    }
+}
+
+/// A line parsed with the syntax of its text
+pub struct ParsedLine<'a> {
+    /// The line, counted from 0
+    pub line: i32,
+    /// Text of the line, with its line terminator
+    pub text: &'a str,
+    /// Scopes at the start of the line
+    pub scopes: &'a ScopeStack,
+    /// Scope changes on the line, with the byte offset each happens at
+    pub ops: &'a [(usize, ScopeStackOp)],
+}
+
+/// Results of a scan through the parsed lines of a buffer
+///
+/// A scan visits the lines in order, updating a state and pushing the items it
+/// finds. The state and the number of items found are saved at the same lines
+/// as the parser states of a [`ScopeCache`], so that after an edit the scan
+/// resumes from the last saved line before the edited one rather than from the
+/// start of the buffer.
+pub struct LineScan<S, T> {
+    /// State of the scan and number of items found, at the start of every
+    /// `SCOPE_CHECKPOINT_LINES`th line
+    checkpoints: Vec<(S, usize)>,
+    /// Items found, in the order they were found
+    pub items: Vec<T>,
+}
+
+impl<S, T> Default for LineScan<S, T> {
+    fn default() -> Self {
+        LineScan { checkpoints: Vec::new(), items: Vec::new() }
+    }
+}
+
+impl<S: Clone + Default, T> LineScan<S, T> {
+    /// Drops the saved states an edit of a line makes out of date
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The edited line
+    pub fn invalidate_from(&mut self, line: i32) {
+        self.checkpoints.truncate((line / SCOPE_CHECKPOINT_LINES + 1) as usize);
+    }
+
+    /// Scans a text from its last saved state up to a line
+    ///
+    /// # Arguments
+    ///
+    /// * `cache` - Parser states of the text
+    /// * `syntax` - Syntax of the text
+    /// * `ps` - Syntax set the syntax belongs to
+    /// * `line_text` - Returns a line of the text, with its line terminator
+    /// * `last_line` - Last line of the text
+    /// * `visit` - Updates the state and the items with a line, stopping the scan when it returns false
+    pub fn resume(
+        &mut self,
+        cache: &mut ScopeCache,
+        syntax: &SyntaxReference,
+        ps: &SyntaxSet,
+        line_text: impl Fn(i32) -> String,
+        last_line: i32,
+        mut visit: impl FnMut(&mut S, &mut Vec<T>, ParsedLine<'_>) -> bool,
+    ) {
+        let from_line = self.checkpoints.len().saturating_sub(1) as i32 * SCOPE_CHECKPOINT_LINES;
+        let mut state = S::default();
+        let mut started = false;
+        cache.parse_lines(syntax, ps, line_text, from_line, last_line, |parsed| {
+            let index = (parsed.line / SCOPE_CHECKPOINT_LINES) as usize;
+            // Parsing starts at or before the last saved state
+            if !started {
+                started = true;
+                self.checkpoints.truncate(index + 1);
+                match self.checkpoints.get(index) {
+                    Some((saved_state, count)) => {
+                        state = saved_state.clone();
+                        self.items.truncate(*count);
+                    }
+                    None => self.items.clear(),
+                }
+            }
+            if parsed.line % SCOPE_CHECKPOINT_LINES == 0 && index == self.checkpoints.len() {
+                self.checkpoints.push((state.clone(), self.items.len()));
+            }
+            visit(&mut state, &mut self.items, parsed)
+        });
+    }
+}
+
+/// Drops the parser states of a buffer past the lines it edits
+fn watch_edits(scope_caches: &ScopeCaches, buffer: &TextBuffer) {
+    let scope_caches_insert = scope_caches.clone();
+    buffer.connect_insert_text(move |buffer, pos, _| {
+        if let Some(cache) = scope_caches_insert.borrow_mut().get_mut(buffer) {
+            cache.invalidate_from(pos.line());
+        }
+    });
+    let scope_caches_delete = scope_caches.clone();
+    buffer.connect_delete_range(move |buffer, start, _| {
+        if let Some(cache) = scope_caches_delete.borrow_mut().get_mut(buffer) {
+            cache.invalidate_from(start.line());
+        }
+    });
+}
+
+/// Returns the parser states of a buffer, watching its edits from the first call
+fn scope_cache<'a>(
+    scope_caches: &'a mut HashMap<TextBuffer, ScopeCache>,
+    shared_scope_caches: &ScopeCaches,
+    buffer: &TextBuffer,
+) -> &'a mut ScopeCache {
+    match scope_caches.entry(buffer.clone()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            watch_edits(shared_scope_caches, buffer);
+            entry.insert(ScopeCache::default())
+        }
+    }
+}
+
+/// Returns a line of a buffer, with its line terminator
+fn buffer_line(buffer: &TextBuffer, line: i32) -> String {
+    let Some(line_start) = buffer.iter_at_line(line) else {
+        return String::new();
+    };
+    let mut line_end = line_start;
+    line_end.forward_line();
+    buffer.text(&line_start, &line_end, true).to_string()
+}
+
+/// Scans the lines of a buffer with the syntax of its language
+///
+/// The scan resumes from its last saved state, and parsing from the parser
+/// state saved for the buffer at or before it. Callers drop the saved states
+/// of the scan past an edited line with [`LineScan::invalidate_from`].
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to scan
+/// * `scan` - Results of the previous scan of the buffer
+/// * `visit` - Updates the state and the items with a line, stopping the scan when it returns false
+pub fn scan_lines<S: Clone + Default, T>(
+    app_context: &Rc<RefCell<crate::AppContext>>,
+    buffer: &TextBuffer,
+    scan: &mut LineScan<S, T>,
+    visit: impl FnMut(&mut S, &mut Vec<T>, ParsedLine<'_>) -> bool,
+) {
+    let syntax_name = syntax_name_for_buffer(app_context, buffer);
+    let context = app_context.borrow();
+    let syntax_context = context.syntax_context.borrow();
+    let ps = &syntax_context.ps;
+    let syntax = ps.find_syntax_by_name(&syntax_name).unwrap_or(&syntax_context.syntax);
+
+    let mut scope_caches = context.scope_caches.borrow_mut();
+    let cache = scope_cache(&mut scope_caches, &context.scope_caches, buffer);
+    let line_text = |line: i32| buffer_line(buffer, line);
+    scan.resume(cache, syntax, ps, line_text, buffer.line_count() - 1, visit);
+}
+
+/// Checks whether a position of a buffer lies inside a string or a comment
+///
+/// The buffer is parsed with the syntax of its language up to the position,
+/// starting from the parser state saved for the buffer closest before it, and
+/// the resulting scope stack is inspected.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `iter` - The position to check
+pub fn is_in_string_or_comment(app_context: &Rc<RefCell<crate::AppContext>>, iter: &TextIter) -> bool {
+    let buffer = iter.buffer();
+    let syntax_name = syntax_name_for_buffer(app_context, &buffer);
+    let context = app_context.borrow();
+    let syntax_context = context.syntax_context.borrow();
+    let ps = &syntax_context.ps;
+    let syntax = ps.find_syntax_by_name(&syntax_name).unwrap_or(&syntax_context.syntax);
+
+    let line_text = |line: i32| buffer_line(&buffer, line);
+    let mut line_start = *iter;
+    line_start.set_line_offset(0);
+    let cursor_byte = buffer.text(&line_start, iter, true).len();
+
+    let mut scope_caches = context.scope_caches.borrow_mut();
+    let cache = scope_cache(&mut scope_caches, &context.scope_caches, &buffer);
+    let Some(scope_stack) = cache.scopes_at(syntax, ps, line_text, iter.line(), cursor_byte) else {
+        return false;
+    };
+
+    let string_scope = Scope::new("string").expect("valid scope");
+    let comment_scope = Scope::new("comment").expect("valid scope");
+    scope_stack
+        .as_slice()
+        .iter()
+        .any(|scope| string_scope.is_prefix_of(*scope) || comment_scope.is_prefix_of(*scope))
+}
+
+/// Forgets the parser states of a buffer, once its tab is closed
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer of the closed tab
+pub fn forget_scopes(app_context: &Rc<RefCell<crate::AppContext>>, buffer: &TextBuffer) {
+    app_context.borrow().scope_caches.borrow_mut().remove(buffer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Rust text whose block comment spans several checkpoints
+    fn rust_lines() -> Vec<String> {
+        let mut lines = vec!["fn main() {\n".to_string(), "    /* start\n".to_string()];
+        lines.extend((0..150).map(|index| format!("    let x{} = \"{}\";\n", index, index)));
+        lines.push("    end */\n".to_string());
+        lines.extend((0..30).map(|index| format!("    let s = \"日本😀 {}\";\n", index)));
+        lines.push("}\n".to_string());
+        lines
+    }
+
+    /// Returns whether the position of a line is in a string or a comment
+    fn in_string_or_comment(cache: &mut ScopeCache, ps: &SyntaxSet, lines: &[String], line: i32, byte: usize) -> bool {
+        let syntax = ps.find_syntax_by_name("Rust").expect("Rust is a default syntax");
+        let scopes = cache
+            .scopes_at(syntax, ps, |line| lines[line as usize].clone(), line, byte)
+            .expect("the text parses");
+        let string_scope = Scope::new("string").unwrap();
+        let comment_scope = Scope::new("comment").unwrap();
+        scopes
+            .as_slice()
+            .iter()
+            .any(|scope| string_scope.is_prefix_of(*scope) || comment_scope.is_prefix_of(*scope))
+    }
+
+    #[test]
+    fn cached_states_give_the_scopes_of_a_full_parse() {
+        let ps = SyntaxSet::load_defaults_newlines();
+        let lines = rust_lines();
+        let mut cache = ScopeCache::default();
+        // Queries out of order reuse the states saved by the previous ones
+        for (line, byte) in [(170, 14), (3, 4), (120, 8), (151, 10), (175, 0), (0, 3), (60, 0)] {
+            let expected = in_string_or_comment(&mut ScopeCache::default(), &ps, &lines, line, byte);
+            assert_eq!(in_string_or_comment(&mut cache, &ps, &lines, line, byte), expected, "line {}", line);
+        }
+        assert_eq!(cache.checkpoints.len(), 4);
+    }
+
+    #[test]
+    fn strings_and_comments_are_found() {
+        let ps = SyntaxSet::load_defaults_newlines();
+        let lines = rust_lines();
+        let mut cache = ScopeCache::default();
+        assert!(in_string_or_comment(&mut cache, &ps, &lines, 120, 4));
+        assert!(in_string_or_comment(&mut cache, &ps, &lines, 152, 4));
+        assert!(!in_string_or_comment(&mut cache, &ps, &lines, 153, 4));
+        // After the opening quote, past the multibyte characters
+        assert!(in_string_or_comment(&mut cache, &ps, &lines, 160, "    let s = \"日本😀".len()));
+        assert!(!in_string_or_comment(&mut cache, &ps, &lines, 0, 0));
+    }
+
+    #[test]
+    fn an_edit_drops_the_states_after_its_line() {
+        let ps = SyntaxSet::load_defaults_newlines();
+        let mut lines = rust_lines();
+        let mut cache = ScopeCache::default();
+        assert!(in_string_or_comment(&mut cache, &ps, &lines, 120, 4));
+
+        // Closing the comment earlier changes the scopes of the following lines
+        lines[60] = "    */\n".to_string();
+        cache.invalidate_from(60);
+        assert_eq!(cache.checkpoints.len(), 2);
+        assert!(!in_string_or_comment(&mut cache, &ps, &lines, 120, 4));
+    }
+
+    #[test]
+    fn a_new_syntax_drops_the_states() {
+        let ps = SyntaxSet::load_defaults_newlines();
+        let lines = rust_lines();
+        let mut cache = ScopeCache::default();
+        in_string_or_comment(&mut cache, &ps, &lines, 120, 4);
+        let plain_text = ps.find_syntax_plain_text();
+        let scopes = cache.scopes_at(plain_text, &ps, |line| lines[line as usize].clone(), 120, 4);
+
+        assert_eq!(cache.syntax_name, plain_text.name);
+        assert!(scopes.is_some_and(|scopes| scopes.as_slice().len() <= 1));
+    }
+}
@@ -4,17 +4,19 @@
 //! editor tabs, including file operations and user interaction handling.
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use gtk4::prelude::*;
 use gtk4::{
-    ApplicationWindow, Box, Button, Label, Notebook, ScrolledWindow, TextBuffer,
+    Box, Button, Label, Notebook, ScrolledWindow, TextBuffer,
 };
 
 use std::cell::RefCell;
 use std::rc::Rc;
 
 use crate::ui::components::{create_line_numbers_area, create_text_view_with_line_numbers};
+use crate::block_selection::add_block_selection_controllers;
+use crate::multi_cursor::add_multi_cursor_controllers;
 use crate::utils::add_zoom_controllers_to_text_view;
 
 /// Opens a file in a new tab
@@ -70,9 +72,12 @@ pub fn open_file_in_new_tab(
     } // `buffer_paths_borrowed` is dropped here, releasing the immutable borrow
 
     // If the file is not already open, proceed to open it in a new tab
-    match std::fs::read_to_string(&path) {
+    let editor_config = crate::editorconfig::config_for_path(path);
+    match crate::editorconfig::read_file(path, &editor_config) {
         Ok(content) => {
             let new_buffer = gtk4::TextBuffer::builder().text(&content).build();
+            // The buffer matches the file on disk
+            new_buffer.set_modified(false);
             // Setup standard buffer tags
             crate::buffer_tags::setup_buffer_tags(&new_buffer);
 
@@ -102,12 +107,21 @@ if let Some(state_bool) = state.get::<bool>() {
             }
 
             add_zoom_controllers_to_text_view(
+                app_context,
                 &new_text_view,
                 current_font_desc.clone(),
                 update_font.clone(),
                 app.clone(),
                 initial_font_size.clone(),
             );
+            // Completion goes first, so that it sees Enter before auto-indent
+            crate::completion::add_completion_controller(app_context, &new_text_view);
+            crate::snippets::add_snippet_controller(app_context, &new_text_view);
+            add_multi_cursor_controllers(app_context, &new_text_view);
+            add_block_selection_controllers(app_context, &new_text_view);
+            crate::auto_pairs::add_auto_pairs_controller(app_context, &new_text_view);
+            crate::indentation::add_auto_indent_controller(app_context, &new_text_view);
+            crate::indentation::add_tab_controller(app_context, &new_text_view);
 
             let scrolled_window = ScrolledWindow::builder()
                 .hscrollbar_policy(gtk4::PolicyType::Automatic)
@@ -117,6 +131,7 @@ if let Some(state_bool) = state.get::<bool>() {
 
             // Line numbers area for the new tab
             let line_numbers_area = create_line_numbers_area(
+                app_context,
                 &new_text_view,
                 &scrolled_window,
                 current_font_desc.clone(),
@@ -144,6 +159,12 @@ if let Some(state_bool) = state.get::<bool>() {
 
             // Connect signals to the new buffer (this will also connect bracket highlighting)
             setup_buffer_connections(&new_buffer, &new_text_view);
+            crate::undo_history::connect_undo_history(app_context, &new_buffer);
+            crate::folding::connect_folding(app_context, &new_text_view);
+            // Hover goes first, so that its tooltip can include the diagnostics
+            crate::lsp::connect_hover(app_context, &new_text_view);
+            crate::outline::connect_outline(app_context, &new_text_view);
+            crate::diagnostics::connect_diagnostics(app_context, &new_text_view);
 
             let filename = path
                 .file_name()
@@ -161,19 +182,16 @@ if let Some(state_bool) = state.get::<bool>() {
                 notebook.append_page(&text_view_with_line_numbers_box, Some(&tab_label_box));
             notebook.set_current_page(Some(page_num));
 
-            let notebook_clone = notebook.clone();
-            let buffer_paths_clone = buffer_paths.clone();
-            if let Some(window) = app.active_window() {
-                if let Some(app_window) = window.downcast_ref::<ApplicationWindow>() {
-                    let window_clone = app_window.clone();
-                    close_button.connect_clicked(move |_| {
-                        close_tab(&window_clone, &notebook_clone, &buffer_paths_clone, page_num);
-                    });
-                }
-            }
+            let app_context_clone = app_context.clone();
+            close_button.connect_clicked(move |_| {
+                close_tab(&app_context_clone, page_num);
+            });
 
             highlight_closure(new_buffer.clone());
             crate::indentation::detect_indent_style(app_context, &new_buffer);
+            crate::editorconfig::apply_config(app_context, &new_text_view, editor_config);
+            crate::diagnostics::show_diagnostics(app_context, &new_text_view);
+            crate::lsp::open_document(app_context, &new_buffer);
         }
         Err(e) => {
             crate::dialogs::show_error_dialog(
@@ -185,6 +203,56 @@ if let Some(state_bool) = state.get::<bool>() {
     }
 }
 
+/// Opens a file and moves the cursor to a position
+///
+/// The file is opened in a new tab, or its tab is selected if it is already
+/// open, and the view is scrolled to show the position.
+///
+/// # Arguments
+///
+/// * `path` - Path to the file to open
+/// * `line` - Line of the position, counted from 0
+/// * `column` - Column of the position in characters, counted from 0
+/// * `app_context` - Reference to the application context
+pub fn open_file_at_position(path: &PathBuf, line: i32, column: i32, app_context: &Rc<RefCell<AppContext>>) {
+    open_file_in_new_tab(path, app_context);
+    let Some(text_view) = crate::ui::helpers::get_current_text_view(&app_context.borrow().notebook) else {
+        return;
+    };
+    // The file could not be opened if the current tab shows another file
+    if app_context.borrow().buffer_paths.borrow().get(&text_view.buffer()) != Some(path) {
+        return;
+    }
+    go_to_position(&text_view, line, column);
+}
+
+/// Moves the cursor of a text view to a position and scrolls it into view
+///
+/// Positions past the end of the line or of the buffer are clamped.
+///
+/// # Arguments
+///
+/// * `text_view` - The text view to move the cursor of
+/// * `line` - Line of the position, counted from 0
+/// * `column` - Column of the position in characters, counted from 0
+pub fn go_to_position(text_view: &gtk4::TextView, line: i32, column: i32) {
+    let buffer = text_view.buffer();
+    let mut iter = buffer.iter_at_line(line.max(0)).unwrap_or_else(|| buffer.end_iter());
+    let mut line_end = iter;
+    if !line_end.ends_line() {
+        line_end.forward_to_line_end();
+    }
+    iter.set_line_offset(column.clamp(0, line_end.line_offset()));
+    buffer.place_cursor(&iter);
+    text_view.grab_focus();
+
+    // The new tab has no size yet, so scroll once it has been laid out
+    let text_view = text_view.clone();
+    glib::idle_add_local_once(move || {
+        text_view.scroll_to_mark(&text_view.buffer().get_insert(), 0.1, true, 0.0, 0.5);
+    });
+}
+
 /// Creates a new untitled file tab
 ///
 /// This function creates a new empty tab for an untitled file, with a
@@ -206,7 +274,6 @@ pub fn create_new_file_tab(
     let context = app_context.borrow();
     let notebook = &context.notebook;
     let highlight_closure = &context.syntax_context.borrow().highlight_closure;
-    let buffer_paths = &context.buffer_paths;
     let app = &context.app;
     let current_font_desc = &context.current_font_desc;
     let update_font = &context.update_font;
@@ -240,12 +307,21 @@ pub fn create_new_file_tab(
     }
 
     add_zoom_controllers_to_text_view(
+        app_context,
         &new_text_view,
         current_font_desc.clone(),
         update_font.clone(),
         app.clone(),
         initial_font_size.clone(),
     );
+    // Completion goes first, so that it sees Enter before auto-indent
+    crate::completion::add_completion_controller(app_context, &new_text_view);
+    crate::snippets::add_snippet_controller(app_context, &new_text_view);
+    add_multi_cursor_controllers(app_context, &new_text_view);
+    add_block_selection_controllers(app_context, &new_text_view);
+    crate::auto_pairs::add_auto_pairs_controller(app_context, &new_text_view);
+    crate::indentation::add_auto_indent_controller(app_context, &new_text_view);
+    crate::indentation::add_tab_controller(app_context, &new_text_view);
 
     let scrolled_window = ScrolledWindow::builder()
         .hscrollbar_policy(gtk4::PolicyType::Automatic)
@@ -255,7 +331,7 @@ pub fn create_new_file_tab(
 
     // Line numbers area for the new tab
     let line_numbers_area =
-        create_line_numbers_area(&new_text_view, &scrolled_window, current_font_desc.clone());
+        create_line_numbers_area(app_context, &new_text_view, &scrolled_window, current_font_desc.clone());
 
     let text_view_with_line_numbers_box =
         create_text_view_with_line_numbers(&new_text_view, &scrolled_window, &line_numbers_area);
@@ -276,6 +352,12 @@ pub fn create_new_file_tab(
 
     // Connect signals to the new buffer (this will also connect bracket highlighting)
     setup_buffer_connections(&new_buffer, &new_text_view);
+    crate::undo_history::connect_undo_history(app_context, &new_buffer);
+    crate::folding::connect_folding(app_context, &new_text_view);
+    // Hover goes first, so that its tooltip can include the diagnostics
+    crate::lsp::connect_hover(app_context, &new_text_view);
+    crate::outline::connect_outline(app_context, &new_text_view);
+    crate::diagnostics::connect_diagnostics(app_context, &new_text_view);
 
     // Generate a unique name for the new tab
     let mut tab_name = "Untitled-1".to_string();
@@ -309,19 +391,14 @@ pub fn create_new_file_tab(
         notebook.append_page(&text_view_with_line_numbers_box, Some(&tab_label_box));
     notebook.set_current_page(Some(page_num));
 
-    let notebook_clone = notebook.clone();
-    let buffer_paths_clone = buffer_paths.clone();
-    if let Some(window) = app.active_window() {
-        if let Some(app_window) = window.downcast_ref::<ApplicationWindow>() {
-            let window_clone = app_window.clone();
-            close_button.connect_clicked(move |_| {
-                close_tab(&window_clone, &notebook_clone, &buffer_paths_clone, page_num);
-            });
-        }
-    }
+    let app_context_clone = app_context.clone();
+    close_button.connect_clicked(move |_| {
+        close_tab(&app_context_clone, page_num);
+    });
 
     highlight_closure(new_buffer.clone());
     crate::indentation::detect_indent_style(app_context, &new_buffer);
+    crate::indentation::update_tab_stops(app_context, &new_text_view);
 }
 
 /// Checks if a buffer has been modified
@@ -344,18 +421,17 @@ pub fn is_buffer_modified(buffer: &TextBuffer, file_path: Option<&PathBuf>) -> b
 /// Prompts the user to save changes before closing a file
 /// This function uses a callback to handle the response since GTK dialogs are asynchronous
 pub fn prompt_save_changes_async<F>(
-    parent: &impl IsA<gtk4::Window>,
+    app_context: &Rc<RefCell<AppContext>>,
     buffer: gtk4::TextBuffer,
     file_path: Option<PathBuf>,
-    buffer_paths: Rc<RefCell<HashMap<gtk4::TextBuffer, PathBuf>>>,
-    notebook: gtk4::Notebook,
     current_page: u32,
     callback: F,
 ) where
     F: FnOnce(bool) + 'static, // true if we should proceed, false if we should cancel
 {
+    let context = app_context.borrow();
     let dialog = gtk4::MessageDialog::builder()
-        .transient_for(parent)
+        .transient_for(&context.window)
         .modal(true)
         .buttons(gtk4::ButtonsType::None)
         .text("Save changes?")
@@ -366,10 +442,11 @@ pub fn prompt_save_changes_async<F>(
     dialog.add_button("Don't Save", gtk4::ResponseType::No);
     dialog.add_button("Cancel", gtk4::ResponseType::Cancel);
 
-    let parent_clone = parent.clone();
+    let app_context_clone = app_context.clone();
+    let parent_clone = context.window.clone();
     let callback = std::rc::Rc::new(std::cell::RefCell::new(Some(callback)));
-    let buffer_paths_clone = buffer_paths.clone();
-    let notebook_clone = notebook.clone();
+    let buffer_paths_clone = context.buffer_paths.clone();
+    let notebook_clone = context.notebook.clone();
     let buffer_clone = buffer.clone();
     dialog.connect_response(move |dialog, response| {
         // Take the callback out of the RefCell
@@ -379,7 +456,7 @@ pub fn prompt_save_changes_async<F>(
             gtk4::ResponseType::Yes => {
                 // User wants to save
                 if let Some(path) = &file_path {
-                    if let Err(e) = save_buffer_to_file(&parent_clone, &buffer_clone, path) {
+                    if let Err(e) = save_buffer_to_file(&app_context_clone, &buffer_clone, path) {
                         
                         // Show error dialog
                         crate::dialogs::show_error_dialog(
@@ -406,17 +483,7 @@ pub fn prompt_save_changes_async<F>(
                     dialog.close();
 
                     // Show save dialog
-                    let parent_clone2 = parent_clone.clone();
-                    let buffer_clone2 = buffer_clone.clone();
-                    let buffer_paths_clone2 = buffer_paths_clone.clone();
-                    let notebook_clone2 = notebook_clone.clone();
-
-                    crate::file_operations::save_file_dialog(
-                        parent_clone2.clone().into(),
-                        buffer_clone2,
-                        buffer_paths_clone2,
-                        Some(notebook_clone2),
-                    );
+                    crate::file_operations::save_file_dialog(&app_context_clone, buffer_clone.clone());
 
                     // For untitled files, we call the callback immediately since we can't wait
                     // for the save dialog to complete (it's asynchronous)
@@ -458,26 +525,58 @@ pub fn prompt_save_changes_async<F>(
 
 /// Saves the content of a buffer to a file
 ///
-/// This function writes the entire content of a text buffer to a file.
+/// This function writes the entire content of a text buffer to a file. The
+/// whitespace clean-up rules are first applied to the buffer as one undoable
+/// edit, and the EditorConfig line terminators and charset of the file are
+/// applied to the written bytes.
 ///
 /// # Arguments
 ///
-/// * `_parent` - Parent window (unused in current implementation)
+/// * `app_context` - Reference to the application context
 /// * `buffer` - Reference to the text buffer to save
 /// * `file_path` - Path to the file to save to
 ///
 /// # Returns
 ///
 /// Result indicating success or failure
+///
+/// The formatter runs the main loop while the buffer is being saved, so a
+/// second save of the buffer is refused until the first one is done, and so
+/// is closing its tab.
 pub fn save_buffer_to_file(
-    _parent: &impl IsA<gtk4::Window>,
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    file_path: &Path,
+) -> Result<(), std::io::Error> {
+    let saving_buffers = app_context.borrow().saving_buffers.clone();
+    if !saving_buffers.borrow_mut().insert(buffer.clone()) {
+        return Err(std::io::Error::other("the file is already being saved"));
+    }
+    let result = write_buffer_to_file(app_context, buffer, file_path);
+    saving_buffers.borrow_mut().remove(buffer);
+    result
+}
+
+/// Formats, cleans up and writes a buffer to a file, see [`save_buffer_to_file`]
+fn write_buffer_to_file(
+    app_context: &Rc<RefCell<AppContext>>,
     buffer: &TextBuffer,
-    file_path: &PathBuf,
+    file_path: &Path,
 ) -> Result<(), std::io::Error> {
+    let editor_config = crate::editorconfig::config_for_path(file_path);
+    crate::formatter::format_before_save(app_context, buffer, file_path);
+    crate::whitespace::clean_up_on_save(app_context, buffer, file_path, &editor_config);
     let start = buffer.start_iter();
     let end = buffer.end_iter();
-    let content = buffer.text(&start, &end, false).to_string();
-    std::fs::write(file_path, content)
+    let content = crate::editorconfig::normalize_line_endings(&buffer.text(&start, &end, true), &editor_config);
+    crate::editorconfig::write_file(file_path, &content, &editor_config)?;
+    if let Some(history_root) = crate::local_history::history_root() {
+        crate::local_history::record_snapshot(&history_root, file_path, &content);
+    }
+    // Marking the buffer as unmodified also persists its undo history
+    buffer.set_modified(false);
+    crate::lsp::did_save(app_context, buffer, file_path);
+    Ok(())
 }
 
 /// Closes a specific tab
@@ -487,30 +586,28 @@ pub fn save_buffer_to_file(
 ///
 /// # Arguments
 ///
-/// * `window` - Reference to the application window
-/// * `notebook` - Reference to the notebook widget managing tabs
-/// * `buffer_paths` - Map of buffers to their file paths
+/// * `app_context` - Reference to the application context
 /// * `page_num` - Page number of the tab to close
-pub fn close_tab(
-    window: &ApplicationWindow,
-    notebook: &Notebook,
-    buffer_paths: &Rc<RefCell<HashMap<gtk4::TextBuffer, PathBuf>>>,
-    page_num: u32,
-) {
+pub fn close_tab(app_context: &Rc<RefCell<AppContext>>, page_num: u32) {
+    let context = app_context.borrow();
+    let notebook = &context.notebook;
+    let buffer_paths = &context.buffer_paths;
     if let Some(page) = notebook.nth_page(Some(page_num)) {
         if let Some(text_view) = crate::ui::helpers::get_text_view_from_page(&page) {
             let buffer = text_view.buffer();
+            // The tab stays open until its buffer is saved
+            if context.saving_buffers.borrow().contains(&buffer) {
+                return;
+            }
             let buffer_paths_borrowed = buffer_paths.borrow();
             let file_path = buffer_paths_borrowed.get(&buffer).cloned();
 
             if is_buffer_modified(&buffer, file_path.as_ref()) {
                 drop(buffer_paths_borrowed);
                 prompt_save_changes_async(
-                    window,
+                    app_context,
                     buffer,
                     file_path,
-                    buffer_paths.clone(),
-                    notebook.clone(),
                     page_num,
                     |_proceed| {},
                 );
@@ -530,16 +627,11 @@ pub fn close_tab(
 ///
 /// # Arguments
 ///
-/// * `window` - Reference to the application window
-/// * `notebook` - Reference to the notebook widget managing tabs
-/// * `buffer_paths` - Map of buffers to their file paths
-pub fn close_current_tab(
-    window: &ApplicationWindow,
-    notebook: &Notebook,
-    buffer_paths: &Rc<RefCell<HashMap<gtk4::TextBuffer, PathBuf>>>,
-) {
-    if let Some(current_page) = notebook.current_page() {
-        close_tab(window, notebook, buffer_paths, current_page);
+/// * `app_context` - Reference to the application context
+pub fn close_current_tab(app_context: &Rc<RefCell<AppContext>>) {
+    let current_page = app_context.borrow().notebook.current_page();
+    if let Some(current_page) = current_page {
+        close_tab(app_context, current_page);
     }
 }
 
@@ -550,14 +642,15 @@ pub fn close_current_tab(
 ///
 /// # Arguments
 ///
-/// * `window` - Reference to the application window
-/// * `notebook` - Reference to the notebook widget managing tabs
-/// * `buffer_paths` - Map of buffers to their file paths
-pub fn close_all_tabs_with_prompts(
-    window: ApplicationWindow,
-    notebook: Notebook,
-    buffer_paths: Rc<RefCell<HashMap<gtk4::TextBuffer, PathBuf>>>,
-) {
+/// * `app_context` - Reference to the application context
+pub fn close_all_tabs_with_prompts(app_context: &Rc<RefCell<AppContext>>) {
+    let notebook = app_context.borrow().notebook.clone();
+    let buffer_paths = app_context.borrow().buffer_paths.clone();
+    // The tabs stay open until their buffers are saved
+    if !app_context.borrow().saving_buffers.borrow().is_empty() {
+        return;
+    }
+
     // Create a list of all buffers that are actually modified
     let mut buffers_to_check = Vec::new();
 
@@ -590,33 +683,27 @@ pub fn close_all_tabs_with_prompts(
     // We need to handle this asynchronously, so we'll process one buffer at a time
     // Create a recursive function to handle each buffer
     fn process_next_buffer(
-        window: ApplicationWindow,
-        notebook: Notebook,
-        buffer_paths: Rc<RefCell<HashMap<TextBuffer, PathBuf>>>,
+        app_context: Rc<RefCell<AppContext>>,
         mut buffers_to_check: Vec<(TextBuffer, Option<PathBuf>, u32)>,
     ) {
         if let Some((buffer, file_path, page_index)) = buffers_to_check.pop() {
-            let buffer_paths_clone = buffer_paths.clone();
-            let notebook_clone = notebook.clone();
-            let window_clone = window.clone();
-
+            let app_context_clone = app_context.clone();
             prompt_save_changes_async(
-                &window,
+                &app_context,
                 buffer,
                 file_path,
-                buffer_paths_clone,
-                notebook_clone,
-                page_index as u32,
+                page_index,
                 move |proceed| {
                     if proceed {
                         // Continue with the next buffer if there are more
-                        process_next_buffer(window_clone, notebook, buffer_paths, buffers_to_check);
+                        process_next_buffer(app_context_clone, buffers_to_check);
                     }
                     // If not proceed, the user cancelled, so we don't close any more tabs
                 },
             );
         } else {
             // All buffers processed or user cancelled, close all remaining tabs
+            let notebook = app_context.borrow().notebook.clone();
             while notebook.n_pages() > 0 {
                 notebook.remove_page(Some(0));
             }
@@ -624,7 +711,7 @@ pub fn close_all_tabs_with_prompts(
     }
 
     // Start processing the buffers
-    process_next_buffer(window, notebook, buffer_paths, buffers_to_check);
+    process_next_buffer(app_context.clone(), buffers_to_check);
 }
 
 /// Gets the paths of all open files
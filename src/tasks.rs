@@ -0,0 +1,823 @@
+//! Module for tasks
+//!
+//! Tasks are commands run in a project, such as `cargo build` or `make test`.
+//! They are read from the project's `.e4code/tasks.json`, or else detected
+//! from its `Cargo.toml` and the targets of its `Makefile`. A task runs in the
+//! background with its output streamed to the Output page of the bottom panel,
+//! where it can be stopped. Positions such as `src/main.rs:12:5` or Python's
+//! `File "app.py", line 3` become links opening the file there, and once the
+//! task ends, the compiler messages of its output are shown as diagnostics.
+//!
+//! A `tasks.json` lists the tasks by name, with an optional working directory
+//! relative to the project:
+//!
+//! ```json
+//! { "tasks": [ { "name": "test", "command": "cargo test", "cwd": "." } ] }
+//! ```
+
+use gtk4::prelude::*;
+use gtk4::{
+    Align, Box, Button, Dialog, EventControllerMotion, GestureClick, Label, ListBox, Notebook, Orientation,
+    ResponseType, ScrolledWindow, TextTag, TextView, TextWindowType,
+};
+use regex::Regex;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+use crate::diagnostics::{self, Diagnostic};
+use crate::AppContext;
+
+/// Source name of the diagnostics parsed from task output
+const PROBLEM_SOURCE: &str = "task";
+
+/// Path of the task definitions, relative to the project
+const TASKS_FILE: &str = ".e4code/tasks.json";
+
+/// Names of the makefiles whose targets become tasks
+const MAKEFILES: [&str; 3] = ["GNUmakefile", "makefile", "Makefile"];
+
+/// Cargo commands offered as tasks in Rust projects
+const CARGO_COMMANDS: [&str; 5] = ["build", "check", "test", "run", "clippy"];
+
+/// A command to run in a project
+#[derive(Clone, Debug)]
+pub struct Task {
+    /// Name shown in the task list
+    pub name: String,
+    /// Command line, split like a shell would without running one
+    pub command: String,
+    /// Directory the command runs in
+    pub cwd: PathBuf,
+}
+
+/// A task as written in `tasks.json`
+#[derive(Deserialize)]
+struct TaskDefinition {
+    /// Name shown in the task list
+    name: String,
+    /// Command line
+    command: String,
+    /// Working directory, relative to the project
+    #[serde(default)]
+    cwd: Option<PathBuf>,
+}
+
+/// Content of `tasks.json`
+#[derive(Deserialize)]
+struct TasksFile {
+    /// The tasks
+    tasks: Vec<TaskDefinition>,
+}
+
+/// A position in a file written in the output, shown as a link
+struct Link {
+    /// Line of the output holding the link
+    output_line: i32,
+    /// Range of the link in the output line, in characters
+    range: (i32, i32),
+    /// The file
+    path: PathBuf,
+    /// Line of the position, counted from 0
+    line: i32,
+    /// Column of the position in characters, counted from 0
+    column: i32,
+}
+
+/// The running task and the Output page
+pub struct Tasks {
+    /// Process of the running task
+    running: Option<gio::Subprocess>,
+    /// Number of the latest run, so that the output of a stopped run is ignored
+    run_id: u64,
+    /// Last task run, for Run last task
+    last: Option<Task>,
+    /// Links of the current output
+    links: Vec<Link>,
+    /// Whether the paths seen in the current output are files, so that each is checked once
+    existing_files: HashMap<PathBuf, bool>,
+    /// Page of the bottom panel showing the output
+    page: Box,
+    /// Name and state of the task
+    status: Label,
+    /// Button stopping the task
+    stop_button: Button,
+    /// View of the output
+    output: TextView,
+}
+
+/// Shared task state
+pub type TasksState = Rc<RefCell<Tasks>>;
+
+/// Creates the Output page and adds it to the bottom panel
+///
+/// # Arguments
+///
+/// * `bottom_panel` - Notebook holding the pages of the bottom panel
+///
+/// # Returns
+///
+/// The task state
+pub fn create_tasks_panel(bottom_panel: &Notebook) -> TasksState {
+    let status = Label::new(Some("No task has run"));
+    status.set_halign(Align::Start);
+    status.set_hexpand(true);
+    let stop_button = Button::with_label("Stop");
+    stop_button.set_sensitive(false);
+    let header = Box::new(Orientation::Horizontal, 6);
+    header.set_margin_start(4);
+    header.set_margin_end(4);
+    header.append(&status);
+    header.append(&stop_button);
+
+    let output = TextView::builder()
+        .editable(false)
+        .cursor_visible(false)
+        .monospace(true)
+        .build();
+    let buffer = output.buffer();
+    let link_tag = TextTag::new(Some("task_link"));
+    link_tag.set_underline(gtk4::pango::Underline::Single);
+    link_tag.set_foreground_rgba(Some(&gtk4::gdk::RGBA::new(0.2, 0.5, 0.9, 1.0)));
+    buffer.tag_table().add(&link_tag);
+    let command_tag = TextTag::new(Some("task_command"));
+    command_tag.set_weight(700);
+    buffer.tag_table().add(&command_tag);
+    buffer.create_mark(Some("task_end"), &buffer.end_iter(), false);
+
+    let scrolled_window = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Automatic)
+        .vscrollbar_policy(gtk4::PolicyType::Automatic)
+        .vexpand(true)
+        .child(&output)
+        .build();
+    let page = Box::new(Orientation::Vertical, 4);
+    page.append(&header);
+    page.append(&scrolled_window);
+    bottom_panel.append_page(&page, Some(&Label::new(Some("Output"))));
+    Rc::new(RefCell::new(Tasks {
+        running: None,
+        run_id: 0,
+        last: None,
+        links: Vec::new(),
+        existing_files: HashMap::new(),
+        page,
+        status,
+        stop_button,
+        output,
+    }))
+}
+
+/// Connects the Stop button and the links of the output
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn connect_tasks_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let (stop_button, output) = {
+        let context = app_context.borrow();
+        let tasks = context.tasks.borrow();
+        (tasks.stop_button.clone(), tasks.output.clone())
+    };
+
+    let app_context_clone = app_context.clone();
+    stop_button.connect_clicked(move |_| stop_task(&app_context_clone));
+
+    // Clicking a link opens its file at its position
+    let click_gesture = GestureClick::new();
+    let app_context_clone = app_context.clone();
+    let output_clone = output.clone();
+    click_gesture.connect_released(move |_, _, x, y| {
+        let link = link_at(&app_context_clone, &output_clone, x, y);
+        if let Some((path, line, column)) = link {
+            crate::navigation::push_current_location(&app_context_clone);
+            crate::tab_manager::open_file_at_position(&path, line, column, &app_context_clone);
+            if let Some(text_view) = crate::ui::helpers::get_current_text_view(&app_context_clone.borrow().notebook) {
+                crate::go_to_line::flash_line(&text_view, line);
+            }
+        }
+    });
+    output.add_controller(click_gesture);
+
+    // The pointer shows which text is a link
+    let motion_controller = EventControllerMotion::new();
+    let app_context_clone = app_context.clone();
+    let output_clone = output.clone();
+    motion_controller.connect_motion(move |_, x, y| {
+        let cursor = if link_at(&app_context_clone, &output_clone, x, y).is_some() { "pointer" } else { "text" };
+        output_clone.set_cursor_from_name(Some(cursor));
+    });
+    output.add_controller(motion_controller);
+}
+
+/// Returns the target of the link under a point of the output
+fn link_at(app_context: &Rc<RefCell<AppContext>>, output: &TextView, x: f64, y: f64) -> Option<(PathBuf, i32, i32)> {
+    let (buffer_x, buffer_y) = output.window_to_buffer_coords(TextWindowType::Widget, x as i32, y as i32);
+    let iter = output.iter_at_location(buffer_x, buffer_y)?;
+    let (line, offset) = (iter.line(), iter.line_offset());
+    let context = app_context.borrow();
+    let tasks = context.tasks.borrow();
+    tasks
+        .links
+        .iter()
+        .find(|link| link.output_line == line && link.range.0 <= offset && offset < link.range.1)
+        .map(|link| (link.path.clone(), link.line, link.column))
+}
+
+/// Returns the directory the tasks of the current file belong to
+///
+/// This is the nearest directory with a `tasks.json`, else the nearest one
+/// with a `Cargo.toml` or a makefile, starting from the directory of the
+/// current file, or from the open directory for untitled files.
+fn project_root(app_context: &Rc<RefCell<AppContext>>) -> Option<PathBuf> {
+    let context = app_context.borrow();
+    let current_file = crate::ui::helpers::get_current_text_view(&context.notebook)
+        .and_then(|text_view| context.buffer_paths.borrow().get(&text_view.buffer()).cloned());
+    let start = match current_file {
+        Some(file) => file.parent()?.to_path_buf(),
+        None => context.app_settings.borrow().last_opened_directory.clone()?,
+    };
+    let root = start
+        .ancestors()
+        .find(|ancestor| ancestor.join(TASKS_FILE).is_file())
+        .or_else(|| {
+            start.ancestors().find(|ancestor| {
+                ancestor.join("Cargo.toml").is_file() || MAKEFILES.iter().any(|name| ancestor.join(name).is_file())
+            })
+        })
+        .unwrap_or(&start);
+    Some(root.to_path_buf())
+}
+
+/// Rule of a makefile, starting at the beginning of a line
+static RULE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?P<target>[A-Za-z0-9_][A-Za-z0-9_./-]*)\s*::?(?P<rest>.*)$").expect("valid regex")
+});
+
+/// Returns the targets of a makefile, in order
+fn makefile_targets(makefile: &str) -> Vec<String> {
+    let mut targets: Vec<String> = Vec::new();
+    for line in makefile.lines() {
+        // `:=` and `::=` are assignments, while `::` starts a double-colon rule
+        if let Some(captures) = RULE.captures(line)
+            && !captures["rest"].starts_with('=')
+        {
+            let target = captures["target"].to_string();
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    targets
+}
+
+/// Returns the tasks of a project
+///
+/// # Returns
+///
+/// The tasks, or an error message if `tasks.json` can't be read
+fn load_tasks(root: &Path) -> Result<Vec<Task>, String> {
+    let tasks_file = root.join(TASKS_FILE);
+    if tasks_file.is_file() {
+        let content = fs::read_to_string(&tasks_file).map_err(|e| format!("{}: {}", tasks_file.display(), e))?;
+        let definitions: TasksFile =
+            serde_json::from_str(&content).map_err(|e| format!("{}: {}", tasks_file.display(), e))?;
+        return Ok(definitions
+            .tasks
+            .into_iter()
+            .map(|definition| Task {
+                name: definition.name,
+                command: definition.command,
+                cwd: root.join(definition.cwd.unwrap_or_default()),
+            })
+            .collect());
+    }
+
+    let mut tasks = Vec::new();
+    if root.join("Cargo.toml").is_file() {
+        tasks.extend(CARGO_COMMANDS.iter().map(|command| Task {
+            name: format!("cargo {}", command),
+            command: format!("cargo {}", command),
+            cwd: root.to_path_buf(),
+        }));
+    }
+    if let Some(makefile) = MAKEFILES.iter().map(|name| root.join(name)).find(|path| path.is_file()) {
+        let content = fs::read_to_string(&makefile).unwrap_or_default();
+        tasks.extend(makefile_targets(&content).into_iter().map(|target| Task {
+            name: format!("make {}", target),
+            command: format!("make {}", target),
+            cwd: root.to_path_buf(),
+        }));
+    }
+    Ok(tasks)
+}
+
+/// Saves the modified files, so that the task sees their current content
+fn save_modified_files(app_context: &Rc<RefCell<AppContext>>) {
+    let text_views = crate::ui::helpers::get_text_views(&app_context.borrow().notebook);
+    for text_view in text_views {
+        let buffer = text_view.buffer();
+        let path = app_context.borrow().buffer_paths.borrow().get(&buffer).cloned();
+        if let Some(path) = path
+            && buffer.is_modified()
+            && let Err(e) = crate::tab_manager::save_buffer_to_file(app_context, &buffer, &path)
+        {
+            crate::dialogs::show_error_dialog(
+                &app_context.borrow().window,
+                "Error saving file",
+                &format!("Could not save file {}: {}", path.display(), e),
+            );
+        }
+    }
+}
+
+/// Escape sequence colouring terminal output
+static ESCAPE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").expect("valid regex"));
+
+/// Position written as `file:line` or `file:line:column`
+static POSITION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?P<file>[^\s:"'(),=<>\[\]]+):(?P<line>\d+)(?::(?P<column>\d+))?"#).expect("valid regex")
+});
+
+/// Position in a Python traceback frame
+static FRAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"File "(?P<file>[^"]+)", line (?P<line>\d+)"#).expect("valid regex"));
+
+/// Removes the escape sequences that colour terminal output
+fn strip_escapes(line: &str) -> String {
+    ESCAPE.replace_all(line.trim_end_matches('\r'), "").into_owned()
+}
+
+/// Finds the positions in files written in a line of output
+///
+/// # Arguments
+///
+/// * `line` - The line
+/// * `cwd` - Directory relative file names are resolved against
+/// * `existing_files` - Whether the paths already checked are files
+///
+/// # Returns
+///
+/// The character range of each position with the file, line and column, for
+/// files that exist
+fn find_links(
+    line: &str,
+    cwd: &Path,
+    existing_files: &mut HashMap<PathBuf, bool>,
+) -> Vec<((i32, i32), PathBuf, i32, i32)> {
+    FRAME
+        .captures_iter(line)
+        .chain(POSITION.captures_iter(line))
+        .filter_map(|captures| {
+            let whole = captures.get(0)?;
+            let path = cwd.join(&captures["file"]);
+            let is_file = *existing_files.entry(path.clone()).or_insert_with_key(|path| path.is_file());
+            if !is_file {
+                return None;
+            }
+            let number = |name: &str| {
+                captures
+                    .name(name)
+                    .and_then(|number| number.as_str().parse::<i32>().ok())
+                    .map_or(0, |number| (number - 1).max(0))
+            };
+            let start = line[..whole.start()].chars().count() as i32;
+            let end = start + whole.as_str().chars().count() as i32;
+            Some(((start, end), path, number("line"), number("column")))
+        })
+        .collect()
+}
+
+/// Appends a line to the output, turning the positions it contains into links
+///
+/// The output follows the new lines, unless it has been scrolled up.
+fn append_line(app_context: &Rc<RefCell<AppContext>>, run_id: u64, cwd: &Path, line: &str, tag: Option<&str>) {
+    let tasks_state = app_context.borrow().tasks.clone();
+    let mut tasks = tasks_state.borrow_mut();
+    if tasks.run_id != run_id {
+        return;
+    }
+    let output = tasks.output.clone();
+    let buffer = output.buffer();
+    let at_bottom = output
+        .vadjustment()
+        .is_none_or(|adjustment| adjustment.value() + adjustment.page_size() >= adjustment.upper() - 1.0);
+
+    let output_line = buffer.end_iter().line();
+    let mut end = buffer.end_iter();
+    match tag {
+        Some(tag) => buffer.insert_with_tags_by_name(&mut end, &format!("{}\n", line), &[tag]),
+        None => buffer.insert(&mut end, &format!("{}\n", line)),
+    }
+    let links = find_links(line, cwd, &mut tasks.existing_files);
+    for (range, path, line, column) in links {
+        // Positions found by both patterns are linked once
+        if tasks.links.iter().any(|link| link.output_line == output_line && link.range.0 < range.1 && range.0 < link.range.1) {
+            continue;
+        }
+        if let (Some(start), Some(end)) = (
+            buffer.iter_at_line_offset(output_line, range.0),
+            buffer.iter_at_line_offset(output_line, range.1),
+        ) {
+            buffer.apply_tag_by_name("task_link", &start, &end);
+        }
+        tasks.links.push(Link {
+            output_line,
+            range,
+            path,
+            line,
+            column,
+        });
+    }
+
+    if at_bottom && let Some(mark) = buffer.mark("task_end") {
+        buffer.move_mark(&mark, &buffer.end_iter());
+        output.scroll_mark_onscreen(&mark);
+    }
+}
+
+/// Shows the diagnostics found in the output of a task, by file
+fn report_diagnostics(app_context: &Rc<RefCell<AppContext>>, output: &str, cwd: &Path) {
+    let mut by_file: HashMap<PathBuf, Vec<Diagnostic>> = HashMap::new();
+    for diagnostic in diagnostics::parse_compiler_output(output, Some(cwd), None, PROBLEM_SOURCE) {
+        if let Some(path) = diagnostic.path.clone().filter(|path| path.is_file()) {
+            by_file.entry(path).or_default().push(diagnostic);
+        }
+    }
+    for (path, file_diagnostics) in by_file {
+        diagnostics::set_diagnostics(app_context, PROBLEM_SOURCE, Some(&path), file_diagnostics, false);
+    }
+}
+
+/// Shows the Output page of the bottom panel
+fn show_output(app_context: &Rc<RefCell<AppContext>>) {
+    let context = app_context.borrow();
+    let page_num = context.bottom_panel.page_num(&context.tasks.borrow().page);
+    context.bottom_panel.set_current_page(page_num);
+    context.bottom_panel.set_visible(true);
+}
+
+/// Shows or hides the bottom panel on the Output page
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn toggle_output_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let showing_output = {
+        let context = app_context.borrow();
+        let page_num = context.bottom_panel.page_num(&context.tasks.borrow().page);
+        context.bottom_panel.is_visible() && context.bottom_panel.current_page() == page_num
+    };
+    if showing_output {
+        app_context.borrow().bottom_panel.set_visible(false);
+    } else {
+        show_output(app_context);
+    }
+}
+
+/// Starts a process for a task, with its error output merged into its output
+///
+/// On Unix, the process leads a new process group, so that stopping the task
+/// also stops the processes it started.
+fn spawn(task: &Task) -> Result<gio::Subprocess, String> {
+    let argv = glib::shell_parse_argv(task.command.as_str())
+        .map_err(|e| format!("Invalid command \"{}\": {}", task.command, e))?;
+    if argv.is_empty() {
+        return Err("The task has no command".to_string());
+    }
+    let launcher = gio::SubprocessLauncher::new(gio::SubprocessFlags::STDOUT_PIPE | gio::SubprocessFlags::STDERR_MERGE);
+    launcher.set_cwd(&task.cwd);
+    #[cfg(unix)]
+    launcher.set_child_setup(|| {
+        // SAFETY: setpgid is async-signal-safe, as required between fork and exec
+        unsafe {
+            libc::setpgid(0, 0);
+        }
+    });
+    let argv: Vec<&std::ffi::OsStr> = argv.iter().map(OsString::as_os_str).collect();
+    launcher.spawn(&argv).map_err(|e| format!("Failed to run \"{}\": {}", task.command, e))
+}
+
+/// Runs a task, stopping the one running
+///
+/// The modified files are saved first. The diagnostics of the previous run
+/// are removed.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `task` - The task
+pub fn run_task(app_context: &Rc<RefCell<AppContext>>, task: Task) {
+    save_modified_files(app_context);
+    stop_task(app_context);
+    diagnostics::clear_diagnostics(app_context, PROBLEM_SOURCE);
+
+    let tasks_state = app_context.borrow().tasks.clone();
+    let run_id = {
+        let mut tasks = tasks_state.borrow_mut();
+        tasks.run_id += 1;
+        tasks.last = Some(task.clone());
+        tasks.links.clear();
+        tasks.existing_files.clear();
+        tasks.output.buffer().set_text("");
+        tasks.status.set_text(&format!("{}: running", task.name));
+        tasks.run_id
+    };
+    show_output(app_context);
+    append_line(app_context, run_id, &task.cwd, &format!("$ {}", task.command), Some("task_command"));
+
+    let subprocess = match spawn(&task) {
+        Ok(subprocess) => subprocess,
+        Err(message) => {
+            append_line(app_context, run_id, &task.cwd, &message, None);
+            tasks_state.borrow().status.set_text(&format!("{}: failed to start", task.name));
+            return;
+        }
+    };
+    {
+        let mut tasks = tasks_state.borrow_mut();
+        tasks.running = Some(subprocess.clone());
+        tasks.stop_button.set_sensitive(true);
+    }
+
+    let stdout = gio::DataInputStream::new(&subprocess.stdout_pipe().expect("stdout is piped"));
+    let app_context = app_context.clone();
+    glib::MainContext::default().spawn_local(async move {
+        let started = Instant::now();
+        let mut output = String::new();
+        while let Ok(Some(line)) = stdout.read_line_future(glib::Priority::DEFAULT).await {
+            let line = strip_escapes(&String::from_utf8_lossy(&line));
+            append_line(&app_context, run_id, &task.cwd, &line, None);
+            output.push_str(&line);
+            output.push('\n');
+        }
+        let _ = subprocess.wait_future().await;
+
+        let tasks_state = app_context.borrow().tasks.clone();
+        if tasks_state.borrow().run_id != run_id {
+            return;
+        }
+        let outcome = if !subprocess.has_exited() {
+            "stopped".to_string()
+        } else if subprocess.is_successful() {
+            "succeeded".to_string()
+        } else {
+            format!("failed with exit status {}", subprocess.exit_status())
+        };
+        let summary = format!("{}: {} in {:.1} s", task.name, outcome, started.elapsed().as_secs_f64());
+        append_line(&app_context, run_id, &task.cwd, &format!("[{}]", summary), Some("task_command"));
+        {
+            let mut tasks = tasks_state.borrow_mut();
+            tasks.running = None;
+            tasks.stop_button.set_sensitive(false);
+            tasks.status.set_text(&summary);
+        }
+        report_diagnostics(&app_context, &output, &task.cwd);
+    });
+}
+
+/// Kills the process of a task and the processes of its group
+#[cfg(unix)]
+fn kill(subprocess: &gio::Subprocess) {
+    // The identifier is gone once the process has exited
+    let Some(pid) = subprocess.identifier().and_then(|pid| pid.parse::<libc::pid_t>().ok()) else {
+        return;
+    };
+    // SAFETY: kill doesn't access the memory of this process
+    if unsafe { libc::kill(-pid, libc::SIGKILL) } != 0 {
+        subprocess.force_exit();
+    }
+}
+
+/// Kills the process of a task
+#[cfg(not(unix))]
+fn kill(subprocess: &gio::Subprocess) {
+    subprocess.force_exit();
+}
+
+/// Stops the running task, if any
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn stop_task(app_context: &Rc<RefCell<AppContext>>) {
+    let running = app_context.borrow().tasks.borrow_mut().running.take();
+    if let Some(subprocess) = running {
+        kill(&subprocess);
+    }
+    app_context.borrow().tasks.borrow().stop_button.set_sensitive(false);
+}
+
+/// Runs the last task again
+///
+/// The task list is shown instead if no task has run yet.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn run_last_task(app_context: &Rc<RefCell<AppContext>>) {
+    let last = app_context.borrow().tasks.borrow().last.clone();
+    match last {
+        Some(task) => run_task(app_context, task),
+        None => show_task_list(app_context),
+    }
+}
+
+/// Lists the tasks of the current project and runs the one chosen
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn show_task_list(app_context: &Rc<RefCell<AppContext>>) {
+    let window = app_context.borrow().window.clone();
+    let Some(root) = project_root(app_context) else {
+        crate::dialogs::show_error_dialog(&window, "No tasks", "Open a file or a directory to run its tasks.");
+        return;
+    };
+    let tasks = match load_tasks(&root) {
+        Ok(tasks) if tasks.is_empty() => {
+            crate::dialogs::show_error_dialog(
+                &window,
+                "No tasks",
+                &format!(
+                    "{} has no {}, Cargo.toml or Makefile to take tasks from.",
+                    root.display(),
+                    TASKS_FILE
+                ),
+            );
+            return;
+        }
+        Ok(tasks) => tasks,
+        Err(message) => {
+            crate::dialogs::show_error_dialog(&window, "Invalid tasks file", &message);
+            return;
+        }
+    };
+
+    let dialog = Dialog::builder()
+        .title(format!("Run Task in {}", root.display()))
+        .transient_for(&window)
+        .modal(true)
+        .default_width(400)
+        .build();
+    dialog.add_button("Cancel", ResponseType::Cancel);
+    dialog.add_button("Run", ResponseType::Ok);
+    dialog.set_default_response(ResponseType::Ok);
+
+    let list = ListBox::new();
+    list.set_selection_mode(gtk4::SelectionMode::Single);
+    for task in &tasks {
+        let row = Box::new(Orientation::Horizontal, 6);
+        row.set_margin_start(4);
+        row.set_margin_end(4);
+        let name_label = Label::new(Some(&task.name));
+        name_label.set_halign(Align::Start);
+        name_label.set_hexpand(true);
+        row.append(&name_label);
+        if task.command != task.name {
+            let command_label = Label::new(Some(&task.command));
+            command_label.add_css_class("dim-label");
+            row.append(&command_label);
+        }
+        list.append(&row);
+    }
+    list.select_row(list.row_at_index(0).as_ref());
+    let scrolled_window = ScrolledWindow::builder()
+        .hscrollbar_policy(gtk4::PolicyType::Never)
+        .vscrollbar_policy(gtk4::PolicyType::Automatic)
+        .min_content_height(200)
+        .vexpand(true)
+        .child(&list)
+        .build();
+    scrolled_window.set_margin_top(10);
+    scrolled_window.set_margin_bottom(10);
+    scrolled_window.set_margin_start(10);
+    scrolled_window.set_margin_end(10);
+    dialog.content_area().append(&scrolled_window);
+
+    let dialog_clone = dialog.clone();
+    list.connect_row_activated(move |_, _| dialog_clone.response(ResponseType::Ok));
+    let app_context_clone = app_context.clone();
+    let list_clone = list.clone();
+    dialog.connect_response(move |dialog, response| {
+        let task = list_clone
+            .selected_row()
+            .and_then(|row| tasks.get(row.index() as usize).cloned());
+        dialog.close();
+        if response == ResponseType::Ok
+            && let Some(task) = task
+        {
+            run_task(&app_context_clone, task);
+        }
+    });
+    dialog.present();
+    list.grab_focus();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    /// Returns the name, command and directory of tasks
+    fn summary(tasks: &[Task]) -> Vec<(&str, &str, &Path)> {
+        tasks
+            .iter()
+            .map(|task| (task.name.as_str(), task.command.as_str(), task.cwd.as_path()))
+            .collect()
+    }
+
+    #[test]
+    fn makefile_targets_skip_assignments_and_duplicates() {
+        let makefile = "\
+CC := gcc
+FLAGS ::= -O2
+PREFIX ?= /usr
+.PHONY: all test
+all: build
+build: main.o
+\tgcc -o main main.o
+main.o:main.c
+test:
+docs:: index
+docs:: api
+build: extra
+%.o: %.c
+";
+        assert_eq!(makefile_targets(makefile), vec!["all", "build", "main.o", "test", "docs"]);
+        assert!(makefile_targets("").is_empty());
+    }
+
+    #[test]
+    fn links_are_found_with_both_patterns() {
+        let cwd = temp_dir("tasks-links");
+        fs::create_dir(cwd.join("src")).expect("the directory can be created");
+        fs::write(cwd.join("src/main.rs"), "").expect("the file can be written");
+        fs::write(cwd.join("app.py"), "").expect("the file can be written");
+
+        let line = "→ src/main.rs:2:5: error, see File \"app.py\", line 3 and gone.rs:1";
+        let mut existing_files = HashMap::new();
+        let links = find_links(line, &cwd, &mut existing_files);
+        assert_eq!(
+            links,
+            vec![
+                ((30, 51), cwd.join("app.py"), 2, 0),
+                ((2, 17), cwd.join("src/main.rs"), 1, 4),
+            ]
+        );
+        // Missing files make no link, and each path is checked once
+        assert_eq!(existing_files.get(&cwd.join("gone.rs")), Some(&false));
+        existing_files.insert(cwd.join("src/main.rs"), false);
+        let links = find_links(line, &cwd, &mut existing_files);
+        assert_eq!(links, vec![((30, 51), cwd.join("app.py"), 2, 0)]);
+        fs::remove_dir_all(&cwd).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn tasks_are_read_from_tasks_json() {
+        let root = temp_dir("tasks-json");
+        fs::create_dir(root.join(".e4code")).expect("the directory can be created");
+        fs::write(root.join("Cargo.toml"), "").expect("the file can be written");
+        fs::write(
+            root.join(TASKS_FILE),
+            r#"{ "tasks": [ { "name": "test", "command": "cargo test", "cwd": "sub" }, { "name": "lint", "command": "make lint" } ] }"#,
+        )
+        .expect("the file can be written");
+        let tasks = load_tasks(&root).expect("valid tasks");
+        let sub = root.join("sub");
+        assert_eq!(
+            summary(&tasks),
+            vec![("test", "cargo test", sub.as_path()), ("lint", "make lint", root.as_path())]
+        );
+
+        fs::write(root.join(TASKS_FILE), "{ \"tasks\": [ { \"name\": \"test\" } ] }").expect("the file can be written");
+        let message = load_tasks(&root).expect_err("a task without a command");
+        assert!(message.starts_with(&root.join(TASKS_FILE).display().to_string()), "{}", message);
+        fs::remove_dir_all(&root).expect("the directory can be removed");
+    }
+
+    #[test]
+    fn tasks_are_detected_from_cargo_and_makefiles() {
+        let root = temp_dir("tasks-detected");
+        assert!(load_tasks(&root).expect("no tasks").is_empty());
+
+        fs::write(root.join("Cargo.toml"), "").expect("the file can be written");
+        fs::write(root.join("Makefile"), "install:\n").expect("the file can be written");
+        // GNU make reads `GNUmakefile` before `Makefile`
+        fs::write(root.join("GNUmakefile"), "all:\ncheck:\n").expect("the file can be written");
+        let tasks = load_tasks(&root).expect("valid tasks");
+        let names: Vec<&str> = tasks.iter().map(|task| task.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["cargo build", "cargo check", "cargo test", "cargo run", "cargo clippy", "make all", "make check"]
+        );
+        assert!(tasks.iter().all(|task| task.cwd == root && task.command == task.name));
+        fs::remove_dir_all(&root).expect("the directory can be removed");
+    }
+}
@@ -0,0 +1,140 @@
+//! Helpers shared by the tests
+//!
+//! GTK and the default main context belong to the thread that initialized
+//! them, while each test runs on a thread of its own. Tests creating widgets
+//! or running the main loop are therefore sent to a single thread kept for
+//! them, which initializes GTK if a display is available. Tests that need GTK
+//! fail without it, and are marked `#[ignore = "needs a display"]` so that
+//! they run with `cargo test -- --ignored` where a display is available.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Longest time a test waits for the main loop
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A test sent to the main thread
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Queue of the main thread
+static MAIN_THREAD: OnceLock<Mutex<Sender<Job>>> = OnceLock::new();
+
+/// Whether GTK could be initialized on the main thread
+static GTK_AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+/// Starts the main thread
+fn start_main_thread() -> Mutex<Sender<Job>> {
+    let (sender, receiver) = mpsc::channel::<Job>();
+    let (ready_sender, ready_receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let gtk = gtk4::init().is_ok();
+        if !gtk {
+            // Without GTK, the main loop is still needed by the gio tasks
+            std::mem::forget(glib::MainContext::default().acquire().expect("the default main context is free"));
+        }
+        let _ = GTK_AVAILABLE.set(gtk);
+        let _ = ready_sender.send(());
+        for job in receiver {
+            job();
+        }
+    });
+    let _ = ready_receiver.recv();
+    Mutex::new(sender)
+}
+
+/// Runs a test on the thread owning GTK and the default main context
+///
+/// A panic of the test is raised again on the calling thread, so that the
+/// test fails as usual.
+///
+/// # Arguments
+///
+/// * `test` - The test
+///
+/// # Returns
+///
+/// The value returned by the test
+pub fn run_on_main_thread<T: Send + 'static>(test: impl FnOnce() -> T + Send + 'static) -> T {
+    let (result_sender, result_receiver) = mpsc::channel();
+    let job: Job = Box::new(move || {
+        let _ = result_sender.send(panic::catch_unwind(AssertUnwindSafe(test)));
+    });
+    MAIN_THREAD
+        .get_or_init(start_main_thread)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .send(job)
+        .expect("the main thread is running");
+    match result_receiver.recv().expect("the main thread answers") {
+        Ok(value) => value,
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+/// Runs a test creating GTK widgets or buffers on the main thread
+///
+/// The test fails if GTK could not be initialized, which requires a display.
+///
+/// # Arguments
+///
+/// * `test` - The test
+///
+/// # Returns
+///
+/// The value returned by the test
+pub fn run_with_gtk<T: Send + 'static>(test: impl FnOnce() -> T + Send + 'static) -> T {
+    run_on_main_thread(move || {
+        assert!(
+            GTK_AVAILABLE.get().copied().unwrap_or(false),
+            "GTK could not be initialized, which requires a display"
+        );
+        test()
+    })
+}
+
+/// Runs the main loop until a condition holds
+///
+/// # Arguments
+///
+/// * `condition` - The condition
+///
+/// # Returns
+///
+/// Whether the condition holds, which is false if it still didn't after a few seconds
+pub fn run_until(condition: impl Fn() -> bool) -> bool {
+    let main_context = glib::MainContext::default();
+    let deadline = Instant::now() + TIMEOUT;
+    while !condition() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        if !main_context.iteration(false) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+    true
+}
+
+/// Returns the path of a file of `tests/fixtures`
+///
+/// # Arguments
+///
+/// * `name` - Name of the file
+pub fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures").join(name)
+}
+
+/// Returns a new, empty directory for a test in the system's temporary directory
+///
+/// # Arguments
+///
+/// * `name` - Name of the directory, unique to the test
+pub fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("e4code-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&path);
+    std::fs::create_dir_all(&path).expect("the temporary directory can be created");
+    path
+}
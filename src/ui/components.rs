@@ -5,23 +5,64 @@
 
 use gtk4::pango;
 use gtk4::prelude::*;
-use gtk4::{DrawingArea, Orientation, ScrolledWindow, TextView};
+use gtk4::{DrawingArea, GestureClick, Orientation, ScrolledWindow, TextView};
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use crate::AppContext;
+
 // Constants for line numbers
 /// Width of the line numbers area in pixels
 pub const LINE_NUMBER_WIDTH: i32 = 50;
 /// Padding around line numbers in pixels
 pub const LINE_NUMBER_PADDING: f64 = 5.0;
+/// Width of the fold toggles column, left of the line numbers, in pixels
+pub const FOLD_TOGGLE_WIDTH: f64 = 14.0;
+/// Width of the diagnostic marks column, left of the fold toggles, in pixels
+pub const DIAGNOSTIC_MARK_WIDTH: f64 = 10.0;
+
+/// Draws a fold toggle centered vertically on `center_y`
+///
+/// Folded regions get a triangle pointing right, unfolded ones a triangle
+/// pointing down.
+fn draw_fold_toggle(cr: &gtk4::cairo::Context, center_y: f64, folded: bool) {
+    let center_x = DIAGNOSTIC_MARK_WIDTH + FOLD_TOGGLE_WIDTH / 2.0;
+    cr.save().expect("Failed to save the cairo state");
+    cr.set_source_rgb(0.45, 0.45, 0.45);
+    if folded {
+        cr.move_to(center_x - 2.5, center_y - 4.0);
+        cr.line_to(center_x + 2.5, center_y);
+        cr.line_to(center_x - 2.5, center_y + 4.0);
+    } else {
+        cr.move_to(center_x - 4.0, center_y - 2.5);
+        cr.line_to(center_x + 4.0, center_y - 2.5);
+        cr.line_to(center_x, center_y + 2.5);
+    }
+    cr.close_path();
+    cr.fill().expect("Failed to draw fold toggle");
+    cr.restore().expect("Failed to restore the cairo state");
+}
+
+/// Draws a diagnostic mark, a dot coloured by severity, centered vertically on `center_y`
+fn draw_diagnostic_mark(cr: &gtk4::cairo::Context, center_y: f64, severity: crate::problems::Severity) {
+    let color = crate::diagnostics::severity_color(severity);
+    cr.save().expect("Failed to save the cairo state");
+    cr.set_source_rgba(color.red() as f64, color.green() as f64, color.blue() as f64, color.alpha() as f64);
+    cr.arc(DIAGNOSTIC_MARK_WIDTH / 2.0, center_y, 3.5, 0.0, 2.0 * std::f64::consts::PI);
+    cr.fill().expect("Failed to draw diagnostic mark");
+    cr.restore().expect("Failed to restore the cairo state");
+}
 
 /// Creates a line numbers area widget for a text view
 ///
 /// This function creates a drawing area that displays line numbers alongside
-/// a text view, automatically updating as the text content changes.
+/// a text view, automatically updating as the text content changes. Lines
+/// starting a foldable region get a toggle, and clicking the toggle folds or
+/// unfolds the region. Lines with diagnostics get a mark coloured by severity.
 ///
 /// # Arguments
 ///
+/// * `app_context` - Reference to the application context
 /// * `text_view` - The text view to display line numbers for
 /// * `scrolled_window` - The scrolled window containing the text view
 /// * `current_font_desc` - Reference to the current font description
@@ -30,6 +71,7 @@ pub const LINE_NUMBER_PADDING: f64 = 5.0;
 ///
 /// A drawing area widget that displays line numbers
 pub fn create_line_numbers_area(
+    app_context: &Rc<RefCell<AppContext>>,
     text_view: &TextView,
     scrolled_window: &ScrolledWindow,
     current_font_desc: Rc<RefCell<pango::FontDescription>>,
@@ -44,6 +86,7 @@ pub fn create_line_numbers_area(
         let scrolled_window_clone = scrolled_window.clone();
         let current_font_desc_clone = current_font_desc.clone();
         let line_numbers_area_clone_for_closure = line_numbers_area.clone();
+        let app_context_clone = app_context.clone();
 
         move |_, cr, width, height| {
             let text_view = text_view_clone.clone();
@@ -66,7 +109,8 @@ pub fn create_line_numbers_area(
             let extents = cr
                 .text_extents(&test_string)
                 .expect("Failed to get text extents");
-            let required_width = extents.width() + LINE_NUMBER_PADDING * 2.0;
+            let required_width =
+                DIAGNOSTIC_MARK_WIDTH + FOLD_TOGGLE_WIDTH + extents.width() + LINE_NUMBER_PADDING * 2.0;
 
             // Update the width_request of the DrawingArea
             if (line_numbers_area_clone_for_closure.width_request() as f64 - required_width).abs()
@@ -76,44 +120,61 @@ pub fn create_line_numbers_area(
             }
 
             let scroll_y = vadjustment.value();
-            let allocation_height = text_view.allocation().height() as f64;
-
-            // More accurate line height calculation using Pango
-            let pango_context = text_view.pango_context();
-            let font_metrics = pango_context.metrics(Some(&font_desc), None);
-            let line_height =
-                (font_metrics.ascent() + font_metrics.descent()) as f64 / pango::SCALE as f64;
-
-            // Calculate visible lines range
-            let start_line = (scroll_y / line_height).floor() as i32;
-            let end_line = ((scroll_y + allocation_height) / line_height).ceil() as i32 + 1;
-
-            // Ensure we don't go out of bounds
-            let start_line = start_line.max(0);
-            let end_line = end_line.min(buffer.line_count().max(1));
-
-            // Draw line numbers for visible lines
-            for line_num in start_line..end_line {
-                if let Some(iter) = buffer.iter_at_line(line_num) {
-                    let (line_y_start, _) = text_view.line_yrange(&iter);
-                    let display_y = line_y_start as f64 - scroll_y;
-
-                    // Only draw if the line is visible
-                    if display_y + line_height >= 0.0 && display_y <= height as f64 {
-                        let line_number = line_num + 1;
-                        let text = format!("{}", line_number);
-                        let extents = cr.text_extents(&text).expect("Failed to get text extents");
-                        let x = width as f64 - extents.width() - LINE_NUMBER_PADDING;
-                        let y = display_y + (line_height / 2.0) + (extents.height() / 2.0);
-
-                        cr.move_to(x, y);
-                        cr.show_text(&text).expect("Failed to draw text");
+            let fold_markers = crate::folding::fold_markers(&app_context_clone, &buffer);
+            let line_severities = crate::diagnostics::line_severities(&app_context_clone, &buffer);
+
+            // Draw line numbers, fold toggles and diagnostic marks for the visible lines, skipping folded ones
+            let (mut iter, _) = text_view.line_at_y(scroll_y as i32);
+            loop {
+                let (line_y_start, line_height) = text_view.line_yrange(&iter);
+                let display_y = line_y_start as f64 - scroll_y;
+                if display_y > height as f64 {
+                    break;
+                }
+
+                if line_height > 0 {
+                    let line_height = line_height as f64;
+                    let text = format!("{}", iter.line() + 1);
+                    let extents = cr.text_extents(&text).expect("Failed to get text extents");
+                    let x = width as f64 - extents.width() - LINE_NUMBER_PADDING;
+                    let y = display_y + (line_height / 2.0) + (extents.height() / 2.0);
+
+                    cr.move_to(x, y);
+                    cr.show_text(&text).expect("Failed to draw text");
+
+                    if let Some(&folded) = fold_markers.get(&iter.line()) {
+                        draw_fold_toggle(cr, display_y + line_height / 2.0, folded);
+                    }
+                    if let Some(&severity) = line_severities.get(&iter.line()) {
+                        draw_diagnostic_mark(cr, display_y + line_height / 2.0, severity);
                     }
                 }
+
+                if !iter.forward_line() {
+                    break;
+                }
             }
         }
     });
 
+    // Clicking the toggle of a region folds or unfolds it
+    let click_gesture = GestureClick::new();
+    let app_context_clone = app_context.clone();
+    let text_view_clone = text_view.clone();
+    let scrolled_window_clone = scrolled_window.clone();
+    click_gesture.connect_pressed(move |_, _, x, y| {
+        if !(DIAGNOSTIC_MARK_WIDTH..DIAGNOSTIC_MARK_WIDTH + FOLD_TOGGLE_WIDTH).contains(&x) {
+            return;
+        }
+        let buffer_y = y + scrolled_window_clone.vadjustment().value();
+        let (iter, _) = text_view_clone.line_at_y(buffer_y as i32);
+        let buffer = text_view_clone.buffer();
+        if crate::folding::fold_markers(&app_context_clone, &buffer).contains_key(&iter.line()) {
+            crate::folding::toggle_fold(&app_context_clone, &text_view_clone, iter.line());
+        }
+    });
+    line_numbers_area.add_controller(click_gesture);
+
     line_numbers_area
 }
 
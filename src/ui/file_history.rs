@@ -0,0 +1,288 @@
+//! File history UI module
+//!
+//! This module provides the History page of the bottom panel, which lists the
+//! local snapshots of a file, shows the difference between a snapshot and the
+//! current text and restores snapshots into the buffer.
+
+use gtk4::prelude::*;
+use gtk4::{
+    Box, Button, Label, ListBox, Notebook, Orientation, Paned, ScrolledWindow, TextBuffer, TextTag, TextView,
+};
+use similar::{ChangeTag, TextDiff};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::AppContext;
+use crate::local_history::{self, Snapshot};
+
+/// The listed snapshots and the widgets of the History page
+pub struct FileHistory {
+    /// File whose snapshots are listed, with the buffer showing it
+    file: Option<(PathBuf, TextBuffer)>,
+    /// Snapshots in display order, newest first
+    snapshots: Vec<Snapshot>,
+    /// Content of the selected snapshot
+    selected_content: Option<String>,
+    /// List showing one row per snapshot
+    list: ListBox,
+    /// View of the difference between the selected snapshot and the current text
+    diff_view: TextView,
+    /// Button restoring the selected snapshot
+    restore_button: Button,
+    /// Page of the bottom panel
+    page: Paned,
+    /// Label of the page, naming the file
+    title: Label,
+}
+
+/// Shared History panel state
+pub type FileHistoryState = Rc<RefCell<FileHistory>>;
+
+/// Formats a snapshot timestamp in local time
+fn format_timestamp(timestamp: i64) -> String {
+    glib::DateTime::from_unix_local(timestamp)
+        .and_then(|date_time| date_time.format("%Y-%m-%d %H:%M:%S"))
+        .map_or_else(|_| timestamp.to_string(), |formatted| formatted.to_string())
+}
+
+/// Adds the tags used to color the diff to a buffer
+fn setup_diff_tags(buffer: &TextBuffer) {
+    let tag_table = buffer.tag_table();
+
+    let added_tag = TextTag::new(Some("diff_added"));
+    added_tag.set_foreground(Some("#2e9e44"));
+    tag_table.add(&added_tag);
+
+    let removed_tag = TextTag::new(Some("diff_removed"));
+    removed_tag.set_foreground(Some("#d03b3b"));
+    tag_table.add(&removed_tag);
+
+    let header_tag = TextTag::new(Some("diff_header"));
+    header_tag.set_foreground(Some("#3b7bd0"));
+    header_tag.set_weight(700);
+    tag_table.add(&header_tag);
+}
+
+/// Shows the unified diff from `old` to `new` in a buffer
+fn show_diff(diff_buffer: &TextBuffer, old: &str, new: &str) {
+    diff_buffer.set_text("");
+    let diff = TextDiff::from_lines(old, new);
+    let mut unified_diff = diff.unified_diff();
+    let hunks = unified_diff.context_radius(3).iter_hunks();
+
+    let mut is_empty = true;
+    for hunk in hunks {
+        is_empty = false;
+        diff_buffer.insert_with_tags_by_name(&mut diff_buffer.end_iter(), &format!("{}\n", hunk.header()), &["diff_header"]);
+        for change in hunk.iter_changes() {
+            let (sign, tags): (&str, &[&str]) = match change.tag() {
+                ChangeTag::Insert => ("+", &["diff_added"]),
+                ChangeTag::Delete => ("-", &["diff_removed"]),
+                ChangeTag::Equal => (" ", &[]),
+            };
+            let mut line = format!("{}{}", sign, change.value());
+            if change.missing_newline() {
+                line.push('\n');
+            }
+            diff_buffer.insert_with_tags_by_name(&mut diff_buffer.end_iter(), &line, tags);
+        }
+    }
+    if is_empty {
+        diff_buffer.set_text("The snapshot is identical to the current text.");
+    }
+}
+
+/// Removes the rows of the snapshot list
+///
+/// Removing the selected row runs the selection handler, which borrows the
+/// panel state, so the state must not be borrowed meanwhile.
+fn remove_rows(list: &ListBox) {
+    while let Some(row) = list.row_at_index(0) {
+        list.remove(&row);
+    }
+}
+
+/// Replaces the content of a buffer with a snapshot, as a single undoable step
+fn restore_snapshot(buffer: &TextBuffer, content: &str) {
+    buffer.begin_user_action();
+    buffer.delete(&mut buffer.start_iter(), &mut buffer.end_iter());
+    buffer.insert(&mut buffer.start_iter(), content);
+    buffer.end_user_action();
+    buffer.place_cursor(&buffer.start_iter());
+}
+
+/// Creates the History page and adds it to the bottom panel
+///
+/// # Arguments
+///
+/// * `bottom_panel` - Notebook holding the pages of the bottom panel
+///
+/// # Returns
+///
+/// The History panel state
+pub fn create_file_history_panel(bottom_panel: &Notebook) -> FileHistoryState {
+    let list = ListBox::new();
+    let list_scrolled_window = ScrolledWindow::builder()
+        .child(&list)
+        .min_content_width(200)
+        .build();
+
+    let diff_view = TextView::builder()
+        .editable(false)
+        .monospace(true)
+        .hexpand(true)
+        .vexpand(true)
+        .build();
+    setup_diff_tags(&diff_view.buffer());
+    let diff_scrolled_window = ScrolledWindow::builder().child(&diff_view).build();
+
+    let restore_button = Button::with_label("Restore");
+    restore_button.set_sensitive(false);
+    restore_button.set_halign(gtk4::Align::End);
+    restore_button.set_margin_top(4);
+    restore_button.set_margin_bottom(4);
+    restore_button.set_margin_end(4);
+    let diff_box = Box::new(Orientation::Vertical, 0);
+    diff_box.append(&diff_scrolled_window);
+    diff_box.append(&restore_button);
+
+    let page = Paned::new(Orientation::Horizontal);
+    page.set_start_child(Some(&list_scrolled_window));
+    page.set_end_child(Some(&diff_box));
+    page.set_position(220);
+    let title = Label::new(Some("History"));
+    bottom_panel.append_page(&page, Some(&title));
+    Rc::new(RefCell::new(FileHistory {
+        file: None,
+        snapshots: Vec::new(),
+        selected_content: None,
+        list,
+        diff_view,
+        restore_button,
+        page,
+        title,
+    }))
+}
+
+/// Shows the selected snapshot and restores it with the Restore button
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+pub fn connect_file_history_panel(app_context: &Rc<RefCell<AppContext>>) {
+    let file_history = app_context.borrow().file_history.clone();
+    let (list, restore_button) = {
+        let state = file_history.borrow();
+        (state.list.clone(), state.restore_button.clone())
+    };
+
+    let file_history_selected = file_history.clone();
+    list.connect_row_selected(move |_, row| {
+        let mut state = file_history_selected.borrow_mut();
+        let Some((path, buffer)) = state.file.clone() else {
+            return;
+        };
+        let content = row
+            .and_then(|row| state.snapshots.get(row.index() as usize))
+            .zip(local_history::history_root())
+            .and_then(|(snapshot, root)| local_history::load_snapshot(&root, &path, snapshot));
+        let diff_buffer = state.diff_view.buffer();
+        match &content {
+            Some(content) => {
+                let current_text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string();
+                show_diff(&diff_buffer, content, &current_text);
+            }
+            None if row.is_none() => diff_buffer.set_text(""),
+            None => diff_buffer.set_text("The snapshot could not be read."),
+        }
+        state.restore_button.set_sensitive(content.is_some());
+        state.selected_content = content;
+    });
+
+    restore_button.connect_clicked(move |_| {
+        let state = file_history.borrow();
+        if let (Some((_, buffer)), Some(content)) = (&state.file, &state.selected_content) {
+            let buffer = buffer.clone();
+            let content = content.clone();
+            drop(state);
+            restore_snapshot(&buffer, &content);
+        }
+    });
+}
+
+/// Lists the snapshots of the file of a text view in the History page and shows it
+///
+/// Selecting a snapshot shows the changes from the snapshot to the current
+/// text; the "Restore" button replaces the buffer content with the snapshot.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `text_view` - The text view showing the file
+pub fn show_file_history(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    let context = app_context.borrow();
+    let buffer = text_view.buffer();
+    let Some(path) = context.buffer_paths.borrow().get(&buffer).cloned() else {
+        crate::dialogs::show_error_dialog(
+            &context.window,
+            "No file history",
+            "The current buffer has not been saved to a file yet.",
+        );
+        return;
+    };
+
+    let snapshots = local_history::history_root()
+        .map(|root| local_history::list_snapshots(&root, &path))
+        .unwrap_or_default();
+    let file_name = path.file_name().and_then(|s| s.to_str()).unwrap_or("Untitled");
+    let list = context.file_history.borrow().list.clone();
+    remove_rows(&list);
+    let mut state = context.file_history.borrow_mut();
+    state.title.set_text(&format!("History: {}", file_name));
+    state.file = Some((path, buffer));
+    state.selected_content = None;
+    state.restore_button.set_sensitive(false);
+    for snapshot in &snapshots {
+        let label = Label::new(Some(&format_timestamp(snapshot.timestamp)));
+        label.set_xalign(0.0);
+        label.set_margin_top(4);
+        label.set_margin_bottom(4);
+        label.set_margin_start(8);
+        label.set_margin_end(8);
+        state.list.append(&label);
+    }
+    let text = if snapshots.is_empty() {
+        "No snapshots yet. A snapshot is stored every time the file is saved."
+    } else {
+        "Select a snapshot to see how it differs from the current text."
+    };
+    state.diff_view.buffer().set_text(text);
+    state.snapshots = snapshots;
+
+    let page_num = context.bottom_panel.page_num(&state.page);
+    context.bottom_panel.set_current_page(page_num);
+    context.bottom_panel.set_visible(true);
+}
+
+/// Empties the History page if it lists the file of a closed buffer
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer whose tab was closed
+pub fn forget_buffer(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let file_history = app_context.borrow().file_history.clone();
+    if file_history.borrow().file.as_ref().is_none_or(|(_, file_buffer)| file_buffer != buffer) {
+        return;
+    }
+    let list = file_history.borrow().list.clone();
+    remove_rows(&list);
+    let mut state = file_history.borrow_mut();
+    state.file = None;
+    state.snapshots.clear();
+    state.selected_content = None;
+    state.restore_button.set_sensitive(false);
+    state.title.set_text("History");
+    state.diff_view.buffer().set_text("");
+}
@@ -4,7 +4,7 @@
 //! such as extracting text views from notebook pages.
 
 use gtk4::prelude::*;
-use gtk4::{Notebook, ScrolledWindow, TextView};
+use gtk4::{DrawingArea, Notebook, ScrolledWindow, TextView};
 
 /// Helper function to get the TextView from a given Notebook page widget.
 /// This encapsulates the common pattern of traversing the widget hierarchy.
@@ -51,3 +51,51 @@ pub fn get_current_text_view(notebook: &Notebook) -> Option<TextView> {
     }
     None
 }
+
+/// Helper function to get the TextViews of all pages of a Notebook.
+///
+/// # Arguments
+///
+/// * `notebook` - Reference to the notebook widget
+///
+/// # Returns
+///
+/// The text views, in page order
+pub fn get_text_views(notebook: &Notebook) -> Vec<TextView> {
+    (0..notebook.n_pages())
+        .filter_map(|i| notebook.nth_page(Some(i)))
+        .filter_map(|page| get_text_view_from_page(&page))
+        .collect()
+}
+
+/// Helper function to get the line numbers area shown next to a TextView.
+///
+/// # Arguments
+///
+/// * `text_view` - Reference to the text view
+///
+/// # Returns
+///
+/// Optional reference to the line numbers area if found
+pub fn get_line_numbers_area(text_view: &TextView) -> Option<DrawingArea> {
+    // The text view sits in a ScrolledWindow, after the line numbers area in the page's Box
+    text_view
+        .parent()
+        .and_then(|scrolled_window| scrolled_window.parent())
+        .and_then(|page| page.first_child())
+        .and_then(|w| w.downcast::<DrawingArea>().ok())
+}
+
+/// Returns the width in pixels of one character of a text view's font
+///
+/// # Arguments
+///
+/// * `text_view` - Reference to the text view
+///
+/// # Returns
+///
+/// The average width of a wide character, at least one pixel
+pub fn char_width(text_view: &TextView) -> f64 {
+    let (width, _) = text_view.create_pango_layout(Some("MMMMMMMMMM")).pixel_size();
+    (width as f64 / 10.0).max(1.0)
+}
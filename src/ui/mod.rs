@@ -4,6 +4,7 @@
 //! used in the application.
 
 pub mod components;
+pub mod file_history;
 pub mod helpers;
 pub mod search_dialog;
 pub mod windows;
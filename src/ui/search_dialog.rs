@@ -15,7 +15,7 @@ pub const RESPONSE_TYPE_REPLACE_ALL: ResponseType = ResponseType::Other(1);
 ///
 /// This function creates a dialog window with controls for searching and
 /// replacing text, including options for case sensitivity, whole word matching,
-/// and regular expressions.
+/// regular expressions and escape sequences (`\n`, `\t`) for multi-line searches.
 ///
 /// # Arguments
 ///
@@ -37,6 +37,7 @@ pub fn create_search_replace_dialog(
     CheckButton,
     CheckButton,
     CheckButton,
+    CheckButton,
     Label,
 ) {
     let dialog = Dialog::builder()
@@ -79,9 +80,14 @@ pub fn create_search_replace_dialog(
     let match_case_cb = CheckButton::with_label("Match case");
     let whole_word_cb = CheckButton::with_label("Whole word");
     let regex_cb = CheckButton::with_label("Regex");
+    let escapes_cb = CheckButton::with_label("Escape sequences (\\n, \\t)");
+    escapes_cb.set_tooltip_text(Some(
+        "Interpret \\n, \\t and \\\\ in the search and replace text, allowing multi-line searches",
+    ));
     options_hbox.append(&match_case_cb);
     options_hbox.append(&whole_word_cb);
     options_hbox.append(&regex_cb);
+    options_hbox.append(&escapes_cb);
     vbox.append(&options_hbox);
 
     // Status label for search results and errors
@@ -98,6 +104,7 @@ pub fn create_search_replace_dialog(
         &match_case_cb,
         &whole_word_cb,
         &regex_cb,
+        &escapes_cb,
         &status_label,
     );
 
@@ -108,6 +115,7 @@ pub fn create_search_replace_dialog(
         match_case_cb,
         whole_word_cb,
         regex_cb,
+        escapes_cb,
         status_label,
     )
 }
@@ -119,6 +127,7 @@ pub fn connect_search_events(
     match_case_cb: &CheckButton,
     whole_word_cb: &CheckButton,
     regex_cb: &CheckButton,
+    escapes_cb: &CheckButton,
     status_label: &Label,
 ) {
     let buffer_clone = buffer.clone();
@@ -126,13 +135,20 @@ pub fn connect_search_events(
     let match_case_cb_clone = match_case_cb.clone();
     let whole_word_cb_clone = whole_word_cb.clone();
     let regex_cb_clone = regex_cb.clone();
+    let escapes_cb_clone = escapes_cb.clone();
     let status_label_clone = status_label.clone();
 
     let update_count = Rc::new(RefCell::new(move || {
-        let search_text = search_entry_clone.text().to_string();
         let match_case = match_case_cb_clone.is_active();
         let whole_word = whole_word_cb_clone.is_active();
         let use_regex = regex_cb_clone.is_active();
+        let search_text = search_entry_clone.text().to_string();
+        // Regex patterns handle their own escapes
+        let search_text = if escapes_cb_clone.is_active() && !use_regex {
+            search::unescape_search_text(&search_text)
+        } else {
+            search_text
+        };
 
         let count = search::count_all_occurrences(
             &buffer_clone,
@@ -171,4 +187,9 @@ pub fn connect_search_events(
     regex_cb.connect_toggled(move |_e| {
         update_count_clone_4.borrow()();
     });
+
+    let update_count_clone_5 = update_count.clone();
+    escapes_cb.connect_toggled(move |_e| {
+        update_count_clone_5.borrow()();
+    });
 }
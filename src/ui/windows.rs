@@ -4,17 +4,22 @@
 //! settings and about dialogs.
 
 use gtk4::prelude::*;
-use gtk4::{AboutDialog, Box, ComboBoxText, Dialog, FontButton, Label, Orientation, ResponseType};
+use gtk4::{
+    AboutDialog, Box, CheckButton, ComboBoxText, Dialog, FontButton, Label, Orientation, ResponseType,
+    SpinButton,
+};
 
 /// Creates a settings dialog
 ///
 /// This function creates a dialog window for configuring application settings
-/// such as theme and font preferences.
+/// such as theme, indentation and font preferences.
 ///
 /// # Arguments
 ///
 /// * `parent` - Parent window for the dialog
 /// * `current_theme` - Current theme setting ("light" or "dark")
+/// * `current_tab_width` - Current tab width setting, in columns
+/// * `current_insert_spaces` - Current insert-spaces setting
 /// * `current_font` - Current font setting in Pango format
 ///
 /// # Returns
@@ -23,6 +28,8 @@ use gtk4::{AboutDialog, Box, ComboBoxText, Dialog, FontButton, Label, Orientatio
 pub fn create_settings_dialog(
     parent: &impl IsA<gtk4::Window>,
     current_theme: &str,
+    current_tab_width: usize,
+    current_insert_spaces: bool,
     current_font: &str,
 ) -> Dialog {
     let dialog = Dialog::builder()
@@ -51,6 +58,18 @@ pub fn create_settings_dialog(
     theme_hbox.append(&theme_combo);
     vbox.append(&theme_hbox);
 
+    let tab_width_hbox = Box::new(Orientation::Horizontal, 10);
+    let tab_width_label = Label::new(Some("Tab width:"));
+    let tab_width_spin = SpinButton::with_range(1.0, 16.0, 1.0);
+    tab_width_spin.set_value(current_tab_width as f64);
+    tab_width_hbox.append(&tab_width_label);
+    tab_width_hbox.append(&tab_width_spin);
+    vbox.append(&tab_width_hbox);
+
+    let insert_spaces_check = CheckButton::with_label("Insert spaces instead of tabs");
+    insert_spaces_check.set_active(current_insert_spaces);
+    vbox.append(&insert_spaces_check);
+
     let font_hbox = Box::new(Orientation::Horizontal, 10);
     let font_label = Label::new(Some("Font:"));
     let font_button = FontButton::builder().font(current_font).build();
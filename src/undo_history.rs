@@ -0,0 +1,686 @@
+//! Module for the undo history of buffers
+//!
+//! GTK's built-in undo stack cannot be inspected or rebuilt, so e4code keeps
+//! its own history of edit operations for each buffer. Operations made within
+//! one user action form a single undo step, and consecutive typed characters of
+//! a word are merged into one step.
+//!
+//! The history is persisted to the cache directory whenever the buffer is saved
+//! or its tab is closed, together with a hash of the file content it applies to.
+//! When the file is reopened with the same content, the history is restored so
+//! undo keeps working across restarts.
+//!
+//! Steps of several buffers made by one operation, such as renaming a symbol
+//! across files, are linked: undoing or redoing one of them undoes or redoes
+//! them all, along with the edits the operation wrote to files on disk.
+
+use gtk4::prelude::*;
+use gtk4::{TextBuffer, TextView};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::AppContext;
+
+/// Maximum number of undo steps kept for a buffer
+const MAX_UNDO_STEPS: usize = 1000;
+/// Maximum size in bytes of the persisted history of one file
+const MAX_HISTORY_FILE_SIZE: usize = 1024 * 1024;
+/// Maximum total size in bytes of the persisted histories
+const MAX_CACHE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// A single edit operation, with offsets in characters
+#[derive(Serialize, Deserialize, Clone, Debug)]
+enum EditOperation {
+    /// `text` was inserted at `offset`
+    Insert { offset: i32, text: String },
+    /// `text` was deleted from `offset`
+    Delete { offset: i32, text: String },
+    /// The step belongs to the operation `id`, which also edited `files`
+    Link { id: u64, files: Vec<FileEdit> },
+}
+
+/// A file on disk edited along with buffers
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileEdit {
+    /// The file
+    pub path: PathBuf,
+    /// Content of the file before the edit
+    pub original: String,
+    /// Content of the file after the edit
+    pub edited: String,
+}
+
+/// Operations undone or redone together
+type EditGroup = Vec<EditOperation>;
+
+/// Undo history of a buffer
+#[derive(Default)]
+pub struct UndoHistory {
+    undo_stack: Vec<EditGroup>,
+    redo_stack: Vec<EditGroup>,
+    /// Operations of the user action in progress
+    current_group: Option<EditGroup>,
+    /// Set while undoing or redoing, so that the edits are not recorded
+    applying: bool,
+    /// File the history belongs to
+    path: Option<PathBuf>,
+}
+
+/// Map of buffers to their undo history
+pub type UndoHistories = Rc<RefCell<HashMap<TextBuffer, UndoHistory>>>;
+
+/// History as stored in the cache directory
+#[derive(Serialize, Deserialize)]
+struct PersistedHistory {
+    path: PathBuf,
+    content_hash: String,
+    undo_stack: Vec<EditGroup>,
+    redo_stack: Vec<EditGroup>,
+}
+
+/// Returns the undo history map of the application context
+fn undo_histories(app_context: &Rc<RefCell<AppContext>>) -> UndoHistories {
+    app_context.borrow().undo_histories.clone()
+}
+
+/// Returns the number of characters of a string as a buffer offset
+fn char_count(text: &str) -> i32 {
+    text.chars().count() as i32
+}
+
+/// Returns the SHA-256 hash of a text as a hex string
+fn content_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+/// Returns the whole text of a buffer
+fn buffer_text(buffer: &TextBuffer) -> String {
+    buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string()
+}
+
+/// Returns the directory holding the persisted histories, creating it if needed
+fn history_cache_dir() -> Option<PathBuf> {
+    let mut path = dirs::cache_dir()?;
+    path.push("e4code");
+    path.push("undo");
+    fs::create_dir_all(&path).ok()?;
+    Some(path)
+}
+
+/// Returns the cache file holding the persisted history of a file
+///
+/// # Arguments
+///
+/// * `cache_dir` - Directory holding the persisted histories
+/// * `path` - The file
+fn history_cache_file(cache_dir: &Path, path: &Path) -> PathBuf {
+    cache_dir.join(format!("{}.json", content_hash(&path.to_string_lossy())))
+}
+
+impl UndoHistory {
+    /// Records an operation, either in the current user action or as its own step
+    fn record(&mut self, operation: EditOperation) {
+        if self.applying {
+            return;
+        }
+        match self.current_group.as_mut() {
+            Some(group) => group.push(operation),
+            None => self.push_group(vec![operation]),
+        }
+    }
+
+    /// Pushes a finished group on the undo stack, merging typed words
+    fn push_group(&mut self, group: EditGroup) {
+        if group.is_empty() {
+            return;
+        }
+        self.redo_stack.clear();
+
+        // A single typed character continues the word being typed, which is a
+        // step of its own: pastes and other multi-operation steps are never extended
+        if let [EditOperation::Insert { offset, text }] = group.as_slice() {
+            let is_word_char = text.chars().count() == 1 && !text.chars().all(char::is_whitespace);
+            if let Some([EditOperation::Insert { offset: last_offset, text: last_text }]) =
+                self.undo_stack.last_mut().map(Vec::as_mut_slice)
+            {
+                let continues = *last_offset + char_count(last_text) == *offset;
+                let after_word = last_text.chars().last().is_some_and(|c| !c.is_whitespace());
+                if is_word_char && continues && after_word {
+                    last_text.push_str(text);
+                    return;
+                }
+            }
+        }
+
+        self.undo_stack.push(group);
+        if self.undo_stack.len() > MAX_UNDO_STEPS {
+            self.undo_stack.remove(0);
+        }
+    }
+}
+
+/// Applies a group of operations, backwards and inverted when undoing
+///
+/// Returns the offset where the cursor should be placed.
+fn apply_group(buffer: &TextBuffer, group: &EditGroup, undo: bool) -> i32 {
+    let mut cursor = 0;
+    buffer.begin_user_action();
+    let operations: Box<dyn Iterator<Item = &EditOperation>> = if undo {
+        Box::new(group.iter().rev())
+    } else {
+        Box::new(group.iter())
+    };
+    for operation in operations {
+        let (insert, offset, text) = match operation {
+            EditOperation::Insert { offset, text } => (!undo, *offset, text),
+            EditOperation::Delete { offset, text } => (undo, *offset, text),
+            EditOperation::Link { .. } => continue,
+        };
+        let mut start = buffer.iter_at_offset(offset);
+        if insert {
+            buffer.insert(&mut start, text);
+            cursor = offset + char_count(text);
+        } else {
+            let mut end = buffer.iter_at_offset(offset + char_count(text));
+            buffer.delete(&mut start, &mut end);
+            cursor = offset;
+        }
+    }
+    buffer.end_user_action();
+    cursor
+}
+
+/// Undoes or redoes the last step of a buffer
+///
+/// # Returns
+///
+/// The step and the offset where the cursor should be placed, or `None` if
+/// there is no step to undo or redo
+fn apply_step(undo_histories: &UndoHistories, buffer: &TextBuffer, undo: bool) -> Option<(EditGroup, i32)> {
+    let group = {
+        let mut undo_histories_borrowed = undo_histories.borrow_mut();
+        let history = undo_histories_borrowed.get_mut(buffer)?;
+        let stack = if undo { &mut history.undo_stack } else { &mut history.redo_stack };
+        let group = stack.pop()?;
+        history.applying = true;
+        group
+    };
+
+    // The buffer signals re-enter this module, so no borrow is held while editing
+    let cursor = apply_group(buffer, &group, undo);
+
+    if let Some(history) = undo_histories.borrow_mut().get_mut(buffer) {
+        history.applying = false;
+        if undo {
+            history.redo_stack.push(group.clone());
+        } else {
+            history.undo_stack.push(group.clone());
+        }
+    }
+    Some((group, cursor))
+}
+
+/// Returns the identifier of the operation a step is linked to
+fn link_id(group: &EditGroup) -> Option<u64> {
+    group.iter().find_map(|operation| match operation {
+        EditOperation::Link { id, .. } => Some(*id),
+        _ => None,
+    })
+}
+
+/// Restores the files on disk edited by linked steps
+///
+/// A file that changed since is left alone. Files are read and written with
+/// their EditorConfig charset.
+fn apply_file_edits(groups: &[EditGroup], undo: bool) {
+    for operation in groups.iter().flatten() {
+        let EditOperation::Link { files, .. } = operation else {
+            continue;
+        };
+        for file in files {
+            let (from, to) = if undo {
+                (&file.edited, &file.original)
+            } else {
+                (&file.original, &file.edited)
+            };
+            let config = crate::editorconfig::config_for_path(&file.path);
+            if crate::editorconfig::read_file(&file.path, &config).is_ok_and(|content| &content == from) {
+                let _ = crate::editorconfig::write_file(&file.path, to, &config);
+            }
+        }
+    }
+}
+
+/// Undoes or redoes the last step of the text view's buffer
+fn undo_or_redo(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView, undo: bool) {
+    let buffer = text_view.buffer();
+    let undo_histories = undo_histories(app_context);
+    let Some((group, cursor)) = apply_step(&undo_histories, &buffer, undo) else {
+        return;
+    };
+
+    if let Some(id) = link_id(&group) {
+        let linked: Vec<TextBuffer> = undo_histories
+            .borrow()
+            .iter()
+            .filter(|(other, history)| {
+                let stack = if undo { &history.undo_stack } else { &history.redo_stack };
+                *other != &buffer && stack.last().and_then(link_id) == Some(id)
+            })
+            .map(|(other, _)| other.clone())
+            .collect();
+        let mut groups = vec![group];
+        groups.extend(
+            linked
+                .iter()
+                .filter_map(|other| apply_step(&undo_histories, other, undo))
+                .map(|(group, _)| group),
+        );
+        apply_file_edits(&groups, undo);
+    }
+
+    buffer.place_cursor(&buffer.iter_at_offset(cursor));
+    text_view.scroll_mark_onscreen(&buffer.get_insert());
+    crate::actions::update_undo_redo_actions(app_context);
+}
+
+/// Undoes the last step of the text view's buffer
+pub fn undo(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    undo_or_redo(app_context, text_view, true);
+}
+
+/// Redoes the last undone step of the text view's buffer
+pub fn redo(app_context: &Rc<RefCell<AppContext>>, text_view: &TextView) {
+    undo_or_redo(app_context, text_view, false);
+}
+
+/// Returns whether undo and redo are available for a buffer
+pub fn undo_redo_availability(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) -> (bool, bool) {
+    undo_histories(app_context)
+        .borrow()
+        .get(buffer)
+        .map_or((false, false), |history| {
+            (!history.undo_stack.is_empty(), !history.redo_stack.is_empty())
+        })
+}
+
+/// Returns a new identifier for linking the steps of an operation
+pub fn new_link_id() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+/// Links the step of the user action in progress in a buffer to an operation
+///
+/// This must be called after the edits of the user action, before it ends.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer being edited
+/// * `id` - Identifier of the operation, from `new_link_id`
+/// * `files` - Files on disk edited by the operation, given with one step only
+pub fn link_current_step(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer, id: u64, files: Vec<FileEdit>) {
+    if let Some(history) = undo_histories(app_context).borrow_mut().get_mut(buffer)
+        && let Some(group) = history.current_group.as_mut()
+    {
+        group.push(EditOperation::Link { id, files });
+    }
+}
+
+/// Clears the undo history of a buffer, e.g. after reloading it from disk
+pub fn clear_history(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    if let Some(history) = undo_histories(app_context).borrow_mut().get_mut(buffer) {
+        history.undo_stack.clear();
+        history.redo_stack.clear();
+        history.current_group = None;
+    }
+    crate::actions::update_undo_redo_actions(app_context);
+}
+
+/// Writes the history of a buffer to the cache directory
+///
+/// The oldest steps are dropped if the history exceeds the size cap, and the
+/// cache is garbage collected afterwards.
+///
+/// # Arguments
+///
+/// * `history` - The history
+/// * `cache_dir` - Directory holding the persisted histories
+/// * `path` - File the history belongs to
+/// * `content` - Content of the file the history applies to
+fn persist_history(history: &UndoHistory, cache_dir: &Path, path: &Path, content: &str) {
+    let cache_file = history_cache_file(cache_dir, path);
+    let mut persisted = PersistedHistory {
+        path: path.to_path_buf(),
+        content_hash: content_hash(content),
+        undo_stack: history.undo_stack.clone(),
+        redo_stack: history.redo_stack.clone(),
+    };
+
+    let json = loop {
+        let Ok(json) = serde_json::to_string(&persisted) else {
+            return;
+        };
+        if json.len() <= MAX_HISTORY_FILE_SIZE {
+            break json;
+        }
+        if !persisted.redo_stack.is_empty() {
+            persisted.redo_stack.clear();
+        } else if !persisted.undo_stack.is_empty() {
+            let excess = persisted.undo_stack.len().div_ceil(4);
+            persisted.undo_stack.drain(..excess);
+        } else {
+            return;
+        }
+    };
+
+    if persisted.undo_stack.is_empty() && persisted.redo_stack.is_empty() {
+        fs::remove_file(&cache_file).ok();
+    } else {
+        fs::write(&cache_file, json).ok();
+    }
+    collect_garbage(cache_dir, MAX_CACHE_SIZE);
+}
+
+/// Removes the least recently written histories until the cache fits a size cap
+///
+/// # Arguments
+///
+/// * `cache_dir` - Directory holding the persisted histories
+/// * `max_size` - Maximum total size in bytes of the histories kept
+fn collect_garbage(cache_dir: &Path, max_size: u64) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return;
+    };
+
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+        })
+        .collect();
+    // Newest first, so that the oldest histories are removed
+    files.sort_by_key(|file| std::cmp::Reverse(file.2));
+
+    let mut total_size = 0;
+    for (path, size, _) in files {
+        total_size += size;
+        if total_size > max_size {
+            fs::remove_file(path).ok();
+        }
+    }
+}
+
+/// Restores the persisted history of a file if it matches the buffer content
+///
+/// # Arguments
+///
+/// * `history` - The history to fill
+/// * `cache_dir` - Directory holding the persisted histories
+/// * `path` - The file
+/// * `content` - Content of the buffer
+fn restore_history(history: &mut UndoHistory, cache_dir: &Path, path: &Path, content: &str) {
+    let Some(persisted) = fs::read_to_string(history_cache_file(cache_dir, path))
+        .ok()
+        .and_then(|json| serde_json::from_str::<PersistedHistory>(&json).ok())
+    else {
+        return;
+    };
+    if persisted.path == path && persisted.content_hash == content_hash(content) {
+        history.undo_stack = persisted.undo_stack;
+        history.redo_stack = persisted.redo_stack;
+    }
+}
+
+/// Starts recording the undo history of a buffer
+///
+/// GTK's own undo stack is disabled. If the buffer belongs to a file with a
+/// persisted history matching its content, that history is restored. The
+/// history is persisted whenever the buffer is marked as unmodified, which the
+/// save functions do after writing the file.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to record
+pub fn connect_undo_history(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    buffer.set_enable_undo(false);
+
+    let undo_histories = undo_histories(app_context);
+    let buffer_paths = app_context.borrow().buffer_paths.clone();
+    let mut history = UndoHistory::default();
+    if let Some(path) = buffer_paths.borrow().get(buffer) {
+        if let Some(cache_dir) = history_cache_dir() {
+            restore_history(&mut history, &cache_dir, path, &buffer_text(buffer));
+        }
+        history.path = Some(path.clone());
+    }
+    undo_histories.borrow_mut().insert(buffer.clone(), history);
+
+    let undo_histories_insert = undo_histories.clone();
+    buffer.connect_insert_text(move |buffer, location, text| {
+        if let Some(history) = undo_histories_insert.borrow_mut().get_mut(buffer) {
+            history.record(EditOperation::Insert {
+                offset: location.offset(),
+                text: text.to_string(),
+            });
+        }
+    });
+
+    let undo_histories_delete = undo_histories.clone();
+    buffer.connect_delete_range(move |buffer, start, end| {
+        if let Some(history) = undo_histories_delete.borrow_mut().get_mut(buffer) {
+            history.record(EditOperation::Delete {
+                offset: start.offset(),
+                text: buffer.text(start, end, true).to_string(),
+            });
+        }
+    });
+
+    let undo_histories_begin = undo_histories.clone();
+    buffer.connect_begin_user_action(move |buffer| {
+        if let Some(history) = undo_histories_begin.borrow_mut().get_mut(buffer)
+            && !history.applying
+        {
+            history.current_group = Some(Vec::new());
+        }
+    });
+
+    let undo_histories_end = undo_histories.clone();
+    buffer.connect_end_user_action(move |buffer| {
+        if let Some(history) = undo_histories_end.borrow_mut().get_mut(buffer)
+            && let Some(group) = history.current_group.take()
+        {
+            history.push_group(group);
+        }
+    });
+
+    // Refresh the undo and redo actions after every change
+    let app_context_changed = app_context.clone();
+    buffer.connect_changed(move |_| {
+        crate::actions::update_undo_redo_actions(&app_context_changed);
+    });
+
+    let undo_histories_saved = undo_histories.clone();
+    buffer.connect_modified_changed(move |buffer| {
+        if buffer.is_modified() {
+            return;
+        }
+        let path = buffer_paths.borrow().get(buffer).cloned();
+        if let (Some(path), Some(history)) = (path, undo_histories_saved.borrow_mut().get_mut(buffer)) {
+            if let Some(cache_dir) = history_cache_dir() {
+                persist_history(history, &cache_dir, &path, &buffer_text(buffer));
+            }
+            history.path = Some(path);
+        }
+    });
+}
+
+/// Stops recording the history of a closed buffer and persists it
+///
+/// The history is only persisted if the buffer matches the file on disk, since
+/// otherwise it could not be applied when the file is reopened.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer whose tab was closed
+pub fn forget_history(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer) {
+    let Some(history) = undo_histories(app_context).borrow_mut().remove(buffer) else {
+        return;
+    };
+    if let Some(path) = &history.path {
+        let content = buffer_text(buffer);
+        if fs::read_to_string(path).is_ok_and(|content_on_disk| content_on_disk == content)
+            && let Some(cache_dir) = history_cache_dir()
+        {
+            persist_history(&history, &cache_dir, path, &content);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(offset: i32, text: &str) -> EditGroup {
+        vec![EditOperation::Insert { offset, text: text.to_string() }]
+    }
+
+    #[test]
+    fn typed_characters_merge_into_a_word() {
+        let mut history = UndoHistory::default();
+        history.push_group(insert(0, "a"));
+        history.push_group(insert(1, "b"));
+        history.push_group(insert(2, "c"));
+        assert_eq!(history.undo_stack.len(), 1);
+        assert!(matches!(history.undo_stack[0].as_slice(), [EditOperation::Insert { text, .. }] if text == "abc"));
+    }
+
+    #[test]
+    fn typing_after_a_multi_operation_step_starts_a_new_step() {
+        let mut history = UndoHistory::default();
+        // A replace: the selection is deleted, then the pasted text inserted
+        history.push_group(vec![
+            EditOperation::Delete { offset: 0, text: "old".to_string() },
+            EditOperation::Insert { offset: 0, text: "pasted".to_string() },
+        ]);
+        history.push_group(insert(6, "x"));
+        assert_eq!(history.undo_stack.len(), 2);
+        assert_eq!(history.undo_stack[0].len(), 2);
+    }
+
+    #[test]
+    fn typing_after_whitespace_starts_a_new_step() {
+        let mut history = UndoHistory::default();
+        history.push_group(insert(0, "a"));
+        history.push_group(insert(1, " "));
+        history.push_group(insert(2, "b"));
+        assert_eq!(history.undo_stack.len(), 3);
+    }
+
+    /// Returns the text inserted by each step of a history
+    fn inserted_texts(steps: &[EditGroup]) -> Vec<String> {
+        steps
+            .iter()
+            .flatten()
+            .filter_map(|operation| match operation {
+                EditOperation::Insert { text, .. } => Some(text.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn history_round_trips_through_the_cache() {
+        let cache_dir = crate::test_support::temp_dir("undo-round-trip");
+        let path = Path::new("/project/main.rs");
+        let mut history = UndoHistory::default();
+        history.push_group(insert(0, "fn"));
+        history.push_group(insert(2, " "));
+        history.redo_stack.push(insert(3, "main"));
+        persist_history(&history, &cache_dir, path, "fn ");
+
+        let mut restored = UndoHistory::default();
+        restore_history(&mut restored, &cache_dir, path, "fn ");
+        assert_eq!(inserted_texts(&restored.undo_stack), vec!["fn", " "]);
+        assert_eq!(inserted_texts(&restored.redo_stack), vec!["main"]);
+    }
+
+    #[test]
+    fn history_of_other_content_is_not_restored() {
+        let cache_dir = crate::test_support::temp_dir("undo-hash-mismatch");
+        let path = Path::new("/project/main.rs");
+        let mut history = UndoHistory::default();
+        history.push_group(insert(0, "fn"));
+        persist_history(&history, &cache_dir, path, "fn");
+
+        let mut restored = UndoHistory::default();
+        restore_history(&mut restored, &cache_dir, path, "fn changed on disk");
+        assert!(restored.undo_stack.is_empty());
+        restore_history(&mut restored, &cache_dir, Path::new("/project/other.rs"), "fn");
+        assert!(restored.undo_stack.is_empty());
+    }
+
+    #[test]
+    fn oversized_history_drops_redo_steps_then_the_oldest_steps() {
+        let cache_dir = crate::test_support::temp_dir("undo-size-cap");
+        let path = Path::new("/project/big.txt");
+        let step = "x".repeat(10 * 1024);
+        let mut history = UndoHistory::default();
+        for index in 0..200 {
+            history.push_group(insert(index, &format!("{} {}", index, step)));
+        }
+        history.redo_stack.push(insert(0, "redo"));
+        persist_history(&history, &cache_dir, path, "content");
+
+        let cache_file = history_cache_file(&cache_dir, path);
+        assert!(fs::metadata(&cache_file).unwrap().len() as usize <= MAX_HISTORY_FILE_SIZE);
+        let mut restored = UndoHistory::default();
+        restore_history(&mut restored, &cache_dir, path, "content");
+        assert!(restored.redo_stack.is_empty());
+        let texts = inserted_texts(&restored.undo_stack);
+        assert!(!texts.is_empty() && texts.len() < 200);
+        assert!(texts.last().unwrap().starts_with("199 "));
+    }
+
+    #[test]
+    fn empty_history_removes_its_cache_file() {
+        let cache_dir = crate::test_support::temp_dir("undo-empty");
+        let path = Path::new("/project/main.rs");
+        let mut history = UndoHistory::default();
+        history.push_group(insert(0, "a"));
+        persist_history(&history, &cache_dir, path, "a");
+        assert!(history_cache_file(&cache_dir, path).exists());
+
+        persist_history(&UndoHistory::default(), &cache_dir, path, "");
+        assert!(!history_cache_file(&cache_dir, path).exists());
+    }
+
+    #[test]
+    fn garbage_collection_removes_the_oldest_histories() {
+        let cache_dir = crate::test_support::temp_dir("undo-garbage");
+        let now = std::time::SystemTime::now();
+        for (age, name) in [(3, "oldest.json"), (2, "older.json"), (1, "newest.json")] {
+            let file = fs::File::create(cache_dir.join(name)).unwrap();
+            std::io::Write::write_all(&mut &file, &[b'x'; 100]).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age * 60)).unwrap();
+        }
+
+        collect_garbage(&cache_dir, 250);
+        assert!(cache_dir.join("newest.json").exists());
+        assert!(cache_dir.join("older.json").exists());
+        assert!(!cache_dir.join("oldest.json").exists());
+
+        collect_garbage(&cache_dir, 1000);
+        assert!(cache_dir.join("older.json").exists());
+    }
+}
@@ -18,12 +18,14 @@ use std::rc::Rc;
 ///
 /// # Arguments
 ///
+/// * `app_context` - Reference to the application context
 /// * `text_view` - The text view to add zoom controllers to
 /// * `current_font_desc` - Reference to the current font description
 /// * `update_font` - Function to update the font
 /// * `app` - Reference to the GTK application
 /// * `initial_font_size` - Reference to the initial font size
 pub fn add_zoom_controllers_to_text_view(
+    app_context: &Rc<RefCell<crate::AppContext>>,
     text_view: &TextView,
     current_font_desc: Rc<RefCell<pango::FontDescription>>,
     update_font: Rc<dyn Fn(&pango::FontDescription)>,
@@ -37,6 +39,7 @@ pub fn add_zoom_controllers_to_text_view(
     let current_font_desc_clone_scroll = current_font_desc.clone();
     let update_font_clone_scroll = update_font.clone();
     let app_clone_scroll = app.clone();
+    let app_context_clone_scroll = app_context.clone();
     let scroll_controller_clone = scroll_controller.clone();
     scroll_controller.connect_scroll(move |_, _, dy| {
         if app_clone_scroll.active_window().is_some() {
@@ -55,6 +58,8 @@ pub fn add_zoom_controllers_to_text_view(
                 }
                 font_desc.set_size((current_size * pango::SCALE as f64) as i32);
                 update_font_clone_scroll(&font_desc);
+                drop(font_desc);
+                crate::indentation::update_all_tab_stops(&app_context_clone_scroll);
                 return glib::Propagation::Stop; // Inhibits default scroll behavior
             }
         }
@@ -65,6 +70,7 @@ pub fn add_zoom_controllers_to_text_view(
     let update_font_clone_key = update_font;
     let initial_font_size_clone_key = initial_font_size;
     let app_clone_key = app;
+    let app_context_clone_key = app_context.clone();
     key_controller.connect_key_pressed(move |_, keyval, _, state| {
         if app_clone_key.active_window().is_some() {
             if state.contains(gdk::ModifierType::CONTROL_MASK) {
@@ -94,6 +100,8 @@ pub fn add_zoom_controllers_to_text_view(
                 if changed {
                     font_desc.set_size((current_size * pango::SCALE as f64) as i32);
                     update_font_clone_key(&font_desc);
+                    drop(font_desc);
+                    crate::indentation::update_all_tab_stops(&app_context_clone_key);
                     return glib::Propagation::Stop; // Inhibits default key behavior
                 }
             }
@@ -0,0 +1,225 @@
+//! Module for whitespace clean-up
+//!
+//! Before a buffer is saved, trailing whitespace can be trimmed (everywhere or
+//! only on the lines that differ from the file on disk), blank lines at the end
+//! of the file collapsed and a final newline ensured. The rules come from the
+//! `whitespace` settings of the buffer's language, then from the global ones,
+//! and EditorConfig properties take precedence over both. This module also
+//! converts the indentation of a buffer between tabs and spaces.
+
+use gtk4::prelude::*;
+use gtk4::TextBuffer;
+use similar::{DiffTag, TextDiff};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::editorconfig::EditorConfig;
+use crate::AppContext;
+
+/// Effective clean-up rules for a buffer
+struct SaveRules {
+    /// Whether to remove whitespace at the end of lines
+    trim: bool,
+    /// Whether to trim only the lines that differ from the file on disk
+    trim_modified_only: bool,
+    /// Whether to remove the blank lines at the end of the file
+    collapse: bool,
+    /// Whether the file must (or must not) end with a newline
+    final_newline: Option<bool>,
+}
+
+/// Resolves the clean-up rules of a buffer
+fn save_rules(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer, editor_config: &EditorConfig) -> SaveRules {
+    let language = crate::syntax_highlighting::language_settings_for_buffer(app_context, buffer);
+    let settings = language
+        .whitespace
+        .or(&app_context.borrow().app_settings.borrow().whitespace);
+    SaveRules {
+        trim: editor_config
+            .trim_trailing_whitespace
+            .unwrap_or(settings.trim_trailing_whitespace == Some(true)),
+        trim_modified_only: settings.trim_modified_lines_only == Some(true),
+        collapse: settings.collapse_trailing_blank_lines == Some(true),
+        final_newline: editor_config
+            .insert_final_newline
+            .or(settings.ensure_final_newline.filter(|&ensure| ensure)),
+    }
+}
+
+/// Returns the lines of a text that differ from the file on disk
+///
+/// # Returns
+///
+/// `None` if the file can't be read, in which case every line counts as modified
+fn modified_lines(text: &str, file_path: &Path, editor_config: &EditorConfig) -> Option<HashSet<usize>> {
+    let saved = crate::editorconfig::read_file(file_path, editor_config).ok()?;
+    let diff = TextDiff::from_lines(saved.as_str(), text);
+    Some(
+        diff.ops()
+            .iter()
+            .filter(|op| op.tag() != DiffTag::Equal && op.tag() != DiffTag::Delete)
+            .flat_map(|op| op.new_range())
+            .collect(),
+    )
+}
+
+/// Removes the whitespace at the end of the given lines
+fn trim_lines(buffer: &TextBuffer, lines: Option<&HashSet<usize>>) {
+    for line in 0..buffer.line_count() {
+        if lines.is_some_and(|lines| !lines.contains(&(line as usize))) {
+            continue;
+        }
+        let Some(mut end) = buffer.iter_at_line(line) else {
+            continue;
+        };
+        if !end.ends_line() {
+            end.forward_to_line_end();
+        }
+        let mut start = end;
+        while !start.starts_line() && start.backward_char() {
+            if !matches!(start.char(), ' ' | '\t') {
+                start.forward_char();
+                break;
+            }
+        }
+        if start.offset() < end.offset() {
+            buffer.delete(&mut start, &mut end);
+        }
+    }
+}
+
+/// Removes the blank lines at the end of a buffer, keeping the line
+/// terminator of the last non-blank line
+fn collapse_trailing_blank_lines(buffer: &TextBuffer) {
+    let mut last = buffer.end_iter();
+    while last.backward_char() {
+        if !last.char().is_whitespace() {
+            break;
+        }
+    }
+    if last.char().is_whitespace() {
+        // The buffer is blank, so there is no last line to keep
+        return;
+    }
+    if !last.ends_line() {
+        last.forward_to_line_end();
+    }
+    if last.forward_line() {
+        buffer.delete(&mut last, &mut buffer.end_iter());
+    }
+}
+
+/// Adds or removes the final newline of a buffer
+fn set_final_newline(buffer: &TextBuffer, final_newline: bool) {
+    let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true).to_string();
+    if final_newline {
+        if !text.is_empty() && !text.ends_with(['\n', '\r']) {
+            let terminator = if text.contains("\r\n") { "\r\n" } else { "\n" };
+            buffer.insert(&mut buffer.end_iter(), terminator);
+        }
+    } else {
+        let kept = text.trim_end_matches(['\n', '\r']).chars().count() as i32;
+        if kept < buffer.char_count() {
+            buffer.delete(&mut buffer.iter_at_offset(kept), &mut buffer.end_iter());
+        }
+    }
+}
+
+/// Applies the whitespace clean-up rules to a buffer about to be saved
+///
+/// All changes form a single undoable edit, and the cursor keeps its position.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer about to be saved
+/// * `file_path` - Path of the file the buffer is saved to
+/// * `editor_config` - EditorConfig properties of the file
+pub fn clean_up_on_save(
+    app_context: &Rc<RefCell<AppContext>>,
+    buffer: &TextBuffer,
+    file_path: &Path,
+    editor_config: &EditorConfig,
+) {
+    let rules = save_rules(app_context, buffer, editor_config);
+    if !rules.trim && !rules.collapse && rules.final_newline.is_none() {
+        return;
+    }
+
+    let modified = if rules.trim && rules.trim_modified_only {
+        let text = buffer.text(&buffer.start_iter(), &buffer.end_iter(), true);
+        modified_lines(&text, file_path, editor_config)
+    } else {
+        None
+    };
+
+    let cursor_mark = buffer.create_mark(None, &buffer.iter_at_mark(&buffer.get_insert()), true);
+    buffer.begin_user_action();
+    if rules.trim {
+        trim_lines(buffer, modified.as_ref());
+    }
+    if rules.collapse {
+        collapse_trailing_blank_lines(buffer);
+    }
+    if let Some(final_newline) = rules.final_newline {
+        set_final_newline(buffer, final_newline);
+    }
+    buffer.end_user_action();
+    buffer.place_cursor(&buffer.iter_at_mark(&cursor_mark));
+    buffer.delete_mark(&cursor_mark);
+}
+
+/// Converts the indentation of every line of a buffer to tabs or spaces
+///
+/// The width of one indentation level comes from `detect_indent_style`, or
+/// from the tab width when the buffer is indented with tabs. Leftover columns
+/// that don't make a whole level stay as spaces when converting to tabs. The
+/// buffer's indentation style is then updated, so that new indentation
+/// follows the conversion.
+///
+/// # Arguments
+///
+/// * `app_context` - Reference to the application context
+/// * `buffer` - The buffer to convert
+/// * `to_tabs` - Whether to convert to tabs rather than spaces
+pub fn convert_indentation(app_context: &Rc<RefCell<AppContext>>, buffer: &TextBuffer, to_tabs: bool) {
+    let (is_tab_indent, indent_width) = crate::indentation::detect_indent_style(app_context, buffer);
+    let level_width = if is_tab_indent {
+        crate::indentation::tab_width(app_context, buffer)
+    } else {
+        indent_width.max(1)
+    };
+
+    let cursor_mark = buffer.create_mark(None, &buffer.iter_at_mark(&buffer.get_insert()), true);
+    buffer.begin_user_action();
+    for line in 0..buffer.line_count() {
+        let Some(mut start) = buffer.iter_at_line(line) else {
+            continue;
+        };
+        let mut end = start;
+        let mut columns = 0;
+        while matches!(end.char(), ' ' | '\t') && !end.ends_line() {
+            columns = if end.char() == '\t' { columns + level_width - columns % level_width } else { columns + 1 };
+            end.forward_char();
+        }
+
+        let indentation = buffer.text(&start, &end, true).to_string();
+        let converted = if to_tabs {
+            format!("{}{}", "\t".repeat(columns / level_width), " ".repeat(columns % level_width))
+        } else {
+            " ".repeat(columns)
+        };
+        if indentation != converted {
+            buffer.delete(&mut start, &mut end);
+            buffer.insert(&mut start, &converted);
+        }
+    }
+    buffer.end_user_action();
+    buffer.place_cursor(&buffer.iter_at_mark(&cursor_mark));
+    buffer.delete_mark(&cursor_mark);
+
+    let style = if to_tabs { (true, 0) } else { (false, level_width) };
+    app_context.borrow().indent_styles.borrow_mut().insert(buffer.clone(), style);
+}
@@ -0,0 +1,35 @@
+#!/usr/bin/env python3
+"""Fake formatter used by the tests of the formatter module.
+
+Reads a document on standard input and writes it on standard output with the
+leading tabs of each line expanded to four spaces and the trailing spaces and
+tabs removed. Line terminators, LF or CRLF, are kept as they are.
+
+With `--sleep SECONDS`, waits that long before reading, like a formatter that
+hangs.
+"""
+
+import re
+import sys
+import time
+
+
+def format_line(line):
+    body = line.rstrip("\r\n")
+    terminator = line[len(body):]
+    indent = len(body) - len(body.lstrip("\t"))
+    return "    " * indent + body[indent:].rstrip(" \t") + terminator
+
+
+def main():
+    if "--sleep" in sys.argv:
+        time.sleep(float(sys.argv[sys.argv.index("--sleep") + 1]))
+    text = sys.stdin.buffer.read().decode("utf-8")
+    lines = re.findall(r"[^\n]*\n|[^\n]+$", text)
+    formatted = "".join(format_line(line) for line in lines)
+    sys.stdout.buffer.write(formatted.encode("utf-8"))
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
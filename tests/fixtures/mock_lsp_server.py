@@ -0,0 +1,81 @@
+#!/usr/bin/env python3
+"""Mock language server used by the tests of the LSP client.
+
+Speaks JSON-RPC framed with a Content-Length header over standard input and
+output. Every message received is sent back in a `mock/received`
+notification, so that tests can check what the client sent and in which
+order. Requests are answered as follows:
+
+* `initialize` - capabilities with the `textDocumentSync` kind given by
+  `--sync` (incremental by default)
+* `shutdown` - null
+* `mock/crash` - not answered: the server exits with status 1
+* anything else - a "method not found" error
+
+The `exit` notification makes the server exit with status 0.
+"""
+
+import json
+import sys
+
+METHOD_NOT_FOUND = -32601
+
+
+def read_message(stdin):
+    length = None
+    while True:
+        line = stdin.readline()
+        if not line:
+            return None
+        line = line.rstrip(b"\r\n")
+        if not line:
+            if length is not None:
+                break
+            continue
+        name, _, value = line.partition(b":")
+        if name.strip().lower() == b"content-length":
+            length = int(value.strip())
+    body = stdin.read(length)
+    if len(body) < length:
+        return None
+    return json.loads(body.decode("utf-8"))
+
+
+def send(stdout, message):
+    body = json.dumps(message, ensure_ascii=False).encode("utf-8")
+    stdout.write(b"Content-Length: %d\r\n\r\n" % len(body))
+    stdout.write(body)
+    stdout.flush()
+
+
+def main():
+    sync = 2
+    if "--sync" in sys.argv:
+        sync = int(sys.argv[sys.argv.index("--sync") + 1])
+    stdin, stdout = sys.stdin.buffer, sys.stdout.buffer
+
+    while True:
+        message = read_message(stdin)
+        if message is None:
+            return 0
+        send(stdout, {"jsonrpc": "2.0", "method": "mock/received", "params": message})
+
+        method = message.get("method")
+        if method == "exit":
+            return 0
+        if method is None or "id" not in message:
+            continue
+        if method == "mock/crash":
+            return 1
+        reply = {"jsonrpc": "2.0", "id": message["id"]}
+        if method == "initialize":
+            reply["result"] = {"capabilities": {"textDocumentSync": sync, "hoverProvider": True}}
+        elif method == "shutdown":
+            reply["result"] = None
+        else:
+            reply["error"] = {"code": METHOD_NOT_FOUND, "message": "Unsupported method " + method}
+        send(stdout, reply)
+
+
+if __name__ == "__main__":
+    sys.exit(main())